@@ -25,17 +25,23 @@ already-built binary (e.g. a release build) without rebuilding, point it at one:
 Exits 0 if every case passes, non-zero otherwise.
 """
 
+import glob
+import gzip
 import http.server
+import json
 import os
 import platform
 import re
 import signal
 import socket
 import struct
+import shutil
 import subprocess
 import sys
+import tempfile
 import threading
 import time
+import urllib.error
 import urllib.request
 
 ROOT = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
@@ -222,6 +228,10 @@ def start_proxy(binary, remote_port, level="debug", extra_args=()):
         stdout=subprocess.PIPE,
         stderr=subprocess.STDOUT,
         text=True,
+        # The proxy writes UTF-8 (e.g. `--formatting utf8-lossy`); decode it as such
+        # rather than in the platform's locale encoding (cp1252 on Windows).
+        encoding="utf-8",
+        errors="replace",
     )
     return proxy, proxy_port
 
@@ -277,6 +287,134 @@ def run_case(binary, formatting, separator, render_byte):
     print("OK [%s] relayed %d bytes and logged them as %s" % (formatting, len(payload), expected))
 
 
+def test_hexdump_rows(binary):
+    """`--formatting hexdump` prints a multi-line `hexdump -C` block per relayed
+    chunk, and every row is its own console line carrying the connection's `[#N]`
+    tag and the direction marker (the in-crate `tests::formatting` module pins the
+    row layout; this pins how the rows reach the console). A width of 8 splits the
+    20-byte payload into three rows, so a block printed as one line, or rows missing
+    the tag, cannot pass."""
+    payload = b"hexdump rows: \x00\x01\x7f\xff!!"
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, extra_args=("--formatting", "hexdump", "--hexdump-width", "8")
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[hexdump] proxy did not start listening")
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(payload)
+            received = recv_exact(client, len(payload))
+        if received != payload:
+            fail("[hexdump] echo mismatch: sent %r, got %r" % (payload, received))
+        time.sleep(0.3)  # let the proxy flush its log lines
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+
+    rows = [
+        "00000000  68 65 78 64 75 6d 70 20  |hexdump |",
+        "00000008  72 6f 77 73 3a 20 00 01  |rows: ..|",
+        "00000010  7f ff 21 21              |..!!|",
+    ]
+    for row in rows:
+        if not re.search(r"\[#\d+\] < " + re.escape(row) + "$", output, re.MULTILINE):
+            fail("[hexdump] row %r was not logged as its own tagged `<` line" % row, output)
+    print("OK [hexdump] every row of the block logged as its own tagged line")
+
+
+def test_text_formatting(binary, formatting, payload, expected):
+    """`--formatting text`/`utf8-lossy` log a chunk as one escaped line of text:
+    the CR/LF that would otherwise split the line are printed as `\\r\\n`, and the
+    line keeps its `[#N]` tag and `<` marker like any other payload line."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(binary, echo_port, extra_args=("--formatting", formatting))
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[%s] proxy did not start listening" % formatting)
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(payload)
+            received = recv_exact(client, len(payload))
+        if received != payload:
+            fail("[%s] echo mismatch: sent %r, got %r" % (formatting, payload, received))
+        time.sleep(0.3)  # let the proxy flush its log lines
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+
+    if not re.search(r"\[#\d+\] < " + re.escape(expected) + "$", output, re.MULTILINE):
+        fail("[%s] payload not logged as the single tagged line %r" % (formatting, expected),
+             output)
+    print("OK [%s] relayed %d bytes and logged them as %s" % (formatting, len(payload), expected))
+
+
+def test_jsonl_output(binary):
+    """`--output-format jsonl` prints every line as one JSON object. Each payload
+    chunk is an event of its own carrying the connection id, the client and the
+    destination addresses, its direction, its byte count and the encoded payload;
+    the half-closes are events of the same connection."""
+    request = b"jsonl \"request\"\n"
+    reply = b"jsonl reply"
+    server, server_port = start_asymmetric_server(reply)
+    proxy, proxy_port = start_proxy(
+        binary, server_port, extra_args=("--output-format", "jsonl", "--formatting", "text")
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[jsonl] proxy did not start listening")
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client_addr = "%s:%d" % client.getsockname()[:2]
+            client.sendall(request)
+            received = recv_exact(client, len(reply))
+        if received != reply:
+            fail("[jsonl] reply mismatch: expected %r, got %r" % (reply, received))
+        time.sleep(0.3)  # let the proxy flush its log lines
+    finally:
+        output = stop_proxy(proxy)
+        server.close()
+
+    events = []
+    for line in output.splitlines():
+        try:
+            events.append(json.loads(line))
+        except ValueError:
+            fail("[jsonl] line is not a JSON object: %r" % line, output)
+    for event in events:
+        if not {"timestamp", "monotonic_us", "level", "message"} <= event.keys():
+            fail("[jsonl] event without the common fields: %r" % event, output)
+    if not any(event.get("event") == "listening" for event in events):
+        fail("[jsonl] no listening event", output)
+
+    mine = [event for event in events if event.get("client") == client_addr]
+    accepted = [event for event in mine if event.get("event") == "accepted"]
+    if len(accepted) != 1:
+        fail("[jsonl] expected one accepted event for %s" % client_addr, output)
+    conn_id = accepted[0]["conn_id"]
+    if any(event.get("conn_id") != conn_id for event in mine):
+        fail("[jsonl] events of one client carry different connection ids", output)
+
+    payloads = {event["direction"]: event for event in mine if event.get("event") == "payload"}
+    expected = {
+        "client_to_destination": (request, 'jsonl "request"\\n'),
+        "destination_to_client": (reply, "jsonl reply"),
+    }
+    for direction, (payload, encoded) in expected.items():
+        event = payloads.get(direction)
+        if event is None:
+            fail("[jsonl] no %s payload event" % direction, output)
+        if event["bytes"] != len(payload) or event["payload"] != encoded:
+            fail("[jsonl] %s payload event is wrong: %r" % (direction, event), output)
+        if event["destination_addr"] != "%s:%d" % (HOST, server_port):
+            fail("[jsonl] payload event without the destination address: %r" % event, output)
+    streams = {event["stream"] for event in mine if event.get("event") == "half_close"}
+    if streams != {"client", "destination"}:
+        fail("[jsonl] expected a half_close event on both streams, got %r" % streams, output)
+    print("OK [jsonl] every line parsed as JSON, payload events carry direction and bytes")
+
+
 def start_asymmetric_server(reply):
     """Start a server that answers every connection with `reply`, whatever it was
     sent. Unlike the echo server the two directions carry DIFFERENT bytes, which is
@@ -498,6 +636,707 @@ def test_no_connection_ids_flag(binary):
     print("OK [no-conn-ids] --no-connection-ids removes the tags, output otherwise intact")
 
 
+def test_close_summary(binary):
+    """A connection's last line is its summary: duration, bytes and chunks each way,
+    the side that closed first and the connect latency."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(binary, echo_port, level="info")
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[close-summary] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client_port = client.getsockname()[1]
+            client.sendall(b"summary")
+            if recv_exact(client, 7) != b"summary":
+                fail("[close-summary] echo mismatch")
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+
+    summary = (r"\[#\d+\] Connection from %s:%d closed after \d+\.\d{3}s "
+               r"\(the client closed first\): 7 bytes in 1 chunk\(s\) from the client, "
+               r"7 bytes in 1 chunk\(s\) from the destination, connected in \d+\.\dms"
+               % (HOST, client_port))
+    if not re.search(summary, output):
+        fail("[close-summary] the connection's summary line was not logged", output)
+    print("OK [close-summary] the closed connection logged its traffic summary")
+
+
+def test_latency(binary):
+    """`--latency-to-destination` and `--latency-to-client` delay each direction,
+    and jitter leaves the echoed stream intact and in order."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="info",
+        extra_args=["--latency-to-destination", "300", "--latency-to-client", "200",
+                    "--jitter-to-client", "50"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[latency] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            started = time.monotonic()
+            client.sendall(b"slow")
+            if recv_exact(client, 4) != b"slow":
+                fail("[latency] echo mismatch", stop_proxy(proxy))
+            elapsed = time.monotonic() - started
+            if elapsed < 0.5:
+                fail("[latency] the round trip took %.3fs, expected at least 0.5s" % elapsed,
+                     stop_proxy(proxy))
+            payload = b"".join(bytes([i]) * 16 for i in range(32))
+            for i in range(32):
+                client.sendall(payload[i * 16:(i + 1) * 16])
+                time.sleep(0.005)
+            if recv_exact(client, len(payload)) != payload:
+                fail("[latency] the jittered stream came back out of order", stop_proxy(proxy))
+    finally:
+        stop_proxy(proxy)
+        echo_server.close()
+    print("OK [latency] both directions were delayed and the stream kept its order")
+
+
+def test_rate_limit(binary):
+    """`--rate-limit-down` paces what the destination sends back: 4 KiB echoed at
+    2 KiB a second take about two seconds."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="info", extra_args=["--rate-limit-down", "2K"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[rate-limit] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            payload = bytes(range(256)) * 16
+            started = time.monotonic()
+            client.sendall(payload)
+            if recv_exact(client, len(payload)) != payload:
+                fail("[rate-limit] echo mismatch", stop_proxy(proxy))
+            elapsed = time.monotonic() - started
+            if not 1.5 <= elapsed < 4.0:
+                fail("[rate-limit] 4 KiB at 2 KiB/s took %.3fs, expected about 2s" % elapsed,
+                     stop_proxy(proxy))
+    finally:
+        stop_proxy(proxy)
+        echo_server.close()
+    print("OK [rate-limit] the echoed payload came back at the limited rate")
+
+
+def test_fault(binary):
+    """`--fault up:reset@10` lets ten bytes of the request through and then resets
+    the client's connection; the seed and the injected fault are logged."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="info",
+        extra_args=["--fault", "up:reset@10", "--seed", "5"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[fault] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(b"0123456789abcdefghij")
+            received = b""
+            try:
+                while True:
+                    chunk = client.recv(1024)
+                    if not chunk:
+                        fail("[fault] the connection closed cleanly instead of a reset",
+                             stop_proxy(proxy))
+                    received += chunk
+            except ConnectionResetError:
+                pass
+            if received not in (b"", b"0123456789"):
+                fail("[fault] unexpected echo before the reset: %r" % received,
+                     stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+    for expected in ("with seed 5",
+                     "Injected fault up:reset@10: resetting the connection after 10 bytes",
+                     "(a fault reset it)"):
+        if expected not in output:
+            fail("[fault] missing %r in the log" % expected, output)
+    print("OK [fault] the connection was reset after 10 bytes and the fault logged")
+
+
+def test_fault_corruption(binary):
+    """`--fault up:flip=1` flips a bit of every request byte; the `<` payload line
+    shows the bytes the destination received (and echoed), not the ones sent."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="debug",
+        extra_args=["--fault", "up:flip=1", "--seed", "5"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[fault-corruption] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(b"abcdef")
+            echo = recv_exact(client, 6)
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+    if echo == b"abcdef":
+        fail("[fault-corruption] the request reached the destination untouched", output)
+    received = ":".join("%02x" % byte for byte in echo)
+    if "< %s" % received not in output:
+        fail("[fault-corruption] missing the received bytes `< %s` in the log" % received,
+             output)
+    if "< 61:62:63:64:65:66" in output:
+        fail("[fault-corruption] the request was logged as sent, not as received", output)
+    print("OK [fault-corruption] the payload line shows the corrupted bytes")
+
+
+def test_fragment(binary):
+    """`--fragment 2 --fragment-delay 10` writes a 6-byte request on in 2-byte
+    pieces, each logged as a payload line of its own, and the echo arrives intact."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="debug",
+        extra_args=["--fragment", "2", "--fragment-delay", "10"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[fragment] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(b"abcdef")
+            if recv_exact(client, 6) != b"abcdef":
+                fail("[fragment] echo mismatch", stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+    for expected in ("< 61:62", "< 63:64", "< 65:66"):
+        if expected not in output:
+            fail("[fragment] missing %r in the log" % expected, output)
+    if "< 61:62:63" in output:
+        fail("[fragment] the request was logged as read, not as written", output)
+    print("OK [fragment] the request was written and logged in 2-byte pieces")
+
+
+def test_tls(binary):
+    """`--tls-cert`/`--tls-key` terminate a client's TLS with a self-signed
+    certificate made by `openssl`: the echo comes back over TLS, and the session and
+    the decrypted payload are logged."""
+    openssl = shutil.which("openssl")
+    if openssl is None:
+        print("OK [tls] skipped: no `openssl` to make a certificate with")
+        return
+    import ssl
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-tls-")
+    cert = os.path.join(directory, "cert.pem")
+    key = os.path.join(directory, "key.pem")
+    subprocess.run(
+        [openssl, "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+         "-subj", "/CN=localhost", "-addext", "subjectAltName=DNS:localhost",
+         "-keyout", key, "-out", cert],
+        check=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL,
+    )
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="debug",
+        extra_args=["--tls-cert", cert, "--tls-key", key],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[tls] proxy did not start listening", stop_proxy(proxy))
+        context = ssl.create_default_context(cafile=cert)
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as raw:
+            with context.wrap_socket(raw, server_hostname="localhost") as client:
+                client.settimeout(IO_TIMEOUT)
+                client.sendall(b"secret")
+                if recv_exact(client, 6) != b"secret":
+                    fail("[tls] echo mismatch", stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+        shutil.rmtree(directory, ignore_errors=True)
+    for expected in ("TLS established with client", "SNI localhost",
+                     "< 73:65:63:72:65:74", "> 73:65:63:72:65:74"):
+        if expected not in output:
+            fail("[tls] missing %r in the log" % expected, output)
+    print("OK [tls] terminated the client's TLS and logged the payload decrypted")
+
+
+def test_tls_mitm(binary):
+    """`--tls-mitm-ca`/`--tls-mitm-key` terminate a client's TLS with a certificate
+    minted for the name it asks for, signed by a CA made by `openssl` that the
+    client trusts, and dial the TLS-only echo server over TLS of the proxy's own,
+    without `--remote-tls`: the echo comes back over TLS, and both sessions and the
+    decrypted payload are logged."""
+    openssl = shutil.which("openssl")
+    if openssl is None:
+        print("OK [tls-mitm] skipped: no `openssl` to make a CA with")
+        return
+    import ssl
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-tls-mitm-")
+    ca = os.path.join(directory, "ca.pem")
+    ca_key = os.path.join(directory, "ca-key.pem")
+    subprocess.run(
+        [openssl, "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+         "-subj", "/CN=Lab CA", "-keyout", ca_key, "-out", ca],
+        check=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL,
+    )
+    cert = os.path.join(directory, "cert.pem")
+    key = os.path.join(directory, "key.pem")
+    subprocess.run(
+        [openssl, "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+         "-subj", "/CN=mitm.lab", "-addext", "subjectAltName=DNS:mitm.lab",
+         "-addext", "basicConstraints=critical,CA:FALSE",
+         "-keyout", key, "-out", cert],
+        check=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL,
+    )
+    server_context = ssl.SSLContext(ssl.PROTOCOL_TLS_SERVER)
+    server_context.load_cert_chain(cert, key)
+    server = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    server.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    server.bind((HOST, 0))
+    server.listen(8)
+    tls_port = server.getsockname()[1]
+    tls_server = server_context.wrap_socket(server, server_side=True)
+    _serve_echo(tls_server)
+    proxy, proxy_port = start_proxy(
+        binary, tls_port, level="debug",
+        extra_args=["--tls-mitm-ca", ca, "--tls-mitm-key", ca_key, "--remote-ca", cert],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[tls-mitm] proxy did not start listening", stop_proxy(proxy))
+        context = ssl.create_default_context(cafile=ca)
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as raw:
+            with context.wrap_socket(raw, server_hostname="mitm.lab") as client:
+                client.settimeout(IO_TIMEOUT)
+                client.sendall(b"secret")
+                if recv_exact(client, 6) != b"secret":
+                    fail("[tls-mitm] echo mismatch", stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        tls_server.close()
+        shutil.rmtree(directory, ignore_errors=True)
+    for expected in ("TLS established with client", "SNI mitm.lab",
+                     "TLS established with destination",
+                     "< 73:65:63:72:65:74", "> 73:65:63:72:65:74"):
+        if expected not in output:
+            fail("[tls-mitm] missing %r in the log" % expected, output)
+    print("OK [tls-mitm] minted a certificate for the client's name, dialed the "
+          "destination over TLS and logged the payload")
+
+
+def test_remote_tls(binary):
+    """`--remote-tls` with `--remote-ca` and `--remote-sni` dials a TLS echo server
+    (Python's `ssl`, with a certificate made by `openssl`) for a plaintext client:
+    the echo comes back, and the session and the plaintext payload are logged."""
+    openssl = shutil.which("openssl")
+    if openssl is None:
+        print("OK [remote-tls] skipped: no `openssl` to make a certificate with")
+        return
+    import ssl
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-remote-tls-")
+    cert = os.path.join(directory, "cert.pem")
+    key = os.path.join(directory, "key.pem")
+    subprocess.run(
+        [openssl, "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+         "-subj", "/CN=device.lab", "-addext", "subjectAltName=DNS:device.lab",
+         # Not a CA: a CA certificate is refused as the server's own.
+         "-addext", "basicConstraints=critical,CA:FALSE",
+         "-keyout", key, "-out", cert],
+        check=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL,
+    )
+    context = ssl.SSLContext(ssl.PROTOCOL_TLS_SERVER)
+    context.load_cert_chain(cert, key)
+    server = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    server.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    server.bind((HOST, 0))
+    server.listen(8)
+    tls_port = server.getsockname()[1]
+    tls_server = context.wrap_socket(server, server_side=True)
+    _serve_echo(tls_server)
+    proxy, proxy_port = start_proxy(
+        binary, tls_port, level="debug",
+        extra_args=["--remote-tls", "--remote-ca", cert, "--remote-sni", "device.lab"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[remote-tls] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(b"secret")
+            if recv_exact(client, 6) != b"secret":
+                fail("[remote-tls] echo mismatch", stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        tls_server.close()
+        shutil.rmtree(directory, ignore_errors=True)
+    for expected in ("TLS established with destination",
+                     "< 73:65:63:72:65:74", "> 73:65:63:72:65:74"):
+        if expected not in output:
+            fail("[remote-tls] missing %r in the log" % expected, output)
+    print("OK [remote-tls] dialed the destination over TLS and logged the plaintext")
+
+
+def test_inspect_tls(binary):
+    """`--inspect-tls` logs the hellos of a TLS session it relays untouched between
+    Python's `ssl` on both ends, with a certificate made by `openssl`: the client's
+    server name and ALPN offer, and the version the server picked."""
+    openssl = shutil.which("openssl")
+    if openssl is None:
+        print("OK [inspect-tls] skipped: no `openssl` to make a certificate with")
+        return
+    import ssl
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-inspect-tls-")
+    cert = os.path.join(directory, "cert.pem")
+    key = os.path.join(directory, "key.pem")
+    subprocess.run(
+        [openssl, "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+         "-subj", "/CN=device.lab", "-addext", "subjectAltName=DNS:device.lab",
+         "-keyout", key, "-out", cert],
+        check=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL,
+    )
+    server_context = ssl.SSLContext(ssl.PROTOCOL_TLS_SERVER)
+    server_context.load_cert_chain(cert, key)
+    server_context.set_alpn_protocols(["http/1.1"])
+    server = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    server.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    server.bind((HOST, 0))
+    server.listen(8)
+    tls_port = server.getsockname()[1]
+    # The handshake is left to the first read, so that the plaintext probe of
+    # `wait_for_listener` fails that connection alone, not the accept loop.
+    tls_server = server_context.wrap_socket(
+        server, server_side=True, do_handshake_on_connect=False)
+    _serve_echo(tls_server)
+    proxy, proxy_port = start_proxy(
+        binary, tls_port, level="info", extra_args=["--inspect-tls"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[inspect-tls] proxy did not start listening", stop_proxy(proxy))
+        context = ssl.create_default_context(cafile=cert)
+        context.minimum_version = ssl.TLSVersion.TLSv1_2
+        context.set_alpn_protocols(["h2", "http/1.1"])
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as raw:
+            with context.wrap_socket(raw, server_hostname="device.lab") as client:
+                client.settimeout(IO_TIMEOUT)
+                client.sendall(b"secret")
+                if recv_exact(client, 6) != b"secret":
+                    fail("[inspect-tls] echo mismatch", stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        tls_server.close()
+        shutil.rmtree(directory, ignore_errors=True)
+    for expected in ("< TLS ClientHello: SNI device.lab, ALPN h2, http/1.1, versions TLS 1.3",
+                     "> TLS ServerHello: TLS 1.3, TLS13_"):
+        if expected not in output:
+            fail("[inspect-tls] missing %r in the log" % expected, output)
+    print("OK [inspect-tls] logged the hellos of a relayed TLS session")
+
+
+def test_sni_route(binary):
+    """`--sni-route` sends a TLS client asking for a routed name to that route's
+    destination, which completes the handshake itself, and a plaintext client to
+    the listener's remote address; a name without a route gets an alert when there
+    is no remote address to fall back on."""
+    openssl = shutil.which("openssl")
+    if openssl is None:
+        print("OK [sni-route] skipped: no `openssl` to make a certificate with")
+        return
+    import ssl
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-sni-route-")
+    cert = os.path.join(directory, "cert.pem")
+    key = os.path.join(directory, "key.pem")
+    subprocess.run(
+        [openssl, "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+         "-subj", "/CN=device.lab", "-addext", "subjectAltName=DNS:device.lab",
+         "-keyout", key, "-out", cert],
+        check=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL,
+    )
+    server_context = ssl.SSLContext(ssl.PROTOCOL_TLS_SERVER)
+    server_context.load_cert_chain(cert, key)
+    server = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    server.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    server.bind((HOST, 0))
+    server.listen(8)
+    tls_port = server.getsockname()[1]
+    tls_server = server_context.wrap_socket(
+        server, server_side=True, do_handshake_on_connect=False)
+    _serve_echo(tls_server)
+    echo_server, echo_port = start_echo_server()
+    route = "device.lab=%s:%d" % (HOST, tls_port)
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="info", extra_args=["--sni-route", route],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[sni-route] proxy did not start listening", stop_proxy(proxy))
+        context = ssl.create_default_context(cafile=cert)
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as raw:
+            with context.wrap_socket(raw, server_hostname="device.lab") as client:
+                client.settimeout(IO_TIMEOUT)
+                client.sendall(b"secret")
+                if recv_exact(client, 6) != b"secret":
+                    fail("[sni-route] TLS echo mismatch", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.sendall(b"PING\r\n")
+            if recv_exact(client, 6) != b"PING\r\n":
+                fail("[sni-route] plaintext echo mismatch", stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+    for expected in ("Server name device.lab routed to %s:%d" % (HOST, tls_port),
+                     "Routing to the default destination %s:%d: the client sent no TLS hello"
+                     % (HOST, echo_port)):
+        if expected not in output:
+            fail("[sni-route] missing %r in the log" % expected, output)
+
+    proxy_port = free_port()
+    proxy = subprocess.Popen(
+        [binary, "--bind-listener-addr", "%s:%d" % (HOST, proxy_port),
+         "--sni-route", route, "--level", "info"],
+        cwd=ROOT, stdout=subprocess.PIPE, stderr=subprocess.STDOUT,
+        text=True, encoding="utf-8", errors="replace",
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[sni-route] proxy without a default did not start listening",
+                 stop_proxy(proxy))
+        context = ssl.create_default_context(cafile=cert)
+        context.check_hostname = False
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as raw:
+            try:
+                context.wrap_socket(raw, server_hostname="nowhere.lab")
+                fail("[sni-route] an unrouted name got a handshake", stop_proxy(proxy))
+            except ssl.SSLError as error:
+                if "UNRECOGNIZED_NAME" not in str(error).upper():
+                    fail("[sni-route] unexpected handshake failure: %s" % error,
+                         stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        tls_server.close()
+        shutil.rmtree(directory, ignore_errors=True)
+    expected = "server name nowhere.lab matches no SNI route, and there is no default destination"
+    if expected not in output:
+        fail("[sni-route] missing %r in the log" % expected, output)
+    print("OK [sni-route] routed by server name, fell back to the default, and "
+          "turned away an unrouted name")
+
+
+def test_metrics(binary):
+    """`--metrics-addr` serves Prometheus counters for the route at `/metrics`,
+    labelled with the route's listen address, and nothing else."""
+    echo_server, echo_port = start_echo_server()
+    metrics_port = free_port()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="info",
+        extra_args=("--metrics-addr", "%s:%d" % (HOST, metrics_port)))
+    opener = urllib.request.build_opener(urllib.request.ProxyHandler({}))
+    try:
+        if not wait_for_listener(proxy_port) or not wait_for_listener(metrics_port):
+            fail("[metrics] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(b"scrape")
+            if recv_exact(client, 6) != b"scrape":
+                fail("[metrics] echo mismatch")
+        time.sleep(0.3)
+        with opener.open("http://%s:%d/metrics" % (HOST, metrics_port),
+                         timeout=IO_TIMEOUT) as response:
+            content_type = response.headers.get("Content-Type", "")
+            scrape = response.read().decode("utf-8")
+        try:
+            opener.open("http://%s:%d/" % (HOST, metrics_port), timeout=IO_TIMEOUT)
+            fail("[metrics] a path other than /metrics must not be served")
+        except urllib.error.HTTPError as error:
+            if error.code != 404:
+                fail("[metrics] expected a 404 for /, got %d" % error.code)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+
+    if not content_type.startswith("text/plain; version=0.0.4"):
+        fail("[metrics] unexpected Content-Type %r" % content_type, output)
+    route = 'route="%s:%d"' % (HOST, proxy_port)
+    for series in [
+        'logged_tcp_proxy_bytes_relayed_total{%s,direction="client_to_destination"} 6' % route,
+        'logged_tcp_proxy_bytes_relayed_total{%s,direction="destination_to_client"} 6' % route,
+        "logged_tcp_proxy_connections_active{%s} 0" % route,
+        "logged_tcp_proxy_accept_errors_total{%s} 0" % route,
+    ]:
+        if series not in scrape.splitlines():
+            fail("[metrics] missing %r in the scrape:\n%s" % (series, scrape), output)
+    if "Serving metrics on http://%s:%d/metrics" % (HOST, metrics_port) not in output:
+        fail("[metrics] the metrics listener was not announced", output)
+    print("OK [metrics] the endpoint served the route's counters over loopback")
+
+
+def test_admin_api(binary):
+    """`--admin-addr` lists a live connection with its traffic, closes it on
+    `DELETE /connections/<id>`, and pauses and resumes accepting."""
+    echo_server, echo_port = start_echo_server()
+    admin_port = free_port()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="info",
+        extra_args=("--admin-addr", "%s:%d" % (HOST, admin_port)))
+    opener = urllib.request.build_opener(urllib.request.ProxyHandler({}))
+
+    def call(method, path):
+        request = urllib.request.Request(
+            "http://%s:%d%s" % (HOST, admin_port, path), method=method)
+        with opener.open(request, timeout=IO_TIMEOUT) as response:
+            return json.loads(response.read().decode("utf-8"))
+
+    try:
+        if not wait_for_listener(proxy_port) or not wait_for_listener(admin_port):
+            fail("[admin] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client_port = client.getsockname()[1]
+            client.sendall(b"admin")
+            if recv_exact(client, 5) != b"admin":
+                fail("[admin] echo mismatch")
+            time.sleep(0.3)
+            client_addr = "%s:%d" % (HOST, client_port)
+            listed = [entry for entry in call("GET", "/connections")["connections"]
+                      if entry["client"] == client_addr]
+            if len(listed) != 1 or listed[0]["client_bytes"] != 5 \
+                    or listed[0]["destination_bytes"] != 5:
+                fail("[admin] the connection was not listed with its traffic: %r" % listed)
+            if call("DELETE", "/connections/%d" % listed[0]["id"]) != {"closed": listed[0]["id"]}:
+                fail("[admin] DELETE did not confirm the close")
+            if client.recv(16) != b"":
+                fail("[admin] the closed connection still delivered data")
+        if call("POST", "/pause") != {"paused": True}:
+            fail("[admin] POST /pause did not pause")
+        if call("POST", "/resume") != {"paused": False}:
+            fail("[admin] POST /resume did not resume")
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(b"again")
+            if recv_exact(client, 5) != b"again":
+                fail("[admin] the proxy did not serve a client after resuming")
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+
+    closing = ("Forcibly closing connection from %s:%d on request of the admin API "
+               "after 5 bytes from the client and 5 bytes from the destination"
+               % (HOST, client_port))
+    if closing not in output:
+        fail("[admin] the close was not logged", output)
+    for line in ["Paused through the admin API", "Resumed through the admin API",
+                 "Serving the admin API on http://%s:%d/" % (HOST, admin_port)]:
+        if line not in output:
+            fail("[admin] missing %r" % line, output)
+    print("OK [admin] listed, closed a connection, paused and resumed over loopback")
+
+
+def test_tui(binary):
+    """`--tui` refuses to start without a terminal, and under a pseudo-terminal
+    shows a relayed connection in its table and shuts down cleanly on `q`."""
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(binary, echo_port, level="info", extra_args=("--tui",))
+    try:
+        output = proxy.communicate(timeout=IO_TIMEOUT)[0]
+    except subprocess.TimeoutExpired:
+        proxy.kill()
+        fail("[tui] the proxy kept running without a terminal", proxy.communicate()[0])
+    if proxy.returncode == 0 or \
+            "Failed to start the terminal UI: standard output is not a terminal" not in output:
+        fail("[tui] expected a non-zero exit without a terminal, got rc=%s"
+             % proxy.returncode, output)
+
+    if platform.system() == "Windows":
+        echo_server.close()
+        print("OK [tui] refused to start without a terminal (pseudo-terminal case skipped)")
+        return
+    import fcntl
+    import pty
+    import termios
+
+    master, slave = pty.openpty()
+    fcntl.ioctl(slave, termios.TIOCSWINSZ, struct.pack("HHHH", 30, 120, 0, 0))
+    proxy_port = free_port()
+    proxy = subprocess.Popen(
+        [
+            binary,
+            "--bind-listener-addr", "%s:%d" % (HOST, proxy_port),
+            "--remote-addr", "%s:%d" % (HOST, echo_port),
+            "--tui",
+        ],
+        cwd=ROOT, stdin=slave, stdout=slave, stderr=slave, close_fds=True,
+    )
+    os.close(slave)
+    screen = bytearray()
+
+    def drawn():
+        """The text drawn so far: the view moves the cursor over blank cells rather
+        than writing them, so every escape sequence reads as a space."""
+        text = re.sub(r"\x1b\[[0-9;?]*[A-Za-z]", " ", screen.decode("utf-8", "replace"))
+        return " ".join(text.split())
+
+    def read_until(text):
+        """Read the terminal until `text` was drawn, or the process exited."""
+        deadline = time.monotonic() + START_TIMEOUT
+        while text not in drawn() and time.monotonic() < deadline:
+            try:
+                chunk = os.read(master, 65536)
+            except OSError:
+                return False  # the process exited, closing the terminal
+            if not chunk:
+                return False
+            screen.extend(chunk)
+        return text in drawn()
+
+    def drain():
+        while True:
+            try:
+                if not os.read(master, 65536):
+                    return
+            except OSError:
+                return
+
+    try:
+        if not read_until("Listener bound to %s:%d" % (HOST, proxy_port)):
+            fail("[tui] the listener line was not drawn", drawn())
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client_port = client.getsockname()[1]
+            client.sendall(b"tui!")
+            if recv_exact(client, 4) != b"tui!":
+                fail("[tui] echo mismatch")
+            if not read_until("%s:%d" % (HOST, client_port)) or not read_until("74:75:69:21"):
+                fail("[tui] the connection and its payload were not drawn", drawn())
+        os.write(master, b"q")
+        drainer = threading.Thread(target=drain, daemon=True)
+        drainer.start()
+        try:
+            proxy.wait(timeout=10)
+        except subprocess.TimeoutExpired:
+            fail("[tui] the proxy did not exit on q")
+    finally:
+        if proxy.poll() is None:
+            proxy.kill()
+            proxy.wait()
+        os.close(master)
+        echo_server.close()
+    if proxy.returncode != 0:
+        fail("[tui] expected a clean exit (0) on q, got rc=%s" % proxy.returncode)
+    print("OK [tui] refused a pipe, drew a connection under a pseudo-terminal, quit on q")
+
+
 def test_level_filters_payload(binary):
     """`--level` controls whether the payload is printed at all.
 
@@ -618,6 +1457,299 @@ def test_bind_failure(binary):
     print("OK [bind-failure] exited non-zero (rc=%d) with a clean error" % completed.returncode)
 
 
+def test_routes(binary):
+    """Repeated `--route` options run several listeners in one process: each relays
+    to its own remote, its lines carry the route's name ahead of the `[#N]` tag, and
+    the ids are unique across the routes."""
+    first_server, first_port = start_asymmetric_server(b"from first")
+    second_server, second_port = start_asymmetric_server(b"from second")
+    first_listen, second_listen = free_port(), free_port()
+    proxy = subprocess.Popen(
+        [
+            binary,
+            "--route", "first=%s:%d=%s:%d" % (HOST, first_listen, HOST, first_port),
+            "--route", "second=%s:%d=%s:%d" % (HOST, second_listen, HOST, second_port),
+        ],
+        cwd=ROOT, stdout=subprocess.PIPE, stderr=subprocess.STDOUT, text=True,
+    )
+    try:
+        for port in (first_listen, second_listen):
+            if not wait_for_listener(port):
+                fail("[routes] a route did not start listening", stop_proxy(proxy))
+        for port, reply in [(first_listen, b"from first"), (second_listen, b"from second"),
+                            (first_listen, b"from first")]:
+            with socket.create_connection((HOST, port), timeout=IO_TIMEOUT) as client:
+                client.settimeout(IO_TIMEOUT)
+                client.sendall(b"hello")
+                if recv_exact(client, len(reply)) != reply:
+                    fail("[routes] port %d did not reach its own remote" % port)
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        first_server.close()
+        second_server.close()
+
+    for name, port in [("first", first_listen), ("second", second_listen)]:
+        if "[%s] Listener bound to %s:%d" % (name, HOST, port) not in output:
+            fail("[routes] route %s did not log its listener" % name, output)
+    accepted = re.findall(r"\[(first|second)\] \[#(\d+)\] Incoming connection", output)
+    ids = [conn_id for _, conn_id in accepted]
+    # Three exchanges plus one readiness probe per route.
+    if len(ids) != 5 or len(set(ids)) != len(ids):
+        fail("[routes] expected 5 accepted connections with distinct ids, got %r"
+             % accepted, output)
+    for name, reply in [("first", b"from first"), ("second", b"from second")]:
+        rendered = ":".join("%02x" % byte for byte in reply)
+        if not re.search(r"\[%s\] \[#\d+\] > %s$" % (name, rendered), output, re.MULTILINE):
+            fail("[routes] route %s's payload line is missing its prefix" % name, output)
+    print("OK [routes] two routes relayed to their own remotes, ids unique across both")
+
+
+def test_config(binary):
+    """`--config` reads the options from a TOML file: a `[[route]]` listens and
+    relays with its own formatting, an option given on the command line wins over
+    the file's, and a bad value fails the startup naming the file and line."""
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-")
+    path = os.path.join(directory, "proxy.toml")
+    echo_server, echo_port = start_echo_server()
+    listen_port = free_port()
+    with open(path, "w") as config:
+        config.write(
+            'level = "debug"\n'
+            'separator = "-"\n'
+            "\n"
+            "[[route]]\n"
+            'name = "cfg"\n'
+            'listen = "%s:%d"\n'
+            'remote = "%s:%d"\n'
+            'formatting = "decimal"\n' % (HOST, listen_port, HOST, echo_port)
+        )
+    proxy = subprocess.Popen(
+        [binary, "--config", path, "--separator", ","],
+        cwd=ROOT, stdout=subprocess.PIPE, stderr=subprocess.STDOUT, text=True,
+    )
+    try:
+        if not wait_for_listener(listen_port):
+            fail("[config] the configured route did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, listen_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(b"hello")
+            if recv_exact(client, 5) != b"hello":
+                fail("[config] echo mismatch through the configured route")
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+    if not re.search(r"^.*\[cfg\] \[#\d+\] < 104,101,108,108,111$", output, re.MULTILINE):
+        fail("[config] expected the route's decimal formatting with the CLI separator", output)
+
+    with open(path, "w") as config:
+        config.write('bind-listener-addr = "%s:0"\n\nthreads = 0\n' % HOST)
+    result = subprocess.run(
+        [binary, "--config", path], cwd=ROOT, capture_output=True, text=True,
+        timeout=IO_TIMEOUT,
+    )
+    shutil.rmtree(directory, ignore_errors=True)
+    if result.returncode == 0 or "%s:3: invalid threads `0`" % path not in result.stderr:
+        fail("[config] a bad value should fail the startup with its line", result.stderr)
+    print("OK [config] the file's route relayed with its own formatting, bad values name their line")
+
+
+def test_reload(binary):
+    """SIGHUP reads the `--config` file again: new connections go to the new remote
+    while one accepted before keeps its old remote, the change is logged, and an
+    invalid file is rejected without affecting the running configuration."""
+    if platform.system() == "Windows":
+        print("SKIP [reload] SIGHUP exists only on POSIX")
+        return
+
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-")
+    path = os.path.join(directory, "proxy.toml")
+    echo_server, echo_port = start_echo_server()
+    new_server, new_port = start_asymmetric_server(b"from new remote")
+    proxy_port = free_port()
+
+    def write_config(remote_port, extra=""):
+        with open(path, "w") as config:
+            config.write(
+                'level = "info"\nbind-listener-addr = "%s:%d"\nremote-addr = "%s:%d"\n%s'
+                % (HOST, proxy_port, HOST, remote_port, extra)
+            )
+
+    write_config(echo_port)
+    proxy = subprocess.Popen(
+        [binary, "--config", path],
+        cwd=ROOT, stdout=subprocess.PIPE, stderr=subprocess.STDOUT, text=True,
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[reload] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as old:
+            old.settimeout(IO_TIMEOUT)
+            old.sendall(b"before")
+            if recv_exact(old, 6) != b"before":
+                fail("[reload] echo mismatch before the reload")
+
+            write_config(new_port)
+            proxy.send_signal(signal.SIGHUP)
+            time.sleep(0.5)
+            with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as new:
+                new.settimeout(IO_TIMEOUT)
+                new.sendall(b"hello")
+                if recv_exact(new, 15) != b"from new remote":
+                    fail("[reload] a new connection did not reach the new remote")
+            old.sendall(b"still old")
+            if recv_exact(old, 9) != b"still old":
+                fail("[reload] the running connection lost its remote")
+
+            write_config(echo_port, "threads = 0\n")
+            proxy.send_signal(signal.SIGHUP)
+            time.sleep(0.5)
+            with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as new:
+                new.settimeout(IO_TIMEOUT)
+                new.sendall(b"hello")
+                if recv_exact(new, 15) != b"from new remote":
+                    fail("[reload] a rejected reload changed the running configuration")
+        time.sleep(0.3)
+    finally:
+        output = stop_proxy(proxy)
+        echo_server.close()
+        new_server.close()
+        shutil.rmtree(directory, ignore_errors=True)
+
+    expected = "Configuration reloaded, for new connections: remote-addr %s:%d -> %s:%d" % (
+        HOST, echo_port, HOST, new_port)
+    if expected not in output:
+        fail("[reload] the reload did not log what changed", output)
+    if "Configuration reload rejected" not in output or ":4: invalid threads `0`" not in output:
+        fail("[reload] the invalid reload was not rejected with its line", output)
+    print("OK [reload] SIGHUP moved new connections to the new remote, kept the old one")
+
+
+def test_log_file(binary):
+    """`--log-file` sends every line — listener-level and per-connection — to the
+    file instead of the console, and `--log-rotate-size` rotates it between records:
+    across the active file and the (gzip-compressed) segments every line is whole,
+    and together they hold the listener line and every relayed payload."""
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-")
+    path = os.path.join(directory, "proxy.log")
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port,
+        extra_args=("--log-file", path, "--log-rotate-size", "1K", "--log-keep", "100",
+                    "--log-compress"),
+    )
+    payloads = [("log file payload %02d" % index).encode() for index in range(20)]
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[log-file] proxy did not start listening")
+        for payload in payloads:
+            with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+                client.settimeout(IO_TIMEOUT)
+                client.sendall(payload)
+                if recv_exact(client, len(payload)) != payload:
+                    fail("[log-file] echo mismatch for %r" % payload)
+        time.sleep(0.5)  # let the proxy write and rotate its last records
+    finally:
+        console = stop_proxy(proxy)
+        echo_server.close()
+
+    try:
+        if console.strip():
+            fail("[log-file] lines still went to the console", console)
+        segments = sorted(glob.glob(path + ".*"), key=lambda name: int(name.split(".")[-2]))
+        if not segments:
+            fail("[log-file] the log file was never rotated")
+        content = ""
+        for segment in segments:
+            if not segment.endswith(".gz"):
+                fail("[log-file] rotated segment %s was not compressed" % segment)
+            with gzip.open(segment, "rt", encoding="utf-8") as compressed:
+                text = compressed.read()
+            if len(text.encode()) > 1024 or not text.endswith("\n"):
+                fail("[log-file] segment %s is oversized or ends mid-line" % segment, text)
+            content += text
+        with open(path, encoding="utf-8") as active:
+            content += active.read()
+    finally:
+        shutil.rmtree(directory, ignore_errors=True)
+
+    if "Listener bound to" not in content:
+        fail("[log-file] the listener line is missing from the log file", content)
+    for payload in payloads:
+        rendered = ":".join("%02x" % byte for byte in payload)
+        if not re.search(r"\[#\d+\] < " + re.escape(rendered) + "$", content, re.MULTILINE):
+            fail("[log-file] payload %r is missing or split" % payload, content)
+    print("OK [log-file] %d rotated segments, every line whole, nothing on the console"
+          % len(segments))
+
+
+def test_replay(binary):
+    """A connection recorded with `--pcap` replays without its peers: `replay server`
+    answers a client's recorded request with the recorded reply, and `replay client`
+    exits 0 against a server that still answers the same and 1 against one that no
+    longer does."""
+    directory = tempfile.mkdtemp(prefix="logged_tcp_proxy-")
+    capture = os.path.join(directory, "session.pcapng")
+    request, reply = b"recorded request", b"recorded reply"
+    server, server_port = start_asymmetric_server(reply)
+    proxy, proxy_port = start_proxy(binary, server_port, extra_args=("--pcap", capture))
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[replay] proxy did not start listening")
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client.sendall(request)
+            if recv_exact(client, len(reply)) != reply:
+                fail("[replay] the recorded exchange went wrong")
+        time.sleep(0.3)  # let the proxy write the closes
+    finally:
+        stop_proxy(proxy)
+        server.close()
+
+    # Only the connection that carried data is in the capture (the readiness probe
+    # carried none), so no `--connection` is needed.
+    try:
+        replay_port = free_port()
+        replayer = subprocess.Popen(
+            [binary, "replay", "server", "-b", "%s:%d" % (HOST, replay_port), capture],
+            cwd=ROOT, stdout=subprocess.PIPE, stderr=subprocess.STDOUT, text=True,
+        )
+        try:
+            if not wait_for_listener(replay_port):
+                fail("[replay] the replayed server did not start listening",
+                     stop_proxy(replayer))
+            with socket.create_connection((HOST, replay_port), timeout=IO_TIMEOUT) as client:
+                client.settimeout(IO_TIMEOUT)
+                client.sendall(request)
+                answer = recv_exact(client, len(reply))
+        finally:
+            output = stop_proxy(replayer)
+        if answer != reply:
+            fail("[replay] the replayed server answered %r" % answer, output)
+
+        for answer, expected_exit in [(reply, 0), (b"something else", 1)]:
+            server, server_port = start_asymmetric_server(answer)
+            try:
+                result = subprocess.run(
+                    [binary, "replay", "client", "-r", "%s:%d" % (HOST, server_port),
+                     "--speed", "0", "-t", "2", capture],
+                    cwd=ROOT, stdout=subprocess.PIPE, stderr=subprocess.STDOUT, text=True,
+                    timeout=30,
+                )
+            finally:
+                server.close()
+            if result.returncode != expected_exit:
+                fail("[replay] replaying against a server answering %r exited %d"
+                     % (answer, result.returncode), result.stdout)
+            verdict = "matches the recording" if expected_exit == 0 else "differs"
+            if verdict not in result.stdout:
+                fail("[replay] the client replay did not log %r" % verdict, result.stdout)
+    finally:
+        shutil.rmtree(directory, ignore_errors=True)
+    print("OK [replay] replayed server answers, replayed client detects a changed reply")
+
+
 def test_ctrl_c(binary):
     """Ctrl-C (SIGINT) triggers a clean shutdown with a zero exit code."""
     if platform.system() == "Windows":
@@ -659,6 +1791,57 @@ def test_ctrl_c(binary):
     print("OK [ctrl-c] proxy shut down cleanly on SIGINT (rc=0)")
 
 
+def test_shutdown_grace(binary):
+    """SIGTERM stops accepting but lets an open connection keep relaying for the
+    `--shutdown-grace` period, then closes it with a tagged line and its byte
+    counts; the proxy still exits cleanly."""
+    if platform.system() == "Windows":
+        print("SKIP [shutdown-grace] SIGTERM exists only on POSIX")
+        return
+
+    echo_server, echo_port = start_echo_server()
+    proxy, proxy_port = start_proxy(
+        binary, echo_port, level="info", extra_args=["--shutdown-grace", "1"])
+    output = ""
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[shutdown-grace] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.settimeout(IO_TIMEOUT)
+            client_port = client.getsockname()[1]
+            client.sendall(b"hello")
+            if recv_exact(client, 5) != b"hello":
+                fail("[shutdown-grace] echo mismatch before the shutdown")
+            # Let the readiness probe's connection finish, so only this one drains.
+            time.sleep(0.3)
+            proxy.send_signal(signal.SIGTERM)
+            time.sleep(0.2)
+            client.sendall(b"more")
+            if recv_exact(client, 4) != b"more":
+                fail("[shutdown-grace] the connection stopped relaying during the grace period")
+            try:
+                output = proxy.communicate(timeout=5)[0]
+            except subprocess.TimeoutExpired:
+                proxy.kill()
+                fail("[shutdown-grace] proxy did not exit within 5s of SIGTERM")
+            if client.recv(16) != b"":
+                fail("[shutdown-grace] the connection was not closed at the deadline")
+    finally:
+        echo_server.close()
+        if proxy.poll() is None:
+            proxy.kill()
+
+    if proxy.returncode != 0:
+        fail("[shutdown-grace] expected a clean exit (0), got rc=%s" % proxy.returncode, output)
+    if "Waiting up to 1s for 1 active connection(s) to finish..." not in output:
+        fail("[shutdown-grace] the drain did not log the active connections", output)
+    forced = (r"\[#\d+\] Forcibly closing connection from %s:%d at shutdown after "
+              r"9 bytes from the client and 9 bytes from the destination" % (HOST, client_port))
+    if not re.search(forced, output):
+        fail("[shutdown-grace] the forced close was not logged with its byte counts", output)
+    print("OK [shutdown-grace] SIGTERM drained for the grace period, then forced the close")
+
+
 def test_http(binary):
     """A real HTTP request/response (stdlib http.server + urllib) is relayed."""
     body = b"Hello through the proxy"
@@ -701,6 +1884,69 @@ def test_http(binary):
     print("OK [http] real HTTP request relayed through the proxy")
 
 
+def test_decode_http(binary):
+    """`--decode http --decode-body` logs two pipelined requests to an HTTP/1.1
+    `http.server` and their responses, one line each, a chunked one included."""
+
+    class Handler(http.server.BaseHTTPRequestHandler):
+        protocol_version = "HTTP/1.1"
+
+        def do_GET(self):  # noqa: N802 (name mandated by BaseHTTPRequestHandler)
+            self.send_response(200)
+            self.send_header("Transfer-Encoding", "chunked")
+            self.end_headers()
+            self.wfile.write(b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n")
+
+        def do_POST(self):  # noqa: N802
+            body = self.rfile.read(int(self.headers["Content-Length"]))
+            self.send_response(201)
+            self.send_header("Content-Length", str(len(body)))
+            self.end_headers()
+            self.wfile.write(body)
+
+        def log_message(self, *args):
+            pass  # keep test output quiet
+
+    httpd = http.server.HTTPServer((HOST, 0), Handler)
+    http_port = httpd.server_address[1]
+    threading.Thread(target=httpd.serve_forever, daemon=True).start()
+
+    proxy, proxy_port = start_proxy(
+        binary, http_port, level="info",
+        extra_args=["--decode", "http", "--decode-body", "--formatting", "text"],
+    )
+    try:
+        if not wait_for_listener(proxy_port):
+            fail("[decode-http] proxy did not start listening", stop_proxy(proxy))
+        with socket.create_connection((HOST, proxy_port), timeout=IO_TIMEOUT) as client:
+            client.sendall(
+                b"GET /greeting HTTP/1.1\r\nHost: lab\r\n\r\n"
+                b"POST /echo HTTP/1.1\r\nHost: lab\r\nContent-Length: 4\r\n"
+                b"Connection: close\r\n\r\nping")
+            received = b""
+            while True:
+                data = client.recv(4096)
+                if not data:
+                    break
+                received += data
+        if not received.endswith(b"\r\n\r\nping"):
+            fail("[decode-http] unexpected responses: %r" % received, stop_proxy(proxy))
+        time.sleep(0.3)
+    finally:
+        httpd.shutdown()
+        output = stop_proxy(proxy)
+    for expected in (
+        "< HTTP request: GET /greeting HTTP/1.1 [Host: lab], body 0 bytes",
+        "< HTTP request: POST /echo HTTP/1.1 [Host: lab, Content-Length: 4, Connection: close], body 4 bytes: ping",
+        "> HTTP response: HTTP/1.1 200 OK [",
+        "body 11 bytes (chunked): hello world",
+        "> HTTP response: HTTP/1.1 201 Created [",
+    ):
+        if expected not in output:
+            fail("[decode-http] missing %r in the log" % expected, output)
+    print("OK [decode-http] logged pipelined requests and their responses, one line each")
+
+
 def start_modbus_server(registers):
     """Start a minimal real Modbus TCP server serving `registers`. Returns (sock, port)."""
     server = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
@@ -944,18 +2190,45 @@ def main():
     run_case(binary, "decimal", ":", lambda b: "%d" % b)
     run_case(binary, "octal", ":", lambda b: "%03o" % b)
     run_case(binary, "binary", ":", lambda b: format(b, "08b"))
+    test_hexdump_rows(binary)
+    test_text_formatting(binary, "text", b"PING a\tb\r\n\x00\xff", "PING a\\tb\\r\\n\\x00\\xff")
+    test_text_formatting(
+        binary, "utf8-lossy", "caf\u00e9\r\n".encode() + b"\xff", "caf\u00e9\\r\\n\ufffd"
+    )
     test_direction_markers_and_no_double_logging(binary)
+    test_jsonl_output(binary)
     test_connection_id_tags(binary)
     test_no_connection_ids_flag(binary)
+    test_close_summary(binary)
+    test_latency(binary)
+    test_rate_limit(binary)
+    test_fault(binary)
+    test_fault_corruption(binary)
+    test_fragment(binary)
+    test_tls(binary)
+    test_remote_tls(binary)
+    test_tls_mitm(binary)
+    test_inspect_tls(binary)
+    test_sni_route(binary)
+    test_metrics(binary)
+    test_admin_api(binary)
+    test_tui(binary)
     test_level_filters_payload(binary)
     test_hostname_remote(binary)
     test_http(binary)
+    test_decode_http(binary)
     test_modbus(binary)
     test_unreachable_remote(binary)
     test_unresolvable_remote(binary)
     test_bind_failure(binary)
+    test_routes(binary)
+    test_config(binary)
+    test_reload(binary)
+    test_log_file(binary)
+    test_replay(binary)
     test_threads(binary)
     test_ctrl_c(binary)
+    test_shutdown_grace(binary)
     print("integration test passed")
 
 
@@ -0,0 +1,228 @@
+//! `--admin-addr <addr>`: a small HTTP API over the running proxy, to see what it is
+//! doing without scrolling the log, and to step in:
+//!
+//! | Request                    | Answer                                                     |
+//! | -------------------------- | ---------------------------------------------------------- |
+//! | `GET /connections`         | The live connections as JSON, and whether accepting is paused |
+//! | `DELETE /connections/<id>` | Closes connection `<id>` (its `[#N]` tag) now              |
+//! | `POST /pause`              | Stops accepting new connections                            |
+//! | `POST /resume`             | Accepts new connections again                              |
+//!
+//! The accept loops register every connection they accept in the [`Registry`] and
+//! remove it once it is closed; in between, the connection's handler records the
+//! destination it reached, and its relays count the traffic and the activity on
+//! the entry's own [`Traffic`] and [`ActivityClock`] — the ones the closing summary
+//! and the idle timeout read.
+
+use crate::conn::ActivityClock;
+use crate::conn::Direction;
+use crate::conn::Traffic;
+use crate::endpoint;
+use crate::endpoint::Request;
+use crate::endpoint::Response;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// The live connections of every route, by id, and whether the accept loops are
+/// paused. Clones share the state.
+#[derive(Clone)]
+pub(crate) struct Registry {
+    connections: Arc<Mutex<BTreeMap<u64, Arc<LiveConnection>>>>,
+    paused: watch::Sender<bool>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            connections: Arc::default(),
+            paused: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Registry {
+    /// Add connection `conn_id`, just accepted on `route` from `client`, to be
+    /// relayed to `destination` (as configured).
+    pub(crate) fn register(
+        &self,
+        conn_id: u64,
+        route: String,
+        client: SocketAddr,
+        destination: String,
+    ) -> Arc<LiveConnection> {
+        let connection = Arc::new(LiveConnection {
+            conn_id,
+            route,
+            client,
+            destination,
+            destination_addr: OnceLock::new(),
+            accepted: Instant::now(),
+            activity: ActivityClock::new(),
+            traffic: Traffic::default(),
+            close: watch::Sender::new(false),
+        });
+        self.connections
+            .lock()
+            .expect("registry mutex poisoned")
+            .insert(conn_id, connection.clone());
+        connection
+    }
+
+    /// Remove connection `conn_id` once it is closed.
+    pub(crate) fn unregister(&self, conn_id: u64) {
+        self.connections
+            .lock()
+            .expect("registry mutex poisoned")
+            .remove(&conn_id);
+    }
+
+    /// Follows whether the accept loops are paused.
+    pub(crate) fn paused(&self) -> watch::Receiver<bool> {
+        self.paused.subscribe()
+    }
+
+    /// Pause or resume the accept loops; `false` if they already were.
+    fn set_paused(&self, paused: bool) -> bool {
+        self.paused.send_if_modified(|current| {
+            let changed = *current != paused;
+            *current = paused;
+            changed
+        })
+    }
+
+    fn connection(&self, conn_id: u64) -> Option<Arc<LiveConnection>> {
+        self.connections
+            .lock()
+            .expect("registry mutex poisoned")
+            .get(&conn_id)
+            .cloned()
+    }
+
+    /// The `GET /connections` document.
+    fn list(&self) -> Value {
+        let connections: Vec<Value> = self
+            .connections
+            .lock()
+            .expect("registry mutex poisoned")
+            .values()
+            .map(|connection| connection.describe())
+            .collect();
+        json!({
+            "paused": *self.paused.borrow(),
+            "connections": connections,
+        })
+    }
+}
+
+/// One connection, from its accept until it is closed.
+pub(crate) struct LiveConnection {
+    conn_id: u64,
+    /// The route's name, or its listen address.
+    route: String,
+    client: SocketAddr,
+    /// The destination as configured.
+    destination: String,
+    /// The address actually reached, once the destination is connected.
+    destination_addr: OnceLock<SocketAddr>,
+    accepted: Instant,
+    /// The connection's activity, which its idle timeout also reads.
+    pub(crate) activity: ActivityClock,
+    /// The connection's traffic, which its closing summary also reports.
+    pub(crate) traffic: Traffic,
+    /// Set by `DELETE /connections/<id>`.
+    close: watch::Sender<bool>,
+}
+
+impl LiveConnection {
+    /// The destination answered, at `destination_addr`.
+    pub(crate) fn connected(&self, destination_addr: SocketAddr) {
+        let _ = self.destination_addr.set(destination_addr);
+    }
+
+    /// Resolves once the connection has been ordered closed through the API.
+    pub(crate) async fn closed(&self) {
+        // `self` holds the sender, so the channel never closes under the wait.
+        let _ = self.close.subscribe().wait_for(|close| *close).await;
+    }
+
+    /// The connection's entry in `GET /connections`.
+    fn describe(&self) -> Value {
+        json!({
+            "id": self.conn_id,
+            "route": self.route,
+            "client": self.client.to_string(),
+            "destination": self.destination,
+            "destination_addr": self.destination_addr.get().map(ToString::to_string),
+            "age_ms": self.accepted.elapsed().as_millis() as u64,
+            "idle_ms": self.activity.idle().as_millis() as u64,
+            "client_bytes": self.traffic.bytes(Direction::ClientToDestination),
+            "destination_bytes": self.traffic.bytes(Direction::DestinationToClient),
+        })
+    }
+}
+
+/// Serve the API on `listener` until the task is aborted.
+pub(crate) async fn serve(listener: TcpListener, registry: Registry) {
+    endpoint::serve(listener, move |request: &Request| {
+        handle(&registry, request)
+    })
+    .await
+}
+
+fn handle(registry: &Registry, request: &Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["connections"]) => Response::json("200 OK", &registry.list()),
+        ("DELETE", ["connections", conn_id]) => {
+            let Ok(conn_id) = conn_id.parse::<u64>() else {
+                return error(
+                    "400 Bad Request",
+                    format!("invalid connection id `{conn_id}`"),
+                );
+            };
+            let Some(connection) = registry.connection(conn_id) else {
+                return error("404 Not Found", format!("no live connection #{conn_id}"));
+            };
+            connection.close.send_replace(true);
+            Response::json("200 OK", &json!({ "closed": conn_id }))
+        }
+        ("POST", [action @ ("pause" | "resume")]) => {
+            let paused = *action == "pause";
+            if registry.set_paused(paused) {
+                if paused {
+                    log::info!(
+                        event = "paused";
+                        "Paused through the admin API: no new connections are accepted"
+                    );
+                } else {
+                    log::info!(
+                        event = "resumed";
+                        "Resumed through the admin API: accepting new connections again"
+                    );
+                }
+            }
+            Response::json("200 OK", &json!({ "paused": paused }))
+        }
+        (_, ["connections"] | ["connections", _] | ["pause"] | ["resume"]) => error(
+            "405 Method Not Allowed",
+            format!("{} is not allowed on {}", request.method, request.path),
+        ),
+        _ => error(
+            "404 Not Found",
+            "the API is GET /connections, DELETE /connections/<id>, POST /pause and POST /resume"
+                .to_string(),
+        ),
+    }
+}
+
+fn error(status: &'static str, message: String) -> Response {
+    Response::json(status, &json!({ "error": message }))
+}
@@ -1,15 +1,30 @@
+use crate::fault::Fault;
+use crate::formatters::HexdumpFormatter;
+use crate::formatters::PayloadFormatter;
+use crate::formatters::TextFormatter;
+use crate::netem::FragmentSize;
+use crate::netem::MAX_DELAY_MILLIS;
+use crate::sni::SniRoute;
+use clap::ArgGroup;
+use clap::Args;
+use clap::CommandFactory;
+use clap::FromArgMatches;
 use clap::Parser;
+use clap::Subcommand;
 use clap::ValueEnum;
+use clap::parser::ValueSource;
 use env_logger::TimestampPrecision as EnvLoggerTimestampPrecision;
 use log::LevelFilter;
 use logged_stream::BinaryFormatter;
-use logged_stream::BufferFormatter;
 use logged_stream::DecimalFormatter;
 use logged_stream::LowercaseHexadecimalFormatter;
 use logged_stream::OctalFormatter;
 use logged_stream::UppercaseHexadecimalFormatter;
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::fmt;
 use std::net;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 macro_rules! argument_impl_from_str {
@@ -81,22 +96,45 @@ pub enum PayloadFormattingKind {
     UpperHex,
     Binary,
     Octal,
+    /// `hexdump -C`-style rows (offset, hex bytes, ASCII gutter), one console line
+    /// per row; the row width comes from `--hexdump-width`.
+    Hexdump,
+    /// The payload as ASCII text on one line, escaping non-printable bytes (`\r`,
+    /// `\n`, `\xNN`, ...).
+    Text,
+    /// Like `text`, but decoded as UTF-8 so non-ASCII characters are shown; invalid
+    /// sequences become U+FFFD.
+    #[value(name = "utf8-lossy")]
+    Utf8Lossy,
 }
 
+/// Build the payload formatter for `kind`. `separator` applies to the single-line
+/// numeric notations; `hexdump_width` (bytes per row) applies only to `hexdump`.
+/// The `hexdump` and text layouts fix their own spacing, so they ignore `separator`.
 pub fn get_formatter_by_kind(
     kind: PayloadFormattingKind,
     separator: &str,
-) -> Box<dyn BufferFormatter> {
+    hexdump_width: usize,
+) -> PayloadFormatter {
     match kind {
-        PayloadFormattingKind::Decimal => Box::new(DecimalFormatter::new(Some(separator))),
+        PayloadFormattingKind::Decimal => {
+            PayloadFormatter::new(DecimalFormatter::new(Some(separator)))
+        }
         PayloadFormattingKind::LowerHex => {
-            Box::new(LowercaseHexadecimalFormatter::new(Some(separator)))
+            PayloadFormatter::new(LowercaseHexadecimalFormatter::new(Some(separator)))
         }
         PayloadFormattingKind::UpperHex => {
-            Box::new(UppercaseHexadecimalFormatter::new(Some(separator)))
+            PayloadFormatter::new(UppercaseHexadecimalFormatter::new(Some(separator)))
+        }
+        PayloadFormattingKind::Binary => {
+            PayloadFormatter::new(BinaryFormatter::new(Some(separator)))
         }
-        PayloadFormattingKind::Binary => Box::new(BinaryFormatter::new(Some(separator))),
-        PayloadFormattingKind::Octal => Box::new(OctalFormatter::new(Some(separator))),
+        PayloadFormattingKind::Octal => PayloadFormatter::new(OctalFormatter::new(Some(separator))),
+        PayloadFormattingKind::Hexdump => {
+            PayloadFormatter::new(HexdumpFormatter::new(hexdump_width))
+        }
+        PayloadFormattingKind::Text => PayloadFormatter::new(TextFormatter::ascii()),
+        PayloadFormattingKind::Utf8Lossy => PayloadFormatter::new(TextFormatter::utf8_lossy()),
     }
 }
 
@@ -125,6 +163,28 @@ impl From<TimestampPrecision> for EnvLoggerTimestampPrecision {
 argument_impl_from_str!(TimestampPrecision);
 argument_impl_display!(TimestampPrecision);
 
+/// How console lines are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-oriented lines (`env_logger`'s format).
+    Text,
+    /// One JSON object per line, with each event's fields (see `jsonl`).
+    Jsonl,
+}
+
+argument_impl_from_str!(OutputFormat);
+argument_impl_display!(OutputFormat);
+
+/// The protocols `--decode` knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DecodeProtocol {
+    /// HTTP/1.0 and HTTP/1.1 (see `decode`).
+    Http,
+}
+
+argument_impl_from_str!(DecodeProtocol);
+argument_impl_display!(DecodeProtocol);
+
 /// A remote destination supplied on the command line: either a literal socket
 /// address (`IP:port`, connected to directly) or a `host:port` whose host is
 /// resolved via DNS when a connection is opened. Only `--remote-addr` accepts a
@@ -250,16 +310,133 @@ impl From<net::SocketAddr> for TargetAddr {
 /// clap value parser for [`TargetAddr`]: validates the `IP:port` / `host:port`
 /// shape at parse time (without resolving DNS), so an obviously malformed value
 /// is rejected at startup rather than on the first connection.
-fn parse_remote_addr(s: &str) -> Result<TargetAddr, String> {
+pub(crate) fn parse_remote_addr(s: &str) -> Result<TargetAddr, String> {
     s.parse()
 }
 
+/// One listener and the destination its connections are relayed to: a `--route`,
+/// or the `--bind-listener-addr` / `--remote-addr` pair.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Shown as a `[name] ` prefix on the route's log lines (and as the `route`
+    /// field of its `--output-format jsonl` events). The `-b`/`-r` pair has none, so
+    /// a single-listener proxy logs exactly as before routes existed.
+    pub name: Option<String>,
+    pub listen_addr: net::SocketAddr,
+    /// `None` only for a `-b` without `-r`, whose connections go wherever their
+    /// `--sni-route` says.
+    pub remote_addr: Option<TargetAddr>,
+    /// What the route sets differently from the proxy-wide options (only a
+    /// `--config` route can).
+    pub settings: RouteSettings,
+}
+
+impl Route {
+    /// The destination as configured: the remote address, or `by SNI` for a
+    /// listener that relies on its `--sni-route`s alone.
+    pub(crate) fn destination(&self) -> String {
+        self.remote_addr
+            .as_ref()
+            .map_or_else(|| "by SNI".to_string(), ToString::to_string)
+    }
+}
+
+/// The options a `[[route]]` of a `--config` file may set for itself, each
+/// overriding the proxy-wide value for that route's connections.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteSettings {
+    pub timeout: Option<u64>,
+    pub max_connections: Option<u32>,
+    pub formatting: Option<PayloadFormattingKind>,
+    pub separator: Option<String>,
+    pub hexdump_width: Option<u32>,
+    pub connection_ids: Option<bool>,
+    pub faults: Option<Vec<Fault>>,
+}
+
+impl RouteSettings {
+    /// `arguments` with the route's own settings in place of the proxy-wide ones.
+    pub fn apply(&self, mut arguments: Arguments) -> Arguments {
+        if let Some(timeout) = self.timeout {
+            arguments.timeout = Some(timeout);
+        }
+        if let Some(max_connections) = self.max_connections {
+            arguments.max_connections = max_connections;
+        }
+        if let Some(formatting) = self.formatting {
+            arguments.formatting = formatting;
+        }
+        if let Some(separator) = &self.separator {
+            arguments.separator = separator.clone();
+        }
+        if let Some(hexdump_width) = self.hexdump_width {
+            arguments.hexdump_width = hexdump_width;
+        }
+        if let Some(connection_ids) = self.connection_ids {
+            arguments.connection_ids = connection_ids;
+        }
+        if let Some(faults) = &self.faults {
+            arguments.faults = faults.clone();
+        }
+        arguments
+    }
+}
+
+/// clap value parser for `--route`: `[NAME=]LISTEN=REMOTE`, where `LISTEN` is a
+/// literal `IP:port` and `REMOTE` anything `--remote-addr` accepts. A route given
+/// without a name is named after its listen address.
+fn parse_route(s: &str) -> Result<Route, String> {
+    let parts: Vec<&str> = s.split('=').collect();
+    let (name, listen, remote) = match parts[..] {
+        [listen, remote] => (None, listen, remote),
+        [name, listen, remote] => (Some(name), listen, remote),
+        _ => {
+            return Err(format!(
+                "invalid route `{s}`: expected `LISTEN=REMOTE` or `NAME=LISTEN=REMOTE`"
+            ));
+        }
+    };
+    let listen_addr: net::SocketAddr = listen.parse().map_err(|_| {
+        format!("invalid route `{s}`: `{listen}` is not a literal `IP:port` listen address")
+    })?;
+    let remote_addr =
+        parse_remote_addr(remote).map_err(|error| format!("invalid route `{s}`: {error}"))?;
+    let name = match name {
+        None => listen_addr.to_string(),
+        Some(name) => {
+            check_route_name(name).map_err(|error| format!("invalid route `{s}`: {error}"))?;
+            name.to_string()
+        }
+    };
+    Ok(Route {
+        name: Some(name),
+        listen_addr,
+        remote_addr: Some(remote_addr),
+        settings: RouteSettings::default(),
+    })
+}
+
+/// A route's name ends up between brackets in the log prefix: keep it one plain
+/// word.
+pub(crate) fn check_route_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name
+            .chars()
+            .any(|character| character.is_whitespace() || "[]#".contains(character))
+    {
+        return Err(format!(
+            "the name `{name}` must be non-empty, without spaces, `[`, `]` or `#`"
+        ));
+    }
+    Ok(())
+}
+
 /// Maximum accepted `--timeout`, in seconds (~100 years). Generous enough to cover
 /// any realistic idle timeout, yet small enough that the connection-start instant
 /// plus the timeout can never overflow the monotonic clock on any platform — which
 /// would otherwise panic the connection task. ("No timeout" is the default anyway,
 /// reached by omitting the flag, so there is no need for larger finite values.)
-const MAX_TIMEOUT_SECONDS: u64 = 60 * 60 * 24 * 365 * 100;
+pub(crate) const MAX_TIMEOUT_SECONDS: u64 = 60 * 60 * 24 * 365 * 100;
 
 /// Upper bound for `--threads`. The async runtime is almost always I/O bound, so a
 /// handful of threads already saturate a typical proxy workload; this cap leaves
@@ -270,7 +447,61 @@ const MAX_TIMEOUT_SECONDS: u64 = 60 * 60 * 24 * 365 * 100;
 /// Typed `i64` to match the bound `clap::value_parser!(u32)` expects for its range
 /// (it validates `u32` arguments against an `i64` range, just as `--max-connections`
 /// relies on its `1..` literal being inferred as `i64`).
-const MAX_THREADS: i64 = 1024;
+pub(crate) const MAX_THREADS: i64 = 1024;
+
+/// Upper bound for `--hexdump-width`. A row is printed as a single console line, so
+/// anything wider than this stops being readable as a table; like [`MAX_THREADS`] it
+/// is typed `i64` for the range `clap::value_parser!(u32)` validates against.
+pub(crate) const MAX_HEXDUMP_WIDTH: i64 = 256;
+
+/// Default for `--shutdown-grace`, in seconds.
+const DEFAULT_SHUTDOWN_GRACE: &str = "10";
+
+/// Default for the replay `--timeout`, in seconds.
+const DEFAULT_REPLAY_TIMEOUT: &str = "5";
+
+/// clap value parser for the replay `--speed`: a finite, non-negative factor.
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed >= 0.0 => Ok(speed),
+        _ => Err(format!(
+            "invalid speed `{s}`: expected a non-negative factor such as 1, 2 or 0.5"
+        )),
+    }
+}
+
+/// Default for `--log-keep`.
+const DEFAULT_LOG_KEEP: &str = "5";
+
+/// clap value parser for `--log-rotate-size`: a byte count, optionally with a
+/// binary-multiple suffix — `K`, `M` or `G` (`KiB`, `MiB` and `GiB` are accepted
+/// too) — so `10M` reads as 10 MiB.
+pub(crate) fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim();
+    let digits_end = trimmed
+        .find(|character: char| !character.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (digits, suffix) = trimmed.split_at(digits_end);
+    let multiplier: u64 = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KIB" => 1 << 10,
+        "M" | "MIB" => 1 << 20,
+        "G" | "GIB" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "invalid size `{s}`: expected a byte count with an optional K, M or G suffix"
+            ));
+        }
+    };
+    let count: u64 = digits.parse().map_err(|_| {
+        format!("invalid size `{s}`: expected a byte count with an optional K, M or G suffix")
+    })?;
+    match count.checked_mul(multiplier) {
+        Some(0) => Err(format!("invalid size `{s}`: must be greater than zero")),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("invalid size `{s}`: too large")),
+    }
+}
 
 /// Custom help template to include the source code URL and author name.
 const HELP_TEMPLATE: &str = "\
@@ -286,8 +517,11 @@ Source: https://github.com/qwerty541/logged-tcp-proxy
 {all-args}{after-help}
 ";
 
-#[derive(Debug, Clone, Parser)]
-#[command(next_line_help = true)]
+/// The command line: the proxy's own options, for which the `proxy` subcommand is
+/// optional (so every command line from before the subcommands existed still
+/// works), or another mode's subcommand.
+#[derive(Debug, Parser)]
+#[command(next_line_help = true, args_conflicts_with_subcommands = true)]
 #[command(
     author = clap::crate_authors!("\n"),
     version,
@@ -295,22 +529,188 @@ Source: https://github.com/qwerty541/logged-tcp-proxy
     long_about = None,
     help_template = HELP_TEMPLATE
 )]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    proxy: Option<Arguments>,
+}
+
+impl Cli {
+    /// The mode to run, with its arguments.
+    pub fn into_command(self) -> Command {
+        match (self.command, self.proxy) {
+            (Some(command), _) => command,
+            (None, Some(arguments)) => Command::Proxy(arguments),
+            // clap requires the proxy's `--bind-listener-addr` and `--remote-addr`
+            // (or a `--route`, or a `--config`) whenever no subcommand is given.
+            (None, None) => unreachable!("clap rejects a command line with neither"),
+        }
+    }
+
+    /// Parse the process's command line like [`Parser::parse`], also noting which
+    /// of the proxy's options it gave explicitly.
+    pub fn parse_explicit() -> (Command, ExplicitArgs) {
+        Self::try_parse_explicit_from(std::env::args_os()).unwrap_or_else(|error| error.exit())
+    }
+
+    /// [`Cli::parse_explicit`] on the given command line.
+    pub fn try_parse_explicit_from<I, T>(argv: I) -> Result<(Command, ExplicitArgs), clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut command = Self::command();
+        let matches = command.try_get_matches_from_mut(argv)?;
+        let cli = Self::from_arg_matches(&matches).map_err(|error| error.format(&mut command))?;
+        // The proxy's options sit at the top level, or under `proxy`.
+        let proxy = matches.subcommand_matches("proxy").unwrap_or(&matches);
+        let explicit = proxy
+            .ids()
+            .filter(|id| proxy.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.to_string())
+            .collect();
+        Ok((cli.into_command(), ExplicitArgs(explicit)))
+    }
+}
+
+/// The proxy options given on the command line itself, by id (the `Arguments`
+/// field name), as opposed to left at their defaults: a `--config` file does not
+/// override them.
+#[derive(Debug, Clone, Default)]
+pub struct ExplicitArgs(HashSet<String>);
+
+impl ExplicitArgs {
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.contains(id)
+    }
+}
+
+// Parsed once at startup, so the size difference between the variants costs
+// nothing worth a `Box`.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run the logging proxy (the default when no subcommand is given).
+    Proxy(Arguments),
+    /// Replay a connection recorded with `--pcap`, as its client or as its server.
+    Replay(ReplayArguments),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReplayArguments {
+    #[command(subcommand)]
+    pub mode: ReplayMode,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ReplayMode {
+    /// Act as the client: send the recorded client chunks to the remote server and
+    /// compare its responses with the recorded ones. Exits non-zero on any
+    /// difference.
+    Client {
+        /// Address of the server to replay against, as `IP:port` or
+        /// `hostname:port`.
+        #[arg(short, long, value_parser = parse_remote_addr)]
+        remote_addr: TargetAddr,
+        #[command(flatten)]
+        options: ReplayOptions,
+    },
+    /// Act as the server: answer each client's recorded requests with the recorded
+    /// responses, until interrupted.
+    Server {
+        /// Address on which the replaying server should listen.
+        #[arg(short, long)]
+        bind_listener_addr: net::SocketAddr,
+        #[command(flatten)]
+        options: ReplayOptions,
+    },
+}
+
+/// Options shared by both replay modes.
+#[derive(Debug, Clone, Args)]
+pub struct ReplayOptions {
+    /// The capture to replay, as written by `--pcap`.
+    #[arg(value_name = "CAPTURE")]
+    pub capture: PathBuf,
+    /// Which connection of the capture to replay, by its `[#N]` id. May be omitted
+    /// when the capture holds a single connection.
+    #[arg(short, long)]
+    pub connection: Option<u64>,
+    /// Replay speed factor applied to the recorded gaps between chunks: `2` replays
+    /// twice as fast, `0.5` at half speed, `0` without any delay.
+    #[arg(long, default_value = "1", value_parser = parse_speed)]
+    pub speed: f64,
+    /// How long to wait for the recorded bytes of the other side, in seconds.
+    #[arg(short, long, default_value = DEFAULT_REPLAY_TIMEOUT, value_parser = clap::value_parser!(u64).range(1..=MAX_TIMEOUT_SECONDS))]
+    pub timeout: u64,
+    /// Application logging level.
+    #[arg(short, long, default_value = "debug")]
+    pub level: LoggingLevel,
+    /// Formatting of console payload output.
+    #[arg(short, long, default_value = "lowerhex")]
+    pub formatting: PayloadFormattingKind,
+    /// Console payload output bytes separator.
+    #[arg(short, long, default_value = ":")]
+    pub separator: String,
+    /// Number of bytes per row with `--formatting hexdump`.
+    #[arg(long, default_value = "16", value_parser = clap::value_parser!(u32).range(1..=MAX_HEXDUMP_WIDTH))]
+    pub hexdump_width: u32,
+    /// Timestamp precision.
+    #[arg(short, long, default_value = "seconds")]
+    pub precision: TimestampPrecision,
+}
+
+/// The proxy's arguments: the `proxy` subcommand, or the top-level options when no
+/// subcommand is given.
+#[derive(Debug, Clone, Parser)]
+#[command(group(
+    ArgGroup::new("tls_to_destination")
+        .args(["remote_tls", "tls_mitm_ca"])
+        .multiple(true)
+))]
 pub struct Arguments {
     /// Application logging level.
     #[arg(short, long, default_value = "debug")]
     pub level: LoggingLevel,
     /// Address on which the TCP listener should be bound.
-    #[arg(short, long)]
-    pub bind_listener_addr: net::SocketAddr,
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["routes", "config"]
+    )]
+    pub bind_listener_addr: Option<net::SocketAddr>,
     /// Address of remote server, as `IP:port` or `hostname:port` (a hostname is
-    /// resolved via DNS when each connection is opened).
-    #[arg(short, long, value_parser = parse_remote_addr)]
-    pub remote_addr: TargetAddr,
+    /// resolved via DNS when each connection is opened). With `--sni-route`s, the
+    /// destination of the clients no route is for, and optional.
+    #[arg(
+        short,
+        long,
+        value_parser = parse_remote_addr,
+        required_unless_present_any = ["routes", "config", "sni_routes"],
+        requires = "bind_listener_addr"
+    )]
+    pub remote_addr: Option<TargetAddr>,
+    /// An additional listener, relayed to its own remote: `LISTEN=REMOTE`, or
+    /// `NAME=LISTEN=REMOTE` to name it in the log prefix (the listen address names it
+    /// otherwise). Repeat for several routes; each has its own `--max-connections`.
+    #[arg(long = "route", value_name = "[NAME=]LISTEN=REMOTE", value_parser = parse_route)]
+    pub routes: Vec<Route>,
+    /// Read the options from this TOML file (see the README for its keys and
+    /// `[[route]]` tables). Options given on the command line take precedence over
+    /// the file's. The file is read again on SIGHUP, for new connections.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
     /// Idle timeout for the connection, in seconds: the connection is closed once
     /// both directions have been silent for this long. If omitted, the proxy waits
     /// indefinitely (until a peer closes the connection or Ctrl-C).
     #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..=MAX_TIMEOUT_SECONDS))]
     pub timeout: Option<u64>,
+    /// On Ctrl-C (SIGINT) or SIGTERM, how long to wait for the active connections to
+    /// finish, in seconds, before closing them; a second signal closes them at once.
+    /// `0` closes them without waiting.
+    #[arg(long, value_name = "SECONDS", default_value = DEFAULT_SHUTDOWN_GRACE, value_parser = clap::value_parser!(u64).range(0..=MAX_TIMEOUT_SECONDS))]
+    pub shutdown_grace: u64,
     /// Maximum number of connections processed concurrently. Once this many are
     /// active, further incoming connections wait until a slot frees.
     #[arg(short, long, default_value = "512", value_parser = clap::value_parser!(u32).range(1..))]
@@ -326,6 +726,9 @@ pub struct Arguments {
     /// Console payload output bytes separator.
     #[arg(short, long, default_value = ":")]
     pub separator: String,
+    /// Number of bytes per row with `--formatting hexdump`.
+    #[arg(long, default_value = "16", value_parser = clap::value_parser!(u32).range(1..=MAX_HEXDUMP_WIDTH))]
+    pub hexdump_width: u32,
     /// Timestamp precision.
     #[arg(short, long, default_value = "seconds")]
     pub precision: TimestampPrecision,
@@ -336,4 +739,208 @@ pub struct Arguments {
     // presence sets it to `false`.
     #[arg(long = "no-connection-ids", action = clap::ArgAction::SetFalse)]
     pub connection_ids: bool,
+    /// Delay each chunk relayed from the client to the destination by this many
+    /// milliseconds, to simulate a slow network.
+    #[arg(long, value_name = "MS", default_value = "0", value_parser = clap::value_parser!(u64).range(0..=MAX_DELAY_MILLIS))]
+    pub latency_to_destination: u64,
+    /// Add a random delay of up to this many milliseconds to each chunk relayed from
+    /// the client to the destination, on top of `--latency-to-destination`. The
+    /// chunks keep their order.
+    #[arg(long, value_name = "MS", default_value = "0", value_parser = clap::value_parser!(u64).range(0..=MAX_DELAY_MILLIS))]
+    pub jitter_to_destination: u64,
+    /// Delay each chunk relayed from the destination to the client by this many
+    /// milliseconds.
+    #[arg(long, value_name = "MS", default_value = "0", value_parser = clap::value_parser!(u64).range(0..=MAX_DELAY_MILLIS))]
+    pub latency_to_client: u64,
+    /// Add a random delay of up to this many milliseconds to each chunk relayed from
+    /// the destination to the client, on top of `--latency-to-client`.
+    #[arg(long, value_name = "MS", default_value = "0", value_parser = clap::value_parser!(u64).range(0..=MAX_DELAY_MILLIS))]
+    pub jitter_to_client: u64,
+    /// Limit each connection's client to destination traffic to this many bytes a
+    /// second, with a `K`, `M` or `G` suffix allowed (e.g. `64K`).
+    #[arg(long, value_name = "BYTES_PER_SEC", value_parser = parse_byte_size)]
+    pub rate_limit_up: Option<u64>,
+    /// Limit each connection's destination to client traffic to this many bytes a
+    /// second.
+    #[arg(long, value_name = "BYTES_PER_SEC", value_parser = parse_byte_size)]
+    pub rate_limit_down: Option<u64>,
+    /// Limit the traffic of all connections together, both directions and every
+    /// route, to this many bytes a second.
+    #[arg(long, value_name = "BYTES_PER_SEC", value_parser = parse_byte_size)]
+    pub rate_limit_global: Option<u64>,
+    /// Split every chunk relayed into pieces of this many bytes, or of a random size
+    /// in a `MIN-MAX` range picked for each piece, written one by one, so that the
+    /// peers' parsers meet messages split across segments.
+    #[arg(long, value_name = "BYTES|MIN-MAX")]
+    pub fragment: Option<FragmentSize>,
+    /// Wait this many milliseconds between the pieces of a `--fragment`ed chunk.
+    #[arg(long, value_name = "MS", default_value = "0", requires = "fragment", value_parser = clap::value_parser!(u64).range(0..=MAX_DELAY_MILLIS))]
+    pub fragment_delay: u64,
+    /// Gather everything read within this many milliseconds of a read into one
+    /// write, so that the peers meet several messages in one segment.
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..=MAX_DELAY_MILLIS))]
+    pub coalesce: Option<u64>,
+    /// Inject a fault into the relayed traffic, `[DIRECTION:]ACTION[=VALUE][@TRIGGER]`:
+    /// `reset`, `truncate`, `stall=2s`, `flip=0.01` or `replace=0.01`, in the `up`
+    /// or `down` direction or `both` (the default), from the start or once a byte
+    /// count (`@4K`) or a time (`@500ms`) is reached. Repeatable.
+    #[arg(long = "fault", value_name = "FAULT")]
+    pub faults: Vec<Fault>,
+    /// Seed the random choices of `--fault`, `--fragment` and the jitter, so that a
+    /// run can be repeated exactly; by default a random seed is picked and logged.
+    #[arg(long, value_name = "N")]
+    pub seed: Option<u64>,
+    /// Terminate TLS on the listeners with this PEM certificate chain, the
+    /// proxy's own certificate first: the clients' payload is logged decrypted and
+    /// relayed to the destination in plaintext. Needs `--tls-key`.
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// The PEM private key of `--tls-cert`.
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Terminate TLS on the listeners with a certificate minted for the server name
+    /// each client asks for (or the address it dialed), signed by this PEM CA
+    /// certificate, which the clients must trust. The destination is dialed over
+    /// TLS of the proxy's own, as with `--remote-tls`, offered the same name and
+    /// ALPN protocols. Needs `--tls-mitm-key`.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_mitm_key",
+        conflicts_with = "tls_cert"
+    )]
+    pub tls_mitm_ca: Option<PathBuf>,
+    /// The PEM (PKCS#8) private key of `--tls-mitm-ca`.
+    #[arg(long, value_name = "FILE", requires = "tls_mitm_ca")]
+    pub tls_mitm_key: Option<PathBuf>,
+    /// Speak TLS to the destination: the proxy is the TLS client, so a plaintext
+    /// client reaches a TLS-only server and its payload is still logged in the
+    /// clear. The server's certificate is checked against the system's trusted
+    /// roots, or `--remote-ca`. Implied by `--tls-mitm-ca`.
+    #[arg(long)]
+    pub remote_tls: bool,
+    /// Trust the certificates of this PEM bundle, instead of the system's, for the
+    /// destination's certificate.
+    #[arg(long, value_name = "FILE", requires = "tls_to_destination")]
+    pub remote_ca: Option<PathBuf>,
+    /// The server name sent to the destination and checked against its
+    /// certificate, instead of the target's host (or its IP address).
+    #[arg(long, value_name = "NAME", requires = "tls_to_destination")]
+    pub remote_sni: Option<String>,
+    /// Authenticate to the destination with this PEM certificate chain. Needs
+    /// `--remote-client-key`.
+    #[arg(long, value_name = "FILE", requires_all = ["tls_to_destination", "remote_client_key"])]
+    pub remote_client_cert: Option<PathBuf>,
+    /// The PEM private key of `--remote-client-cert`.
+    #[arg(long, value_name = "FILE", requires_all = ["tls_to_destination", "remote_client_cert"])]
+    pub remote_client_key: Option<PathBuf>,
+    /// Accept any certificate from the destination, e.g. a lab device's
+    /// self-signed one. The connection is then open to impersonation.
+    #[arg(long, requires = "tls_to_destination", conflicts_with = "remote_ca")]
+    pub remote_insecure: bool,
+    /// Log what the TLS handshakes relayed in the clear show, without decrypting
+    /// anything: the client's server name, ALPN protocols, versions and cipher
+    /// suites, the server's picks, and alerts.
+    #[arg(long)]
+    pub inspect_tls: bool,
+    /// Pick each connection's destination by the server name its client's TLS
+    /// hello asks for, `NAME=REMOTE`, with `NAME` a host name or a `*.domain`
+    /// wildcard (the longest matching one wins over the others, an exact name
+    /// over all). The hello is relayed untouched. A client no route is for goes to
+    /// the listener's remote address or, for a `-b` without `-r`, is turned away.
+    /// Repeatable.
+    #[arg(long = "sni-route", value_name = "NAME=REMOTE")]
+    pub sni_routes: Vec<SniRoute>,
+    /// Parse the traffic as this protocol and log one line per message: for
+    /// `http`, each request's method, target and headers, each response's status
+    /// and headers, and the size of their bodies. Pipelined and chunked messages are
+    /// followed; decoding stops once the peers switch to another protocol.
+    #[arg(long, value_name = "PROTOCOL")]
+    pub decode: Option<DecodeProtocol>,
+    /// Also show the start of each `--decode`d message's body, formatted per
+    /// `--formatting`.
+    #[arg(long, requires = "decode")]
+    pub decode_body: bool,
+    /// Also write the relayed traffic to this pcapng file (created or truncated at
+    /// startup), one synthesized TCP stream per connection, for Wireshark.
+    #[arg(long, value_name = "FILE")]
+    pub pcap: Option<PathBuf>,
+    /// Also write each connection to a file of its own in this directory (created
+    /// if missing): both directions with timestamps, then the byte totals and why
+    /// the connection closed.
+    #[arg(long, value_name = "DIR")]
+    pub capture_dir: Option<PathBuf>,
+    /// Serve Prometheus metrics over HTTP on this address, at `/metrics`:
+    /// connection counts, bytes relayed, connect failures and waits per route.
+    #[arg(long, value_name = "SOCKET_ADDR")]
+    pub metrics_addr: Option<net::SocketAddr>,
+    /// Serve the admin HTTP API on this address: list the live connections, close
+    /// one by its id, pause and resume accepting new connections.
+    #[arg(long, value_name = "SOCKET_ADDR")]
+    pub admin_addr: Option<net::SocketAddr>,
+    /// Show an interactive terminal view instead of the console log: the live and
+    /// recently closed connections, the payload of the selected one and the proxy's
+    /// own lines. `q` shuts the proxy down. Needs a terminal; a `--log-file` is
+    /// still written.
+    #[arg(long)]
+    pub tui: bool,
+    /// Console output format: human-oriented `text` lines, or `jsonl` — one JSON
+    /// object per event, carrying the connection id, addresses and timestamps.
+    #[arg(long, default_value = "text")]
+    pub output_format: OutputFormat,
+    /// Write the log to this file (appended to) instead of stderr.
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+    /// Rotate the log file before a record would grow it past this size, in bytes
+    /// or with a `K`, `M` or `G` suffix (e.g. `10M`).
+    #[arg(long, value_name = "SIZE", requires = "log_file", value_parser = parse_byte_size)]
+    pub log_rotate_size: Option<u64>,
+    /// Rotate the log file once it has been written to for this many seconds.
+    #[arg(long, value_name = "SECONDS", requires = "log_file", value_parser = clap::value_parser!(u64).range(1..=MAX_TIMEOUT_SECONDS))]
+    pub log_rotate_interval: Option<u64>,
+    /// Number of rotated log files kept; older ones are deleted.
+    #[arg(long, value_name = "COUNT", default_value = DEFAULT_LOG_KEEP, value_parser = clap::value_parser!(u32).range(1..))]
+    pub log_keep: u32,
+    /// Gzip-compress rotated log files.
+    #[arg(long, requires = "log_file")]
+    pub log_compress: bool,
+}
+
+impl Arguments {
+    /// Whether the proxy speaks TLS to the destinations: asked for with
+    /// `--remote-tls`, or implied by `--tls-mitm-ca`, whose clients expect the TLS
+    /// they spoke to reach the destination.
+    pub fn originates_tls(&self) -> bool {
+        self.remote_tls || self.tls_mitm_ca.is_some()
+    }
+
+    /// Every listener to run: the `-b`/`-r` pair, if given, then each `--route`.
+    /// Route names must be unique, or their log lines could not be told apart.
+    pub fn routes(&self) -> Result<Vec<Route>, String> {
+        let mut routes = Vec::with_capacity(self.routes.len() + 1);
+        if let Some(listen_addr) = self.bind_listener_addr {
+            if self.remote_addr.is_none() && self.sni_routes.is_empty() {
+                return Err(
+                    "`--bind-listener-addr` needs a `--remote-addr`, or `--sni-route`s to pick the destination by"
+                        .to_string(),
+                );
+            }
+            routes.push(Route {
+                name: None,
+                listen_addr,
+                remote_addr: self.remote_addr.clone(),
+                settings: RouteSettings::default(),
+            });
+        }
+        for route in &self.routes {
+            if routes.iter().any(|other| other.name == route.name) {
+                return Err(format!(
+                    "route name `{}` is used more than once",
+                    route.name.as_deref().unwrap_or_default()
+                ));
+            }
+            routes.push(route.clone());
+        }
+        Ok(routes)
+    }
 }
@@ -0,0 +1,244 @@
+//! `--capture-dir <dir>`: every connection written to a file of its own, so one
+//! session can be read start to finish without untangling it from the interleaved
+//! console output of the others.
+//!
+//! A capture file is plain text. A `#` header names the connection, a line per
+//! event follows — the relayed chunks with the console's `<`/`>` direction markers
+//! and payload formatting, the injected `--fault`s marked `!`, each stamped with
+//! the wall-clock time and the offset from the connection's start — and a `#`
+//! trailer closes it with the byte and chunk totals of both directions and the
+//! reason the connection ended:
+//!
+//! ```text
+//! # connection #3
+//! # client: 127.0.0.1:50412
+//! # destination: localhost:8080
+//! # started: 2024-05-01T12:00:00Z
+//! 2024-05-01T12:00:00Z +0.000412s * connected to 127.0.0.1:8080
+//! 2024-05-01T12:00:00Z +0.001093s < 68:65:6c:6c:6f
+//! 2024-05-01T12:00:00Z +0.001377s > 68:65:6c:6c:6f
+//! # ended: 2024-05-01T12:00:01Z
+//! # duration: 0.912207s
+//! # client to destination: 5 bytes in 1 chunks
+//! # destination to client: 5 bytes in 1 chunks
+//! # close reason: the client closed the connection
+//! ```
+
+use crate::args::TimestampPrecision;
+use crate::conn::Direction;
+use crate::conn::RelayEnd;
+use crate::formatters::PayloadFormatter;
+use jiff::Timestamp;
+use logged_stream::BufferFormatter;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+use std::io::{self};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+/// The `--capture-dir` directory, shared by every connection.
+#[derive(Debug, Clone)]
+pub(crate) struct CaptureDir {
+    path: PathBuf,
+    precision: TimestampPrecision,
+}
+
+impl CaptureDir {
+    /// Use `path` (created if missing) for the capture files, stamping their lines
+    /// at `precision`.
+    pub(crate) fn create(path: &Path, precision: TimestampPrecision) -> io::Result<Self> {
+        fs::create_dir_all(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            precision,
+        })
+    }
+
+    /// Start the capture file of connection `conn_id`, accepted from `client` just
+    /// now, to the configured `destination`. The file is named after all three —
+    /// `conn-3_127.0.0.1-50412_20240501T120000.123Z.log` — so the files of one run
+    /// sort by id and those of different runs (whose ids restart at 1) stay apart.
+    pub(crate) fn connection(
+        &self,
+        conn_id: u64,
+        client: SocketAddr,
+        destination: &dyn fmt::Display,
+        formatter: PayloadFormatter,
+    ) -> io::Result<ConnCapture> {
+        let started_at = Timestamp::now();
+        let name = format!(
+            "conn-{conn_id}_{}-{}_{}.log",
+            // `:` and the IPv6 brackets are not valid in Windows file names.
+            client.ip().to_string().replace(':', "_"),
+            client.port(),
+            started_at.strftime("%Y%m%dT%H%M%S%.3fZ"),
+        );
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.path.join(name))?;
+        let capture = ConnCapture {
+            file: Mutex::new(Some(BufWriter::new(file))),
+            formatter,
+            precision: self.precision,
+            started: Instant::now(),
+            totals: Default::default(),
+            close_reason: OnceLock::new(),
+        };
+        capture.write(&format!(
+            "# connection #{conn_id}\n# client: {client}\n# destination: {destination}\n# started: {}\n",
+            capture.timestamp(started_at)
+        ));
+        Ok(capture)
+    }
+}
+
+/// Byte and chunk totals of one direction.
+#[derive(Default)]
+struct DirectionTotals {
+    bytes: AtomicU64,
+    chunks: AtomicU64,
+}
+
+/// One connection's capture file. Shared by both relay directions by reference,
+/// so it is written under a mutex; like `--pcap`, every line is flushed as it is
+/// written and the first write error abandons the file (logged once) while the
+/// connection keeps relaying. The trailer is written when the capture is dropped,
+/// so it is there however the connection ended — including at proxy shutdown.
+pub(crate) struct ConnCapture {
+    file: Mutex<Option<BufWriter<File>>>,
+    formatter: PayloadFormatter,
+    precision: TimestampPrecision,
+    started: Instant,
+    totals: [DirectionTotals; 2],
+    /// Why the connection ended: the first reason reported wins.
+    close_reason: OnceLock<String>,
+}
+
+impl ConnCapture {
+    /// The destination answered, at `destination_addr`.
+    pub(crate) fn connected(&self, destination_addr: SocketAddr) {
+        self.event('*', format_args!("connected to {destination_addr}"));
+    }
+
+    /// A chunk was relayed in `direction`.
+    pub(crate) fn data(&self, direction: Direction, payload: &[u8]) {
+        let totals = &self.totals[direction_index(direction)];
+        totals
+            .bytes
+            .fetch_add(payload.len() as u64, Ordering::Relaxed);
+        totals.chunks.fetch_add(1, Ordering::Relaxed);
+        let marker = direction.marker();
+        // A multi-line rendering (`--formatting hexdump`) gets the stamp and the
+        // marker on every row, as on the console.
+        for row in self.formatter.format_buffer(payload).split('\n') {
+            self.event(marker, format_args!("{row}"));
+        }
+    }
+
+    /// The relay in `direction` stopped, for reason `end`. The first relay to stop
+    /// is what ended the connection; the other one only follows it.
+    pub(crate) fn ended(&self, direction: Direction, end: RelayEnd) {
+        let (reader, writer) = match direction {
+            Direction::ClientToDestination => ("client", "destination"),
+            Direction::DestinationToClient => ("destination", "client"),
+        };
+        self.close(match end {
+            RelayEnd::Closed => format!("the {reader} closed the connection"),
+            RelayEnd::ReadFailed => format!("reading from the {reader} failed"),
+            RelayEnd::WriteFailed => format!("writing to the {writer} failed"),
+            RelayEnd::Truncated => format!("a fault truncated the stream from the {reader}"),
+        });
+    }
+
+    /// A `--fault` tampered with the connection, as `message` tells.
+    pub(crate) fn fault(&self, message: fmt::Arguments<'_>) {
+        self.event('!', message);
+    }
+
+    /// Record why the connection ended, unless a reason was already recorded.
+    pub(crate) fn close(&self, reason: String) {
+        let _ = self.close_reason.set(reason);
+    }
+
+    /// Append one event line: `{time} +{offset}s {marker} {message}`.
+    fn event(&self, marker: char, message: fmt::Arguments<'_>) {
+        self.write(&format!(
+            "{} +{:.6}s {marker} {message}\n",
+            self.timestamp(Timestamp::now()),
+            self.started.elapsed().as_secs_f64()
+        ));
+    }
+
+    /// `timestamp` in RFC 3339, at `--precision`.
+    fn timestamp(&self, timestamp: Timestamp) -> String {
+        let digits = match self.precision {
+            TimestampPrecision::Seconds => 0,
+            TimestampPrecision::Milliseconds => 3,
+            TimestampPrecision::Microseconds => 6,
+            TimestampPrecision::Nanoseconds => 9,
+        };
+        format!("{timestamp:.digits$}")
+    }
+
+    fn write(&self, text: &str) {
+        let mut file = self.file.lock().expect("capture file mutex poisoned");
+        let Some(writer) = file.as_mut() else {
+            return;
+        };
+        if let Err(error) = writer
+            .write_all(text.as_bytes())
+            .and_then(|()| writer.flush())
+        {
+            log::error!(
+                event = "capture_failed";
+                "Failed to write to a capture file, capture of the connection stopped: {error}"
+            );
+            *file = None;
+        }
+    }
+}
+
+impl Drop for ConnCapture {
+    fn drop(&mut self) {
+        let mut trailer = format!(
+            "# ended: {}\n# duration: {:.6}s\n",
+            self.timestamp(Timestamp::now()),
+            self.started.elapsed().as_secs_f64()
+        );
+        for (direction, name) in [
+            (Direction::ClientToDestination, "client to destination"),
+            (Direction::DestinationToClient, "destination to client"),
+        ] {
+            let totals = &self.totals[direction_index(direction)];
+            trailer.push_str(&format!(
+                "# {name}: {} bytes in {} chunks\n",
+                totals.bytes.load(Ordering::Relaxed),
+                totals.chunks.load(Ordering::Relaxed)
+            ));
+        }
+        let reason = self
+            .close_reason
+            .get()
+            .map_or("the proxy shut down", String::as_str);
+        trailer.push_str(&format!("# close reason: {reason}\n"));
+        self.write(&trailer);
+    }
+}
+
+fn direction_index(direction: Direction) -> usize {
+    match direction {
+        Direction::ClientToDestination => 0,
+        Direction::DestinationToClient => 1,
+    }
+}
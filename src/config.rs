@@ -0,0 +1,929 @@
+//! `--config`: the proxy's options read from a TOML file, so a long command line
+//! can live in a file instead of a shell script.
+//!
+//! The top-level keys are the long option names (`max-connections = 64`,
+//! `connection-ids = false`, ...), and each `[[route]]` table is a listener of its
+//! own (`name`, `listen`, `remote`) that may set `timeout`, `max-connections`,
+//! `formatting`, `separator`, `hexdump-width`, `connection-ids` and `fault` for
+//! itself. An option given on the command line beats the file everywhere, routes
+//! included.
+//!
+//! Values are checked by the same rules as on the command line — the same address
+//! parsers, ranges and value names — and a bad one is reported as
+//! `FILE:LINE: message`.
+//!
+//! On SIGHUP the proxy reads the file again ([`reload`]): the new remote
+//! addresses and connection settings apply to new connections, while the options
+//! that shape the process itself (threads, log sinks, ...) and the set of
+//! listeners keep their startup values until a restart.
+
+use crate::args::Arguments;
+use crate::args::DecodeProtocol;
+use crate::args::ExplicitArgs;
+use crate::args::LoggingLevel;
+use crate::args::MAX_HEXDUMP_WIDTH;
+use crate::args::MAX_THREADS;
+use crate::args::MAX_TIMEOUT_SECONDS;
+use crate::args::OutputFormat;
+use crate::args::PayloadFormattingKind;
+use crate::args::Route;
+use crate::args::RouteSettings;
+use crate::args::TargetAddr;
+use crate::args::TimestampPrecision;
+use crate::args::check_route_name;
+use crate::args::parse_byte_size;
+use crate::args::parse_remote_addr;
+use crate::fault;
+use crate::fault::Fault;
+use crate::netem::FragmentSize;
+use crate::netem::MAX_DELAY_MILLIS;
+use crate::sni;
+use crate::sni::SniRoute;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::net;
+use std::ops::Range;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use toml::Spanned;
+
+/// The command line the proxy was started with, kept so the configuration can be
+/// read again while it runs.
+#[derive(Debug, Clone)]
+pub struct CommandLine {
+    pub arguments: Arguments,
+    pub explicit: ExplicitArgs,
+}
+
+impl CommandLine {
+    /// The configuration as of now: the command line over the `--config` file.
+    pub fn load(&self) -> Result<Arguments, String> {
+        apply(&self.arguments, &self.explicit)
+    }
+}
+
+/// The command-line `arguments` with the `--config` file's values filled in
+/// wherever the command line did not give an option explicitly. Without
+/// `--config`, the arguments as they are. The error is ready to print: it names
+/// the file, and the line for a bad value.
+pub fn apply(arguments: &Arguments, explicit: &ExplicitArgs) -> Result<Arguments, String> {
+    let Some(path) = &arguments.config else {
+        return Ok(arguments.clone());
+    };
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| format!("{}: failed to read: {error}", path.display()))?;
+    let source = Source { path, text: &text };
+    let file: File = toml::from_str(&text)
+        .map_err(|error| source.error(error.span().unwrap_or_default(), error.message()))?;
+    let values = source.validate(file)?;
+    let mut merged = arguments.clone();
+    macro_rules! merge {
+        ($($field:ident),* $(,)?) => {
+            $(
+                if let Some(value) = values.$field {
+                    if !explicit.contains(stringify!($field)) {
+                        merged.$field = value;
+                    }
+                }
+            )*
+        };
+    }
+    merge!(
+        level,
+        bind_listener_addr,
+        remote_addr,
+        timeout,
+        shutdown_grace,
+        max_connections,
+        threads,
+        formatting,
+        separator,
+        hexdump_width,
+        precision,
+        connection_ids,
+        latency_to_destination,
+        jitter_to_destination,
+        latency_to_client,
+        jitter_to_client,
+        rate_limit_up,
+        rate_limit_down,
+        rate_limit_global,
+        fragment,
+        fragment_delay,
+        coalesce,
+        faults,
+        seed,
+        tls_cert,
+        tls_key,
+        tls_mitm_ca,
+        tls_mitm_key,
+        remote_tls,
+        remote_ca,
+        remote_sni,
+        remote_client_cert,
+        remote_client_key,
+        remote_insecure,
+        inspect_tls,
+        sni_routes,
+        decode,
+        decode_body,
+        pcap,
+        capture_dir,
+        metrics_addr,
+        admin_addr,
+        output_format,
+        log_file,
+        log_rotate_size,
+        log_rotate_interval,
+        log_keep,
+        log_compress,
+    );
+
+    // The file's routes come first; a command-line option set for the whole proxy
+    // also wins over a route's own setting.
+    let mut routes = values.routes;
+    for route in &mut routes {
+        let settings = &mut route.settings;
+        if explicit.contains("timeout") {
+            settings.timeout = None;
+        }
+        if explicit.contains("max_connections") {
+            settings.max_connections = None;
+        }
+        if explicit.contains("formatting") {
+            settings.formatting = None;
+        }
+        if explicit.contains("separator") {
+            settings.separator = None;
+        }
+        if explicit.contains("hexdump_width") {
+            settings.hexdump_width = None;
+        }
+        if explicit.contains("connection_ids") {
+            settings.connection_ids = None;
+        }
+        if explicit.contains("faults") {
+            settings.faults = None;
+        }
+    }
+    routes.append(&mut merged.routes);
+    merged.routes = routes;
+
+    let path = path.display();
+    if merged.remote_addr.is_some() && merged.bind_listener_addr.is_none() {
+        return Err(format!(
+            "{path}: `bind-listener-addr` and `remote-addr` must be given together"
+        ));
+    }
+    if merged.bind_listener_addr.is_some()
+        && merged.remote_addr.is_none()
+        && merged.sni_routes.is_empty()
+    {
+        return Err(format!(
+            "{path}: `bind-listener-addr` needs a `remote-addr`, or `sni-route`s to pick the destination by"
+        ));
+    }
+    if merged.bind_listener_addr.is_none() && merged.routes.is_empty() {
+        return Err(format!(
+            "{path}: nothing to listen on: set `bind-listener-addr` and `remote-addr`, or add a `[[route]]`"
+        ));
+    }
+    if merged.log_file.is_none()
+        && (merged.log_rotate_size.is_some()
+            || merged.log_rotate_interval.is_some()
+            || merged.log_compress)
+    {
+        return Err(format!(
+            "{path}: `log-rotate-size`, `log-rotate-interval` and `log-compress` need a `log-file`"
+        ));
+    }
+    if merged.fragment.is_none() && merged.fragment_delay > 0 {
+        return Err(format!("{path}: `fragment-delay` needs a `fragment`"));
+    }
+    if merged.decode.is_none() && merged.decode_body {
+        return Err(format!("{path}: `decode-body` needs a `decode`"));
+    }
+    if merged.tls_cert.is_some() != merged.tls_key.is_some() {
+        return Err(format!(
+            "{path}: `tls-cert` and `tls-key` must be given together"
+        ));
+    }
+    if merged.tls_mitm_ca.is_some() != merged.tls_mitm_key.is_some() {
+        return Err(format!(
+            "{path}: `tls-mitm-ca` and `tls-mitm-key` must be given together"
+        ));
+    }
+    if merged.tls_mitm_ca.is_some() && merged.tls_cert.is_some() {
+        return Err(format!(
+            "{path}: `tls-mitm-ca` and `tls-cert` cannot be used together"
+        ));
+    }
+    if merged.remote_client_cert.is_some() != merged.remote_client_key.is_some() {
+        return Err(format!(
+            "{path}: `remote-client-cert` and `remote-client-key` must be given together"
+        ));
+    }
+    if !merged.originates_tls()
+        && (merged.remote_ca.is_some()
+            || merged.remote_sni.is_some()
+            || merged.remote_client_cert.is_some()
+            || merged.remote_insecure)
+    {
+        return Err(format!(
+            "{path}: `remote-ca`, `remote-sni`, `remote-client-cert` and `remote-insecure` need `remote-tls` or `tls-mitm-ca`"
+        ));
+    }
+    if merged.remote_insecure && merged.remote_ca.is_some() {
+        return Err(format!(
+            "{path}: `remote-insecure` and `remote-ca` cannot be used together"
+        ));
+    }
+    Ok(merged)
+}
+
+/// A configuration read again while the proxy runs, ready to hand to the
+/// listeners.
+pub(crate) struct Reload {
+    /// The new configuration, with the restart-only options kept at their running
+    /// values.
+    pub(crate) arguments: Arguments,
+    /// The new routes, in the running listeners' order.
+    pub(crate) routes: Vec<Route>,
+    /// What changed for new connections, one entry per route and option:
+    /// `[db] timeout none -> 30s`.
+    pub(crate) changes: Vec<String>,
+    /// The restart-only options the file changed, by option name.
+    pub(crate) ignored: Vec<&'static str>,
+}
+
+/// Read the configuration again and compare it with the `running` one. An invalid
+/// file, or one that adds, removes or moves a listener, is an error: the running
+/// configuration stays as it is.
+pub(crate) fn reload(command_line: &CommandLine, running: &Arguments) -> Result<Reload, String> {
+    let mut arguments = command_line.load()?;
+    let routes = arguments.routes()?;
+    let running_routes = running.routes()?;
+    let listeners = |routes: &[Route]| -> Vec<(Option<String>, net::SocketAddr)> {
+        routes
+            .iter()
+            .map(|route| (route.name.clone(), route.listen_addr))
+            .collect()
+    };
+    if listeners(&routes) != listeners(&running_routes) {
+        return Err(
+            "the listeners differ from the running ones: adding, removing or moving a listener needs a restart"
+                .to_string(),
+        );
+    }
+
+    let mut ignored = Vec::new();
+    macro_rules! keep_running {
+        ($($field:ident => $name:literal),* $(,)?) => {
+            $(
+                if arguments.$field != running.$field {
+                    ignored.push($name);
+                    arguments.$field = running.$field.clone();
+                }
+            )*
+        };
+    }
+    keep_running!(
+        level => "level",
+        threads => "threads",
+        precision => "precision",
+        output_format => "output-format",
+        pcap => "pcap",
+        capture_dir => "capture-dir",
+        metrics_addr => "metrics-addr",
+        admin_addr => "admin-addr",
+        rate_limit_global => "rate-limit-global",
+        seed => "seed",
+        tls_cert => "tls-cert",
+        tls_key => "tls-key",
+        tls_mitm_ca => "tls-mitm-ca",
+        tls_mitm_key => "tls-mitm-key",
+        remote_tls => "remote-tls",
+        remote_ca => "remote-ca",
+        remote_sni => "remote-sni",
+        remote_client_cert => "remote-client-cert",
+        remote_client_key => "remote-client-key",
+        remote_insecure => "remote-insecure",
+        log_file => "log-file",
+        log_rotate_size => "log-rotate-size",
+        log_rotate_interval => "log-rotate-interval",
+        log_keep => "log-keep",
+        log_compress => "log-compress",
+    );
+
+    let mut changes = Vec::new();
+    if arguments.shutdown_grace != running.shutdown_grace {
+        changes.push(format!(
+            "shutdown-grace {}s -> {}s",
+            running.shutdown_grace, arguments.shutdown_grace
+        ));
+    }
+    for (option, old, new) in [
+        (
+            "latency-to-destination",
+            running.latency_to_destination,
+            arguments.latency_to_destination,
+        ),
+        (
+            "jitter-to-destination",
+            running.jitter_to_destination,
+            arguments.jitter_to_destination,
+        ),
+        (
+            "latency-to-client",
+            running.latency_to_client,
+            arguments.latency_to_client,
+        ),
+        (
+            "jitter-to-client",
+            running.jitter_to_client,
+            arguments.jitter_to_client,
+        ),
+        (
+            "fragment-delay",
+            running.fragment_delay,
+            arguments.fragment_delay,
+        ),
+    ] {
+        if old != new {
+            changes.push(format!("{option} {old}ms -> {new}ms"));
+        }
+    }
+    let rate = |rate: Option<u64>| rate.map_or("none".to_string(), |r| format!("{r}B/s"));
+    for (option, old, new) in [
+        (
+            "rate-limit-up",
+            running.rate_limit_up,
+            arguments.rate_limit_up,
+        ),
+        (
+            "rate-limit-down",
+            running.rate_limit_down,
+            arguments.rate_limit_down,
+        ),
+    ] {
+        if old != new {
+            changes.push(format!("{option} {} -> {}", rate(old), rate(new)));
+        }
+    }
+    if arguments.fragment != running.fragment {
+        let size = |size: Option<FragmentSize>| size.map_or("none".to_string(), |s| s.to_string());
+        changes.push(format!(
+            "fragment {} -> {}",
+            size(running.fragment),
+            size(arguments.fragment)
+        ));
+    }
+    if arguments.coalesce != running.coalesce {
+        let window = |window: Option<u64>| window.map_or("none".to_string(), |w| format!("{w}ms"));
+        changes.push(format!(
+            "coalesce {} -> {}",
+            window(running.coalesce),
+            window(arguments.coalesce)
+        ));
+    }
+    let (old_sni_routes, new_sni_routes) = (
+        sni::describe(&running.sni_routes),
+        sni::describe(&arguments.sni_routes),
+    );
+    if old_sni_routes != new_sni_routes {
+        changes.push(format!("sni-route {old_sni_routes} -> {new_sni_routes}"));
+    }
+    if arguments.inspect_tls != running.inspect_tls {
+        changes.push(format!(
+            "inspect-tls {} -> {}",
+            running.inspect_tls, arguments.inspect_tls
+        ));
+    }
+    if arguments.decode != running.decode {
+        let protocol = |decode: Option<DecodeProtocol>| {
+            decode.map_or("none".to_string(), |protocol| protocol.to_string())
+        };
+        changes.push(format!(
+            "decode {} -> {}",
+            protocol(running.decode),
+            protocol(arguments.decode)
+        ));
+    }
+    if arguments.decode_body != running.decode_body {
+        changes.push(format!(
+            "decode-body {} -> {}",
+            running.decode_body, arguments.decode_body
+        ));
+    }
+    for (old_route, new_route) in running_routes.iter().zip(&routes) {
+        let old = old_route.settings.apply(running.clone());
+        let new = new_route.settings.apply(arguments.clone());
+        let prefix = match &new_route.name {
+            Some(name) => format!("[{name}] "),
+            None => String::new(),
+        };
+        let mut change = |option: &str, old: String, new: String| {
+            if old != new {
+                changes.push(format!("{prefix}{option} {old} -> {new}"));
+            }
+        };
+        change(
+            "remote-addr",
+            old_route.destination(),
+            new_route.destination(),
+        );
+        let seconds =
+            |timeout: Option<u64>| timeout.map_or("none".to_string(), |t| format!("{t}s"));
+        change("timeout", seconds(old.timeout), seconds(new.timeout));
+        change(
+            "max-connections",
+            old.max_connections.to_string(),
+            new.max_connections.to_string(),
+        );
+        change(
+            "formatting",
+            old.formatting.to_string(),
+            new.formatting.to_string(),
+        );
+        change(
+            "separator",
+            format!("{:?}", old.separator),
+            format!("{:?}", new.separator),
+        );
+        change(
+            "hexdump-width",
+            old.hexdump_width.to_string(),
+            new.hexdump_width.to_string(),
+        );
+        change(
+            "connection-ids",
+            old.connection_ids.to_string(),
+            new.connection_ids.to_string(),
+        );
+        change(
+            "fault",
+            fault::describe(&old.faults),
+            fault::describe(&new.faults),
+        );
+    }
+    Ok(Reload {
+        arguments,
+        routes,
+        changes,
+        ignored,
+    })
+}
+
+/// The file as written: strings and integers, checked by [`Source::validate`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct File {
+    level: Option<Spanned<String>>,
+    bind_listener_addr: Option<Spanned<String>>,
+    remote_addr: Option<Spanned<String>>,
+    timeout: Option<Spanned<i64>>,
+    shutdown_grace: Option<Spanned<i64>>,
+    max_connections: Option<Spanned<i64>>,
+    threads: Option<Spanned<i64>>,
+    formatting: Option<Spanned<String>>,
+    separator: Option<String>,
+    hexdump_width: Option<Spanned<i64>>,
+    precision: Option<Spanned<String>>,
+    connection_ids: Option<bool>,
+    latency_to_destination: Option<Spanned<i64>>,
+    jitter_to_destination: Option<Spanned<i64>>,
+    latency_to_client: Option<Spanned<i64>>,
+    jitter_to_client: Option<Spanned<i64>>,
+    /// Bytes a second, as a number or a string with a suffix like `log-rotate-size`.
+    rate_limit_up: Option<Spanned<toml::Value>>,
+    rate_limit_down: Option<Spanned<toml::Value>>,
+    rate_limit_global: Option<Spanned<toml::Value>>,
+    /// A byte count, or a `MIN-MAX` string.
+    fragment: Option<Spanned<toml::Value>>,
+    fragment_delay: Option<Spanned<i64>>,
+    coalesce: Option<Spanned<i64>>,
+    /// Faults as written on the command line, one string each.
+    fault: Option<Vec<Spanned<String>>>,
+    seed: Option<Spanned<i64>>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_mitm_ca: Option<PathBuf>,
+    tls_mitm_key: Option<PathBuf>,
+    remote_tls: Option<bool>,
+    remote_ca: Option<PathBuf>,
+    remote_sni: Option<String>,
+    remote_client_cert: Option<PathBuf>,
+    remote_client_key: Option<PathBuf>,
+    remote_insecure: Option<bool>,
+    inspect_tls: Option<bool>,
+    /// SNI routes as written on the command line, one string each.
+    sni_route: Option<Vec<Spanned<String>>>,
+    decode: Option<Spanned<String>>,
+    decode_body: Option<bool>,
+    pcap: Option<PathBuf>,
+    capture_dir: Option<PathBuf>,
+    metrics_addr: Option<Spanned<String>>,
+    admin_addr: Option<Spanned<String>>,
+    output_format: Option<Spanned<String>>,
+    log_file: Option<PathBuf>,
+    /// A byte count, or a string with a `K`, `M` or `G` suffix like on the
+    /// command line.
+    log_rotate_size: Option<Spanned<toml::Value>>,
+    log_rotate_interval: Option<Spanned<i64>>,
+    log_keep: Option<Spanned<i64>>,
+    log_compress: Option<bool>,
+    #[serde(default)]
+    route: Vec<RouteTable>,
+}
+
+/// One `[[route]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RouteTable {
+    name: Option<Spanned<String>>,
+    listen: Spanned<String>,
+    remote: Spanned<String>,
+    timeout: Option<Spanned<i64>>,
+    max_connections: Option<Spanned<i64>>,
+    formatting: Option<Spanned<String>>,
+    separator: Option<String>,
+    hexdump_width: Option<Spanned<i64>>,
+    connection_ids: Option<bool>,
+    fault: Option<Vec<Spanned<String>>>,
+}
+
+/// The file's values, checked and typed like the matching `Arguments` fields
+/// (`None`: the file does not set it).
+struct Values {
+    level: Option<LoggingLevel>,
+    bind_listener_addr: Option<Option<net::SocketAddr>>,
+    remote_addr: Option<Option<TargetAddr>>,
+    timeout: Option<Option<u64>>,
+    shutdown_grace: Option<u64>,
+    max_connections: Option<u32>,
+    threads: Option<u32>,
+    formatting: Option<PayloadFormattingKind>,
+    separator: Option<String>,
+    hexdump_width: Option<u32>,
+    precision: Option<TimestampPrecision>,
+    connection_ids: Option<bool>,
+    latency_to_destination: Option<u64>,
+    jitter_to_destination: Option<u64>,
+    latency_to_client: Option<u64>,
+    jitter_to_client: Option<u64>,
+    rate_limit_up: Option<Option<u64>>,
+    rate_limit_down: Option<Option<u64>>,
+    rate_limit_global: Option<Option<u64>>,
+    fragment: Option<Option<FragmentSize>>,
+    fragment_delay: Option<u64>,
+    coalesce: Option<Option<u64>>,
+    faults: Option<Vec<Fault>>,
+    seed: Option<Option<u64>>,
+    tls_cert: Option<Option<PathBuf>>,
+    tls_key: Option<Option<PathBuf>>,
+    tls_mitm_ca: Option<Option<PathBuf>>,
+    tls_mitm_key: Option<Option<PathBuf>>,
+    remote_tls: Option<bool>,
+    remote_ca: Option<Option<PathBuf>>,
+    remote_sni: Option<Option<String>>,
+    remote_client_cert: Option<Option<PathBuf>>,
+    remote_client_key: Option<Option<PathBuf>>,
+    remote_insecure: Option<bool>,
+    inspect_tls: Option<bool>,
+    sni_routes: Option<Vec<SniRoute>>,
+    decode: Option<Option<DecodeProtocol>>,
+    decode_body: Option<bool>,
+    pcap: Option<Option<PathBuf>>,
+    capture_dir: Option<Option<PathBuf>>,
+    metrics_addr: Option<Option<net::SocketAddr>>,
+    admin_addr: Option<Option<net::SocketAddr>>,
+    output_format: Option<OutputFormat>,
+    log_file: Option<Option<PathBuf>>,
+    log_rotate_size: Option<Option<u64>>,
+    log_rotate_interval: Option<Option<u64>>,
+    log_keep: Option<u32>,
+    log_compress: Option<bool>,
+    routes: Vec<Route>,
+}
+
+/// The file being read, to point errors at a line of it.
+struct Source<'a> {
+    path: &'a Path,
+    text: &'a str,
+}
+
+impl Source<'_> {
+    fn error(&self, span: Range<usize>, message: impl std::fmt::Display) -> String {
+        let line = self.text[..span.start.min(self.text.len())]
+            .matches('\n')
+            .count()
+            + 1;
+        format!("{}:{line}: {message}", self.path.display())
+    }
+
+    fn validate(&self, file: File) -> Result<Values, String> {
+        let timeout_range = 1..=MAX_TIMEOUT_SECONDS;
+        let mut values = Values {
+            level: self.value_enum(file.level.as_ref(), "level")?,
+            bind_listener_addr: file
+                .bind_listener_addr
+                .as_ref()
+                .map(|listen| self.listen_addr(listen))
+                .transpose()?
+                .map(Some),
+            remote_addr: file
+                .remote_addr
+                .as_ref()
+                .map(|remote| self.remote_addr(remote))
+                .transpose()?
+                .map(Some),
+            timeout: self
+                .ranged(file.timeout.as_ref(), "timeout", timeout_range.clone())?
+                .map(Some),
+            shutdown_grace: self.ranged(
+                file.shutdown_grace.as_ref(),
+                "shutdown-grace",
+                0..=MAX_TIMEOUT_SECONDS,
+            )?,
+            max_connections: self.count(
+                file.max_connections.as_ref(),
+                "max-connections",
+                1..=u32::MAX.into(),
+            )?,
+            threads: self.count(file.threads.as_ref(), "threads", 1..=MAX_THREADS as u64)?,
+            formatting: self.value_enum(file.formatting.as_ref(), "formatting")?,
+            separator: file.separator,
+            hexdump_width: self.count(
+                file.hexdump_width.as_ref(),
+                "hexdump-width",
+                1..=MAX_HEXDUMP_WIDTH as u64,
+            )?,
+            precision: self.value_enum(file.precision.as_ref(), "precision")?,
+            connection_ids: file.connection_ids,
+            latency_to_destination: self.ranged(
+                file.latency_to_destination.as_ref(),
+                "latency-to-destination",
+                0..=MAX_DELAY_MILLIS,
+            )?,
+            jitter_to_destination: self.ranged(
+                file.jitter_to_destination.as_ref(),
+                "jitter-to-destination",
+                0..=MAX_DELAY_MILLIS,
+            )?,
+            latency_to_client: self.ranged(
+                file.latency_to_client.as_ref(),
+                "latency-to-client",
+                0..=MAX_DELAY_MILLIS,
+            )?,
+            jitter_to_client: self.ranged(
+                file.jitter_to_client.as_ref(),
+                "jitter-to-client",
+                0..=MAX_DELAY_MILLIS,
+            )?,
+            rate_limit_up: file
+                .rate_limit_up
+                .as_ref()
+                .map(|rate| self.byte_size(rate))
+                .transpose()?
+                .map(Some),
+            rate_limit_down: file
+                .rate_limit_down
+                .as_ref()
+                .map(|rate| self.byte_size(rate))
+                .transpose()?
+                .map(Some),
+            rate_limit_global: file
+                .rate_limit_global
+                .as_ref()
+                .map(|rate| self.byte_size(rate))
+                .transpose()?
+                .map(Some),
+            fragment: file
+                .fragment
+                .as_ref()
+                .map(|size| {
+                    let text = match size.get_ref() {
+                        toml::Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    text.parse().map_err(|error| self.error(size.span(), error))
+                })
+                .transpose()?
+                .map(Some),
+            fragment_delay: self.ranged(
+                file.fragment_delay.as_ref(),
+                "fragment-delay",
+                0..=MAX_DELAY_MILLIS,
+            )?,
+            coalesce: self
+                .ranged(file.coalesce.as_ref(), "coalesce", 1..=MAX_DELAY_MILLIS)?
+                .map(Some),
+            faults: self.list(file.fault.as_deref())?,
+            seed: self
+                .ranged(file.seed.as_ref(), "seed", 0..=i64::MAX as u64)?
+                .map(Some),
+            tls_cert: file.tls_cert.map(Some),
+            tls_key: file.tls_key.map(Some),
+            tls_mitm_ca: file.tls_mitm_ca.map(Some),
+            tls_mitm_key: file.tls_mitm_key.map(Some),
+            remote_tls: file.remote_tls,
+            remote_ca: file.remote_ca.map(Some),
+            remote_sni: file.remote_sni.map(Some),
+            remote_client_cert: file.remote_client_cert.map(Some),
+            remote_client_key: file.remote_client_key.map(Some),
+            remote_insecure: file.remote_insecure,
+            inspect_tls: file.inspect_tls,
+            sni_routes: self.list(file.sni_route.as_deref())?,
+            decode: self.value_enum(file.decode.as_ref(), "decode")?.map(Some),
+            decode_body: file.decode_body,
+            pcap: file.pcap.map(Some),
+            capture_dir: file.capture_dir.map(Some),
+            metrics_addr: file
+                .metrics_addr
+                .as_ref()
+                .map(|listen| self.listen_addr(listen))
+                .transpose()?
+                .map(Some),
+            admin_addr: file
+                .admin_addr
+                .as_ref()
+                .map(|listen| self.listen_addr(listen))
+                .transpose()?
+                .map(Some),
+            output_format: self.value_enum(file.output_format.as_ref(), "output-format")?,
+            log_file: file.log_file.map(Some),
+            log_rotate_size: file
+                .log_rotate_size
+                .as_ref()
+                .map(|size| self.byte_size(size))
+                .transpose()?
+                .map(Some),
+            log_rotate_interval: self
+                .ranged(
+                    file.log_rotate_interval.as_ref(),
+                    "log-rotate-interval",
+                    timeout_range.clone(),
+                )?
+                .map(Some),
+            log_keep: self.count(file.log_keep.as_ref(), "log-keep", 1..=u32::MAX.into())?,
+            log_compress: file.log_compress,
+            routes: Vec::with_capacity(file.route.len()),
+        };
+        for table in file.route {
+            let listen_addr = self.listen_addr(&table.listen)?;
+            let name = match &table.name {
+                Some(name) => {
+                    check_route_name(name.get_ref())
+                        .map_err(|error| self.error(name.span(), error))?;
+                    name.get_ref().clone()
+                }
+                None => listen_addr.to_string(),
+            };
+            if values
+                .routes
+                .iter()
+                .any(|route| route.name.as_deref() == Some(name.as_str()))
+            {
+                let span = table.name.as_ref().unwrap_or(&table.listen).span();
+                return Err(self.error(span, format!("route name `{name}` is used more than once")));
+            }
+            let settings = RouteSettings {
+                timeout: self.ranged(table.timeout.as_ref(), "timeout", timeout_range.clone())?,
+                max_connections: self.count(
+                    table.max_connections.as_ref(),
+                    "max-connections",
+                    1..=u32::MAX.into(),
+                )?,
+                formatting: self.value_enum(table.formatting.as_ref(), "formatting")?,
+                separator: table.separator,
+                hexdump_width: self.count(
+                    table.hexdump_width.as_ref(),
+                    "hexdump-width",
+                    1..=MAX_HEXDUMP_WIDTH as u64,
+                )?,
+                connection_ids: table.connection_ids,
+                faults: self.list(table.fault.as_deref())?,
+            };
+            values.routes.push(Route {
+                name: Some(name),
+                listen_addr,
+                remote_addr: Some(self.remote_addr(&table.remote)?),
+                settings,
+            });
+        }
+        Ok(values)
+    }
+
+    fn listen_addr(&self, value: &Spanned<String>) -> Result<net::SocketAddr, String> {
+        value.get_ref().parse().map_err(|_| {
+            self.error(
+                value.span(),
+                format!(
+                    "`{}` is not a literal `IP:port` listen address",
+                    value.get_ref()
+                ),
+            )
+        })
+    }
+
+    fn remote_addr(&self, value: &Spanned<String>) -> Result<TargetAddr, String> {
+        parse_remote_addr(value.get_ref()).map_err(|error| self.error(value.span(), error))
+    }
+
+    /// One of the names the command line accepts for `T`.
+    fn value_enum<T: ValueEnum>(
+        &self,
+        value: Option<&Spanned<String>>,
+        key: &str,
+    ) -> Result<Option<T>, String> {
+        value
+            .map(|value| {
+                T::from_str(value.get_ref(), false).map_err(|_| {
+                    let names: Vec<String> = T::value_variants()
+                        .iter()
+                        .filter_map(|variant| variant.to_possible_value())
+                        .map(|value| value.get_name().to_string())
+                        .collect();
+                    self.error(
+                        value.span(),
+                        format!(
+                            "invalid {key} `{}`: expected one of {}",
+                            value.get_ref(),
+                            names.join(", ")
+                        ),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// An integer within `range`, the command line's range for `key`.
+    fn ranged(
+        &self,
+        value: Option<&Spanned<i64>>,
+        key: &str,
+        range: RangeInclusive<u64>,
+    ) -> Result<Option<u64>, String> {
+        value
+            .map(|value| {
+                u64::try_from(*value.get_ref())
+                    .ok()
+                    .filter(|number| range.contains(number))
+                    .ok_or_else(|| {
+                        self.error(
+                            value.span(),
+                            format!(
+                                "invalid {key} `{}`: expected {}..={}",
+                                value.get_ref(),
+                                range.start(),
+                                range.end()
+                            ),
+                        )
+                    })
+            })
+            .transpose()
+    }
+
+    /// [`Source::ranged`] for a `u32` option.
+    fn count(
+        &self,
+        value: Option<&Spanned<i64>>,
+        key: &str,
+        range: RangeInclusive<u64>,
+    ) -> Result<Option<u32>, String> {
+        Ok(self
+            .ranged(value, key, range)?
+            .map(|number| u32::try_from(number).expect("the range fits a u32")))
+    }
+
+    /// A list of values written as on the command line, one string each.
+    fn list<T>(&self, values: Option<&[Spanned<String>]>) -> Result<Option<Vec<T>>, String>
+    where
+        T: FromStr<Err = String>,
+    {
+        values
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| {
+                        value
+                            .get_ref()
+                            .parse()
+                            .map_err(|error| self.error(value.span(), error))
+                    })
+                    .collect()
+            })
+            .transpose()
+    }
+
+    fn byte_size(&self, value: &Spanned<toml::Value>) -> Result<u64, String> {
+        let text = match value.get_ref() {
+            toml::Value::Integer(bytes) => bytes.to_string(),
+            toml::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        parse_byte_size(&text).map_err(|error| self.error(value.span(), error))
+    }
+}
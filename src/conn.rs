@@ -1,15 +1,69 @@
+use crate::admin;
+use crate::admin::LiveConnection;
+use crate::admin::Registry;
 use crate::args::Arguments;
+use crate::args::DecodeProtocol;
+use crate::args::OutputFormat;
+use crate::args::Route;
 use crate::args::TargetAddr;
 use crate::args::get_formatter_by_kind;
+use crate::capture::CaptureDir;
+use crate::capture::ConnCapture;
+use crate::config;
+use crate::config::CommandLine;
+use crate::decode::Decoded;
+use crate::decode::HttpDecoder;
+use crate::decode::MAX_BODY_SHOWN;
+use crate::decode::StartLine;
+use crate::fault;
+use crate::fault::DirectionFaults;
+use crate::fault::Fault;
+use crate::fault::Reset;
+use crate::fault::Step;
+use crate::formatters::PayloadFormatter;
+use crate::inspect;
+use crate::inspect::Inspection;
+use crate::inspect::Message;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::metrics::RouteMetrics;
+use crate::netem;
+use crate::netem::Delays;
+use crate::netem::Draw;
+use crate::netem::Fragmenter;
+use crate::netem::Impairments;
+use crate::netem::SplitMix64;
+use crate::netem::Throttle;
+use crate::netem::TokenBucket;
+use crate::pcap::PcapConnection;
+use crate::pcap::PcapWriter;
+use crate::sni;
+use crate::sni::Peeked;
+use crate::sni::SniRoute;
+use crate::tls;
+use crate::tls::Hello;
+use crate::tls::MaybeTls;
+use crate::tls::Mint;
+use crate::tls::RemoteTls;
+use crate::tui;
+use crate::tui::Link;
 use bytes::BytesMut;
-use logged_stream::ConsoleLogger;
+use logged_stream::BufferFormatter;
 use logged_stream::DefaultFilter;
 use logged_stream::LoggedStream;
+use logged_stream::Logger;
+use logged_stream::Record;
+use logged_stream::RecordFilter;
 use logged_stream::RecordKind;
 use logged_stream::RecordKindFilter;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
 use std::fmt;
+use std::hash::BuildHasher;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
@@ -19,39 +73,475 @@ use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
 use tokio::io::{self};
 use tokio::net as tokio_net;
+use tokio::sync::OwnedSemaphorePermit;
 use tokio::sync::Semaphore;
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::task::{self};
 use tokio::time::Instant;
 use tokio::time::sleep;
 use tokio::time::sleep_until;
+use tokio::time::timeout_at;
+use tokio_rustls::TlsAcceptor;
 
-pub async fn initialize_tcp_listener(arguments: Arguments) -> io::Result<()> {
-    let listener = match tokio_net::TcpListener::bind(arguments.bind_listener_addr).await {
-        Ok(listener) => listener,
+/// Bind every route and serve them until Ctrl-C or SIGTERM, then drain the active
+/// connections (see [`drain`]). With the `command_line` the proxy was started with,
+/// a SIGHUP reads the configuration again (see [`config::reload`]) and hands it to
+/// the accept loops for their new connections. With the `--tui` view's `tui` link,
+/// the relays feed it their payload and its quit key shuts the proxy down too.
+pub async fn initialize_tcp_listener(
+    arguments: Arguments,
+    command_line: Option<CommandLine>,
+    mut tui: Option<Link>,
+) -> io::Result<()> {
+    let routes = match arguments.routes() {
+        Ok(routes) => routes,
+        Err(error) => {
+            log::error!(event = "invalid_routes"; "Invalid routes: {error}");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+    };
+    let mut sinks = Sinks::open(&arguments)?;
+    let random = arguments.jitter_to_destination > 0
+        || arguments.jitter_to_client > 0
+        || arguments.fragment.is_some_and(|size| size.min != size.max)
+        || !arguments.faults.is_empty()
+        || routes.iter().any(|route| route.settings.faults.is_some());
+    if random {
+        log::info!(
+            event = "seed", seed = sinks.seed;
+            "Drawing the jitter and faults with seed {} (repeat a run with `--seed {}`)",
+            sinks.seed,
+            sinks.seed
+        );
+    }
+    if let Some(link) = &tui {
+        sinks.feed_tui(link.feed.clone());
+    }
+    let metrics = Arc::new(Metrics::default());
+    // Every route is bound before any is served, so an unavailable address is a
+    // startup error rather than a proxy running only some of its routes.
+    let mut listeners = Vec::with_capacity(routes.len());
+    for route in routes {
+        let route_log = RouteLog::new(&route, &arguments);
+        let listener = match tokio_net::TcpListener::bind(route.listen_addr).await {
+            Ok(listener) => listener,
+            Err(error) => {
+                route_log.log(
+                    log::Level::Error,
+                    "bind_failed",
+                    &[],
+                    format_args!("Failed to bind listener on {}: {error}", route.listen_addr),
+                );
+                return Err(error);
+            }
+        };
+        let bound_addr = listener.local_addr()?;
+        route_log.log(
+            log::Level::Info,
+            "listening",
+            &[("listen_addr", log::kv::Value::from_display(&bound_addr))],
+            format_args!("Listener bound to {bound_addr}, waiting for incoming connections..."),
+        );
+        let label = route.name.clone().unwrap_or_else(|| bound_addr.to_string());
+        let route_metrics = metrics.route(label.clone());
+        listeners.push((listener, route, route_metrics, label));
+    }
+    let metrics_listener = bind_endpoint(
+        arguments.metrics_addr,
+        "metrics_listening",
+        "metrics",
+        "/metrics",
+    )
+    .await?;
+    let admin_listener = bind_endpoint(
+        arguments.admin_addr,
+        "admin_listening",
+        "the admin API",
+        "/",
+    )
+    .await?;
+
+    // Serve until interrupted. The accept loops and the endpoints never return on
+    // their own, so the `select!` runs them until Ctrl-C (SIGINT) or SIGTERM fires,
+    // or until one of them fails, then stops accepting: aborting the loops closes
+    // the listeners and releases the ports. The connections already accepted get
+    // the drain phase; whatever is left after it is torn down when the runtime
+    // shuts down. A failed task makes the proxy exit with its error.
+    let conn_ids = ConnIds::default();
+    let shutdown = Shutdown::default();
+    let registry = Registry::default();
+    let mut services = Services::default();
+    let mut route_configs = Vec::with_capacity(listeners.len());
+    for (listener, route, route_metrics, label) in listeners {
+        let (sender, receiver) = watch::channel(RouteConfig::new(arguments.clone(), route));
+        route_configs.push(sender);
+        services.spawn(
+            format!("the accept loop for {label}"),
+            serve_route(
+                listener,
+                receiver,
+                sinks.clone(),
+                conn_ids.clone(),
+                shutdown.clone(),
+                route_metrics,
+                registry.clone(),
+            ),
+        );
+    }
+    if let Some(listener) = metrics_listener {
+        services.spawn(
+            "the metrics endpoint".to_string(),
+            metrics::serve(listener, metrics),
+        );
+    }
+    if let Some(listener) = admin_listener {
+        services.spawn(
+            "the admin API".to_string(),
+            admin::serve(listener, registry),
+        );
+    }
+    let mut running = arguments;
+    let mut hangups = UnixSignal::hangup();
+    let mut terminations = UnixSignal::terminate();
+    let mut failure = None;
+    loop {
+        tokio::select! {
+            error = services.failed() => {
+                log::error!(event = "service_failed"; "{error}, stopping the proxy.");
+                failure = Some(error);
+                break;
+            }
+            result = shutdown_signal(&mut terminations, &mut tui) => {
+                if let Err(error) = result {
+                    log::error!(
+                        event = "signal_failed";
+                        "Failed to listen for shutdown signal: {error}"
+                    );
+                    return Ok(());
+                }
+                log::info!(event = "shutdown"; "Received shutdown signal, stopping listener.");
+                break;
+            }
+            () = hangups.recv(), if command_line.is_some() => {
+                let command_line = command_line.as_ref().expect("guarded by the branch");
+                reload(command_line, &mut running, &route_configs);
+            }
+        }
+    }
+    services.shutdown().await;
+    drain(
+        &shutdown,
+        Duration::from_secs(running.shutdown_grace),
+        shutdown_signal(&mut terminations, &mut tui),
+    )
+    .await;
+
+    failure.map_or(Ok(()), Err)
+}
+
+/// Bind the listener of an HTTP endpoint (`--metrics-addr`, `--admin-addr`) if its
+/// address is set, announcing where `name` is served. Like a route's, a listener
+/// that cannot be bound is a startup error.
+async fn bind_endpoint(
+    addr: Option<SocketAddr>,
+    event: &'static str,
+    name: &str,
+    path: &str,
+) -> io::Result<Option<tokio_net::TcpListener>> {
+    let Some(addr) = addr else {
+        return Ok(None);
+    };
+    match tokio_net::TcpListener::bind(addr).await {
+        Ok(listener) => {
+            let bound_addr = listener.local_addr()?;
+            log::info!(
+                event = event, listen_addr = log::kv::Value::from_display(&bound_addr);
+                "Serving {name} on http://{bound_addr}{path}"
+            );
+            Ok(Some(listener))
+        }
         Err(error) => {
             log::error!(
-                "Failed to bind listener on {}: {error}",
-                arguments.bind_listener_addr
+                event = "bind_failed";
+                "Failed to bind the listener for {name} on {addr}: {error}"
             );
-            return Err(error);
+            Err(error)
         }
-    };
+    }
+}
 
-    let bound_addr = listener.local_addr()?;
-    log::info!("Listener bound to {bound_addr}, waiting for incoming connections...");
+/// How long, once the connections are told to close, their closing lines get to
+/// be logged before the runtime shuts down.
+const FORCED_CLOSE_WAIT: Duration = Duration::from_secs(1);
 
-    // Serve until interrupted. `run_accept_loop` never returns on its own, so the
-    // `select!` runs the accept loop until Ctrl-C (SIGINT) fires, then stops
-    // accepting. Dropping the accept-loop future closes the listener and releases
-    // the port; in-flight connections are torn down when the runtime shuts down.
+/// The drain phase of a shutdown: the listeners are closed, and the connections
+/// still active get up to `grace` to finish on their own. At the deadline, or when
+/// `second_signal` resolves, the rest are closed, each logging its own line.
+pub(crate) async fn drain(
+    shutdown: &Shutdown,
+    grace: Duration,
+    second_signal: impl Future<Output = io::Result<()>>,
+) {
+    let active = shutdown.active();
+    if active == 0 {
+        return;
+    }
+    log::info!(
+        event = "draining", active = active;
+        "Waiting up to {}s for {active} active connection(s) to finish...",
+        grace.as_secs()
+    );
     tokio::select! {
-        _ = run_accept_loop(listener, arguments) => {}
-        result = tokio::signal::ctrl_c() => match result {
-            Ok(()) => log::info!("Received shutdown signal, stopping listener."),
-            Err(error) => log::error!("Failed to listen for shutdown signal: {error}"),
-        },
+        () = shutdown.drained() => {
+            log::info!(event = "drained"; "All connections finished.");
+            return;
+        }
+        () = sleep(grace) => {
+            log::warn!(
+                event = "drain_timeout", active = shutdown.active();
+                "Shutdown grace period of {}s is over, closing the {} remaining connection(s)",
+                grace.as_secs(),
+                shutdown.active()
+            );
+        }
+        _ = second_signal => {
+            log::warn!(
+                event = "drain_interrupted", active = shutdown.active();
+                "Received a second shutdown signal, closing the {} remaining connection(s)",
+                shutdown.active()
+            );
+        }
     }
+    shutdown.force();
+    let _ = tokio::time::timeout(FORCED_CLOSE_WAIT, shutdown.drained()).await;
+}
 
-    Ok(())
+/// Resolves at Ctrl-C (SIGINT), SIGTERM where there is one, or the `--tui` view's
+/// quit key (the terminal is in raw mode then, so Ctrl-C raises no SIGINT). An
+/// error means Ctrl-C cannot be listened for.
+async fn shutdown_signal(terminations: &mut UnixSignal, tui: &mut Option<Link>) -> io::Result<()> {
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        () = terminations.recv() => Ok(()),
+        () = Link::quit(tui) => Ok(()),
+    }
+}
+
+/// Apply a SIGHUP: read the configuration again and, if it is valid, hand each
+/// route its new settings and log what changed. A rejected reload is logged and
+/// changes nothing.
+fn reload(
+    command_line: &CommandLine,
+    running: &mut Arguments,
+    route_configs: &[watch::Sender<RouteConfig>],
+) {
+    log::info!(event = "reloading"; "Received SIGHUP, reloading the configuration...");
+    let reload = match config::reload(command_line, running) {
+        Ok(reload) => reload,
+        Err(error) => {
+            log::error!(
+                event = "reload_failed";
+                "Configuration reload rejected, keeping the running configuration: {error}"
+            );
+            return;
+        }
+    };
+    if !reload.ignored.is_empty() {
+        log::warn!(
+            event = "reload_ignored";
+            "Configuration reload does not change {} until a restart",
+            reload.ignored.join(", ")
+        );
+    }
+    for (sender, route) in route_configs.iter().zip(reload.routes) {
+        sender.send_replace(RouteConfig::new(reload.arguments.clone(), route));
+    }
+    *running = reload.arguments;
+    if reload.changes.is_empty() {
+        log::info!(event = "reloaded"; "Configuration reloaded: nothing changed");
+    } else {
+        log::info!(
+            event = "reloaded";
+            "Configuration reloaded, for new connections: {}",
+            reload.changes.join(", ")
+        );
+    }
+}
+
+/// Resolves at each delivery of a Unix signal. Never resolves where there is no
+/// such signal (Windows), or if the handler cannot be installed, which is logged
+/// once.
+struct UnixSignal {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl UnixSignal {
+    /// SIGHUP, which reloads the configuration.
+    fn hangup() -> Self {
+        #[cfg(unix)]
+        {
+            Self::new(
+                tokio::signal::unix::SignalKind::hangup(),
+                "SIGHUP, reloading is disabled",
+            )
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    /// SIGTERM, which shuts the proxy down like Ctrl-C.
+    fn terminate() -> Self {
+        #[cfg(unix)]
+        {
+            Self::new(
+                tokio::signal::unix::SignalKind::terminate(),
+                "SIGTERM, only Ctrl-C shuts the proxy down gracefully",
+            )
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    #[cfg(unix)]
+    fn new(kind: tokio::signal::unix::SignalKind, consequence: &str) -> Self {
+        let signal = tokio::signal::unix::signal(kind)
+            .inspect_err(|error| {
+                log::warn!(
+                    event = "signal_failed";
+                    "Failed to listen for {consequence}: {error}"
+                );
+            })
+            .ok();
+        Self { signal }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+/// The outputs the proxy writes besides the console, the `--rate-limit-global`
+/// bucket and the `--seed`, opened once at startup and shared by every connection.
+/// Opening them before the listener is bound means a bad path is a startup error
+/// (logged, non-zero exit) rather than something the first connection trips over.
+#[derive(Clone)]
+pub(crate) struct Sinks {
+    /// The `--pcap` capture file, if one was requested.
+    pcap: Option<PcapWriter>,
+    /// The `--capture-dir` directory, if one was requested.
+    capture: Option<CaptureDir>,
+    /// The `--tui` view's payload pane, when it runs.
+    tui: Option<tui::Feed>,
+    /// The `--rate-limit-global` budget, shared by every connection's relays.
+    global_rate: Option<Arc<TokenBucket>>,
+    /// `--seed`, or one picked at random: what every connection's jitter and
+    /// faults are drawn from.
+    pub(crate) seed: u64,
+    /// Terminates the clients' TLS, with `--tls-cert`.
+    tls: Option<TlsAcceptor>,
+    /// Originates TLS to the destinations, with `--remote-tls`.
+    remote_tls: Option<RemoteTls>,
+    /// Mints the clients' certificates, with `--tls-mitm-ca`.
+    mint: Option<Arc<Mint>>,
+}
+
+impl Sinks {
+    pub(crate) fn open(arguments: &Arguments) -> io::Result<Self> {
+        let pcap = match &arguments.pcap {
+            None => None,
+            Some(path) => match PcapWriter::create(path) {
+                Ok(writer) => Some(writer),
+                Err(error) => {
+                    log::error!(
+                        event = "pcap_failed";
+                        "Failed to create pcap file {}: {error}",
+                        path.display()
+                    );
+                    return Err(error);
+                }
+            },
+        };
+        let capture = match &arguments.capture_dir {
+            None => None,
+            Some(path) => match CaptureDir::create(path, arguments.precision) {
+                Ok(directory) => Some(directory),
+                Err(error) => {
+                    log::error!(
+                        event = "capture_failed";
+                        "Failed to create capture directory {}: {error}",
+                        path.display()
+                    );
+                    return Err(error);
+                }
+            },
+        };
+        let tls = match (&arguments.tls_cert, &arguments.tls_key) {
+            (Some(cert), Some(key)) => match tls::acceptor(cert, key) {
+                Ok(acceptor) => Some(acceptor),
+                Err(error) => {
+                    log::error!(
+                        event = "tls_failed";
+                        "Failed to load the TLS certificate and key: {error}"
+                    );
+                    return Err(error);
+                }
+            },
+            _ => None,
+        };
+        let mint = match (&arguments.tls_mitm_ca, &arguments.tls_mitm_key) {
+            (Some(ca), Some(key)) => match Mint::load(ca, key) {
+                Ok(mint) => Some(Arc::new(mint)),
+                Err(error) => {
+                    log::error!(
+                        event = "tls_failed";
+                        "Failed to load the TLS CA certificate and key: {error}"
+                    );
+                    return Err(error);
+                }
+            },
+            _ => None,
+        };
+        let remote_tls = match RemoteTls::new(arguments) {
+            Ok(remote_tls) => remote_tls,
+            Err(error) => {
+                log::error!(
+                    event = "tls_failed";
+                    "Failed to set up TLS to the destination: {error}"
+                );
+                return Err(error);
+            }
+        };
+        Ok(Self {
+            pcap,
+            capture,
+            tui: None,
+            global_rate: arguments
+                .rate_limit_global
+                .map(|rate| Arc::new(TokenBucket::new(rate))),
+            // The standard library's per-process random keys make a good enough
+            // random seed.
+            seed: arguments
+                .seed
+                .unwrap_or_else(|| RandomState::new().hash_one(0u8)),
+            tls,
+            remote_tls,
+            mint,
+        })
+    }
+
+    /// Also hand every relayed chunk to the `--tui` view.
+    pub(crate) fn feed_tui(&mut self, feed: tui::Feed) {
+        self.tui = Some(feed);
+    }
 }
 
 /// Minimum delay before retrying after a failed `accept()`. Applied to every
@@ -67,41 +557,330 @@ pub(crate) fn next_accept_backoff(current: Duration) -> Duration {
     (current * 2).min(ACCEPT_BACKOFF_MAX)
 }
 
+/// Mints the per-connection ids, sequentially in accept order, starting at 1 for
+/// each proxy run. Shared by every route's accept loop, so an id names one
+/// connection across the whole process.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnIds(Arc<AtomicU64>);
+
+impl ConnIds {
+    fn next(&self) -> u64 {
+        // `Relaxed`: the counter guards no other memory; each id only has to be
+        // handed out once.
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// The connections still open, for the drain phase at shutdown (see [`drain`]),
+/// and the order that closes them once it is over. Clones share the state.
+#[derive(Clone)]
+pub(crate) struct Shutdown {
+    active: watch::Sender<usize>,
+    forced: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            active: watch::Sender::new(0),
+            forced: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Count a connection as active until the returned guard is dropped.
+    pub(crate) fn track(&self) -> ActiveConnection {
+        self.active.send_modify(|active| *active += 1);
+        ActiveConnection(self.active.clone())
+    }
+
+    /// How many connections are active.
+    pub(crate) fn active(&self) -> usize {
+        *self.active.borrow()
+    }
+
+    /// Resolves once no connection is active.
+    pub(crate) async fn drained(&self) {
+        // `self` holds the sender, so the channel never closes under the wait.
+        let _ = self
+            .active
+            .subscribe()
+            .wait_for(|active| *active == 0)
+            .await;
+    }
+
+    /// Tell every connection, those accepted later included, to close now.
+    pub(crate) fn force(&self) {
+        self.forced.send_replace(true);
+    }
+
+    /// Resolves once [`Shutdown::force`] has been called.
+    async fn forced(&self) {
+        let _ = self.forced.subscribe().wait_for(|forced| *forced).await;
+    }
+}
+
+/// A connection counted by [`Shutdown::track`], until dropped.
+pub(crate) struct ActiveConnection(watch::Sender<usize>);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.send_modify(|active| *active -= 1);
+    }
+}
+
+/// The tasks that serve the proxy — the routes' accept loops and the HTTP
+/// endpoints — each with a name for the log. None of them ends while the proxy
+/// runs, so one that does has failed.
+#[derive(Default)]
+pub(crate) struct Services {
+    tasks: JoinSet<()>,
+    names: HashMap<task::Id, String>,
+}
+
+impl Services {
+    /// Run `service` in the background, known as `name`.
+    pub(crate) fn spawn<F>(&mut self, name: String, service: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let id = self.tasks.spawn(service).id();
+        self.names.insert(id, name);
+    }
+
+    /// Resolves once a service has ended, returned, panicked or been cancelled,
+    /// with an error that says which one and how; never, while there are none.
+    /// Cancel safe, for a `select!` loop.
+    pub(crate) async fn failed(&mut self) -> io::Error {
+        let Some(ended) = self.tasks.join_next_with_id().await else {
+            return std::future::pending().await;
+        };
+        let (id, how) = match ended {
+            Ok((id, ())) => (id, "stopped".to_string()),
+            Err(error) if error.is_panic() => {
+                let id = error.id();
+                let panic = error.into_panic();
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("a non-string payload");
+                (id, format!("panicked: {message}"))
+            }
+            Err(error) => (error.id(), "was cancelled".to_string()),
+        };
+        let name = self
+            .names
+            .remove(&id)
+            .unwrap_or_else(|| "a task".to_string());
+        io::Error::other(format!("{name} {how}"))
+    }
+
+    /// Abort every service and wait for them to be gone.
+    pub(crate) async fn shutdown(&mut self) {
+        self.tasks.shutdown().await;
+    }
+}
+
+/// What a route's new connections are set up with: the proxy-wide arguments with
+/// the route's own settings applied, and the route. A reload replaces it as a
+/// whole; a connection keeps the one it was accepted with.
+#[derive(Clone)]
+pub(crate) struct RouteConfig {
+    arguments: Arguments,
+    route: Arc<Route>,
+}
+
+impl RouteConfig {
+    pub(crate) fn new(arguments: Arguments, route: Route) -> Self {
+        Self {
+            arguments: route.settings.apply(arguments),
+            route: Arc::new(route),
+        }
+    }
+}
+
+/// A route's `--max-connections` slots, which a reload may resize while
+/// connections hold some.
+pub(crate) struct ConnectionLimit {
+    semaphore: Arc<Semaphore>,
+    size: u32,
+    /// Slots still to withdraw after a shrink that found them in use: each is
+    /// taken out of circulation once a connection gives it back.
+    surplus: u32,
+}
+
+impl ConnectionLimit {
+    pub(crate) fn new(size: u32) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(size as usize)),
+            size,
+            surplus: 0,
+        }
+    }
+
+    /// Wait for a slot. Does not borrow the limit, so it can be resized meanwhile;
+    /// the slot must then go through [`ConnectionLimit::admit`].
+    pub(crate) fn acquire(&self) -> impl Future<Output = Option<OwnedSemaphorePermit>> + use<> {
+        let semaphore = self.semaphore.clone();
+        // The semaphore is never closed, so this only ends a stuck loop.
+        async move { semaphore.acquire_owned().await.ok() }
+    }
+
+    /// The acquired slot, or `None` if it is one a shrink withdrew: acquire
+    /// another.
+    pub(crate) fn admit(&mut self, permit: OwnedSemaphorePermit) -> Option<OwnedSemaphorePermit> {
+        if self.surplus > 0 {
+            self.surplus -= 1;
+            permit.forget();
+            return None;
+        }
+        Some(permit)
+    }
+
+    /// Whether every slot is taken.
+    fn saturated(&self) -> bool {
+        self.semaphore.available_permits() == 0
+    }
+
+    pub(crate) fn resize(&mut self, size: u32) {
+        if size > self.size {
+            let added = size - self.size;
+            let cancelled = added.min(self.surplus);
+            self.surplus -= cancelled;
+            self.semaphore.add_permits((added - cancelled) as usize);
+        } else {
+            let removed = self.size - size;
+            let forgotten = self.semaphore.forget_permits(removed as usize) as u32;
+            self.surplus += removed - forgotten;
+        }
+        self.size = size;
+    }
+}
+
 /// Accept connections on an already-bound listener and spawn a relay handler for
-/// each one. Split out from [`initialize_tcp_listener`] so tests can drive it
-/// with a listener bound to an ephemeral port.
-pub(crate) async fn run_accept_loop(listener: tokio_net::TcpListener, arguments: Arguments) {
+/// each one, set up from the latest [`RouteConfig`] sent on `config`, counted by
+/// `shutdown` and listed in `registry` while it is open. The loop accepts nothing
+/// while the `registry` is paused. Split out from [`initialize_tcp_listener`] so
+/// tests can drive it with a listener bound to an ephemeral port.
+pub(crate) async fn serve_route(
+    listener: tokio_net::TcpListener,
+    mut config: watch::Receiver<RouteConfig>,
+    sinks: Sinks,
+    conn_ids: ConnIds,
+    shutdown: Shutdown,
+    metrics: Arc<RouteMetrics>,
+    registry: Registry,
+) {
+    let (route_log, route_label, max_connections) = {
+        let current = config.borrow_and_update();
+        let route_label = current.route.name.clone().unwrap_or_else(|| {
+            listener
+                .local_addr()
+                .unwrap_or(current.route.listen_addr)
+                .to_string()
+        });
+        (
+            RouteLog::new(&current.route, &current.arguments),
+            route_label,
+            current.arguments.max_connections,
+        )
+    };
+    let mut paused = registry.paused();
     // Bound how many connections are handled concurrently. A permit is acquired
     // *before* accepting, so once `--max-connections` are active the loop stops
     // pulling connections off the backlog (natural backpressure) instead of
     // spawning unbounded handlers; each handler holds its permit until it closes.
-    let connection_limit = Arc::new(Semaphore::new(arguments.max_connections as usize));
+    // Every route has a limit of its own, so a busy route cannot starve another.
+    let mut connection_limit = ConnectionLimit::new(max_connections);
     let mut accept_backoff = ACCEPT_BACKOFF_MIN;
-    // Per-connection ids are minted here, sequentially in accept order, starting at
-    // 1 for each proxy run. A plain (non-atomic) counter is deliberate: this accept
-    // loop is a single task and the only writer, and each spawned handler receives
-    // the value by copy, so there is nothing to synchronize.
-    let mut next_conn_id: u64 = 1;
+    // Set while every slot is taken, for the metrics' slot-wait time.
+    let mut waiting_since = None;
     loop {
-        let Ok(permit) = connection_limit.clone().acquire_owned().await else {
-            break; // the semaphore is never closed, so this only ends a stuck loop
+        if waiting_since.is_none() && connection_limit.saturated() {
+            waiting_since = Some(Instant::now());
+        }
+        let permit = tokio::select! {
+            permit = connection_limit.acquire(), if !*paused.borrow() => {
+                let Some(permit) = permit else {
+                    break;
+                };
+                match connection_limit.admit(permit) {
+                    Some(permit) => permit,
+                    None => continue,
+                }
+            }
+            Ok(()) = config.changed() => {
+                // A reload: resize the slots now, even while waiting for one.
+                let max_connections = config.borrow_and_update().arguments.max_connections;
+                connection_limit.resize(max_connections);
+                continue;
+            }
+            // Paused or resumed: wait for a slot again, or stop waiting.
+            Ok(()) = paused.changed() => continue,
+        };
+        if let Some(since) = waiting_since.take() {
+            metrics.slot_waited(since.elapsed());
+        }
+        // A pause also takes effect while the loop waits for a connection; the
+        // ones arriving meanwhile wait in the listen backlog.
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Ok(_) = paused.wait_for(|paused| *paused) => continue,
         };
-        let cloned_arguments = arguments.clone();
-        match listener.accept().await {
+        match accepted {
             Ok((stream, addr)) => {
                 accept_backoff = ACCEPT_BACKOFF_MIN; // recovered -> reset the backoff
-                let conn_id = next_conn_id;
-                next_conn_id += 1;
-                let conn_log = ConnLog::new(&arguments, conn_id);
-                conn_log.info(format_args!("Incoming connection from {addr}"));
+                metrics.accept_recovered();
+                metrics.accepted();
+                let route_config = config.borrow().clone();
+                let conn_id = conn_ids.next();
+                let conn_log = ConnLog::new(
+                    &route_config.arguments,
+                    &route_config.route,
+                    &route_log,
+                    conn_id,
+                    addr,
+                );
+                conn_log.info("accepted", format_args!("Incoming connection from {addr}"));
+                let live = registry.register(
+                    conn_id,
+                    route_label.clone(),
+                    addr,
+                    route_config.route.destination(),
+                );
+                let cloned_sinks = sinks.clone();
+                let cloned_shutdown = shutdown.clone();
+                let cloned_metrics = metrics.clone();
+                let cloned_registry = registry.clone();
+                let active = shutdown.track();
                 tokio::spawn(async move {
-                    incoming_connection_handle(cloned_arguments, stream, conn_log, addr).await;
+                    incoming_connection_handle(
+                        route_config,
+                        cloned_sinks,
+                        stream,
+                        conn_log,
+                        &live,
+                        cloned_shutdown,
+                        &cloned_metrics,
+                    )
+                    .await;
+                    cloned_registry.unregister(conn_id);
+                    cloned_metrics.closed();
                     drop(permit); // release the slot once the connection is done
+                    drop(active);
                 });
             }
             Err(e) => {
-                log::error!("Failed to accept incoming connection due to {e}");
+                route_log.log(
+                    log::Level::Error,
+                    "accept_failed",
+                    &[],
+                    format_args!("Failed to accept incoming connection due to {e}"),
+                );
                 drop(permit); // nothing was accepted, so free the slot
+                metrics.accept_failed(accept_backoff);
 
                 // Back off before retrying. A persistent error (e.g. file-descriptor
                 // exhaustion, where the connection stays in the backlog) would
@@ -116,14 +895,77 @@ pub(crate) async fn run_accept_loop(listener: tokio_net::TcpListener, arguments:
 
 /// Open a connection to the proxy's remote destination for one accepted client.
 /// A literal `IP:port` target is dialed directly; a `hostname:port` target is
-/// resolved via DNS at this point (once per connection), with tokio trying each
-/// resolved address in turn until one connects. A resolution failure surfaces as
-/// an `Err` here, handled by the caller exactly like any other connect failure.
-async fn connect_to_target(target: &TargetAddr) -> io::Result<tokio_net::TcpStream> {
-    match target {
-        TargetAddr::Socket(addr) => tokio_net::TcpStream::connect(*addr).await,
+/// resolved via DNS at this point (once per connection), and each resolved address
+/// is tried in turn until one connects. With `tls` (`--remote-tls`), the TLS
+/// handshake follows on the connected socket. A resolution failure is handled by
+/// the caller like any other connect failure, only counted apart in the metrics.
+pub(crate) async fn connect_to_target(
+    target: &TargetAddr,
+    tls: Option<&RemoteTls>,
+) -> Result<MaybeTls<tokio_net::TcpStream>, ConnectError> {
+    let stream = match target {
+        TargetAddr::Socket(addr) => tokio_net::TcpStream::connect(*addr)
+            .await
+            .map_err(ConnectError::Connect)?,
         TargetAddr::Named { host, port } => {
-            tokio_net::TcpStream::connect((host.as_str(), *port)).await
+            let addrs = tokio_net::lookup_host((host.as_str(), *port))
+                .await
+                .map_err(ConnectError::Resolve)?;
+            let mut connected = None;
+            let mut last_error = None;
+            for addr in addrs {
+                match tokio_net::TcpStream::connect(addr).await {
+                    Ok(stream) => {
+                        connected = Some(stream);
+                        break;
+                    }
+                    Err(error) => last_error = Some(error),
+                }
+            }
+            match (connected, last_error) {
+                (Some(stream), _) => stream,
+                (None, Some(error)) => return Err(ConnectError::Connect(error)),
+                (None, None) => {
+                    return Err(ConnectError::Resolve(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "could not resolve to any address",
+                    )));
+                }
+            }
+        }
+    };
+    match tls {
+        None => Ok(MaybeTls::Plain(stream)),
+        Some(tls) => tls.connect(target, stream).await.map_err(ConnectError::Tls),
+    }
+}
+
+/// Why [`connect_to_target`] failed.
+#[derive(Debug)]
+pub(crate) enum ConnectError {
+    /// The hostname did not resolve to any address.
+    Resolve(io::Error),
+    /// No address accepted the connection.
+    Connect(io::Error),
+    /// The destination's TLS handshake failed (`--remote-tls`).
+    Tls(io::Error),
+}
+
+impl From<ConnectError> for io::Error {
+    fn from(error: ConnectError) -> Self {
+        match error {
+            ConnectError::Resolve(error)
+            | ConnectError::Connect(error)
+            | ConnectError::Tls(error) => error,
+        }
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Resolve(error) | ConnectError::Connect(error) => error.fmt(f),
+            ConnectError::Tls(error) => write!(f, "TLS handshake failed: {error}"),
         }
     }
 }
@@ -135,10 +977,93 @@ async fn connect_to_target(target: &TargetAddr) -> io::Result<tokio_net::TcpStre
 /// matching nothing (see `strip_conn_tag` in [`log_capture`](crate::tests::log_capture)).
 pub(crate) const CONN_TAG_OPEN: &str = "[#";
 /// Closing delimiter of a connection's `[#N] ` console tag. The trailing space is
-/// part of it: [`ConsoleLogger`] renders the prefix verbatim, immediately before
+/// part of it: [`ConnStreamLogger`] renders the prefix verbatim, immediately before
 /// the record-kind character, with no separator of its own.
 pub(crate) const CONN_TAG_CLOSE: &str = "] ";
 
+/// The listener-level lines of one route — bound, bind and accept failures —
+/// prefixed `[name] ` when the route is named (as every line of its connections
+/// is, ahead of their `[#N] ` tag), and carrying the name as their `route` field.
+/// With `--output-format jsonl` the name is only a field, like the connection id.
+#[derive(Clone)]
+struct RouteLog {
+    name: Option<String>,
+    prefix: String,
+}
+
+impl RouteLog {
+    fn new(route: &Route, arguments: &Arguments) -> Self {
+        let structured = arguments.output_format == OutputFormat::Jsonl;
+        Self {
+            name: route.name.clone(),
+            prefix: match &route.name {
+                Some(name) if !structured => format!("[{name}] "),
+                _ => String::new(),
+            },
+        }
+    }
+
+    /// Log a line of the route at the given level, as `event`, with `extra`
+    /// key-values after the route's own.
+    fn log(
+        &self,
+        level: log::Level,
+        event: &'static str,
+        extra: &[(&'static str, log::kv::Value<'_>)],
+        message: fmt::Arguments<'_>,
+    ) {
+        if level > log::max_level() {
+            return;
+        }
+        let fields = RouteFields {
+            route_log: self,
+            event,
+            extra,
+        };
+        emit(level, &fields, format_args!("{}{message}", self.prefix));
+    }
+}
+
+/// The key-values of one [`RouteLog`] line: `event`, `route` (for a named route),
+/// then the line's own extras.
+struct RouteFields<'a> {
+    route_log: &'a RouteLog,
+    event: &'static str,
+    extra: &'a [(&'static str, log::kv::Value<'a>)],
+}
+
+impl log::kv::Source for RouteFields<'_> {
+    fn visit<'kvs>(
+        &'kvs self,
+        visitor: &mut dyn log::kv::VisitSource<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        visitor.visit_pair("event".into(), self.event.into())?;
+        if let Some(name) = &self.route_log.name {
+            visitor.visit_pair("route".into(), name.as_str().into())?;
+        }
+        for (key, value) in self.extra {
+            visitor.visit_pair((*key).into(), value.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// Hand one record to the logger: what `log::log!` expands to, spelled out
+/// because the macro only takes a fixed list of key-values.
+fn emit(level: log::Level, key_values: &dyn log::kv::Source, message: fmt::Arguments<'_>) {
+    log::logger().log(
+        &log::Record::builder()
+            .level(level)
+            .target(module_path!())
+            .module_path_static(Some(module_path!()))
+            .file_static(Some(file!()))
+            .line(Some(line!()))
+            .key_values(key_values)
+            .args(message)
+            .build(),
+    );
+}
+
 /// Everything one proxied connection logs, carrying that connection's id tag.
 ///
 /// The tag is `"[#N] "` (see [`CONN_TAG_OPEN`] / [`CONN_TAG_CLOSE`]), or an empty
@@ -149,194 +1074,1390 @@ pub(crate) const CONN_TAG_CLOSE: &str = "] ";
 /// Every per-connection line goes through this type: the lifecycle lines via
 /// [`log`](Self::log) / [`trace`](Self::trace) / [`debug`](Self::debug) /
 /// [`info`](Self::info) / [`warn`](Self::warn) / [`error`](Self::error), and both
-/// `LoggedStream`s' console records via [`prefix`](Self::prefix). That is what keeps
+/// `LoggedStream`s' console records via [`stream_logger`](Self::stream_logger). That is what keeps
 /// the tag from being forgotten — a new per-connection line cannot be logged without
 /// one, so the "every line of a connection is attributable" guarantee is structural
 /// rather than a convention each future call site has to remember.
+///
+/// The same goes for the fields `--output-format jsonl` renders: every line is an
+/// `event` and carries the connection's id, client and destination as `log`
+/// key-values (see [`ConnFields`]). In that format the id is a field of its own, so
+/// the tag is left out of the message.
+#[derive(Clone)]
 struct ConnLog {
     prefix: String,
+    /// The name of the route the connection came in on, if it has one.
+    route: Option<String>,
+    conn_id: u64,
+    client: SocketAddr,
+    /// The destination as configured (`--remote-addr`), then as picked by the
+    /// `--sni-route`s.
+    destination: String,
+    /// The address actually reached, once the destination is connected. Shared by
+    /// the clones handed to the `LoggedStream`s, which exist before it is known.
+    destination_addr: Arc<OnceLock<SocketAddr>>,
+    /// `--output-format jsonl`: payload chunks are logged by the relays, which know
+    /// their direction and byte count, rather than by the `LoggedStream`s.
+    structured: bool,
 }
 
 impl ConnLog {
-    /// Build the logger for connection `conn_id`, honouring `--no-connection-ids`.
-    fn new(arguments: &Arguments, conn_id: u64) -> Self {
+    /// Build the logger for connection `conn_id` from `client`, accepted on
+    /// `route`, honouring `--no-connection-ids` and `--output-format`.
+    fn new(
+        arguments: &Arguments,
+        route: &Route,
+        route_log: &RouteLog,
+        conn_id: u64,
+        client: SocketAddr,
+    ) -> Self {
+        let structured = arguments.output_format == OutputFormat::Jsonl;
+        let conn_tag = if arguments.connection_ids && !structured {
+            format!("{CONN_TAG_OPEN}{conn_id}{CONN_TAG_CLOSE}")
+        } else {
+            String::new()
+        };
         Self {
-            prefix: if arguments.connection_ids {
-                format!("{CONN_TAG_OPEN}{conn_id}{CONN_TAG_CLOSE}")
-            } else {
-                String::new()
-            },
+            prefix: format!("{}{conn_tag}", route_log.prefix),
+            route: route.name.clone(),
+            conn_id,
+            client,
+            destination: route.destination(),
+            destination_addr: Arc::new(OnceLock::new()),
+            structured,
+        }
+    }
+
+    /// The `logged-stream` logger for the connection's `peer` stream, carrying the
+    /// connection's tag and fields.
+    fn stream_logger(&self, peer: Peer) -> ConnStreamLogger {
+        ConnStreamLogger {
+            conn_log: self.clone(),
+            peer,
         }
     }
 
-    /// The connection's tag, for [`ConsoleLogger::with_prefix`].
-    fn prefix(&self) -> &str {
-        &self.prefix
+    /// Log a line with the connection's tag at the given level, as `event`. The
+    /// `message` argument is a `format_args!`-style `fmt::Arguments` value, so the
+    /// caller can use `{}`-style formatting without allocating a `String`.
+    fn log(&self, level: log::Level, event: &'static str, message: fmt::Arguments<'_>) {
+        self.log_with(level, event, &[], message);
     }
 
-    /// Log a line with the connection's tag at the given level. The `message`
-    /// argument is a `format_args!`-style `fmt::Arguments` value, so the caller
-    /// can use `{}`-style formatting without allocating a `String`.
-    #[allow(dead_code)]
-    fn log(&self, level: log::Level, message: fmt::Arguments<'_>) {
-        log::log!(level, "{}{message}", self.prefix);
+    /// [`log`](Self::log) with `extra` key-values after the connection's own.
+    fn log_with(
+        &self,
+        level: log::Level,
+        event: &'static str,
+        extra: &[(&'static str, log::kv::Value<'_>)],
+        message: fmt::Arguments<'_>,
+    ) {
+        if level > log::max_level() {
+            return;
+        }
+        let fields = ConnFields {
+            conn_log: self,
+            event,
+            extra,
+        };
+        emit(level, &fields, format_args!("{}{message}", self.prefix));
     }
 
     /// Log one of the connection's debug lines, tagged, at the `trace` level.
     #[allow(dead_code)]
-    fn trace(&self, message: fmt::Arguments<'_>) {
-        self.log(log::Level::Trace, message);
+    fn trace(&self, event: &'static str, message: fmt::Arguments<'_>) {
+        self.log(log::Level::Trace, event, message);
     }
 
     /// Log one of the connection's debug lines, tagged, at the `debug` level.
     #[allow(dead_code)]
-    fn debug(&self, message: fmt::Arguments<'_>) {
-        self.log(log::Level::Debug, message);
+    fn debug(&self, event: &'static str, message: fmt::Arguments<'_>) {
+        self.log(log::Level::Debug, event, message);
     }
 
     /// Log one of the connection's lifecycle lines, tagged, at the `info` level.
-    fn info(&self, message: fmt::Arguments<'_>) {
-        self.log(log::Level::Info, message);
+    fn info(&self, event: &'static str, message: fmt::Arguments<'_>) {
+        self.log(log::Level::Info, event, message);
     }
 
     /// Log one of the connection's warning lines, tagged, at the `warn` level.
     #[allow(dead_code)]
-    fn warn(&self, message: fmt::Arguments<'_>) {
-        self.log(log::Level::Warn, message);
+    fn warn(&self, event: &'static str, message: fmt::Arguments<'_>) {
+        self.log(log::Level::Warn, event, message);
     }
 
     /// Log one of the connection's failure lines, tagged, at the `error` level.
-    fn error(&self, message: fmt::Arguments<'_>) {
-        self.log(log::Level::Error, message);
+    fn error(&self, event: &'static str, message: fmt::Arguments<'_>) {
+        self.log(log::Level::Error, event, message);
+    }
+
+    /// Log what the TLS session with `peer`, reached at `addr`, negotiated.
+    fn tls_established(&self, peer: Peer, addr: &dyn fmt::Display, session: &tls::Session) {
+        let server_name = session.server_name.as_deref().unwrap_or("");
+        let alpn = session.alpn.as_deref().unwrap_or("");
+        self.log_with(
+            log::Level::Info,
+            "tls_established",
+            &[
+                ("stream", peer.name().into()),
+                ("tls_version", session.version.into()),
+                ("cipher_suite", session.cipher_suite.as_str().into()),
+                ("server_name", server_name.into()),
+                ("alpn", alpn.into()),
+            ],
+            format_args!(
+                "TLS established with {} {addr}: {}, {}{}{}",
+                peer.name(),
+                session.version,
+                session.cipher_suite,
+                session
+                    .server_name
+                    .as_ref()
+                    .map_or_else(String::new, |name| format!(", SNI {name}")),
+                session
+                    .alpn
+                    .as_ref()
+                    .map_or_else(String::new, |protocol| format!(", ALPN {protocol}"))
+            ),
+        );
+    }
+
+    /// Log what `--inspect-tls` saw of the handshake relayed in `direction`.
+    fn tls_inspected(&self, direction: Direction, message: &Message) {
+        let marker = direction.marker();
+        match message {
+            Message::ClientHello(hello) => {
+                let server_name = hello.server_name.as_deref().unwrap_or("");
+                let alpn = hello.alpn.join(", ");
+                let versions = hello
+                    .versions
+                    .iter()
+                    .map(|version| inspect::version_name(*version))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let cipher_suites = hello
+                    .cipher_suites
+                    .iter()
+                    .map(|suite| inspect::cipher_suite_name(*suite))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                self.log_with(
+                    log::Level::Info,
+                    "tls_client_hello",
+                    &[
+                        ("direction", direction.name().into()),
+                        ("server_name", server_name.into()),
+                        ("alpn", alpn.as_str().into()),
+                        ("tls_versions", versions.as_str().into()),
+                        ("cipher_suites", cipher_suites.as_str().into()),
+                    ],
+                    format_args!(
+                        "{marker} TLS ClientHello: {}{}, versions {versions}, cipher suites {cipher_suites}",
+                        hello
+                            .server_name
+                            .as_ref()
+                            .map_or("no SNI".to_string(), |name| format!("SNI {name}")),
+                        if alpn.is_empty() {
+                            String::new()
+                        } else {
+                            format!(", ALPN {alpn}")
+                        },
+                    ),
+                );
+            }
+            Message::ServerHello(hello) => {
+                let version = inspect::version_name(hello.version);
+                let cipher_suite = inspect::cipher_suite_name(hello.cipher_suite);
+                let alpn = hello.alpn.as_deref().unwrap_or("");
+                self.log_with(
+                    log::Level::Info,
+                    "tls_server_hello",
+                    &[
+                        ("direction", direction.name().into()),
+                        ("tls_version", version.as_str().into()),
+                        ("cipher_suite", cipher_suite.as_str().into()),
+                        ("alpn", alpn.into()),
+                        ("hello_retry", hello.retry.into()),
+                    ],
+                    format_args!(
+                        "{marker} TLS {}: {version}, {cipher_suite}{}",
+                        if hello.retry {
+                            "HelloRetryRequest"
+                        } else {
+                            "ServerHello"
+                        },
+                        hello
+                            .alpn
+                            .as_ref()
+                            .map_or_else(String::new, |protocol| format!(", ALPN {protocol}"))
+                    ),
+                );
+            }
+            Message::Alert { level, description } => {
+                let alert = inspect::alert_name(*level, *description);
+                self.log_with(
+                    log::Level::Info,
+                    "tls_alert",
+                    &[
+                        ("direction", direction.name().into()),
+                        ("alert", alert.as_str().into()),
+                    ],
+                    format_args!("{marker} TLS alert: {alert}"),
+                );
+            }
+        }
+    }
+
+    /// Log what `--decode http` made of the bytes read in `direction`: a message,
+    /// with the start of its body formatted by `body` for `--decode-body`, or the
+    /// end of the decoding.
+    fn http_decoded(
+        &self,
+        direction: Direction,
+        decoded: &Decoded,
+        body: Option<&PayloadFormatter>,
+    ) {
+        let marker = direction.marker();
+        let message = match decoded {
+            Decoded::Message(message) => message,
+            Decoded::Stopped(reason) => {
+                self.log_with(
+                    log::Level::Info,
+                    "http_decode_stopped",
+                    &[
+                        ("direction", direction.name().into()),
+                        ("reason", reason.as_str().into()),
+                    ],
+                    format_args!("{marker} HTTP decoding stopped: {reason}"),
+                );
+                return;
+            }
+        };
+        let headers: Vec<String> = message
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}"))
+            .collect();
+        let lines = headers.join("\n");
+        let mut size = format!("body {} bytes", message.body_size);
+        if message.chunked {
+            size.push_str(" (chunked)");
+        }
+        let shown = match (body, &message.body) {
+            (Some(formatter), Some(bytes)) if !bytes.is_empty() => {
+                if message.body_size > MAX_BODY_SHOWN as u64 {
+                    size.push_str(&format!(", the first {} shown", bytes.len()));
+                }
+                Some(formatter.format_buffer(bytes))
+            }
+            _ => None,
+        };
+        let mut fields = vec![("direction", log::kv::Value::from(direction.name()))];
+        let (event, start) = match &message.start {
+            StartLine::Request {
+                method,
+                target,
+                version,
+            } => {
+                fields.push(("method", method.as_str().into()));
+                fields.push(("target", target.as_str().into()));
+                fields.push(("version", version.as_str().into()));
+                (
+                    "http_request",
+                    format!("request: {method} {target} {version}"),
+                )
+            }
+            StartLine::Response {
+                version,
+                status,
+                reason,
+            } => {
+                fields.push(("version", version.as_str().into()));
+                fields.push(("status", u64::from(*status).into()));
+                fields.push(("reason", reason.as_str().into()));
+                let line = format!("response: {version} {status} {reason}");
+                ("http_response", line.trim_end().to_string())
+            }
+        };
+        fields.push(("headers", lines.as_str().into()));
+        fields.push(("body_bytes", message.body_size.into()));
+        fields.push(("chunked", message.chunked.into()));
+        let line = format!("{marker} HTTP {start} [{}], {size}", headers.join(", "));
+        match &shown {
+            None => self.log_with(log::Level::Info, event, &fields, format_args!("{line}")),
+            Some(shown) if self.structured => {
+                fields.push(("body", shown.as_str().into()));
+                self.log_with(log::Level::Info, event, &fields, format_args!("{line}"));
+            }
+            // A one-line body goes on the message's line, `hexdump` rows below it.
+            Some(shown) if !shown.contains('\n') => {
+                self.log_with(
+                    log::Level::Info,
+                    event,
+                    &fields,
+                    format_args!("{line}: {shown}"),
+                );
+            }
+            Some(shown) => {
+                self.log_with(log::Level::Info, event, &fields, format_args!("{line}:"));
+                for row in shown.split('\n') {
+                    self.log_with(
+                        log::Level::Info,
+                        event,
+                        &fields,
+                        format_args!("{marker} {row}"),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The key-values of one [`ConnLog`] line: `event`, `route` (for a named route),
+/// `conn_id`, `client`, `destination`, `destination_addr` (once known), then the
+/// line's own extras.
+struct ConnFields<'a> {
+    conn_log: &'a ConnLog,
+    event: &'static str,
+    extra: &'a [(&'static str, log::kv::Value<'a>)],
+}
+
+impl log::kv::Source for ConnFields<'_> {
+    fn visit<'kvs>(
+        &'kvs self,
+        visitor: &mut dyn log::kv::VisitSource<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let conn_log = self.conn_log;
+        visitor.visit_pair("event".into(), self.event.into())?;
+        if let Some(route) = &conn_log.route {
+            visitor.visit_pair("route".into(), route.as_str().into())?;
+        }
+        visitor.visit_pair("conn_id".into(), conn_log.conn_id.into())?;
+        visitor.visit_pair(
+            "client".into(),
+            log::kv::Value::from_display(&conn_log.client),
+        )?;
+        visitor.visit_pair("destination".into(), conn_log.destination.as_str().into())?;
+        if let Some(destination_addr) = conn_log.destination_addr.get() {
+            visitor.visit_pair(
+                "destination_addr".into(),
+                log::kv::Value::from_display(destination_addr),
+            )?;
+        }
+        for (key, value) in self.extra {
+            visitor.visit_pair((*key).into(), value.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// One side of a proxied connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Peer {
+    Client,
+    Destination,
+}
+
+impl Peer {
+    /// The `stream` field of the side's records.
+    fn name(self) -> &'static str {
+        match self {
+            Peer::Client => "client",
+            Peer::Destination => "destination",
+        }
+    }
+}
+
+/// Which way one of a connection's two relays copies bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    /// Bytes read from the client and written to the destination (logged as `<`).
+    ClientToDestination,
+    /// Bytes read from the destination and written back to the client (`>`).
+    DestinationToClient,
+}
+
+impl Direction {
+    /// The `direction` field of the direction's `payload` events.
+    fn name(self) -> &'static str {
+        match self {
+            Direction::ClientToDestination => "client_to_destination",
+            Direction::DestinationToClient => "destination_to_client",
+        }
+    }
+
+    /// The direction's marker on the console and in capture files: that of the
+    /// client stream's record for the same bytes.
+    pub(crate) fn marker(self) -> char {
+        match self {
+            Direction::ClientToDestination => '<',
+            Direction::DestinationToClient => '>',
+        }
+    }
+
+    /// The direction in words, for the `payload` events' message.
+    fn describe(self) -> &'static str {
+        match self {
+            Direction::ClientToDestination => "from client to destination",
+            Direction::DestinationToClient => "from destination to client",
+        }
+    }
+
+    /// The side the direction's bytes are read from.
+    fn reader(self) -> Peer {
+        match self {
+            Direction::ClientToDestination => Peer::Client,
+            Direction::DestinationToClient => Peer::Destination,
+        }
+    }
+
+    /// The opposite direction of the same connection.
+    fn reverse(self) -> Self {
+        match self {
+            Direction::ClientToDestination => Direction::DestinationToClient,
+            Direction::DestinationToClient => Direction::ClientToDestination,
+        }
+    }
+}
+
+/// The [`Logger`] behind a connection's `LoggedStream`s. It renders a record exactly
+/// as `logged-stream`'s `ConsoleLogger` does — `{tag}{kind} {message}`, at `debug`
+/// except for `Error` records, which always log at `error` — with one difference: a
+/// multi-line message (a `--formatting hexdump` block) is printed one console line
+/// per row, and every row repeats the tag and the direction marker, so each line of
+/// the output stays attributable and greppable on its own.
+///
+/// Each record is also an event of the `peer` stream: the client stream's
+/// `Shutdown` record, for instance, is a `half_close` of the client connection.
+struct ConnStreamLogger {
+    conn_log: ConnLog,
+    peer: Peer,
+}
+
+impl Logger for ConnStreamLogger {
+    fn log(&mut self, record: Record) {
+        let (level, event) = match record.kind {
+            RecordKind::Error => (log::Level::Error, "stream_error"),
+            RecordKind::Shutdown => (log::Level::Debug, "half_close"),
+            RecordKind::Drop => (log::Level::Debug, "stream_closed"),
+            RecordKind::Open => (log::Level::Debug, "stream_opened"),
+            RecordKind::Read | RecordKind::Write => (log::Level::Debug, "payload"),
+        };
+        let extra = [("stream", log::kv::Value::from(self.peer.name()))];
+        if self.conn_log.structured {
+            // One object per record, whatever the message holds.
+            self.conn_log
+                .log_with(level, event, &extra, format_args!("{}", record.message));
+            return;
+        }
+        // `split` rather than `lines`: an empty message still yields one (empty)
+        // line, as it did with `ConsoleLogger`.
+        for line in record.message.split('\n') {
+            self.conn_log
+                .log_with(level, event, &extra, format_args!("{} {line}", record.kind));
+        }
+    }
+}
+
+/// How a client's TLS handshake ([`client_handshake`]) ended.
+enum ClientHandshake {
+    /// Completed. With `--tls-mitm-ca`, the destination was dialed on the way,
+    /// and is handed over with the time it took to connect.
+    Done(
+        MaybeTls<tokio_net::TcpStream>,
+        Option<(MaybeTls<tokio_net::TcpStream>, Duration)>,
+    ),
+    /// The client failed it, or did not complete it in time.
+    Failed(io::Error),
+    /// `--tls-mitm-ca`: the destination the handshake waited for could not be
+    /// connected to.
+    Unreachable(ConnectError),
+}
+
+/// Complete the client's handshake on `stream`: with the `--tls-cert`
+/// certificate, or with one minted for the name the client asks for
+/// (`--tls-mitm-ca`), once the destination has been dialed with that name and
+/// the client's ALPN protocols, and has picked one of them.
+async fn client_handshake(
+    sinks: &Sinks,
+    target: &TargetAddr,
+    stream: tokio_net::TcpStream,
+) -> ClientHandshake {
+    let Some(mint) = &sinks.mint else {
+        return match &sinks.tls {
+            Some(acceptor) => match tls::accept(acceptor, stream).await {
+                Ok(stream) => ClientHandshake::Done(stream, None),
+                Err(error) => ClientHandshake::Failed(error),
+            },
+            None => ClientHandshake::Done(MaybeTls::Plain(stream), None),
+        };
+    };
+    let dialed_addr = match stream.local_addr() {
+        Ok(addr) => addr.ip(),
+        Err(error) => return ClientHandshake::Failed(error),
+    };
+    let hello = match Hello::read(stream).await {
+        Ok(hello) => hello,
+        Err(error) => return ClientHandshake::Failed(error),
+    };
+    let remote_tls = sinks
+        .remote_tls
+        .as_ref()
+        .map(|remote_tls| remote_tls.for_hello(&hello));
+    let connecting = Instant::now();
+    let destination = match connect_to_target(target, remote_tls.as_ref()).await {
+        Ok(destination) => destination,
+        Err(error) => return ClientHandshake::Unreachable(error),
+    };
+    let connect_latency = connecting.elapsed();
+    let alpn = destination.session().and_then(|session| session.alpn);
+    match hello.complete(mint, dialed_addr, alpn.as_deref()).await {
+        Ok(stream) => ClientHandshake::Done(stream, Some((destination, connect_latency))),
+        Err(error) => ClientHandshake::Failed(error),
+    }
+}
+
+/// Pick the destination of a connection by the `--sni-route`s, from what its
+/// client's first bytes said, falling back on the listener's `default`
+/// destination, and log the choice. Otherwise the reason the connection ends, for
+/// its capture file, logged too, and whether the client sent a TLS hello, to be
+/// answered with an alert.
+fn sni_destination(
+    conn_log: &ConnLog,
+    routes: &[SniRoute],
+    default: Option<&TargetAddr>,
+    peeked: io::Result<Peeked>,
+) -> Result<TargetAddr, (String, bool)> {
+    let (server_name, hello) = match &peeked {
+        Ok(Peeked::Name(name)) => (Some(name.as_str()), true),
+        Ok(Peeked::NoName) => (None, true),
+        _ => (None, false),
+    };
+    let fields = [("server_name", server_name.unwrap_or_default().into())];
+    if let Some(name) = server_name {
+        if let Some(target) = sni::pick(routes, name) {
+            conn_log.log_with(
+                log::Level::Info,
+                "sni_routed",
+                &fields,
+                format_args!("Server name {name} routed to {target}"),
+            );
+            return Ok(target.clone());
+        }
+    }
+    let unrouted = match &peeked {
+        Ok(Peeked::Name(name)) => format!("server name {name} matches no SNI route"),
+        Ok(Peeked::NoName) => "the client's hello asks for no server name".to_string(),
+        Ok(Peeked::NotHello) => "the client sent no TLS hello".to_string(),
+        Ok(Peeked::Closed) => "closed by the client before its TLS hello".to_string(),
+        Ok(Peeked::Silent) => format!(
+            "the client sent nothing within {}s",
+            sni::SILENCE_TIMEOUT.as_secs()
+        ),
+        Err(error) => format!("the client's hello did not arrive: {error}"),
+    };
+    let reason = match (&peeked, default) {
+        // A client that went away has nothing to relay, and is no routing failure:
+        // a load balancer's TCP health check hangs up just so.
+        (Ok(Peeked::Closed), _) => {
+            conn_log.info(
+                "closed_before_hello",
+                format_args!("Connection from {} {unrouted}", conn_log.client),
+            );
+            return Err((unrouted, false));
+        }
+        // The default destination takes whatever is not TLS, a protocol in which
+        // the server speaks first included.
+        (_, Some(default)) => {
+            conn_log.log_with(
+                log::Level::Info,
+                "sni_routed",
+                &fields,
+                format_args!("Routing to the default destination {default}: {unrouted}"),
+            );
+            return Ok(default.clone());
+        }
+        (_, None) => format!("{unrouted}, and there is no default destination"),
+    };
+    conn_log.log_with(
+        log::Level::Error,
+        "sni_rejected",
+        &fields,
+        format_args!("Rejecting connection from {}: {reason}", conn_log.client),
+    );
+    Err((format!("rejected: {reason}"), hello))
+}
+
+/// Report a failed [`connect_to_target`]: counted, logged, and the capture file
+/// closed with it as the reason.
+fn connect_failed(
+    conn_log: &ConnLog,
+    metrics: &RouteMetrics,
+    capture: Option<&ConnCapture>,
+    remote_addr: &TargetAddr,
+    error: &ConnectError,
+) {
+    match error {
+        ConnectError::Resolve(_) => metrics.dns_failed(),
+        ConnectError::Connect(error) => metrics.connect_failed(error),
+        ConnectError::Tls(_) => metrics.destination_tls_failed(),
     }
+    if let Some(capture) = capture {
+        capture.close(format!("failed to connect to the destination: {error}"));
+    }
+    conn_log.error(
+        "connect_failed",
+        format_args!("Failed to connect to destination {remote_addr}: {error}"),
+    );
 }
 
 async fn incoming_connection_handle(
-    arguments: Arguments,
+    RouteConfig { arguments, route }: RouteConfig,
+    sinks: Sinks,
     source_stream: tokio_net::TcpStream,
-    conn_log: ConnLog,
-    client_addr: SocketAddr,
+    mut conn_log: ConnLog,
+    live: &LiveConnection,
+    shutdown: Shutdown,
+    metrics: &RouteMetrics,
 ) {
+    let accepted = Instant::now();
+    let conn_id = conn_log.conn_id;
+    let client_addr = conn_log.client;
+    // The capture file starts at accept time, so a connection whose destination
+    // cannot be reached still gets one, closed with the connect error as its reason.
+    let capture = sinks.capture.as_ref().and_then(|directory| {
+        let formatter = get_formatter_by_kind(
+            arguments.formatting,
+            arguments.separator.as_str(),
+            arguments.hexdump_width as usize,
+        );
+        match directory.connection(conn_id, client_addr, &route.destination(), formatter) {
+            Ok(capture) => Some(capture),
+            Err(error) => {
+                conn_log.error(
+                    "capture_failed",
+                    format_args!("Failed to create the connection's capture file: {error}"),
+                );
+                None
+            }
+        }
+    });
+    // With `--output-format jsonl`, or when re-chunking or a `--fault` makes the
+    // chunks written differ from those read, the relays log the payload chunks
+    // themselves (see `RelayTaps::relayed`), so the client stream keeps only its
+    // other records.
+    let relays_log_payload =
+        conn_log.structured || netem::rechunks(&arguments) || !arguments.faults.is_empty();
+    // Without Nagle's algorithm, which would merge the fragments again.
+    if arguments.fragment.is_some() {
+        let _ = source_stream.set_nodelay(true);
+    }
+    let source_filter: Box<dyn RecordFilter> = if relays_log_payload {
+        Box::new(RecordKindFilter::new(&[
+            RecordKind::Open,
+            RecordKind::Drop,
+            RecordKind::Error,
+            RecordKind::Shutdown,
+        ]))
+    } else {
+        Box::new(DefaultFilter)
+    };
+    // Both sockets close with a reset instead of a FIN once a `--fault` asks for it.
+    let reset = Reset::default();
+    // With `--sni-route`s the destination is picked by the server name of the
+    // client's hello, which is only peeked at: it is still there to be relayed, or
+    // to start the handshake of `--tls-cert` or `--tls-mitm-ca`.
+    let target = match &route.remote_addr {
+        Some(remote_addr) if arguments.sni_routes.is_empty() => remote_addr.clone(),
+        default => {
+            let peeked = tokio::select! {
+                peeked = sni::peek_server_name(&source_stream, default.is_some()) => peeked,
+                interrupt = Interrupt::wait(&shutdown, live) => {
+                    if let Some(capture) = &capture {
+                        capture.close(interrupt.reason().to_string());
+                    }
+                    conn_log.info(
+                        interrupt.event(),
+                        format_args!(
+                            "Forcibly closing connection from {client_addr} {}, before its TLS hello arrived",
+                            interrupt.occasion()
+                        ),
+                    );
+                    return;
+                }
+            };
+            match sni_destination(&conn_log, &arguments.sni_routes, default.as_ref(), peeked) {
+                Ok(target) => {
+                    conn_log.destination = target.to_string();
+                    target
+                }
+                Err((reason, alert)) => {
+                    if let Some(capture) = &capture {
+                        capture.close(reason);
+                    }
+                    if alert {
+                        sni::turn_away(source_stream).await;
+                    }
+                    // Returning drops the stream, closing the client connection.
+                    return;
+                }
+            }
+        }
+    };
+    // With `--tls-cert` the client's handshake comes first, before the destination
+    // is dialed: a client that fails it is never relayed anywhere. With
+    // `--tls-mitm-ca` the destination is dialed in the middle of it, once the
+    // client's hello has said which name and protocols to offer it.
+    let mut dialed = None;
+    let source_stream = if sinks.tls.is_none() && sinks.mint.is_none() {
+        MaybeTls::Plain(source_stream)
+    } else {
+        let handshake = tokio::select! {
+            handshake = client_handshake(&sinks, &target, source_stream) => handshake,
+            interrupt = Interrupt::wait(&shutdown, live) => {
+                if let Some(capture) = &capture {
+                    capture.close(interrupt.reason().to_string());
+                }
+                conn_log.info(
+                    interrupt.event(),
+                    format_args!(
+                        "Forcibly closing connection from {client_addr} {}, during the TLS handshake",
+                        interrupt.occasion()
+                    ),
+                );
+                return;
+            }
+        };
+        match handshake {
+            ClientHandshake::Done(stream, destination) => {
+                if let Some(session) = stream.session() {
+                    conn_log.tls_established(Peer::Client, &client_addr, &session);
+                }
+                dialed = destination;
+                stream
+            }
+            ClientHandshake::Failed(error) => {
+                metrics.client_tls_failed();
+                if let Some(capture) = &capture {
+                    capture.close(format!("TLS handshake failed: {error}"));
+                }
+                conn_log.error(
+                    "tls_handshake_failed",
+                    format_args!("TLS handshake with client {client_addr} failed: {error}"),
+                );
+                // Returning drops the stream, closing the client connection.
+                return;
+            }
+            ClientHandshake::Unreachable(error) => {
+                connect_failed(&conn_log, metrics, capture.as_ref(), &target, &error);
+                return;
+            }
+        }
+    };
     let (source_stream_read_half, source_stream_write_half) = io::split(LoggedStream::new(
-        source_stream,
-        get_formatter_by_kind(arguments.formatting, arguments.separator.as_str()),
-        DefaultFilter,
-        ConsoleLogger::new_unchecked("debug").with_prefix(conn_log.prefix().to_string()),
+        reset.guard(source_stream),
+        get_formatter_by_kind(
+            arguments.formatting,
+            arguments.separator.as_str(),
+            arguments.hexdump_width as usize,
+        ),
+        source_filter,
+        conn_log.stream_logger(Peer::Client),
     ));
-    let destination_stream = match connect_to_target(&arguments.remote_addr).await {
+    // A shutdown that runs out of grace, or a close through the admin API, does not
+    // wait for a slow connect either.
+    let (connected, connect_latency) = match dialed {
+        Some((stream, latency)) => (Ok(stream), latency),
+        None => {
+            let connecting = Instant::now();
+            let connected = tokio::select! {
+                connected = connect_to_target(&target, sinks.remote_tls.as_ref()) => connected,
+                interrupt = Interrupt::wait(&shutdown, live) => {
+                    if let Some(capture) = &capture {
+                        capture.close(interrupt.reason().to_string());
+                    }
+                    conn_log.info(
+                        interrupt.event(),
+                        format_args!(
+                            "Forcibly closing connection from {client_addr} {}, before the destination answered",
+                            interrupt.occasion()
+                        ),
+                    );
+                    return;
+                }
+            };
+            (connected, connecting.elapsed())
+        }
+    };
+    let destination_stream = match connected {
         Ok(stream) => stream,
         Err(error) => {
-            conn_log.error(format_args!(
-                "Failed to connect to destination {}: {error}",
-                arguments.remote_addr
-            ));
+            connect_failed(&conn_log, metrics, capture.as_ref(), &target, &error);
             // Returning drops the source halves, closing the client connection.
             return;
         }
     };
+    // The address actually reached: for a literal target it is the target itself,
+    // for a hostname whichever resolved record accepted the connection. `None` only
+    // if `peer_addr()` fails for a named target, which is best-effort everywhere it
+    // is used.
+    let destination_addr = match &target {
+        TargetAddr::Socket(addr) => Some(*addr),
+        TargetAddr::Named { .. } => destination_stream.get_ref().peer_addr().ok(),
+    };
+    if let Some(destination_addr) = destination_addr {
+        let _ = conn_log.destination_addr.set(destination_addr);
+        live.connected(destination_addr);
+        if let Some(capture) = &capture {
+            capture.connected(destination_addr);
+        }
+    }
+    // Without Nagle's algorithm, as for the client stream.
+    if arguments.fragment.is_some() {
+        let _ = destination_stream.get_ref().set_nodelay(true);
+    }
     // For a hostname target, report that the connection was established, appending
     // which resolved address was actually reached when that is available (useful when
     // a name has several records or sits behind DNS-based failover). The `peer_addr()`
     // detail is best-effort: the line is always logged, so a rare `peer_addr()` failure
     // never silently swallows it. (A literal `IP:port` target would just repeat itself,
-    // so it is left out.)
-    if let TargetAddr::Named { .. } = &arguments.remote_addr {
-        let peer_suffix = destination_stream
-            .peer_addr()
+    // so it is left out of the text output; as a JSON event it still marks the moment
+    // the destination answered.)
+    if matches!(target, TargetAddr::Named { .. }) || conn_log.structured {
+        let peer_suffix = destination_addr
+            .filter(|_| matches!(target, TargetAddr::Named { .. }))
             .map(|peer| format!(" ({peer})"))
             .unwrap_or_default();
-        conn_log.info(format_args!(
-            "Connected to destination {}{peer_suffix}",
-            arguments.remote_addr
-        ));
+        conn_log.info(
+            "connected",
+            format_args!("Connected to destination {}{peer_suffix}", target),
+        );
+    }
+    if let Some(session) = destination_stream.session() {
+        conn_log.tls_established(Peer::Destination, &target, &session);
     }
     // The destination stream carries the same `[#N] ` prefix as the source stream:
     // its Drop/Error/Shutdown records are the connection's lines too, and without
     // the shared tag they would be unattributable.
     let (destination_stream_read_half, destination_stream_write_half) =
         io::split(LoggedStream::new(
-            destination_stream,
-            get_formatter_by_kind(arguments.formatting, arguments.separator.as_str()),
+            reset.guard(destination_stream),
+            get_formatter_by_kind(
+                arguments.formatting,
+                arguments.separator.as_str(),
+                arguments.hexdump_width as usize,
+            ),
             RecordKindFilter::new(&[RecordKind::Drop, RecordKind::Error, RecordKind::Shutdown]),
-            ConsoleLogger::new_unchecked("debug").with_prefix(conn_log.prefix().to_string()),
+            conn_log.stream_logger(Peer::Destination),
         ));
+    // The idle timeout, and the faults triggered by time, count from here: waiting
+    // for the destination to answer is not inactivity.
+    live.activity.record();
+    let relaying = Instant::now();
+    let random = |direction| SplitMix64::derive(sinks.seed, conn_id, direction, Draw::Faults);
+    let taps = RelayTaps {
+        live,
+        metrics,
+        first_end: OnceLock::new(),
+        log: &conn_log,
+        payload_formatter: relays_log_payload.then(|| {
+            get_formatter_by_kind(
+                arguments.formatting,
+                arguments.separator.as_str(),
+                arguments.hexdump_width as usize,
+            )
+        }),
+        pcap: sinks.pcap.as_ref().and_then(|writer| {
+            let destination_addr = destination_addr?;
+            Some(writer.connection(conn_id, client_addr, destination_addr))
+        }),
+        capture,
+        tui: sinks.tui.as_ref(),
+        reset: &reset,
+        inspection: arguments.inspect_tls.then(Inspection::new),
+        decoder: arguments
+            .decode
+            .map(|DecodeProtocol::Http| Mutex::new(HttpDecoder::new(arguments.decode_body))),
+        body_formatter: arguments.decode_body.then(|| {
+            get_formatter_by_kind(
+                arguments.formatting,
+                arguments.separator.as_str(),
+                arguments.hexdump_width as usize,
+            )
+        }),
+    };
 
     // Relay both directions concurrently, running each to completion. As each
     // direction ends (end-of-stream or a read/write error) it shuts down its
     // writer, forwarding the close to that peer; the other direction keeps relaying
     // until it ends too, so data still in flight is delivered rather than dropped
     // (this correctly handles a peer that half-closes while a response is pending).
-    //
+    let relays = async {
+        tokio::join!(
+            relay(
+                source_stream_read_half,
+                destination_stream_write_half,
+                Direction::ClientToDestination,
+                Impairments::new(
+                    &arguments,
+                    Direction::ClientToDestination,
+                    sinks.global_rate.as_ref(),
+                    sinks.seed,
+                    conn_id,
+                ),
+                DirectionFaults::new(
+                    &arguments.faults,
+                    Direction::ClientToDestination,
+                    relaying,
+                    random(Direction::ClientToDestination),
+                ),
+                &taps,
+            ),
+            relay(
+                destination_stream_read_half,
+                source_stream_write_half,
+                Direction::DestinationToClient,
+                Impairments::new(
+                    &arguments,
+                    Direction::DestinationToClient,
+                    sinks.global_rate.as_ref(),
+                    sinks.seed,
+                    conn_id,
+                ),
+                DirectionFaults::new(
+                    &arguments.faults,
+                    Direction::DestinationToClient,
+                    relaying,
+                    random(Direction::DestinationToClient),
+                ),
+                &taps,
+            ),
+        );
+    };
+
     // When `--timeout` is set, a single idle-timeout watchdog runs alongside the
     // relays and tears the connection down once *both* directions have been silent
     // for the timeout. Activity in either direction resets it (via the shared
     // `ActivityClock`), so an actively-transferring one-directional connection is
     // never interrupted.
-    match arguments.timeout {
-        None => {
-            tokio::join!(
-                relay(source_stream_read_half, destination_stream_write_half, None),
-                relay(destination_stream_read_half, source_stream_write_half, None),
+    let connection = async {
+        match arguments.timeout {
+            None => {
+                relays.await;
+                Stop::Relays
+            }
+            Some(seconds) => {
+                let idle = Duration::from_secs(seconds);
+                // The idle-close line is logged *inside* the winning branch's future,
+                // not in the arm handler: `select!` drops the losing `relays` future —
+                // closing the sockets and sending the FIN — before an arm handler
+                // would run, so logging in the handler could let a peer observe the
+                // close before the line exists. Logging first also orders the line
+                // before the streams' shutdown/drop records.
+                tokio::select! {
+                    () = relays => Stop::Relays,
+                    () = async {
+                        wait_until_idle(&taps.live.activity, idle).await;
+                        metrics.idle_timeout();
+                        if let Some(capture) = &taps.capture {
+                            capture.close(format!("idle for {seconds}s"));
+                        }
+                        // The client address makes the line self-correlating even where
+                        // the `[#N]` tag is absent (`--no-connection-ids`) or ambiguous
+                        // (ids restart at 1 for every proxy run).
+                        conn_log.info(
+                            "idle_close",
+                            format_args!(
+                                "Closing idle connection from {client_addr} after {seconds}s of inactivity"
+                            ),
+                        );
+                    } => Stop::IdleTimeout,
+                }
+            }
+        }
+    };
+    // A `reset` fault aborts the connection: one triggered by time from here, one
+    // triggered by a byte count through the relay that reached it.
+    let resetting = async {
+        match fault::reset_at(&arguments.faults, relaying) {
+            Some((at, fault)) => tokio::select! {
+                () = reset.requested() => {}
+                () = sleep_until(at) => taps.reset(None, &fault),
+            },
+            None => reset.requested().await,
+        }
+        if let Some(capture) = &taps.capture {
+            capture.close("reset by a fault".to_string());
+        }
+    };
+    // A shutdown that runs out of grace, or the admin API, closes the connection,
+    // logging it first for the same reason as the idle close above.
+    let stop = tokio::select! {
+        stop = connection => stop,
+        () = resetting => Stop::Reset,
+        stop = async {
+            let interrupt = Interrupt::wait(&shutdown, live).await;
+            if let Some(capture) = &taps.capture {
+                capture.close(interrupt.reason().to_string());
+            }
+            conn_log.info(
+                interrupt.event(),
+                format_args!(
+                    "Forcibly closing connection from {client_addr} {} after {} bytes from the client and {} bytes from the destination",
+                    interrupt.occasion(),
+                    live.traffic.bytes(Direction::ClientToDestination),
+                    live.traffic.bytes(Direction::DestinationToClient),
+                ),
             );
+            match interrupt {
+                Interrupt::Shutdown => Stop::Shutdown,
+                Interrupt::Closed => Stop::Closed,
+            }
+        } => stop,
+    };
+    // Logged once the relays are gone, so it is the connection's last line.
+    taps.summarize(client_addr, stop, accepted.elapsed(), connect_latency);
+}
+
+/// What stopped a connection's relays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    /// Both directions ended.
+    Relays,
+    /// `--timeout` elapsed with neither direction moving data.
+    IdleTimeout,
+    /// The shutdown's grace period ran out.
+    Shutdown,
+    /// `DELETE /connections/<id>` on the admin API.
+    Closed,
+    /// A `--fault` reset it.
+    Reset,
+}
+
+/// What closes a connection from outside its relays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interrupt {
+    /// The shutdown's grace period ran out.
+    Shutdown,
+    /// The admin API closed it.
+    Closed,
+}
+
+impl Interrupt {
+    /// Resolves with whichever comes first.
+    async fn wait(shutdown: &Shutdown, live: &LiveConnection) -> Self {
+        tokio::select! {
+            () = shutdown.forced() => Self::Shutdown,
+            () = live.closed() => Self::Closed,
         }
-        Some(seconds) => {
-            let idle = Duration::from_secs(seconds);
-            let clock = ActivityClock::new();
-            let relays = async {
-                tokio::join!(
-                    relay(
-                        source_stream_read_half,
-                        destination_stream_write_half,
-                        Some(&clock),
-                    ),
-                    relay(
-                        destination_stream_read_half,
-                        source_stream_write_half,
-                        Some(&clock),
-                    ),
+    }
+
+    /// The closing line's `event`.
+    fn event(self) -> &'static str {
+        match self {
+            Self::Shutdown => "forced_close",
+            Self::Closed => "admin_close",
+        }
+    }
+
+    /// When, in the closing line: "Forcibly closing connection from <client> ...".
+    fn occasion(self) -> &'static str {
+        match self {
+            Self::Shutdown => "at shutdown",
+            Self::Closed => "on request of the admin API",
+        }
+    }
+
+    /// What the connection's capture file gives as the close reason.
+    fn reason(self) -> &'static str {
+        match self {
+            Self::Shutdown => "closed by the proxy's shutdown",
+            Self::Closed => "closed through the admin API",
+        }
+    }
+}
+
+/// What a connection's relays report as they move data: the shared activity clock
+/// behind the idle timeout, the traffic counts and which relay ended first (for the
+/// closing summary), the optional `--pcap` conversation, `--capture-dir` file and
+/// `--tui` payload pane, the `--inspect-tls` handshake lines and, with
+/// `--output-format jsonl`, a re-chunking option or a `--fault`, the `payload`
+/// events. One instance is shared by both directions of a connection, by
+/// reference, so everything in it is updated through `&self`.
+struct RelayTaps<'a> {
+    /// The connection's entry in the admin API's registry, which keeps its
+    /// activity clock and traffic counts.
+    live: &'a LiveConnection,
+    /// The route's `--metrics-addr` counters.
+    metrics: &'a RouteMetrics,
+    /// The first relay to stop, and why: what ended the connection.
+    first_end: OnceLock<(Direction, RelayEnd)>,
+    log: &'a ConnLog,
+    /// Encodes the `payload` events' chunks; only set with `--output-format jsonl`,
+    /// `--fragment`, `--coalesce` or a `--fault`.
+    payload_formatter: Option<PayloadFormatter>,
+    pcap: Option<PcapConnection>,
+    capture: Option<ConnCapture>,
+    /// The `--tui` view's payload pane.
+    tui: Option<&'a tui::Feed>,
+    /// Asked for by a `reset` fault.
+    reset: &'a Reset,
+    /// The `--inspect-tls` parsers.
+    inspection: Option<Inspection>,
+    /// The `--decode http` parser of both directions.
+    decoder: Option<Mutex<HttpDecoder>>,
+    /// Formats the bodies `--decode-body` shows.
+    body_formatter: Option<PayloadFormatter>,
+}
+
+/// How one relay direction ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RelayEnd {
+    /// The reader reached end-of-stream: its peer closed (or half-closed).
+    Closed,
+    /// Reading from the reader's peer failed.
+    ReadFailed,
+    /// Writing to the writer's peer failed.
+    WriteFailed,
+    /// A `truncate` fault cut the direction short.
+    Truncated,
+}
+
+impl RelayTaps<'_> {
+    /// The relay moved data: a chunk was read (see [`Self::read`]), or a slice of
+    /// a rate-limited one was written.
+    fn active(&self) {
+        self.live.activity.record();
+    }
+
+    /// A chunk was read in `direction`: activity, and the next bytes for
+    /// `--inspect-tls` and `--decode` to look at.
+    fn read(&self, direction: Direction, chunk: &[u8]) {
+        self.active();
+        if let Some(inspection) = &self.inspection {
+            for message in inspection.feed(direction, chunk) {
+                self.log.tls_inspected(direction, &message);
+            }
+        }
+        if let Some(decoder) = &self.decoder {
+            let decoded = decoder
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .feed(direction, chunk);
+            for decoded in decoded {
+                self.log
+                    .http_decoded(direction, &decoded, self.body_formatter.as_ref());
+            }
+        }
+    }
+
+    /// A chunk was written on in `direction`.
+    fn relayed(&self, direction: Direction, payload: &[u8]) {
+        self.live.traffic.add(direction, payload.len());
+        self.metrics.relayed(direction, payload.len());
+        if let Some(formatter) = &self.payload_formatter {
+            if self.log.structured {
+                self.log.log_with(
+                    log::Level::Debug,
+                    "payload",
+                    &[
+                        ("direction", direction.name().into()),
+                        ("bytes", (payload.len() as u64).into()),
+                        (
+                            "payload",
+                            log::kv::Value::from_display(&formatter.format_buffer(payload)),
+                        ),
+                    ],
+                    format_args!("Relayed {} bytes {}", payload.len(), direction.describe()),
                 );
-            };
-            // The idle-close line is logged *inside* the winning branch's future,
-            // not in the arm handler: `select!` drops the losing `relays` future —
-            // closing the sockets and sending the FIN — before an arm handler
-            // would run, so logging in the handler could let a peer observe the
-            // close before the line exists. Logging first also orders the line
-            // before the streams' shutdown/drop records.
-            tokio::select! {
-                _ = relays => {}
-                _ = async {
-                    wait_until_idle(&clock, idle).await;
-                    // The client address makes the line self-correlating even where
-                    // the `[#N]` tag is absent (`--no-connection-ids`) or ambiguous
-                    // (ids restart at 1 for every proxy run).
-                    conn_log.info(format_args!(
-                        "Closing idle connection from {client_addr} after {seconds}s of inactivity"
-                    ));
-                } => {}
+            } else {
+                // The client stream's `payload` lines, as its logger would print them
+                // had the chunk been read from or written to the client in one go.
+                let extra = [("stream", log::kv::Value::from(Peer::Client.name()))];
+                for row in formatter.format_buffer(payload).split('\n') {
+                    self.log.log_with(
+                        log::Level::Debug,
+                        "payload",
+                        &extra,
+                        format_args!("{} {row}", direction.marker()),
+                    );
+                }
+            }
+        }
+        if let Some(pcap) = &self.pcap {
+            pcap.data(direction, payload);
+        }
+        if let Some(capture) = &self.capture {
+            capture.data(direction, payload);
+        }
+        if let Some(feed) = self.tui {
+            feed.payload(self.log.conn_id, direction, payload);
+        }
+    }
+
+    /// The relay in `direction` stopped, for reason `end`.
+    fn ended(&self, direction: Direction, end: RelayEnd) {
+        let _ = self.first_end.set((direction, end));
+        if let (Some(decoder), RelayEnd::Closed) = (&self.decoder, end) {
+            let decoded = decoder
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .finish(direction);
+            for decoded in decoded {
+                self.log
+                    .http_decoded(direction, &decoded, self.body_formatter.as_ref());
+            }
+        }
+        if let Some(capture) = &self.capture {
+            capture.ended(direction, end);
+        }
+        if let Some(pcap) = &self.pcap {
+            match end {
+                RelayEnd::Closed => pcap.fin(direction),
+                RelayEnd::ReadFailed => pcap.reset(direction),
+                // The peer being written to is the one that went away.
+                RelayEnd::WriteFailed => pcap.reset(direction.reverse()),
+                RelayEnd::Truncated => pcap.fin(direction),
             }
         }
     }
+
+    /// A `--fault` tampered with the traffic in `direction` (`None`: the whole
+    /// connection), as `what` tells: logged on the connection and in its capture
+    /// file, so that what was tampered with is on record.
+    fn fault(&self, direction: Option<Direction>, fault: &Fault, what: fmt::Arguments<'_>) {
+        let mut fields = vec![("fault", log::kv::Value::from_display(fault))];
+        if let Some(direction) = direction {
+            fields.push(("direction", direction.name().into()));
+        }
+        self.log.log_with(
+            log::Level::Info,
+            "fault",
+            &fields,
+            format_args!("Injected fault {fault}: {what}"),
+        );
+        if let Some(capture) = &self.capture {
+            capture.fault(format_args!("fault {fault}: {what}"));
+        }
+    }
+
+    /// A `reset` fault fired: log it and have the connection reset.
+    fn reset(&self, direction: Option<Direction>, fault: &Fault) {
+        let traffic = &self.live.traffic;
+        self.fault(
+            direction,
+            fault,
+            format_args!(
+                "resetting the connection after {} bytes from the client and {} bytes from the destination",
+                traffic.bytes(Direction::ClientToDestination),
+                traffic.bytes(Direction::DestinationToClient),
+            ),
+        );
+        if let Some(pcap) = &self.pcap {
+            pcap.reset(Direction::ClientToDestination);
+            pcap.reset(Direction::DestinationToClient);
+        }
+        self.reset.request();
+    }
+
+    /// Log the connection's closing summary: how long it lasted, what it relayed
+    /// each way, what ended it and how long the destination took to answer.
+    fn summarize(
+        &self,
+        client_addr: SocketAddr,
+        stop: Stop,
+        duration: Duration,
+        connect_latency: Duration,
+    ) {
+        let first_end = self.first_end.get().copied();
+        let closed_first = first_end.and_then(|(direction, end)| match end {
+            RelayEnd::Closed | RelayEnd::ReadFailed => Some(direction.reader()),
+            // The peer being written to is the one that went away.
+            RelayEnd::WriteFailed => Some(direction.reverse().reader()),
+            // Neither peer: the proxy did.
+            RelayEnd::Truncated => None,
+        });
+        let mut ending: Vec<String> = first_end
+            .map(|(direction, end)| match end {
+                RelayEnd::Closed => format!("the {} closed first", direction.reader().name()),
+                RelayEnd::ReadFailed => {
+                    format!(
+                        "reading from the {} failed first",
+                        direction.reader().name()
+                    )
+                }
+                RelayEnd::WriteFailed => format!(
+                    "writing to the {} failed first",
+                    direction.reverse().reader().name()
+                ),
+                RelayEnd::Truncated => format!(
+                    "a fault truncated the stream from the {} first",
+                    direction.reader().name()
+                ),
+            })
+            .into_iter()
+            .collect();
+        match stop {
+            Stop::Relays => {}
+            Stop::IdleTimeout => ending.push("the idle timeout fired".to_string()),
+            Stop::Shutdown => ending.push("the shutdown closed it".to_string()),
+            Stop::Closed => ending.push("the admin API closed it".to_string()),
+            Stop::Reset => ending.push("a fault reset it".to_string()),
+        }
+        let traffic = &self.live.traffic;
+        let counts = |direction| (traffic.bytes(direction), traffic.chunks(direction));
+        let (client_bytes, client_chunks) = counts(Direction::ClientToDestination);
+        let (destination_bytes, destination_chunks) = counts(Direction::DestinationToClient);
+        let mut fields = vec![
+            ("duration_us", (duration.as_micros() as u64).into()),
+            ("connect_us", (connect_latency.as_micros() as u64).into()),
+            ("client_bytes", client_bytes.into()),
+            ("client_chunks", client_chunks.into()),
+            ("destination_bytes", destination_bytes.into()),
+            ("destination_chunks", destination_chunks.into()),
+            ("idle_timeout", (stop == Stop::IdleTimeout).into()),
+            ("shutdown", (stop == Stop::Shutdown).into()),
+            ("admin_close", (stop == Stop::Closed).into()),
+            ("fault_reset", (stop == Stop::Reset).into()),
+        ];
+        if let Some(peer) = closed_first {
+            fields.push(("closed_first", peer.name().into()));
+        }
+        self.log.log_with(
+            log::Level::Info,
+            "closed",
+            &fields,
+            format_args!(
+                "Connection from {client_addr} closed after {:.3}s ({}): {client_bytes} bytes in {client_chunks} chunk(s) from the client, {destination_bytes} bytes in {destination_chunks} chunk(s) from the destination, connected in {:.1}ms",
+                duration.as_secs_f64(),
+                ending.join(", then "),
+                connect_latency.as_secs_f64() * 1000.0,
+            ),
+        );
+    }
 }
 
 /// Shared "last activity" clock for a connection's idle timeout. It records the
 /// most recent moment either direction relayed data, as milliseconds since the
-/// connection started; interior mutability lets both relay directions update it
-/// through a shared reference.
-struct ActivityClock {
+/// connection was accepted; interior mutability lets both relay directions update
+/// it through a shared reference. It is kept for every connection, whether or not
+/// `--timeout` is set — a single relaxed store per chunk — and the admin API reports
+/// it as the connection's idle time.
+pub(crate) struct ActivityClock {
     started: Instant,
     // `Relaxed` is deliberate. The relays and the watchdog that touch this are
     // cooperatively-scheduled sub-futures of a *single* task (composed with
     // `join!`/`select!`, not separate spawns — note they borrow `&self`), so they
-    // never access it from two threads at once. It is also a self-contained
-    // timestamp that guards no other memory, so there is nothing for Acquire/Release
-    // to publish; single-location coherence is the whole requirement, and the
-    // watchdog re-reads after sleeping whole seconds, far longer than any store can
-    // take to become visible.
+    // never access it from two threads at once; the admin API only reads it. It is
+    // also a self-contained timestamp that guards no other memory, so there is
+    // nothing for Acquire/Release to publish; single-location coherence is the whole
+    // requirement, and the watchdog re-reads after sleeping whole seconds, far
+    // longer than any store can take to become visible.
     last_active_millis: AtomicU64,
 }
 
 impl ActivityClock {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             started: Instant::now(),
             last_active_millis: AtomicU64::new(0),
@@ -349,6 +2470,12 @@ impl ActivityClock {
             .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
     }
 
+    /// How long the connection has gone without activity.
+    pub(crate) fn idle(&self) -> Duration {
+        let last_active = Duration::from_millis(self.last_active_millis.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last_active)
+    }
+
     /// The instant at which the connection is considered idle for `idle`.
     fn idle_deadline(&self, idle: Duration) -> Instant {
         let last_active = Duration::from_millis(self.last_active_millis.load(Ordering::Relaxed));
@@ -359,6 +2486,73 @@ impl ActivityClock {
     }
 }
 
+/// The bytes and chunks a connection has relayed in each direction, updated by
+/// both relays through a shared reference like the [`ActivityClock`] (and with the
+/// same `Relaxed` reasoning).
+#[derive(Default)]
+pub(crate) struct Traffic {
+    client_to_destination: Counts,
+    destination_to_client: Counts,
+}
+
+/// One direction's [`Traffic`].
+#[derive(Default)]
+struct Counts {
+    bytes: AtomicU64,
+    chunks: AtomicU64,
+}
+
+impl Traffic {
+    fn counts(&self, direction: Direction) -> &Counts {
+        match direction {
+            Direction::ClientToDestination => &self.client_to_destination,
+            Direction::DestinationToClient => &self.destination_to_client,
+        }
+    }
+
+    /// Count a chunk of `bytes` relayed in `direction`.
+    fn add(&self, direction: Direction, bytes: usize) {
+        let counts = self.counts(direction);
+        counts.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        counts.chunks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The bytes relayed in `direction` so far.
+    pub(crate) fn bytes(&self, direction: Direction) -> u64 {
+        self.counts(direction).bytes.load(Ordering::Relaxed)
+    }
+
+    /// The chunks relayed in `direction` so far.
+    fn chunks(&self, direction: Direction) -> u64 {
+        self.counts(direction).chunks.load(Ordering::Relaxed)
+    }
+}
+
+/// Write `chunk` to `writer`, in slices paced by the `throttle`'s token buckets
+/// when a `--rate-limit-*` applies. Each slice written counts as activity for the
+/// idle timeout, so a transfer the limit stretches out is not taken for an idle one.
+async fn write_throttled<W>(
+    writer: &mut W,
+    mut chunk: &[u8],
+    throttle: &Throttle,
+    taps: &RelayTaps<'_>,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if throttle.is_unlimited() {
+        return writer.write_all(chunk).await;
+    }
+    while !chunk.is_empty() {
+        let (slice, rest) = chunk.split_at(chunk.len().min(throttle.slice()));
+        throttle.wait(slice.len()).await;
+        writer.write_all(slice).await?;
+        taps.active();
+        chunk = rest;
+    }
+    Ok(())
+}
+
 /// Resolve once the connection has seen no activity in either direction for
 /// `idle`, re-arming whenever fresh activity pushes the deadline out.
 async fn wait_until_idle(clock: &ActivityClock, idle: Duration) {
@@ -373,36 +2567,304 @@ async fn wait_until_idle(clock: &ActivityClock, idle: Duration) {
 /// Copy bytes from `reader` to `writer` until the stream ends or an I/O error
 /// occurs, then shut the writer down so the close is forwarded to its peer.
 ///
-/// Each non-empty chunk is recorded on the shared `activity` clock (when one is
-/// provided), so the connection's idle-timeout watchdog can tell that this
-/// direction is still moving data. The copy ends when `reader` reaches
-/// end-of-stream (`read_buf` yields `Ok(0)`) or a read/write fails; treating a
-/// zero-length read as end-of-stream (rather than retrying) is what stops a closed
-/// peer from being polled in a tight loop. On return the writer is shut down (a
-/// half-close); because the opposite direction is driven to completion
-/// independently, any data still in flight there is delivered before the
-/// connection closes.
-async fn relay<R, W>(mut reader: R, mut writer: W, activity: Option<&ActivityClock>)
+/// Each non-empty chunk is reported to the connection's `taps` — as activity on the
+/// shared clock when it is read, so the idle-timeout watchdog can tell that this
+/// direction is still moving data, and as relayed once it has been written on. The
+/// copy ends when `reader` reaches end-of-stream (`read_buf` yields `Ok(0)`) or a
+/// read/write fails; treating a zero-length read as end-of-stream (rather than
+/// retrying) is what stops a closed peer from being polled in a tight loop. On
+/// return the writer is shut down (a half-close); because the opposite direction
+/// is driven to completion independently, any data still in flight there is
+/// delivered before the connection closes.
+///
+/// With a latency set, each chunk is written that long after it was read (see
+/// [`relay_delayed`]); the half-close then follows the last delayed chunk. With a
+/// rate limit, each chunk is written in slices paced by the limit's token buckets
+/// (see [`write_throttled`]). With `--fault`s for the direction, each chunk goes
+/// through them on its way out (see [`deliver`]), and a `truncate` triggered by time
+/// cuts the copy short wherever it is. With `--coalesce`, a chunk is everything read
+/// within the window after its first read (see [`read_chunk`]); with `--fragment`,
+/// it is written in pieces (see [`write_out`]).
+async fn relay<R, W>(
+    mut reader: R,
+    mut writer: W,
+    direction: Direction,
+    impairments: Impairments,
+    faults: DirectionFaults,
+    taps: &RelayTaps<'_>,
+) where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let Impairments {
+        latency,
+        jitter,
+        throttle,
+        fragmenter,
+        coalesce,
+    } = impairments;
+    let truncate_at = faults.truncate_at();
+    let mut shaping = Shaping {
+        faults,
+        fragmenter,
+        throttle,
+    };
+    let copying = async {
+        if latency.is_zero() {
+            relay_directly(
+                &mut reader,
+                &mut writer,
+                direction,
+                coalesce,
+                &mut shaping,
+                taps,
+            )
+            .await
+        } else {
+            relay_delayed(
+                &mut reader,
+                &mut writer,
+                direction,
+                latency.delays(jitter),
+                coalesce,
+                &mut shaping,
+                taps,
+            )
+            .await
+        }
+    };
+    let end = match truncate_at {
+        None => copying.await,
+        Some((at, fault)) => tokio::select! {
+            end = copying => end,
+            () = sleep_until(at) => {
+                taps.fault(
+                    Some(direction),
+                    &fault,
+                    format_args!(
+                        "truncating the stream after {} bytes",
+                        taps.live.traffic.bytes(direction)
+                    ),
+                );
+                RelayEnd::Truncated
+            }
+        },
+    };
+    taps.ended(direction, end);
+    // Forward the end-of-stream to the peer (half-close). Errors are ignored: the
+    // writer may already be closed by a failed write or by the peer.
+    let _ = writer.shutdown().await;
+}
+
+/// What a relay does to the chunks it writes on, besides delaying them.
+struct Shaping {
+    faults: DirectionFaults,
+    fragmenter: Fragmenter,
+    throttle: Throttle,
+}
+
+/// Read the next chunk to relay from `reader` into `buffer`: what one read yields
+/// or, with a `coalesce` window, everything read until the window after that read
+/// closes (or [`netem::MAX_COALESCED`] bytes have gathered). Returns how the reader
+/// ended if it did, with whatever was read before that left in `buffer`.
+async fn read_chunk<R>(
+    reader: &mut R,
+    buffer: &mut BytesMut,
+    coalesce: Option<Duration>,
+) -> Option<RelayEnd>
+where
+    R: AsyncRead + Unpin,
+{
+    buffer.reserve(2048);
+    match reader.read_buf(buffer).await {
+        Ok(0) => return Some(RelayEnd::Closed),
+        Ok(_) => {}
+        Err(_) => return Some(RelayEnd::ReadFailed),
+    }
+    let deadline = Instant::now() + coalesce?;
+    while buffer.len() < netem::MAX_COALESCED {
+        buffer.reserve(2048);
+        match timeout_at(deadline, reader.read_buf(buffer)).await {
+            Err(_) => break,
+            Ok(Ok(0)) => return Some(RelayEnd::Closed),
+            Ok(Ok(_)) => {}
+            Ok(Err(_)) => return Some(RelayEnd::ReadFailed),
+        }
+    }
+    None
+}
+
+/// The copy loop of [`relay`]: each chunk is written as soon as it is read.
+async fn relay_directly<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    direction: Direction,
+    coalesce: Option<Duration>,
+    shaping: &mut Shaping,
+    taps: &RelayTaps<'_>,
+) -> RelayEnd
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
     let mut buffer = BytesMut::with_capacity(2048);
-    // The loop ends on a read error (the `while let` binding fails), on end-of-stream
-    // (`read_length == 0`), or on a write error.
-    while let Ok(read_length) = reader.read_buf(&mut buffer).await {
-        if read_length == 0 {
-            break;
+    loop {
+        let end = read_chunk(reader, &mut buffer, coalesce).await;
+        if !buffer.is_empty() {
+            taps.read(direction, &buffer);
+            if let Err(end) = deliver(writer, &mut buffer, direction, shaping, taps).await {
+                return end;
+            }
+            buffer.clear();
         }
-        if let Some(activity) = activity {
-            activity.record();
+        if let Some(end) = end {
+            return end;
+        }
+    }
+}
+
+/// The copy loop of [`relay`] with `--latency-to-*`/`--jitter-to-*`: reading and
+/// writing run side by side, so the delays of successive chunks overlap instead of
+/// adding up. Each chunk is stamped with its delivery time when it is read — which
+/// is also when it counts as activity for the idle timeout — and written once that
+/// time has come, but never before the chunk read ahead of it, so the stream keeps
+/// its order whatever the jitter. When the reader ends, the chunks still in flight
+/// are written before the end is returned; a failed write stops the reading too.
+async fn relay_delayed<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    direction: Direction,
+    mut delays: Delays,
+    coalesce: Option<Duration>,
+    shaping: &mut Shaping,
+    taps: &RelayTaps<'_>,
+) -> RelayEnd
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (chunks, mut in_flight) = mpsc::channel::<(Instant, BytesMut)>(netem::IN_FLIGHT_CHUNKS);
+    let reading = async move {
+        let mut buffer = BytesMut::new();
+        loop {
+            let end = read_chunk(reader, &mut buffer, coalesce).await;
+            if !buffer.is_empty() {
+                taps.read(direction, &buffer);
+                let due = Instant::now() + delays.next();
+                if chunks.send((due, buffer.split())).await.is_err() {
+                    return RelayEnd::WriteFailed;
+                }
+            }
+            if let Some(end) = end {
+                return end;
+            }
         }
-        if writer.write_all(&buffer[0..read_length]).await.is_err() {
-            break;
+    };
+    let writing = async {
+        let mut not_before = Instant::now();
+        while let Some((due, mut chunk)) = in_flight.recv().await {
+            not_before = not_before.max(due);
+            sleep_until(not_before).await;
+            deliver(writer, &mut chunk, direction, shaping, taps).await?;
         }
-        buffer.clear();
+        Ok(())
+    };
+    tokio::pin!(reading, writing);
+    // `writing` only runs out of chunks once `reading` has returned (dropping
+    // `chunks`), so until then it can only stop on a failed write or a fault.
+    tokio::select! {
+        end = &mut reading => match writing.await {
+            Ok(()) => end,
+            Err(end) => end,
+        },
+        Err(end) = &mut writing => end,
     }
-    // Forward the end-of-stream to the peer (half-close). Errors are ignored: the
-    // writer may already be closed by a failed write or by the peer.
-    let _ = writer.shutdown().await;
+}
+
+/// Write `chunk`, read in `direction`, on through the direction's faults (see
+/// [`write_out`] for how it is written). A corrupting fault changes bytes of it
+/// first, a stall waits before the rest of it, and a `truncate` or `reset` reached
+/// within it stops the copy there: the former by returning [`RelayEnd::Truncated`],
+/// the latter by never returning, for the connection's task to drop the relays and
+/// reset both sockets.
+async fn deliver<W>(
+    writer: &mut W,
+    chunk: &mut [u8],
+    direction: Direction,
+    shaping: &mut Shaping,
+    taps: &RelayTaps<'_>,
+) -> Result<(), RelayEnd>
+where
+    W: AsyncWrite + Unpin,
+{
+    if shaping.faults.is_empty() {
+        return write_out(writer, chunk, direction, shaping, taps).await;
+    }
+    let (steps, tampered) = shaping.faults.plan(chunk);
+    for (fault, changes) in tampered {
+        taps.fault(Some(direction), &fault, format_args!("changed {changes}"));
+    }
+    for step in steps {
+        match step {
+            Step::Write(range) => {
+                write_out(writer, &chunk[range], direction, shaping, taps).await?;
+            }
+            Step::Stall(fault, duration) => {
+                taps.fault(
+                    Some(direction),
+                    &fault,
+                    format_args!(
+                        "stalling for {:.3}s after {} bytes",
+                        duration.as_secs_f64(),
+                        taps.live.traffic.bytes(direction)
+                    ),
+                );
+                sleep(duration).await;
+            }
+            Step::Truncate(fault) => {
+                taps.fault(
+                    Some(direction),
+                    &fault,
+                    format_args!(
+                        "truncating the stream after {} bytes",
+                        taps.live.traffic.bytes(direction)
+                    ),
+                );
+                return Err(RelayEnd::Truncated);
+            }
+            Step::Reset(fault) => {
+                taps.reset(Some(direction), &fault);
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `part` of a chunk on and report it relayed: whole or, with `--fragment`,
+/// piece by piece with the `--fragment-delay` gap between pieces, each reported as
+/// it is written so that the payload logged is what each write carried.
+async fn write_out<W>(
+    writer: &mut W,
+    part: &[u8],
+    direction: Direction,
+    shaping: &mut Shaping,
+    taps: &RelayTaps<'_>,
+) -> Result<(), RelayEnd>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut rest = part;
+    while !rest.is_empty() {
+        if rest.len() < part.len() && !shaping.fragmenter.gap().is_zero() {
+            sleep(shaping.fragmenter.gap()).await;
+        }
+        let (piece, after) = rest.split_at(shaping.fragmenter.next_piece(rest.len()));
+        write_throttled(writer, piece, &shaping.throttle, taps)
+            .await
+            .map_err(|_| RelayEnd::WriteFailed)?;
+        taps.relayed(direction, piece);
+        rest = after;
+    }
+    Ok(())
 }
@@ -0,0 +1,582 @@
+//! `--decode http`: each direction of a connection parsed as HTTP/1.x as it is
+//! read, one [`Message`] reported per request and response — the start line, the
+//! headers and the size of the body, and the body itself with `--decode-body` —
+//! while the bytes are relayed, and logged, untouched.
+//!
+//! Bodies are framed as RFC 9112 (section 6.3) says: by `Transfer-Encoding:
+//! chunked`, by `Content-Length`, or, for a response with neither, by the close of
+//! the connection. A response's framing also depends on its request (`HEAD`,
+//! `CONNECT`), so the methods of the requests not answered yet are queued, which
+//! keeps pipelined requests paired with their responses.
+//!
+//! Decoding a direction stops, with the reason, at bytes that are not HTTP, and for
+//! good in both directions once the peers switch protocols: a `101` response to an
+//! `Upgrade`, or a `2xx` to a `CONNECT`.
+
+use crate::conn::Direction;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+/// The largest head (start line and headers) decoded; a longer one is not taken
+/// for HTTP.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// How much of a body `--decode-body` shows.
+pub(crate) const MAX_BODY_SHOWN: usize = 4096;
+
+/// The longest method looked for at the start of a request.
+const MAX_METHOD: usize = 32;
+
+/// The first line of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StartLine {
+    Request {
+        method: String,
+        target: String,
+        version: String,
+    },
+    Response {
+        version: String,
+        status: u16,
+        reason: String,
+    },
+}
+
+/// A request or a response, complete with its body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Message {
+    pub(crate) start: StartLine,
+    /// As sent, in order; a folded value unfolded.
+    pub(crate) headers: Vec<(String, String)>,
+    /// The size of the body, without its chunked coding.
+    pub(crate) body_size: u64,
+    pub(crate) chunked: bool,
+    /// The first [`MAX_BODY_SHOWN`] bytes of the body, without its chunked coding;
+    /// only kept for `--decode-body`.
+    pub(crate) body: Option<Vec<u8>>,
+}
+
+/// What decoding a direction came up with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Decoded {
+    Message(Message),
+    /// The direction is not decoded any more, for this reason.
+    Stopped(String),
+}
+
+/// How the end of a body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// This many bytes are left.
+    Length(u64),
+    Chunked(Chunk),
+    /// The body ends with the connection.
+    UntilClose,
+}
+
+/// Where a chunked body is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    /// At a chunk's size line.
+    Size,
+    /// Inside a chunk's data, with this many bytes left.
+    Data(u64),
+    /// At the line break after a chunk's data.
+    DataEnd,
+    /// After the last chunk, at the trailer fields or the final line break.
+    Trailers,
+}
+
+enum State {
+    Head,
+    Body { message: Message, framing: Framing },
+    Done,
+}
+
+/// One direction's parser, fed the bytes as they are read.
+struct Parser {
+    /// Whether the direction carries requests (the client's) or responses.
+    requests: bool,
+    /// Bytes not parsed yet.
+    buffer: Vec<u8>,
+    state: State,
+    keep_body: bool,
+    /// A response switched the peers to another protocol.
+    switched: bool,
+}
+
+/// Both directions of a connection.
+pub(crate) struct HttpDecoder {
+    requests: Parser,
+    responses: Parser,
+    /// The methods of the requests not answered yet, oldest first.
+    methods: VecDeque<String>,
+}
+
+impl HttpDecoder {
+    /// A decoder of a new connection, keeping the bodies if `keep_body`.
+    pub(crate) fn new(keep_body: bool) -> Self {
+        Self {
+            requests: Parser::new(true, keep_body),
+            responses: Parser::new(false, keep_body),
+            methods: VecDeque::new(),
+        }
+    }
+
+    /// Parse the next `bytes` read in `direction` and return what they complete.
+    pub(crate) fn feed(&mut self, direction: Direction, bytes: &[u8]) -> Vec<Decoded> {
+        let mut decoded = Vec::new();
+        match direction {
+            Direction::ClientToDestination => {
+                self.requests.feed(bytes, &mut self.methods, &mut decoded);
+            }
+            Direction::DestinationToClient => {
+                self.responses.feed(bytes, &mut self.methods, &mut decoded);
+                if self.responses.switched {
+                    self.requests.stop();
+                }
+            }
+        }
+        decoded
+    }
+
+    /// The stream read in `direction` ended: a body framed by the close is
+    /// complete, while a message cut short is reported as such.
+    pub(crate) fn finish(&mut self, direction: Direction) -> Vec<Decoded> {
+        let parser = match direction {
+            Direction::ClientToDestination => &mut self.requests,
+            Direction::DestinationToClient => &mut self.responses,
+        };
+        let mut decoded = Vec::new();
+        match std::mem::replace(&mut parser.state, State::Done) {
+            State::Body {
+                message,
+                framing: Framing::UntilClose,
+            } => decoded.push(Decoded::Message(message)),
+            State::Body { message, .. } => decoded.push(Decoded::Stopped(format!(
+                "the stream ended {} bytes into a body",
+                message.body_size
+            ))),
+            State::Head if !parser.buffer.is_empty() => decoded.push(Decoded::Stopped(
+                "the stream ended in the middle of a head".to_string(),
+            )),
+            State::Head | State::Done => {}
+        }
+        parser.buffer = Vec::new();
+        decoded
+    }
+}
+
+impl Parser {
+    fn new(requests: bool, keep_body: bool) -> Self {
+        Self {
+            requests,
+            buffer: Vec::new(),
+            state: State::Head,
+            keep_body,
+            switched: false,
+        }
+    }
+
+    /// Decode nothing more.
+    fn stop(&mut self) {
+        self.state = State::Done;
+        self.buffer = Vec::new();
+    }
+
+    fn feed(&mut self, bytes: &[u8], methods: &mut VecDeque<String>, decoded: &mut Vec<Decoded>) {
+        if matches!(self.state, State::Done) {
+            return;
+        }
+        self.buffer.extend_from_slice(bytes);
+        let mut start = 0;
+        let result = loop {
+            match self.step(&mut start, methods, decoded) {
+                Ok(true) => {}
+                Ok(false) => break Ok(()),
+                Err(reason) => break Err(reason),
+            }
+        };
+        match result {
+            Ok(()) if !matches!(self.state, State::Done) => {
+                self.buffer.drain(..start);
+            }
+            Ok(()) => self.buffer = Vec::new(),
+            Err(reason) => {
+                decoded.push(Decoded::Stopped(reason));
+                self.stop();
+            }
+        }
+    }
+
+    /// Parse what `self.buffer` holds from `start` on, as far as the state allows,
+    /// moving `start` past what is parsed: whether to go on, or wait for more
+    /// bytes.
+    fn step(
+        &mut self,
+        start: &mut usize,
+        methods: &mut VecDeque<String>,
+        decoded: &mut Vec<Decoded>,
+    ) -> Result<bool, String> {
+        let State::Body { message, framing } = &mut self.state else {
+            if matches!(self.state, State::Done) {
+                return Ok(false);
+            }
+            // The line breaks a client may send between two requests.
+            while let Some(b'\r' | b'\n') = self.buffer.get(*start) {
+                *start += 1;
+            }
+            let rest = &self.buffer[*start..];
+            if rest.is_empty() {
+                return Ok(false);
+            }
+            if !plausible(rest, self.requests) {
+                return Err(format!(
+                    "`{}` is not the start of an HTTP/1.x {}",
+                    excerpt(rest),
+                    if self.requests { "request" } else { "response" }
+                ));
+            }
+            let Some(end) = head_end(rest) else {
+                if rest.len() > MAX_HEAD {
+                    return Err(format!("a head is over {} KiB", MAX_HEAD / 1024));
+                }
+                return Ok(false);
+            };
+            let head = String::from_utf8_lossy(&rest[..end]);
+            let (start_line, headers) = parse_head(&head, self.requests)?;
+            let mut message = Message {
+                start: start_line,
+                headers,
+                body_size: 0,
+                chunked: false,
+                body: self.keep_body.then(Vec::new),
+            };
+            *start += end;
+            let framing = if self.requests {
+                request_framing(&message, methods)?
+            } else {
+                match response_framing(&message, methods)? {
+                    ResponseBody::Framed(framing) => framing,
+                    ResponseBody::Switched(reason) => {
+                        decoded.push(Decoded::Message(message));
+                        decoded.push(Decoded::Stopped(reason));
+                        self.switched = true;
+                        self.state = State::Done;
+                        return Ok(false);
+                    }
+                }
+            };
+            message.chunked = matches!(framing, Some(Framing::Chunked(_)));
+            match framing {
+                None | Some(Framing::Length(0)) => decoded.push(Decoded::Message(message)),
+                Some(framing) => self.state = State::Body { message, framing },
+            }
+            return Ok(true);
+        };
+        let rest = &self.buffer[*start..];
+        if rest.is_empty() {
+            return Ok(false);
+        }
+        let complete = match framing {
+            Framing::Length(left) => {
+                let taken = take(message, rest, *left);
+                *left -= taken as u64;
+                *start += taken;
+                *left == 0
+            }
+            Framing::UntilClose => {
+                *start += take(message, rest, u64::MAX);
+                false
+            }
+            Framing::Chunked(Chunk::Data(left)) => {
+                let taken = take(message, rest, *left);
+                *left -= taken as u64;
+                *start += taken;
+                if *left == 0 {
+                    *framing = Framing::Chunked(Chunk::DataEnd);
+                }
+                false
+            }
+            Framing::Chunked(chunk) => {
+                let Some(line_end) = rest.iter().position(|byte| *byte == b'\n') else {
+                    if rest.len() > MAX_HEAD {
+                        return Err(format!("a chunk line is over {} KiB", MAX_HEAD / 1024));
+                    }
+                    return Ok(false);
+                };
+                let line = String::from_utf8_lossy(&rest[..line_end]);
+                let line = line.trim_end_matches('\r');
+                *start += line_end + 1;
+                match chunk {
+                    Chunk::Size => {
+                        let size = line.split(';').next().unwrap_or_default().trim();
+                        let size = u64::from_str_radix(size, 16).map_err(|_| {
+                            format!("`{}` is not a chunk size", excerpt(line.as_bytes()))
+                        })?;
+                        *chunk = if size == 0 {
+                            Chunk::Trailers
+                        } else {
+                            Chunk::Data(size)
+                        };
+                        false
+                    }
+                    Chunk::DataEnd if line.is_empty() => {
+                        *chunk = Chunk::Size;
+                        false
+                    }
+                    Chunk::DataEnd => {
+                        return Err("a chunk is longer than its size says".to_string());
+                    }
+                    // A trailer field is skipped; the empty line ends the body.
+                    Chunk::Trailers => line.is_empty(),
+                    Chunk::Data(_) => unreachable!("matched above"),
+                }
+            }
+        };
+        if complete {
+            let State::Body { message, .. } = std::mem::replace(&mut self.state, State::Head)
+            else {
+                unreachable!("in a body");
+            };
+            decoded.push(Decoded::Message(message));
+        }
+        Ok(true)
+    }
+}
+
+/// Count up to `left` bytes of `rest` into `message`'s body, keeping them if it
+/// keeps its body: how many were taken.
+fn take(message: &mut Message, rest: &[u8], left: u64) -> usize {
+    let taken = rest.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+    message.body_size += taken as u64;
+    if let Some(body) = &mut message.body {
+        let room = MAX_BODY_SHOWN.saturating_sub(body.len());
+        body.extend_from_slice(&rest[..taken.min(room)]);
+    }
+    taken
+}
+
+/// The framing of a request's body, with its method queued for the response.
+fn request_framing(
+    message: &Message,
+    methods: &mut VecDeque<String>,
+) -> Result<Option<Framing>, String> {
+    if let StartLine::Request { method, .. } = &message.start {
+        methods.push_back(method.clone());
+    }
+    if let Some(coding) = header(&message.headers, "transfer-encoding") {
+        if last_coding_is_chunked(coding) {
+            return Ok(Some(Framing::Chunked(Chunk::Size)));
+        }
+        return Err(format!(
+            "a request's transfer coding `{coding}` leaves its length unknown"
+        ));
+    }
+    Ok(content_length(&message.headers)?.map(Framing::Length))
+}
+
+/// What follows a response's head.
+enum ResponseBody {
+    /// A body framed so, if any.
+    Framed(Option<Framing>),
+    /// Another protocol, for this reason.
+    Switched(String),
+}
+
+/// What follows a response's head, its request's method taken off the queue
+/// unless it is an interim response.
+fn response_framing(
+    message: &Message,
+    methods: &mut VecDeque<String>,
+) -> Result<ResponseBody, String> {
+    let StartLine::Response { status, .. } = &message.start else {
+        return Ok(ResponseBody::Framed(None));
+    };
+    let status = *status;
+    if status == 101 {
+        methods.pop_front();
+        let protocol = header(&message.headers, "upgrade").unwrap_or("another protocol");
+        return Ok(ResponseBody::Switched(format!(
+            "the peers switched to {protocol}"
+        )));
+    }
+    // An interim response: the final one is still to come.
+    if (100..200).contains(&status) {
+        return Ok(ResponseBody::Framed(None));
+    }
+    let method = methods.pop_front();
+    if method.as_deref() == Some("CONNECT") && (200..300).contains(&status) {
+        return Ok(ResponseBody::Switched(
+            "CONNECT opened a tunnel".to_string(),
+        ));
+    }
+    if method.as_deref() == Some("HEAD") || status == 204 || status == 304 {
+        return Ok(ResponseBody::Framed(None));
+    }
+    let framing = match header(&message.headers, "transfer-encoding") {
+        Some(coding) if last_coding_is_chunked(coding) => Framing::Chunked(Chunk::Size),
+        Some(_) => Framing::UntilClose,
+        None => content_length(&message.headers)?.map_or(Framing::UntilClose, Framing::Length),
+    };
+    Ok(ResponseBody::Framed(Some(framing)))
+}
+
+fn last_coding_is_chunked(coding: &str) -> bool {
+    coding
+        .rsplit(',')
+        .next()
+        .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// The `Content-Length`, if any; repeated, the values must agree.
+fn content_length(headers: &[(String, String)]) -> Result<Option<u64>, String> {
+    let mut length = None;
+    for (_, value) in headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+    {
+        for value in value.split(',') {
+            let parsed: u64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid Content-Length `{value}`"))?;
+            if length.is_some_and(|length| length != parsed) {
+                return Err("conflicting Content-Length values".to_string());
+            }
+            length = Some(parsed);
+        }
+    }
+    Ok(length)
+}
+
+/// The first value of the header `name`.
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether `bytes` may start a request (a method, then a space) or a response
+/// (`HTTP/`), as far as they go.
+fn plausible(bytes: &[u8], request: bool) -> bool {
+    if !request {
+        let prefix = &b"HTTP/"[..bytes.len().min(5)];
+        return bytes.starts_with(prefix);
+    }
+    match bytes.iter().position(|byte| *byte == b' ') {
+        Some(0) => false,
+        Some(end) => end <= MAX_METHOD && bytes[..end].iter().all(|byte| is_token(*byte)),
+        None => bytes.len() <= MAX_METHOD && bytes.iter().all(|byte| is_token(*byte)),
+    }
+}
+
+/// A `tchar` of RFC 9110, which names methods and fields.
+fn is_token(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Where the head at the start of `bytes` ends: after its empty line.
+fn head_end(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'\n')
+        .find_map(|(at, _)| match bytes.get(at + 1..) {
+            Some([b'\n', ..]) => Some(at + 2),
+            Some([b'\r', b'\n', ..]) => Some(at + 3),
+            _ => None,
+        })
+}
+
+/// The start line and the headers of a head.
+fn parse_head(head: &str, request: bool) -> Result<(StartLine, Vec<(String, String)>), String> {
+    let mut lines = head
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line));
+    let line = lines.next().unwrap_or_default();
+    let start = if request {
+        request_line(line)
+    } else {
+        status_line(line)
+    }
+    .ok_or_else(|| {
+        format!(
+            "`{}` is not an HTTP/1.x {} line",
+            excerpt(line.as_bytes()),
+            if request { "request" } else { "status" }
+        )
+    })?;
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in lines.take_while(|line| !line.is_empty()) {
+        if line.starts_with([' ', '\t']) {
+            // An obsolete folded line, continuing the previous value.
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+                continue;
+            }
+        }
+        let field = line
+            .split_once(':')
+            .filter(|(name, _)| !name.is_empty() && name.bytes().all(is_token));
+        let Some((name, value)) = field else {
+            return Err(format!(
+                "`{}` is not a header field",
+                excerpt(line.as_bytes())
+            ));
+        };
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+    Ok((start, headers))
+}
+
+fn request_line(line: &str) -> Option<StartLine> {
+    let mut parts = line.split(' ');
+    let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+    let valid = parts.next().is_none()
+        && !method.is_empty()
+        && method.bytes().all(is_token)
+        && !target.is_empty()
+        && is_version(version);
+    valid.then(|| StartLine::Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        version: version.to_string(),
+    })
+}
+
+fn status_line(line: &str) -> Option<StartLine> {
+    let (version, rest) = line.split_once(' ')?;
+    let (status, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+    if !is_version(version) || status.len() != 3 {
+        return None;
+    }
+    Some(StartLine::Response {
+        version: version.to_string(),
+        status: status.parse().ok()?,
+        reason: reason.to_string(),
+    })
+}
+
+fn is_version(version: &str) -> bool {
+    version
+        .strip_prefix("HTTP/1.")
+        .is_some_and(|minor| minor.len() == 1 && minor.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+/// The start of `bytes` for an error message: at most a line of 40 characters,
+/// escaped.
+fn excerpt(bytes: &[u8]) -> String {
+    let line = bytes
+        .split(|byte| *byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let text: Cow<'_, str> = String::from_utf8_lossy(&line[..line.len().min(40)]);
+    let mut excerpt: String = text.trim_end_matches('\r').escape_debug().collect();
+    if line.len() > 40 {
+        excerpt.push_str("...");
+    }
+    excerpt
+}
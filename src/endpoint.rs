@@ -0,0 +1,132 @@
+//! The minimal HTTP/1.1 server behind `--metrics-addr` and `--admin-addr`: one
+//! request per connection, answered and then closed. Only the request head is
+//! read — the method and the path are all either endpoint looks at — which is
+//! enough for a scraper, `curl` or a script, and keeps an HTTP stack out of the
+//! dependencies.
+
+use crate::conn::ACCEPT_BACKOFF_MIN;
+use crate::conn::next_accept_backoff;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::{self};
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// The largest request head a client may send.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What an endpoint is asked: the request line's method and its path, without the
+/// query string.
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+}
+
+/// What an endpoint answers.
+pub(crate) struct Response {
+    /// The status code and reason, such as `200 OK`.
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub(crate) fn new(status: &'static str, content_type: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    /// A plain-text answer.
+    pub(crate) fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body.into())
+    }
+
+    /// A JSON answer, ending with a line break for the terminal's sake.
+    pub(crate) fn json(status: &'static str, body: &serde_json::Value) -> Self {
+        Self::new(status, "application/json", format!("{body}\n"))
+    }
+}
+
+/// Answer every request on `listener` with `handle` until the task is aborted. A
+/// request that cannot be read is dropped unanswered. A failed accept is retried
+/// after the same growing backoff as a route's.
+pub(crate) async fn serve<H>(listener: TcpListener, handle: H)
+where
+    H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    let mut accept_backoff = ACCEPT_BACKOFF_MIN;
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => {
+                accept_backoff = ACCEPT_BACKOFF_MIN;
+                stream
+            }
+            Err(error) => {
+                log::debug!(
+                    event = "endpoint_accept_failed";
+                    "Failed to accept an HTTP connection due to {error}, retrying in {}ms",
+                    accept_backoff.as_millis()
+                );
+                // Like a route's loop, never spin on a persistent error (e.g.
+                // file-descriptor exhaustion).
+                sleep(accept_backoff).await;
+                accept_backoff = next_accept_backoff(accept_backoff);
+                continue;
+            }
+        };
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let _ = respond(stream, &*handle).await;
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    handle: &(dyn Fn(&Request) -> Response + Send + Sync),
+) -> io::Result<()> {
+    let head = timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut request_line = head.split(|&byte| byte == b'\r');
+    let request_line = String::from_utf8_lossy(request_line.next().unwrap_or_default());
+    let mut parts = request_line.split(' ');
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let response = handle(&Request { method, path });
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Read the request up to the blank line that ends its head.
+async fn read_head(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read_length = stream.read(&mut buffer).await?;
+        if read_length == 0 || head.len() + read_length > MAX_REQUEST_HEAD {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        head.extend_from_slice(&buffer[..read_length]);
+    }
+    Ok(head)
+}
@@ -0,0 +1,546 @@
+//! `--fault`: faults injected into the relayed traffic on purpose — resets,
+//! truncation, corrupted bytes and stalls — to see how the peers cope with a
+//! misbehaving one. A fault is written `[DIRECTION:]ACTION[=VALUE][@TRIGGER]`:
+//!
+//! | Part | Values |
+//! | --- | --- |
+//! | `DIRECTION` | `up` (client to destination), `down` (destination to client) or `both`, the default |
+//! | `ACTION` | `reset`, `truncate`, `stall=<duration>`, `flip=<probability>`, `replace=<probability>` |
+//! | `TRIGGER` | a byte count of the direction (`1000`, `4K`) or a time since the destination answered (`500ms`, `5s`) |
+//!
+//! Without a trigger a fault applies from the start. The random choices come from
+//! a generator seeded per connection and direction from `--seed`, so a run with
+//! the same seed tampers with the same bytes.
+
+use crate::args::MAX_TIMEOUT_SECONDS;
+use crate::args::parse_byte_size;
+use crate::conn::Direction;
+use crate::netem::SplitMix64;
+use crate::tls::MaybeTls;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// How many tampered bytes a `fault` event lists; the rest are only counted.
+const LISTED_BYTES: usize = 16;
+
+/// One `--fault`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub side: Side,
+    pub action: Action,
+    /// When the fault starts; `None`: from the start.
+    pub trigger: Option<Trigger>,
+}
+
+/// The directions a fault applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// Client to destination.
+    Up,
+    /// Destination to client.
+    Down,
+    Both,
+}
+
+/// What a fault does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Abort the whole connection: both peers get a TCP reset.
+    Reset,
+    /// Half-close the direction early: nothing more is relayed in it.
+    Truncate,
+    /// Relay nothing in the direction for this long.
+    Stall(Duration),
+    /// Flip one random bit of each byte with this probability.
+    Flip(Probability),
+    /// Replace each byte with a different random one with this probability.
+    Replace(Probability),
+}
+
+/// When a fault starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Once this many bytes were relayed in the direction.
+    Bytes(u64),
+    /// This long after the destination answered.
+    After(Duration),
+}
+
+/// A probability, in millionths (kept as an integer so faults compare exactly).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probability(u32);
+
+impl Fault {
+    /// Whether the fault applies to `direction`.
+    fn applies_to(&self, direction: Direction) -> bool {
+        matches!(
+            (self.side, direction),
+            (Side::Both, _)
+                | (Side::Up, Direction::ClientToDestination)
+                | (Side::Down, Direction::DestinationToClient)
+        )
+    }
+
+    /// The time trigger, with no trigger counting as one at the start.
+    fn after(&self) -> Option<Duration> {
+        match self.trigger {
+            None => Some(Duration::ZERO),
+            Some(Trigger::After(after)) => Some(after),
+            Some(Trigger::Bytes(_)) => None,
+        }
+    }
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| format!("invalid fault `{s}`: {reason}");
+        let (side, rest) = match s.split_once(':') {
+            None => (Side::Both, s),
+            Some(("up", rest)) => (Side::Up, rest),
+            Some(("down", rest)) => (Side::Down, rest),
+            Some(("both", rest)) => (Side::Both, rest),
+            Some((side, _)) => {
+                return Err(invalid(format!(
+                    "unknown direction `{side}`: expected up, down or both"
+                )));
+            }
+        };
+        let (action, trigger) = match rest.split_once('@') {
+            Some((action, trigger)) => (action, Some(trigger)),
+            None => (rest, None),
+        };
+        let (name, value) = match action.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (action, None),
+        };
+        let action = match (name, value) {
+            ("reset", None) => Action::Reset,
+            ("truncate", None) => Action::Truncate,
+            ("stall", Some(value)) => Action::Stall(parse_duration(value).map_err(invalid)?),
+            ("flip", Some(value)) => Action::Flip(value.parse().map_err(invalid)?),
+            ("replace", Some(value)) => Action::Replace(value.parse().map_err(invalid)?),
+            ("reset" | "truncate", Some(_)) => {
+                return Err(invalid(format!("`{name}` takes no value")));
+            }
+            ("stall", None) => {
+                return Err(invalid(
+                    "`stall` needs a duration, such as `stall=2s`".to_string(),
+                ));
+            }
+            ("flip" | "replace", None) => {
+                return Err(invalid(format!(
+                    "`{name}` needs a probability, such as `{name}=0.01`"
+                )));
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "unknown action `{name}`: expected reset, truncate, stall, flip or replace"
+                )));
+            }
+        };
+        let trigger = trigger
+            .map(|trigger| {
+                if trigger.ends_with('s') {
+                    parse_duration(trigger).map(Trigger::After)
+                } else {
+                    parse_byte_size(trigger).map(Trigger::Bytes)
+                }
+            })
+            .transpose()
+            .map_err(invalid)?;
+        Ok(Fault {
+            side,
+            action,
+            trigger,
+        })
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.side {
+            Side::Up => write!(f, "up:")?,
+            Side::Down => write!(f, "down:")?,
+            Side::Both => write!(f, "both:")?,
+        }
+        match self.action {
+            Action::Reset => write!(f, "reset")?,
+            Action::Truncate => write!(f, "truncate")?,
+            Action::Stall(duration) => write!(f, "stall={}", DisplayDuration(duration))?,
+            Action::Flip(probability) => write!(f, "flip={probability}")?,
+            Action::Replace(probability) => write!(f, "replace={probability}")?,
+        }
+        match self.trigger {
+            None => Ok(()),
+            Some(Trigger::Bytes(bytes)) => write!(f, "@{bytes}"),
+            Some(Trigger::After(after)) => write!(f, "@{}", DisplayDuration(after)),
+        }
+    }
+}
+
+impl FromStr for Probability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f64>() {
+            Ok(probability) if probability > 0.0 && probability <= 1.0 => {
+                match (probability * 1_000_000.0).round() as u32 {
+                    0 => Err(format!("probability `{s}` is below one in a million")),
+                    millionths => Ok(Probability(millionths)),
+                }
+            }
+            _ => Err(format!(
+                "invalid probability `{s}`: expected a number above 0 and at most 1"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Probability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (f64::from(self.0) / 1_000_000.0).fmt(f)
+    }
+}
+
+/// A duration as a fault is written: whole seconds as `5s`, anything else in
+/// milliseconds.
+struct DisplayDuration(Duration);
+
+impl fmt::Display for DisplayDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis();
+        if millis % 1000 == 0 {
+            write!(f, "{}s", millis / 1000)
+        } else {
+            write!(f, "{millis}ms")
+        }
+    }
+}
+
+/// A fault's duration: a whole number of milliseconds (`500ms`) or seconds (`5s`).
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (digits, millis_per_unit) = match s.strip_suffix("ms") {
+        Some(digits) => (digits, 1),
+        None => match s.strip_suffix('s') {
+            Some(digits) => (digits, 1000),
+            None => ("", 0),
+        },
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(millis_per_unit))
+        .filter(|millis| *millis <= MAX_TIMEOUT_SECONDS * 1000)
+        .map(Duration::from_millis)
+        .ok_or_else(|| {
+            format!("invalid duration `{s}`: expected milliseconds (`500ms`) or seconds (`5s`)")
+        })
+}
+
+/// `faults` as a reload's `fault` change shows them: `[up:reset@1000, ...]`, or
+/// `none`.
+pub(crate) fn describe(faults: &[Fault]) -> String {
+    if faults.is_empty() {
+        return "none".to_string();
+    }
+    let faults: Vec<String> = faults.iter().map(Fault::to_string).collect();
+    format!("[{}]", faults.join(", "))
+}
+
+/// When the first `reset` triggered by time (or untriggered) is due, and which
+/// fault it is; such a reset aborts the connection whatever direction it names.
+pub(crate) fn reset_at(faults: &[Fault], started: Instant) -> Option<(Instant, Fault)> {
+    faults
+        .iter()
+        .filter(|fault| fault.action == Action::Reset)
+        .filter_map(|fault| Some((started + fault.after()?, *fault)))
+        .min_by_key(|(at, _)| *at)
+}
+
+/// One step of relaying a chunk through a direction's faults.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// Write these bytes of the chunk on.
+    Write(Range<usize>),
+    /// Relay nothing for this long.
+    Stall(Fault, Duration),
+    /// Relay nothing more in the direction and half-close it.
+    Truncate(Fault),
+    /// Abort the connection with a reset.
+    Reset(Fault),
+}
+
+/// The faults one relay direction injects, and how far the direction has got.
+pub(crate) struct DirectionFaults {
+    /// The faults applying to the direction.
+    faults: Vec<Fault>,
+    /// Which of `faults` already fired, for the one-off ones.
+    fired: Vec<bool>,
+    /// When the destination answered, for the time triggers.
+    started: Instant,
+    /// The bytes of the direction planned so far.
+    offset: u64,
+    random: SplitMix64,
+}
+
+impl DirectionFaults {
+    pub(crate) fn new(
+        faults: &[Fault],
+        direction: Direction,
+        started: Instant,
+        random: SplitMix64,
+    ) -> Self {
+        let faults: Vec<Fault> = faults
+            .iter()
+            .filter(|fault| fault.applies_to(direction))
+            .copied()
+            .collect();
+        Self {
+            fired: vec![false; faults.len()],
+            faults,
+            started,
+            offset: 0,
+            random,
+        }
+    }
+
+    /// No fault applies to the direction.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.faults.is_empty()
+    }
+
+    /// When the first `truncate` triggered by time (or untriggered) is due, and
+    /// which fault it is.
+    pub(crate) fn truncate_at(&self) -> Option<(Instant, Fault)> {
+        self.faults
+            .iter()
+            .filter(|fault| fault.action == Action::Truncate)
+            .filter_map(|fault| Some((self.started + fault.after()?, *fault)))
+            .min_by_key(|(at, _)| *at)
+    }
+
+    /// Tamper with `chunk` — the next bytes read in the direction — as the
+    /// corrupting faults have it, and plan how to relay it. Returns the steps and,
+    /// for each corrupting fault that changed a byte, what it changed.
+    pub(crate) fn plan(&mut self, chunk: &mut [u8]) -> (Vec<Step>, Vec<(Fault, String)>) {
+        let start = self.offset;
+        let end = start + chunk.len() as u64;
+        self.offset = end;
+        let elapsed = self.started.elapsed();
+        let mut tampered = Vec::new();
+        let mut events: Vec<(usize, Step)> = Vec::new();
+        for (index, fault) in self.faults.iter().enumerate() {
+            // Where in the chunk the fault starts, if it does by its end.
+            let from = match fault.trigger {
+                Some(Trigger::Bytes(bytes)) if bytes > end => continue,
+                Some(Trigger::Bytes(bytes)) => bytes.saturating_sub(start) as usize,
+                Some(Trigger::After(after)) if elapsed < after => continue,
+                _ => 0,
+            };
+            match fault.action {
+                Action::Flip(probability) | Action::Replace(probability) => {
+                    let changes = corrupt(
+                        &mut chunk[from..],
+                        fault.action,
+                        probability,
+                        &mut self.random,
+                    );
+                    if !changes.is_empty() {
+                        let offset = start + from as u64;
+                        tampered.push((*fault, describe_changes(offset, &changes)));
+                    }
+                }
+                // Timed ones have their own timers; those by bytes fire where the
+                // count is reached, once.
+                Action::Reset | Action::Truncate => {
+                    if let Some(Trigger::Bytes(bytes)) = fault.trigger {
+                        if !self.fired[index] && bytes > start {
+                            self.fired[index] = true;
+                            let step = match fault.action {
+                                Action::Reset => Step::Reset(*fault),
+                                _ => Step::Truncate(*fault),
+                            };
+                            events.push((from, step));
+                        }
+                    }
+                }
+                Action::Stall(duration) => {
+                    if self.fired[index] {
+                        continue;
+                    }
+                    match fault.trigger {
+                        Some(Trigger::Bytes(bytes)) => {
+                            if bytes > start {
+                                self.fired[index] = true;
+                                events.push((from, Step::Stall(*fault, duration)));
+                            }
+                        }
+                        // A stall in time holds what arrives within its window.
+                        _ => {
+                            self.fired[index] = true;
+                            let until = fault.after().unwrap_or_default() + duration;
+                            if let Some(left) = until.checked_sub(elapsed) {
+                                if !left.is_zero() {
+                                    events.push((0, Step::Stall(*fault, left)));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        events.sort_by_key(|(at, _)| *at);
+        let mut steps = Vec::with_capacity(events.len() * 2 + 1);
+        let mut written = 0;
+        for (at, step) in events {
+            if at > written {
+                steps.push(Step::Write(written..at));
+                written = at;
+            }
+            let last = matches!(step, Step::Truncate(_) | Step::Reset(_));
+            steps.push(step);
+            if last {
+                return (steps, tampered);
+            }
+        }
+        if written < chunk.len() {
+            steps.push(Step::Write(written..chunk.len()));
+        }
+        (steps, tampered)
+    }
+}
+
+/// Flip a bit of, or replace, each byte of `bytes` with `probability`, returning
+/// each change as its index, the old and the new byte.
+fn corrupt(
+    bytes: &mut [u8],
+    action: Action,
+    probability: Probability,
+    random: &mut SplitMix64,
+) -> Vec<(usize, u8, u8)> {
+    let mut changes = Vec::new();
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        if random.next() % 1_000_000 >= u64::from(probability.0) {
+            continue;
+        }
+        let old = *byte;
+        *byte = match action {
+            Action::Flip(_) => old ^ (1 << (random.next() % 8)),
+            // Any other value: the old one plus 1 to 255.
+            _ => old.wrapping_add(1 + (random.next() % 255) as u8),
+        };
+        changes.push((index, old, *byte));
+    }
+    changes
+}
+
+/// The changes `corrupt` made, at their offsets in the direction:
+/// `2 byte(s) at offsets 12 (0x41 -> 0x49), 40 (0x0a -> 0x8a)`.
+fn describe_changes(offset: u64, changes: &[(usize, u8, u8)]) -> String {
+    let listed: Vec<String> = changes
+        .iter()
+        .take(LISTED_BYTES)
+        .map(|(index, old, new)| format!("{} ({old:#04x} -> {new:#04x})", offset + *index as u64))
+        .collect();
+    let more = match changes.len().saturating_sub(LISTED_BYTES) {
+        0 => String::new(),
+        more => format!(" and {more} more"),
+    };
+    format!(
+        "{} byte(s) at offsets {}{more}",
+        changes.len(),
+        listed.join(", ")
+    )
+}
+
+/// A connection's pending reset: the relays ask for it, the connection's task
+/// carries it out by dropping both sockets, which then close with a reset rather
+/// than the usual FIN.
+#[derive(Default)]
+pub(crate) struct Reset {
+    requested: Arc<AtomicBool>,
+    notify: Notify,
+}
+
+impl Reset {
+    /// Wrap one of the connection's sockets so that it is reset when dropped
+    /// after a [`request`](Self::request).
+    pub(crate) fn guard(&self, stream: MaybeTls<TcpStream>) -> ResetOnDrop {
+        ResetOnDrop {
+            stream,
+            requested: self.requested.clone(),
+        }
+    }
+
+    /// Ask for the connection to be reset.
+    pub(crate) fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    /// Resolve once a reset was requested.
+    pub(crate) async fn requested(&self) {
+        self.notify.notified().await;
+    }
+}
+
+/// A socket that closes with a TCP reset (`SO_LINGER` of zero) if its
+/// connection's [`Reset`] was requested by the time it is dropped. With TLS on
+/// top, the reset cuts the session short without a `close_notify`.
+pub(crate) struct ResetOnDrop {
+    stream: MaybeTls<TcpStream>,
+    requested: Arc<AtomicBool>,
+}
+
+impl Drop for ResetOnDrop {
+    fn drop(&mut self) {
+        if self.requested.load(Ordering::Relaxed) {
+            let _ = self.stream.get_ref().set_zero_linger();
+        }
+    }
+}
+
+impl AsyncRead for ResetOnDrop {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ResetOnDrop {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,196 @@
+//! Payload formatters that `logged-stream` does not provide out of the box.
+//!
+//! The single-line numeric notations (`decimal`, `lowerhex`, ...) come straight from
+//! `logged-stream`; the formatters here implement [`BufferFormatter`] themselves so
+//! [`get_formatter_by_kind`](crate::args::get_formatter_by_kind) can hand them out
+//! through the same [`PayloadFormatter`] as the built-in ones.
+
+use logged_stream::BufferFormatter;
+use std::fmt::Write;
+
+/// The formatter a connection's `LoggedStream`s are built with: whichever
+/// [`BufferFormatter`] `--formatting` selected, behind one concrete type.
+///
+/// A plain `Box<dyn BufferFormatter>` is not enough: `logged-stream` implements the
+/// trait for the box by forwarding only the two required methods, so the box falls
+/// back to the default `format_buffer` (bytes joined by the separator) and silently
+/// drops any formatter's own override of it — which is the whole rendering for the
+/// formatters in this module. This wrapper forwards all three.
+///
+/// It is `Sync` so a connection's two relays can share one (`--output-format jsonl`
+/// encodes the payload events with it).
+pub struct PayloadFormatter(Box<dyn BufferFormatter + Sync>);
+
+impl PayloadFormatter {
+    pub fn new(formatter: impl BufferFormatter + Sync) -> Self {
+        Self(Box::new(formatter))
+    }
+}
+
+// Each call goes through `*self.0` — the trait object itself — on purpose: a plain
+// `self.0.method()` would resolve to the `Box` impl described above.
+impl BufferFormatter for PayloadFormatter {
+    fn get_separator(&self) -> &str {
+        (*self.0).get_separator()
+    }
+
+    fn format_byte(&self, byte: &u8) -> String {
+        (*self.0).format_byte(byte)
+    }
+
+    fn format_buffer(&self, buffer: &[u8]) -> String {
+        (*self.0).format_buffer(buffer)
+    }
+}
+
+/// Number of bytes after which a hexdump row inserts an extra space between its hex
+/// columns, as `hexdump -C` and Wireshark do, so long rows stay easy to count.
+const HEXDUMP_GROUP: usize = 8;
+
+/// The classic `hexdump -C` view of a relayed chunk: rows of `width` bytes, each
+/// with an offset column, the hex bytes (grouped in eights) and a printable-ASCII
+/// gutter in which every non-printable byte is shown as `.`:
+///
+/// ```text
+/// 00000000  48 65 6c 6c 6f 2c 20 4d  4f 44 42 55 53 21 0d 0a  |Hello, MODBUS!..|
+/// ```
+///
+/// The rendering is a multi-line block (rows joined by `\n`); the per-connection
+/// logger prints each row as its own console line. Offsets are relative to the start
+/// of the chunk, so every relayed chunk starts again at `00000000`. The last row is
+/// padded so its ASCII gutter lines up with the rows above it.
+#[derive(Debug, Clone)]
+pub struct HexdumpFormatter {
+    width: usize,
+}
+
+impl HexdumpFormatter {
+    /// Build a formatter that renders `width` bytes per row. `width` must be non-zero
+    /// (`--hexdump-width` is range-validated by clap).
+    pub fn new(width: usize) -> Self {
+        debug_assert!(width > 0, "a hexdump row must hold at least one byte");
+        Self { width }
+    }
+
+    /// Append one row (at most `width` bytes of `chunk`, starting at `offset`).
+    fn write_row(&self, output: &mut String, offset: usize, row: &[u8]) {
+        let _ = write!(output, "{offset:08x} ");
+        for column in 0..self.width {
+            if column % HEXDUMP_GROUP == 0 {
+                output.push(' ');
+            }
+            match row.get(column) {
+                Some(byte) => {
+                    let _ = write!(output, "{byte:02x} ");
+                }
+                None => output.push_str("   "),
+            }
+        }
+        output.push_str(" |");
+        output.extend(row.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        output.push('|');
+    }
+}
+
+impl BufferFormatter for HexdumpFormatter {
+    fn get_separator(&self) -> &str {
+        " "
+    }
+
+    fn format_byte(&self, byte: &u8) -> String {
+        format!("{byte:02x}")
+    }
+
+    fn format_buffer(&self, buffer: &[u8]) -> String {
+        let mut output = String::new();
+        for (index, row) in buffer.chunks(self.width).enumerate() {
+            if index > 0 {
+                output.push('\n');
+            }
+            self.write_row(&mut output, index * self.width, row);
+        }
+        output
+    }
+}
+
+/// Printable-text views of a relayed chunk, for line-based protocols (HTTP, SMTP,
+/// Redis, ...) that are painful to read in hex. The chunk is rendered on a single
+/// line: printable characters are shown as themselves, and everything else is
+/// escaped — `\r`, `\n` and `\t` by name, a backslash as `\\` (so the output stays
+/// unambiguous), and any other byte as `\xNN` — so one console line still equals
+/// one relayed chunk.
+#[derive(Debug, Clone)]
+pub struct TextFormatter {
+    utf8: bool,
+}
+
+impl TextFormatter {
+    /// `--formatting text`: every byte stands on its own, so only printable ASCII is
+    /// shown as-is and any non-ASCII byte is escaped as `\xNN`.
+    pub fn ascii() -> Self {
+        Self { utf8: false }
+    }
+
+    /// `--formatting utf8-lossy`: the chunk is decoded as UTF-8, so non-ASCII text
+    /// is shown as characters. A byte that is not part of a valid sequence becomes
+    /// U+FFFD (`�`), as does a multi-byte character that a TCP segment boundary cut
+    /// in two; non-printable characters, and the Unicode line and paragraph
+    /// separators (U+2028, U+2029), are still escaped.
+    pub fn utf8_lossy() -> Self {
+        Self { utf8: true }
+    }
+
+    /// Append `character`, escaped if it is not printable.
+    fn push_escaped(output: &mut String, character: char) {
+        match character {
+            '\r' => output.push_str("\\r"),
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            '\\' => output.push_str("\\\\"),
+            ' ' => output.push(' '),
+            _ if character.is_ascii_graphic() => output.push(character),
+            _ if character.is_ascii() => {
+                let _ = write!(output, "\\x{:02x}", character as u8);
+            }
+            // NEL is a control character already; the line and paragraph
+            // separators are not, but some viewers break lines at them too.
+            _ if character.is_control() || matches!(character, '\u{2028}' | '\u{2029}') => {
+                let _ = write!(output, "\\u{{{:x}}}", character as u32);
+            }
+            _ => output.push(character),
+        }
+    }
+}
+
+impl BufferFormatter for TextFormatter {
+    fn get_separator(&self) -> &str {
+        ""
+    }
+
+    fn format_byte(&self, byte: &u8) -> String {
+        if byte.is_ascii() {
+            let mut output = String::new();
+            Self::push_escaped(&mut output, *byte as char);
+            output
+        } else {
+            format!("\\x{byte:02x}")
+        }
+    }
+
+    fn format_buffer(&self, buffer: &[u8]) -> String {
+        if !self.utf8 {
+            return buffer.iter().map(|byte| self.format_byte(byte)).collect();
+        }
+        let mut output = String::with_capacity(buffer.len());
+        for character in String::from_utf8_lossy(buffer).chars() {
+            Self::push_escaped(&mut output, character);
+        }
+        output
+    }
+}
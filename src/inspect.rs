@@ -0,0 +1,369 @@
+//! `--inspect-tls`: a passive look at the TLS handshakes the proxy relays without
+//! terminating them. Each direction's first bytes are parsed as TLS records, and
+//! what the handshake shows in the clear is reported — the client's server name,
+//! ALPN protocols, versions and cipher suites, the server's picks and any alerts
+//! — while the bytes themselves are relayed, and logged, untouched.
+//!
+//! The parsing gives up quietly: on bytes that are not TLS, once the peers switch
+//! to encrypted records, or after [`MAX_INSPECTED`] bytes.
+//!
+//! `--sni-route` reads a client's hello with the same parser ([`opening`]), to
+//! pick its destination by the server name.
+
+use crate::conn::Direction;
+use std::sync::Mutex;
+
+/// How much of a direction is looked at before the inspection gives up: enough
+/// for a hello and a certificate chain.
+const MAX_INSPECTED: usize = 64 * 1024;
+
+/// The largest record length TLS allows (2^14 bytes of plaintext, plus the
+/// expansion allowed for a protected record).
+const MAX_RECORD: usize = 16384 + 2048;
+
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+
+const SERVER_NAME: u16 = 0;
+const ALPN: u16 = 16;
+const SUPPORTED_VERSIONS: u16 = 43;
+
+/// The `random` of a ServerHello that is a HelloRetryRequest (RFC 8446, 4.1.3).
+const HELLO_RETRY_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// What a client offers in its ClientHello.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ClientHello {
+    pub(crate) server_name: Option<String>,
+    pub(crate) alpn: Vec<String>,
+    /// From the `supported_versions` extension, or the legacy version field
+    /// without one; GREASE values left out.
+    pub(crate) versions: Vec<u16>,
+    /// GREASE values left out.
+    pub(crate) cipher_suites: Vec<u16>,
+}
+
+/// What a server picks in its ServerHello.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ServerHello {
+    pub(crate) version: u16,
+    pub(crate) cipher_suite: u16,
+    pub(crate) alpn: Option<String>,
+    /// A HelloRetryRequest: the server asks the client for another ClientHello.
+    pub(crate) retry: bool,
+}
+
+/// What an inspection saw in the clear.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Message {
+    ClientHello(ClientHello),
+    ServerHello(ServerHello),
+    /// An alert record: its level (1, warning; 2, fatal) and description.
+    Alert {
+        level: u8,
+        description: u8,
+    },
+}
+
+/// One direction's parser, fed the bytes as they are read.
+pub(crate) struct Inspector {
+    /// Bytes of a record not complete yet.
+    pending: Vec<u8>,
+    /// Bytes of a handshake message not complete yet, across records.
+    handshake: Vec<u8>,
+    /// Bytes looked at so far.
+    seen: usize,
+    /// Nothing more to look at.
+    done: bool,
+}
+
+impl Inspector {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            handshake: Vec::new(),
+            seen: 0,
+            done: false,
+        }
+    }
+
+    /// Parse the next `bytes` of the direction and return what they complete.
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> Vec<Message> {
+        let mut messages = Vec::new();
+        if self.done {
+            return messages;
+        }
+        self.seen += bytes.len();
+        self.pending.extend_from_slice(bytes);
+        let mut start = 0;
+        while let Some(header) = self.pending.get(start..start + 5) {
+            let (kind, major) = (header[0], header[1]);
+            let length = usize::from(u16::from_be_bytes([header[3], header[4]]));
+            let plaintext = kind == HANDSHAKE || kind == ALERT;
+            if !plaintext || major != 3 || length > MAX_RECORD {
+                // Not TLS at all, or the handshake's plaintext part is over: a
+                // ChangeCipherSpec or application data record follows.
+                self.done = true;
+                break;
+            }
+            let Some(fragment) = self.pending.get(start + 5..start + 5 + length) else {
+                break;
+            };
+            if kind == ALERT {
+                for alert in fragment.chunks_exact(2) {
+                    messages.push(Message::Alert {
+                        level: alert[0],
+                        description: alert[1],
+                    });
+                }
+            } else {
+                self.handshake.extend_from_slice(fragment);
+                self.handshake_messages(&mut messages);
+            }
+            start += 5 + length;
+        }
+        self.pending.drain(..start.min(self.pending.len()));
+        if self.done || self.seen > MAX_INSPECTED {
+            self.done = true;
+            self.pending = Vec::new();
+            self.handshake = Vec::new();
+        }
+        messages
+    }
+
+    /// Take the complete handshake messages off `self.handshake`.
+    fn handshake_messages(&mut self, messages: &mut Vec<Message>) {
+        let mut start = 0;
+        while let Some(header) = self.handshake.get(start..start + 4) {
+            let kind = header[0];
+            let length =
+                usize::from(header[1]) << 16 | usize::from(header[2]) << 8 | usize::from(header[3]);
+            let Some(body) = self.handshake.get(start + 4..start + 4 + length) else {
+                break;
+            };
+            let message = match kind {
+                CLIENT_HELLO => client_hello(&mut Reader(body)).map(Message::ClientHello),
+                SERVER_HELLO => server_hello(&mut Reader(body)).map(Message::ServerHello),
+                _ => None,
+            };
+            messages.extend(message);
+            start += 4 + length;
+        }
+        self.handshake.drain(..start);
+    }
+}
+
+/// Both directions' [`Inspector`]s of a connection, fed by its two relays
+/// through a shared reference.
+pub(crate) struct Inspection {
+    client_to_destination: Mutex<Inspector>,
+    destination_to_client: Mutex<Inspector>,
+}
+
+impl Inspection {
+    pub(crate) fn new() -> Self {
+        Self {
+            client_to_destination: Mutex::new(Inspector::new()),
+            destination_to_client: Mutex::new(Inspector::new()),
+        }
+    }
+
+    /// Parse the next `bytes` read in `direction`.
+    pub(crate) fn feed(&self, direction: Direction, bytes: &[u8]) -> Vec<Message> {
+        let inspector = match direction {
+            Direction::ClientToDestination => &self.client_to_destination,
+            Direction::DestinationToClient => &self.destination_to_client,
+        };
+        inspector
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .feed(bytes)
+    }
+}
+
+/// What the first bytes of a client's stream say of its hello.
+pub(crate) enum Opening {
+    Hello(ClientHello),
+    /// A TLS record that is not complete yet.
+    Incomplete,
+    /// Anything but a ClientHello.
+    NotHello,
+}
+
+/// Parse the first `bytes` of a client's stream as its ClientHello.
+pub(crate) fn opening(bytes: &[u8]) -> Opening {
+    let mut inspector = Inspector::new();
+    match inspector.feed(bytes).into_iter().next() {
+        Some(Message::ClientHello(hello)) => Opening::Hello(hello),
+        Some(_) => Opening::NotHello,
+        None if inspector.done => Opening::NotHello,
+        None => Opening::Incomplete,
+    }
+}
+
+/// A cursor over a handshake message's body; every read is `None` past its end.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A vector with a one-byte length.
+    fn vector8(&mut self) -> Option<Reader<'a>> {
+        let length = self.u8()?;
+        self.take(length.into()).map(Reader)
+    }
+
+    /// A vector with a two-byte length.
+    fn vector16(&mut self) -> Option<Reader<'a>> {
+        let length = self.u16()?;
+        self.take(length.into()).map(Reader)
+    }
+
+    /// The extensions at the end of a hello, as `(type, body)`; none if the
+    /// hello ends before them.
+    fn extensions(&mut self) -> Option<Vec<(u16, Reader<'a>)>> {
+        let mut extensions = Vec::new();
+        if self.0.is_empty() {
+            return Some(extensions);
+        }
+        let mut list = self.vector16()?;
+        while !list.0.is_empty() {
+            let kind = list.u16()?;
+            extensions.push((kind, list.vector16()?));
+        }
+        Some(extensions)
+    }
+}
+
+/// The values TLS reserves to keep peers tolerant of unknown ones (RFC 8701).
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// The protocols of an ALPN extension.
+fn protocols(mut extension: Reader<'_>) -> Option<Vec<String>> {
+    let mut list = extension.vector16()?;
+    let mut protocols = Vec::new();
+    while !list.0.is_empty() {
+        protocols.push(String::from_utf8_lossy(list.vector8()?.0).into_owned());
+    }
+    Some(protocols)
+}
+
+fn client_hello(body: &mut Reader<'_>) -> Option<ClientHello> {
+    let legacy_version = body.u16()?;
+    body.take(32)?;
+    body.vector8()?;
+    let mut suites = body.vector16()?;
+    let mut cipher_suites = Vec::new();
+    while let Some(suite) = suites.u16() {
+        if !is_grease(suite) {
+            cipher_suites.push(suite);
+        }
+    }
+    body.vector8()?;
+    let mut hello = ClientHello {
+        server_name: None,
+        alpn: Vec::new(),
+        versions: vec![legacy_version],
+        cipher_suites,
+    };
+    for (kind, mut extension) in body.extensions()? {
+        match kind {
+            SERVER_NAME => {
+                let mut list = extension.vector16()?;
+                while !list.0.is_empty() {
+                    let name_type = list.u8()?;
+                    let name = list.vector16()?;
+                    // 0: a host name, the only type defined.
+                    if name_type == 0 && hello.server_name.is_none() {
+                        hello.server_name = Some(String::from_utf8_lossy(name.0).into_owned());
+                    }
+                }
+            }
+            ALPN => hello.alpn = protocols(extension)?,
+            SUPPORTED_VERSIONS => {
+                let mut list = extension.vector8()?;
+                hello.versions.clear();
+                while let Some(version) = list.u16() {
+                    if !is_grease(version) {
+                        hello.versions.push(version);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(hello)
+}
+
+fn server_hello(body: &mut Reader<'_>) -> Option<ServerHello> {
+    let mut version = body.u16()?;
+    let retry = body.take(32)? == HELLO_RETRY_RANDOM;
+    body.vector8()?;
+    let cipher_suite = body.u16()?;
+    body.u8()?;
+    let mut alpn = None;
+    for (kind, mut extension) in body.extensions()? {
+        match kind {
+            ALPN => alpn = protocols(extension)?.into_iter().next(),
+            SUPPORTED_VERSIONS => version = extension.u16()?,
+            _ => {}
+        }
+    }
+    Some(ServerHello {
+        version,
+        cipher_suite,
+        alpn,
+        retry,
+    })
+}
+
+/// A protocol version as people write it: `TLS 1.3`.
+pub(crate) fn version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSL 3.0".to_string(),
+        0x0301 => "TLS 1.0".to_string(),
+        0x0302 => "TLS 1.1".to_string(),
+        0x0303 => "TLS 1.2".to_string(),
+        0x0304 => "TLS 1.3".to_string(),
+        _ => format!("0x{version:04x}"),
+    }
+}
+
+/// A cipher suite by its IANA name, as in the `tls_established` lines.
+pub(crate) fn cipher_suite_name(suite: u16) -> String {
+    format!("{:?}", rustls::CipherSuite::from(suite))
+}
+
+/// An alert's level and description: `fatal HandshakeFailure`.
+pub(crate) fn alert_name(level: u8, description: u8) -> String {
+    let level = match level {
+        1 => "warning".to_string(),
+        2 => "fatal".to_string(),
+        other => format!("level {other}"),
+    };
+    format!("{level} {:?}", rustls::AlertDescription::from(description))
+}
@@ -0,0 +1,92 @@
+//! `--output-format jsonl`: every console line as one JSON object, for piping the
+//! proxy's output into tools instead of parsing the human-oriented lines with
+//! regexes.
+//!
+//! Nothing logs JSON directly. Lines keep going through the `log` facade (so
+//! `--level` filters them as before); the events that matter attach their
+//! structured fields — the `event` name, the connection id, the client and
+//! destination addresses, a payload chunk's direction and byte count, ... — as `log`
+//! key-values, which the text output ignores and [`render`] turns into JSON fields.
+
+use crate::args::TimestampPrecision;
+use env_logger::fmt::Formatter;
+use log::kv;
+use std::fmt::Display;
+use std::io::Write;
+use std::io::{self};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// The reference point of the `monotonic_us` field: when the first line was
+/// rendered, i.e. effectively when the proxy started. Unlike the wall-clock
+/// `timestamp`, it never jumps, so it is the field to compute durations from.
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// The `env_logger` format function for `--output-format jsonl`, stamping each
+/// line's wall-clock `timestamp` at `precision`.
+pub fn formatter(
+    precision: TimestampPrecision,
+) -> impl Fn(&mut Formatter, &log::Record<'_>) -> io::Result<()> + Sync + Send {
+    LazyLock::force(&STARTED);
+    move |buffer, record| {
+        let timestamp = match precision {
+            TimestampPrecision::Seconds => buffer.timestamp_seconds(),
+            TimestampPrecision::Milliseconds => buffer.timestamp_millis(),
+            TimestampPrecision::Microseconds => buffer.timestamp_micros(),
+            TimestampPrecision::Nanoseconds => buffer.timestamp_nanos(),
+        };
+        let monotonic_us = STARTED.elapsed().as_micros() as u64;
+        writeln!(buffer, "{}", render(record, timestamp, monotonic_us))
+    }
+}
+
+/// Render one record as a single-line JSON object: `timestamp`, `monotonic_us` and
+/// `level` first, then the record's key-values in the order they were attached,
+/// then the human-readable `message`. Unsigned integers and booleans stay JSON
+/// numbers and booleans; every other value is written as a string.
+pub(crate) fn render(
+    record: &log::Record<'_>,
+    timestamp: impl Display,
+    monotonic_us: u64,
+) -> String {
+    let mut line = String::from("{");
+    push_field(&mut line, "timestamp", &string(timestamp));
+    push_field(&mut line, "monotonic_us", &monotonic_us.to_string());
+    push_field(&mut line, "level", &string(record.level()));
+    let _ = record.key_values().visit(&mut Fields(&mut line));
+    push_field(&mut line, "message", &string(record.args()));
+    line.push('}');
+    line
+}
+
+/// Collects a record's key-values into the object being rendered.
+struct Fields<'a>(&'a mut String);
+
+impl<'kvs> kv::VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            number.to_string()
+        } else if let Some(flag) = value.to_bool() {
+            flag.to_string()
+        } else {
+            string(value)
+        };
+        push_field(self.0, key.as_str(), &value);
+        Ok(())
+    }
+}
+
+/// Append `"key":value`, where `value` is already JSON.
+fn push_field(line: &mut String, key: &str, value: &str) {
+    if line.len() > 1 {
+        line.push(',');
+    }
+    line.push_str(&string(key));
+    line.push(':');
+    line.push_str(value);
+}
+
+/// `value`'s `Display` output as a JSON string literal.
+fn string(value: impl Display) -> String {
+    serde_json::Value::String(value.to_string()).to_string()
+}
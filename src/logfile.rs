@@ -0,0 +1,233 @@
+//! `--log-file`: the console output written to a file instead of stderr, rotated by
+//! size and/or age, with old segments optionally gzip-compressed.
+//!
+//! [`LogFile`] is handed to `env_logger` as its output target, so every line the
+//! proxy logs — listener-level and per-connection alike — lands in it. `env_logger`
+//! renders each record into a buffer of its own and writes the whole buffer in one
+//! call, and [`LogFile`] only ever rotates *between* two such writes, so a record is
+//! never split across segments or cut off mid-line.
+//!
+//! Rotated segments are named after the active file with an increasing sequence
+//! number — `proxy.log.1`, `proxy.log.2`, ... (`.gz` appended once compressed), the
+//! highest being the most recent — and numbering resumes after the highest existing
+//! segment when the proxy restarts. Compressing and pruning run on a background
+//! thread so a rotation never stalls the relays behind a large segment.
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+use std::io::{self};
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// When the active file is rotated, and what happens to the rotated segments.
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    /// `--log-rotate-size`: rotate before a record would grow the file past this.
+    pub size: Option<u64>,
+    /// `--log-rotate-interval`: rotate once the file has been written to this long.
+    pub interval: Option<Duration>,
+    /// `--log-keep`: how many rotated segments are kept; older ones are deleted.
+    pub keep: usize,
+    /// `--log-compress`: gzip each rotated segment.
+    pub compress: bool,
+}
+
+/// The active log file and its rotation state.
+pub struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: BufWriter<File>,
+    /// Bytes in the active file, including what it held when it was opened.
+    size: u64,
+    /// When the active file was opened (or last rotated).
+    opened: Instant,
+    /// Sequence number of the next rotated segment.
+    next_segment: u64,
+    /// The compress-and-prune job of the last rotation, if it may still be running.
+    housekeeping: Option<JoinHandle<()>>,
+}
+
+impl LogFile {
+    /// Open (or create) `path` for appending. Existing content counts towards
+    /// `--log-rotate-size`, and existing segments towards `--log-keep`.
+    pub fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        let next_segment = segments(path)?
+            .last()
+            .map_or(1, |segment| segment.number + 1);
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            file: BufWriter::new(file),
+            size,
+            opened: Instant::now(),
+            next_segment,
+            housekeeping: None,
+        })
+    }
+
+    /// Whether the active file must be rotated before `incoming` more bytes go in.
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        // An empty file is never rotated: a record larger than the size limit gets
+        // a segment of its own instead of an endless run of empty ones.
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .rotation
+            .size
+            .is_some_and(|limit| self.size + incoming as u64 > limit);
+        let too_old = self
+            .rotation
+            .interval
+            .is_some_and(|interval| self.opened.elapsed() >= interval);
+        too_big || too_old
+    }
+
+    /// Move the active file aside as the next segment and start a fresh one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let segment = segment_path(&self.path, self.next_segment, false);
+        fs::rename(&self.path, &segment)?;
+        self.next_segment += 1;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        self.opened = Instant::now();
+
+        // One job at a time: a rotation that outpaces compression waits for it
+        // rather than racing it over the same segments.
+        self.finish_housekeeping();
+        let path = self.path.clone();
+        let rotation = self.rotation.clone();
+        self.housekeeping = Some(std::thread::spawn(move || {
+            if rotation.compress {
+                let _ = compress(&segment);
+            }
+            let _ = prune(&path, rotation.keep);
+        }));
+        Ok(())
+    }
+
+    /// Wait for the last rotation's compress-and-prune job, if any.
+    fn finish_housekeeping(&mut self) {
+        if let Some(job) = self.housekeeping.take() {
+            let _ = job.join();
+        }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buffer.len()) {
+            // A failed rotation must not lose the record: it goes to the file that
+            // is already open, which then simply keeps growing.
+            let _ = self.rotate();
+        }
+        // Always take the whole buffer, so `write_all` never splits a record.
+        self.file.write_all(buffer)?;
+        self.size += buffer.len() as u64;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        let _ = self.file.flush();
+        self.finish_housekeeping();
+    }
+}
+
+/// A rotated segment of a log file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Segment {
+    pub(crate) number: u64,
+    pub(crate) path: PathBuf,
+}
+
+/// `{path}.{number}`, or `{path}.{number}.gz` when `compressed`.
+fn segment_path(path: &Path, number: u64, compressed: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{number}"));
+    if compressed {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+
+/// The rotated segments of `path` on disk, oldest first. A segment that is being
+/// compressed is listed once, under whichever of its two files is found first.
+pub(crate) fn segments(path: &Path) -> io::Result<Vec<Segment>> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let Some(prefix) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let mut segments: Vec<Segment> = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(number) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|rest| rest.strip_prefix('.'))
+            .map(|rest| rest.strip_suffix(".gz").unwrap_or(rest))
+            .and_then(|number| number.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        if segments.iter().all(|segment| segment.number != number) {
+            segments.push(Segment {
+                number,
+                path: entry.path(),
+            });
+        }
+    }
+    segments.sort_by_key(|segment| segment.number);
+    Ok(segments)
+}
+
+/// Gzip `segment` into `{segment}.gz`, removing the original once the copy is
+/// complete.
+fn compress(segment: &Path) -> io::Result<()> {
+    let mut name = segment.as_os_str().to_owned();
+    name.push(".gz");
+    let compressed = PathBuf::from(name);
+    let mut encoder = GzEncoder::new(
+        BufWriter::new(File::create(&compressed)?),
+        Compression::default(),
+    );
+    io::copy(&mut File::open(segment)?, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    fs::remove_file(segment)
+}
+
+/// Delete the oldest segments of `path` until at most `keep` are left.
+fn prune(path: &Path, keep: usize) -> io::Result<()> {
+    let segments = segments(path)?;
+    let excess = segments.len().saturating_sub(keep);
+    for segment in &segments[..excess] {
+        for compressed in [false, true] {
+            let _ = fs::remove_file(segment_path(path, segment.number, compressed));
+        }
+    }
+    Ok(())
+}
@@ -1,29 +1,168 @@
+mod admin;
 mod args;
+mod capture;
+mod config;
 mod conn;
+mod decode;
+mod endpoint;
+mod fault;
+mod formatters;
+mod inspect;
+mod jsonl;
+mod logfile;
+mod metrics;
+mod netem;
+mod pcap;
+mod replay;
+mod sni;
 #[cfg(test)]
 mod tests;
+mod tls;
+mod tui;
 
 use args::Arguments;
-use clap::Parser;
+use args::Cli;
+use args::Command;
+use args::ExplicitArgs;
+use args::OutputFormat;
+use args::ReplayArguments;
+use args::ReplayMode;
+use config::CommandLine;
 use conn::initialize_tcp_listener;
+use logfile::LogFile;
+use logfile::Rotation;
+use std::time::Duration;
+use tui::Tui;
 
 fn main() {
-    let arguments = Arguments::parse();
+    match Cli::parse_explicit() {
+        (Command::Proxy(arguments), explicit) => run_proxy(arguments, explicit),
+        (Command::Replay(arguments), _) => run_replay(arguments),
+    }
+}
 
-    env_logger::builder()
+fn run_proxy(arguments: Arguments, explicit: ExplicitArgs) {
+    // A `--config` file fills in what the command line leaves out. A bad file is
+    // reported once the logger is up (set up from the command line alone), and the
+    // proxy exits non-zero before binding anything.
+    let command_line = CommandLine {
+        arguments,
+        explicit,
+    };
+    let (arguments, config_error) = match command_line.load() {
+        Ok(merged) => (merged, None),
+        Err(error) => (command_line.arguments.clone(), Some(error)),
+    };
+
+    let mut logger = env_logger::builder();
+    logger
         .parse_default_env()
         .filter_level(arguments.level.into())
         .format_target(false)
         .format_module_path(false)
-        .format_timestamp(Some(From::from(arguments.precision)))
+        .format_timestamp(Some(From::from(arguments.precision)));
+    if arguments.output_format == OutputFormat::Jsonl {
+        logger.format(jsonl::formatter(arguments.precision));
+    }
+    // `--log-file` replaces stderr for every line, listener-level and per-connection
+    // alike. A file that cannot be opened is reported on stderr, the only place
+    // left to report it, and the proxy exits non-zero before binding anything.
+    let mut log_file_error = None;
+    if let Some(path) = &arguments.log_file {
+        let rotation = Rotation {
+            size: arguments.log_rotate_size,
+            interval: arguments.log_rotate_interval.map(Duration::from_secs),
+            keep: arguments.log_keep as usize,
+            compress: arguments.log_compress,
+        };
+        match LogFile::open(path, rotation) {
+            Ok(file) => {
+                logger.target(env_logger::Target::Pipe(Box::new(file)));
+            }
+            Err(error) => log_file_error = Some(error),
+        }
+    }
+    // `--tui` takes the terminal over, its logger in place of the console one. It is
+    // started only once the configuration is known to be good, so the errors above
+    // still reach stderr; one that cannot start is reported there too.
+    let mut tui = None;
+    let mut tui_error = None;
+    if arguments.tui && config_error.is_none() && log_file_error.is_none() {
+        match Tui::start(&arguments) {
+            Ok(started) => tui = Some(started),
+            Err(error) => tui_error = Some(error),
+        }
+    }
+    match &tui {
+        Some(tui) => tui
+            .logger(logger.build(), arguments.log_file.is_some())
+            .install()
+            .expect("the logger is installed once"),
+        None => logger.init(),
+    }
+    if let Some(error) = tui_error {
+        log::error!(event = "tui_failed"; "Failed to start the terminal UI: {error}");
+        std::process::exit(1);
+    }
+    if let Some(error) = config_error {
+        log::error!(event = "config_failed"; "Invalid configuration: {error}");
+        std::process::exit(1);
+    }
+    if let (Some(path), Some(error)) = (&arguments.log_file, log_file_error) {
+        log::error!(
+            event = "log_file_failed";
+            "Failed to open log file {}: {error}",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+
+    let runtime = build_runtime(arguments.threads as usize);
+
+    // A fatal failure (e.g. the listener address is unavailable, or an endpoint
+    // that panicked) is logged inside `initialize_tcp_listener`; exit non-zero so
+    // callers/scripts notice.
+    // The command line is kept so a SIGHUP can read the configuration again. The
+    // terminal is given back before exiting, whichever way the proxy stops.
+    let link = tui.as_ref().map(Tui::link);
+    let result = runtime.block_on(initialize_tcp_listener(arguments, Some(command_line), link));
+    if let Some(tui) = tui {
+        tui.stop();
+    }
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
+
+fn run_replay(arguments: ReplayArguments) {
+    let (ReplayMode::Client { options, .. } | ReplayMode::Server { options, .. }) = &arguments.mode;
+    env_logger::builder()
+        .parse_default_env()
+        .filter_level(options.level.into())
+        .format_target(false)
+        .format_module_path(false)
+        .format_timestamp(Some(From::from(options.precision)))
         .init();
 
-    // Build the multi-threaded Tokio runtime by hand (instead of via the
-    // `#[tokio::main]` macro) so its worker-thread count comes from the `--threads`
-    // CLI argument at startup rather than being fixed at compile time. A runtime that
-    // fails to build is logged and the process exits non-zero.
-    let runtime = match tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(arguments.threads as usize)
+    // One connection is replayed at a time (per client, in server mode): the
+    // default worker count is plenty.
+    let runtime =
+        build_runtime(std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get));
+
+    // Failures are logged inside `replay::run`; a replay that ran but differed
+    // from the recording exits non-zero as well, so CI notices.
+    if !matches!(runtime.block_on(replay::run(arguments.mode)), Ok(true)) {
+        std::process::exit(1);
+    }
+}
+
+/// Build the multi-threaded Tokio runtime by hand (instead of via the
+/// `#[tokio::main]` macro) so its worker-thread count comes from the `--threads`
+/// CLI argument at startup rather than being fixed at compile time. A runtime that
+/// fails to build is logged and the process exits non-zero.
+fn build_runtime(worker_threads: usize) -> tokio::runtime::Runtime {
+    match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
         .enable_all()
         .build()
     {
@@ -32,14 +171,5 @@ fn main() {
             log::error!("Failed to build the Tokio runtime: {error}");
             std::process::exit(1);
         }
-    };
-
-    // A fatal startup failure (e.g. the listener address is unavailable) is logged
-    // inside `initialize_tcp_listener`; exit non-zero so callers/scripts notice.
-    if runtime
-        .block_on(initialize_tcp_listener(arguments))
-        .is_err()
-    {
-        std::process::exit(1);
     }
 }
@@ -0,0 +1,339 @@
+//! `--metrics-addr <addr>`: counters about every route and its connections, served
+//! in the Prometheus text exposition format for dashboards over long-running rigs.
+//!
+//! Every route keeps a [`RouteMetrics`] of plain atomics, updated where the event
+//! happens (the accept loop, the connect, the relays), and every series carries the
+//! route's name as its `route` label. [`serve`] answers `GET /metrics` with all of
+//! them, through the [`endpoint`] server:
+//!
+//! ```text
+//! # HELP logged_tcp_proxy_connections_accepted_total Connections accepted.
+//! # TYPE logged_tcp_proxy_connections_accepted_total counter
+//! logged_tcp_proxy_connections_accepted_total{route="db"} 42
+//! ```
+
+use crate::conn::Direction;
+use crate::endpoint;
+use crate::endpoint::Request;
+use crate::endpoint::Response;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// Every metric name starts with this.
+const PREFIX: &str = "logged_tcp_proxy";
+
+/// The metrics of every route, in the order the routes were bound.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    routes: Mutex<Vec<Arc<RouteMetrics>>>,
+}
+
+impl Metrics {
+    /// Register the route named `route` and return its metrics.
+    pub(crate) fn route(&self, route: String) -> Arc<RouteMetrics> {
+        let metrics = Arc::new(RouteMetrics {
+            route,
+            ..RouteMetrics::default()
+        });
+        self.routes
+            .lock()
+            .expect("metrics mutex poisoned")
+            .push(metrics.clone());
+        metrics
+    }
+
+    /// Everything, in the Prometheus text format.
+    pub(crate) fn render(&self) -> String {
+        let routes = self.routes.lock().expect("metrics mutex poisoned").clone();
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, samples: &dyn Fn(&mut String)| {
+            let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+            let _ = writeln!(out, "# TYPE {PREFIX}_{name} {kind}");
+            samples(&mut out);
+        };
+        let each = |out: &mut String, name: &str, value: &dyn Fn(&RouteMetrics) -> String| {
+            for route in &routes {
+                let _ = writeln!(
+                    out,
+                    "{PREFIX}_{name}{{route=\"{}\"}} {}",
+                    escape(&route.route),
+                    value(route)
+                );
+            }
+        };
+        family(
+            "connections_accepted_total",
+            "counter",
+            "Connections accepted.",
+            &|out| each(out, "connections_accepted_total", &|r| load(&r.accepted)),
+        );
+        family(
+            "connections_active",
+            "gauge",
+            "Connections accepted and not closed yet.",
+            &|out| {
+                each(out, "connections_active", &|r| {
+                    let closed = r.closed.load(Ordering::Relaxed);
+                    r.accepted
+                        .load(Ordering::Relaxed)
+                        .saturating_sub(closed)
+                        .to_string()
+                })
+            },
+        );
+        family(
+            "connections_closed_total",
+            "counter",
+            "Connections closed, whatever ended them.",
+            &|out| each(out, "connections_closed_total", &|r| load(&r.closed)),
+        );
+        family(
+            "bytes_relayed_total",
+            "counter",
+            "Bytes relayed, by direction.",
+            &|out| {
+                for route in &routes {
+                    for (direction, bytes) in [
+                        ("client_to_destination", &route.client_to_destination),
+                        ("destination_to_client", &route.destination_to_client),
+                    ] {
+                        let _ = writeln!(
+                            out,
+                            "{PREFIX}_bytes_relayed_total{{route=\"{}\",direction=\"{direction}\"}} {}",
+                            escape(&route.route),
+                            load(bytes)
+                        );
+                    }
+                }
+            },
+        );
+        family(
+            "connect_failures_total",
+            "counter",
+            "Connections to the destination that failed, by error kind (DNS and TLS failures not included).",
+            &|out| {
+                for route in &routes {
+                    let failures = route
+                        .connect_failures
+                        .lock()
+                        .expect("metrics mutex poisoned")
+                        .clone();
+                    for (kind, count) in failures {
+                        let _ = writeln!(
+                            out,
+                            "{PREFIX}_connect_failures_total{{route=\"{}\",kind=\"{kind}\"}} {count}",
+                            escape(&route.route)
+                        );
+                    }
+                }
+            },
+        );
+        family(
+            "dns_failures_total",
+            "counter",
+            "Destination hostnames that did not resolve.",
+            &|out| each(out, "dns_failures_total", &|r| load(&r.dns_failures)),
+        );
+        family(
+            "tls_handshake_failures_total",
+            "counter",
+            "TLS handshakes that failed or timed out, by peer: the client's (--tls-cert) or the destination's (--remote-tls).",
+            &|out| {
+                for route in &routes {
+                    for (peer, failures) in [
+                        ("client", &route.client_tls_failures),
+                        ("destination", &route.destination_tls_failures),
+                    ] {
+                        let _ = writeln!(
+                            out,
+                            "{PREFIX}_tls_handshake_failures_total{{route=\"{}\",peer=\"{peer}\"}} {}",
+                            escape(&route.route),
+                            load(failures)
+                        );
+                    }
+                }
+            },
+        );
+        family(
+            "idle_timeouts_total",
+            "counter",
+            "Connections closed by the idle timeout.",
+            &|out| each(out, "idle_timeouts_total", &|r| load(&r.idle_timeouts)),
+        );
+        family(
+            "accept_errors_total",
+            "counter",
+            "Failed accepts on the listener.",
+            &|out| each(out, "accept_errors_total", &|r| load(&r.accept_errors)),
+        );
+        family(
+            "accept_backoff_seconds",
+            "gauge",
+            "The delay before the next accept after a failed one; 0 once an accept succeeds.",
+            &|out| {
+                each(out, "accept_backoff_seconds", &|r| {
+                    seconds(r.accept_backoff_micros.load(Ordering::Relaxed))
+                })
+            },
+        );
+        family(
+            "connection_slot_wait_seconds",
+            "summary",
+            "Time spent waiting for a free --max-connections slot, counted only when all were taken.",
+            &|out| {
+                for route in &routes {
+                    let route_label = escape(&route.route);
+                    let _ = writeln!(
+                        out,
+                        "{PREFIX}_connection_slot_wait_seconds_sum{{route=\"{route_label}\"}} {}",
+                        seconds(route.slot_wait_micros.load(Ordering::Relaxed))
+                    );
+                    let _ = writeln!(
+                        out,
+                        "{PREFIX}_connection_slot_wait_seconds_count{{route=\"{route_label}\"}} {}",
+                        load(&route.slot_waits)
+                    );
+                }
+            },
+        );
+        out
+    }
+}
+
+/// One route's counters. Like the relays' own counts, they guard no other memory,
+/// so every access is `Relaxed`.
+#[derive(Default)]
+pub(crate) struct RouteMetrics {
+    /// The `route` label: the route's name, or its listen address.
+    route: String,
+    accepted: AtomicU64,
+    closed: AtomicU64,
+    client_to_destination: AtomicU64,
+    destination_to_client: AtomicU64,
+    /// By the `kind` label: the `io::ErrorKind` in snake case.
+    connect_failures: Mutex<BTreeMap<String, u64>>,
+    dns_failures: AtomicU64,
+    client_tls_failures: AtomicU64,
+    destination_tls_failures: AtomicU64,
+    idle_timeouts: AtomicU64,
+    accept_errors: AtomicU64,
+    accept_backoff_micros: AtomicU64,
+    slot_waits: AtomicU64,
+    slot_wait_micros: AtomicU64,
+}
+
+impl RouteMetrics {
+    pub(crate) fn accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn closed(&self) {
+        self.closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn relayed(&self, direction: Direction, bytes: usize) {
+        let counter = match direction {
+            Direction::ClientToDestination => &self.client_to_destination,
+            Direction::DestinationToClient => &self.destination_to_client,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connect_failed(&self, error: &io::Error) {
+        *self
+            .connect_failures
+            .lock()
+            .expect("metrics mutex poisoned")
+            .entry(error_kind(error.kind()))
+            .or_default() += 1;
+    }
+
+    pub(crate) fn dns_failed(&self) {
+        self.dns_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn client_tls_failed(&self) {
+        self.client_tls_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn destination_tls_failed(&self) {
+        self.destination_tls_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn idle_timeout(&self) {
+        self.idle_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An accept failed, and the loop waits `backoff` before the next.
+    pub(crate) fn accept_failed(&self, backoff: Duration) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+        self.accept_backoff_micros
+            .store(backoff.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// An accept succeeded: no backoff.
+    pub(crate) fn accept_recovered(&self) {
+        self.accept_backoff_micros.store(0, Ordering::Relaxed);
+    }
+
+    /// The accept loop found every slot taken and waited `waited` for one.
+    pub(crate) fn slot_waited(&self, waited: Duration) {
+        self.slot_waits.fetch_add(1, Ordering::Relaxed);
+        self.slot_wait_micros
+            .fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Serve `GET /metrics` on `listener` until the task is aborted. Any other request
+/// gets a 404.
+pub(crate) async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    endpoint::serve(listener, move |request: &Request| {
+        if request.method == "GET" && request.path == "/metrics" {
+            Response::new(
+                "200 OK",
+                "text/plain; version=0.0.4; charset=utf-8",
+                metrics.render(),
+            )
+        } else {
+            Response::text("404 Not Found", "Not found: the metrics are at /metrics\n")
+        }
+    })
+    .await
+}
+
+fn load(counter: &AtomicU64) -> String {
+    counter.load(Ordering::Relaxed).to_string()
+}
+
+fn seconds(micros: u64) -> String {
+    format!("{:.6}", micros as f64 / 1_000_000.0)
+}
+
+/// A label value with `\`, `"` and line breaks escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// An `io::ErrorKind` as a `kind` label: `ConnectionRefused` becomes
+/// `connection_refused`.
+fn error_kind(kind: io::ErrorKind) -> String {
+    let mut label = String::new();
+    for (index, character) in format!("{kind:?}").char_indices() {
+        if character.is_ascii_uppercase() && index > 0 {
+            label.push('_');
+        }
+        label.push(character.to_ascii_lowercase());
+    }
+    label
+}
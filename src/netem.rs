@@ -0,0 +1,346 @@
+//! Simulated network conditions for the relays: a fixed latency plus random jitter
+//! added to every relayed chunk, and bandwidth limits, set separately for each
+//! direction; and re-chunking, which splits every chunk into fragments or gathers
+//! small reads into one write, so that the peers see other segment boundaries than
+//! the sender made.
+
+use crate::args::Arguments;
+use crate::conn::Direction;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::time::sleep_until;
+
+/// Maximum accepted latency or jitter, in milliseconds (an hour).
+pub(crate) const MAX_DELAY_MILLIS: u64 = 60 * 60 * 1000;
+
+/// How many chunks a delayed relay holds before it stops reading: past this, the
+/// sender is slowed down by TCP flow control rather than buffered without bound.
+pub(crate) const IN_FLIGHT_CHUNKS: usize = 64;
+
+/// How often a rate-limited write goes out: each is a slice of a twentieth of the
+/// rate, so a slow link trickles rather than sending a chunk per second.
+const SLICES_PER_SECOND: u64 = 20;
+
+/// The largest slice a rate-limited write is cut into, however high the rate.
+const MAX_SLICE: u64 = 64 * 1024;
+
+/// The most `--coalesce` gathers into one write.
+pub(crate) const MAX_COALESCED: usize = 64 * 1024;
+
+/// The largest `--fragment` size.
+const MAX_FRAGMENT: u64 = 64 * 1024;
+
+/// The network conditions one direction of a connection is relayed under.
+pub(crate) struct Impairments {
+    pub(crate) latency: Latency,
+    /// Picks the jitter.
+    pub(crate) jitter: SplitMix64,
+    pub(crate) throttle: Throttle,
+    pub(crate) fragmenter: Fragmenter,
+    /// `--coalesce`: how long to keep reading after a read before writing it all.
+    pub(crate) coalesce: Option<Duration>,
+}
+
+impl Impairments {
+    /// The settings for `direction` of connection `conn_id`, with the
+    /// `--rate-limit-global` bucket shared by every connection and the random
+    /// choices drawn from `seed`.
+    pub(crate) fn new(
+        arguments: &Arguments,
+        direction: Direction,
+        global: Option<&Arc<TokenBucket>>,
+        seed: u64,
+        conn_id: u64,
+    ) -> Self {
+        Self {
+            latency: Latency::new(arguments, direction),
+            jitter: SplitMix64::derive(seed, conn_id, direction, Draw::Jitter),
+            throttle: Throttle::new(arguments, direction, global),
+            fragmenter: Fragmenter {
+                size: arguments.fragment,
+                gap: Duration::from_millis(arguments.fragment_delay),
+                random: SplitMix64::derive(seed, conn_id, direction, Draw::Fragments),
+            },
+            coalesce: arguments.coalesce.map(Duration::from_millis),
+        }
+    }
+}
+
+/// Whether the relays re-chunk what they relay (`--fragment`, `--coalesce`), so
+/// that a chunk read is no longer the chunk written.
+pub(crate) fn rechunks(arguments: &Arguments) -> bool {
+    arguments.fragment.is_some() || arguments.coalesce.is_some()
+}
+
+/// The delay added to one direction of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Latency {
+    /// Added to every chunk.
+    pub(crate) fixed: Duration,
+    /// Up to this much more is added to each chunk, picked at random.
+    pub(crate) jitter: Duration,
+}
+
+impl Latency {
+    /// The `--latency-to-*` and `--jitter-to-*` settings for `direction`.
+    pub(crate) fn new(arguments: &Arguments, direction: Direction) -> Self {
+        let (fixed, jitter) = match direction {
+            Direction::ClientToDestination => (
+                arguments.latency_to_destination,
+                arguments.jitter_to_destination,
+            ),
+            Direction::DestinationToClient => {
+                (arguments.latency_to_client, arguments.jitter_to_client)
+            }
+        };
+        Self {
+            fixed: Duration::from_millis(fixed),
+            jitter: Duration::from_millis(jitter),
+        }
+    }
+
+    /// No delay at all: the relay writes each chunk as soon as it is read.
+    pub(crate) fn is_zero(&self) -> bool {
+        self.fixed.is_zero() && self.jitter.is_zero()
+    }
+
+    /// The delays of a relay's successive chunks, the jitter drawn from `random`.
+    pub(crate) fn delays(self, random: SplitMix64) -> Delays {
+        Delays {
+            latency: self,
+            random,
+        }
+    }
+}
+
+/// The delay of each chunk, in the order they are read.
+pub(crate) struct Delays {
+    latency: Latency,
+    random: SplitMix64,
+}
+
+impl Delays {
+    /// The delay of the next chunk: the fixed latency plus a uniform pick of the
+    /// jitter, to the microsecond.
+    pub(crate) fn next(&mut self) -> Duration {
+        let jitter = self.latency.jitter.as_micros() as u64;
+        if jitter == 0 {
+            return self.latency.fixed;
+        }
+        self.latency.fixed + Duration::from_micros(self.random.next() % (jitter + 1))
+    }
+}
+
+/// A small, fast pseudo-random generator. The jitter and the `--fault`s need
+/// spread and repeatability, not unpredictability: every connection draws from
+/// generators derived from the one `--seed`.
+pub(crate) struct SplitMix64(u64);
+
+/// What a connection draws random numbers for: each use has a generator of its
+/// own, so adding a fault does not change the jitter, nor the other way round.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Draw {
+    Jitter,
+    Faults,
+    Fragments,
+}
+
+impl SplitMix64 {
+    /// The generator for `draw` in `direction` of connection `conn_id`, under
+    /// `seed`. The inputs are mixed once more so that neighbouring connections do
+    /// not start on overlapping sequences.
+    pub(crate) fn derive(seed: u64, conn_id: u64, direction: Direction, draw: Draw) -> Self {
+        let stream = match (draw, direction) {
+            (Draw::Jitter, Direction::ClientToDestination) => 0,
+            (Draw::Jitter, Direction::DestinationToClient) => 1,
+            (Draw::Faults, Direction::ClientToDestination) => 2,
+            (Draw::Faults, Direction::DestinationToClient) => 3,
+            (Draw::Fragments, Direction::ClientToDestination) => 4,
+            (Draw::Fragments, Direction::DestinationToClient) => 5,
+        };
+        let mut mixer = SplitMix64(seed ^ conn_id.rotate_left(32) ^ stream);
+        SplitMix64(mixer.next())
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// `--fragment`: the size of the pieces a chunk is split into, fixed (`MIN` equal
+/// to `MAX`) or picked at random in `MIN..=MAX` for every piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentSize {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl FromStr for FragmentSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let size = |text: &str| {
+            text.parse::<u64>()
+                .ok()
+                .filter(|size| (1..=MAX_FRAGMENT).contains(size))
+        };
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (size(min), size(max)),
+            None => (size(s), size(s)),
+        };
+        match (min, max) {
+            (Some(min), Some(max)) if min <= max => Ok(FragmentSize { min, max }),
+            _ => Err(format!(
+                "invalid fragment size `{s}`: expected a byte count or a `MIN-MAX` range within 1..={MAX_FRAGMENT}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FragmentSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+/// Splits one direction's chunks into `--fragment` pieces.
+pub(crate) struct Fragmenter {
+    size: Option<FragmentSize>,
+    /// `--fragment-delay`: the pause between two pieces of a chunk.
+    gap: Duration,
+    random: SplitMix64,
+}
+
+impl Fragmenter {
+    /// The pause between two pieces of a chunk.
+    pub(crate) fn gap(&self) -> Duration {
+        self.gap
+    }
+
+    /// The size of the next piece, with `left` bytes of the chunk still to write.
+    pub(crate) fn next_piece(&mut self, left: usize) -> usize {
+        let Some(FragmentSize { min, max }) = self.size else {
+            return left;
+        };
+        let size = min + self.random.next() % (max - min + 1);
+        left.min(size as usize)
+    }
+}
+
+/// The `--rate-limit-*` budgets one direction of a connection writes against.
+pub(crate) struct Throttle {
+    /// `--rate-limit-up` or `--rate-limit-down`: this connection's own.
+    connection: Option<TokenBucket>,
+    /// `--rate-limit-global`: shared by every connection and both directions.
+    global: Option<Arc<TokenBucket>>,
+}
+
+impl Throttle {
+    /// The `--rate-limit-up` (client to destination) or `--rate-limit-down`
+    /// (destination to client) bucket for `direction`, and the `global` one.
+    pub(crate) fn new(
+        arguments: &Arguments,
+        direction: Direction,
+        global: Option<&Arc<TokenBucket>>,
+    ) -> Self {
+        let rate = match direction {
+            Direction::ClientToDestination => arguments.rate_limit_up,
+            Direction::DestinationToClient => arguments.rate_limit_down,
+        };
+        Self {
+            connection: rate.map(TokenBucket::new),
+            global: global.cloned(),
+        }
+    }
+
+    /// No limit applies: a chunk is written in one go.
+    pub(crate) fn is_unlimited(&self) -> bool {
+        self.connection.is_none() && self.global.is_none()
+    }
+
+    /// The most to write at once: the smaller slice of the buckets that apply.
+    pub(crate) fn slice(&self) -> usize {
+        let connection = self.connection.as_ref().map(TokenBucket::slice);
+        let global = self.global.as_deref().map(TokenBucket::slice);
+        connection
+            .into_iter()
+            .chain(global)
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Take `bytes` from every bucket that applies, waiting until all of them
+    /// allow it.
+    pub(crate) async fn wait(&self, bytes: usize) {
+        let connection = self.connection.as_ref().map(|bucket| bucket.take(bytes));
+        let global = self.global.as_deref().map(|bucket| bucket.take(bytes));
+        if let Some(due) = connection.into_iter().chain(global).max() {
+            sleep_until(due).await;
+        }
+    }
+}
+
+/// A token bucket refilled at `rate` bytes a second, holding at most one slice.
+/// Taking more than it holds runs it into debt, which later takers wait out in
+/// turn, so the connections sharing the global bucket are served in order.
+pub(crate) struct TokenBucket {
+    rate: u64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    /// May be negative: the bytes taken ahead of the refill.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket for `rate` bytes a second, starting full.
+    pub(crate) fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: slice_size(rate) as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    fn slice(&self) -> usize {
+        slice_size(self.rate)
+    }
+
+    /// Take `bytes`, returning when they may be written.
+    fn take(&self, bytes: usize) -> Instant {
+        let rate = self.rate as f64;
+        let capacity = self.slice() as f64;
+        let mut state = self.state.lock().expect("not poisoned");
+        let now = Instant::now();
+        let refill = (now - state.updated).as_secs_f64() * rate;
+        state.tokens = (state.tokens + refill).min(capacity) - bytes as f64;
+        state.updated = now;
+        if state.tokens >= 0.0 {
+            now
+        } else {
+            now + Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+}
+
+/// The bytes a `rate` refills in one [`SLICES_PER_SECOND`] tick: the size of the
+/// writes, and the capacity of the bucket.
+fn slice_size(rate: u64) -> usize {
+    (rate / SLICES_PER_SECOND).clamp(1, MAX_SLICE) as usize
+}
@@ -0,0 +1,517 @@
+//! `--pcap <file>`: a pcapng capture of the relayed traffic that Wireshark can open
+//! directly, so a session no longer needs a `tcpdump` running next to the proxy.
+//!
+//! The proxy never sees real packets — it relays a byte stream — so each
+//! connection is written as a *synthesized* TCP conversation between the client
+//! and the destination: a three-way handshake when the destination is reached,
+//! one segment per relayed chunk (with Ethernet, IPv4/IPv6 and TCP headers, correct
+//! addresses, ports, sequence/acknowledgement numbers and checksums), and a FIN (or
+//! an RST after an I/O error) when a direction ends. Wireshark's "Follow TCP
+//! Stream" therefore shows each proxied connection as one stream. Every packet also
+//! carries a `connection #N` comment, matching the connection's console tag.
+//!
+//! [`read_capture`] reads such a capture back, for `replay`.
+
+use crate::conn::Direction;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::io::{self};
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const LINKTYPE_ETHERNET: u16 = 1;
+const OPTION_END: u16 = 0;
+const OPTION_COMMENT: u16 = 1;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const IP_PROTOCOL_TCP: u8 = 6;
+/// Locally administered MAC addresses for the two synthesized endpoints. The
+/// proxy has no link layer of its own; these only have to be stable and distinct.
+const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const DESTINATION_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_WINDOW: u16 = 0xFFFF;
+/// The largest payload a single synthesized segment carries. A relayed chunk is
+/// normally far smaller, but the IPv4 total-length field caps a packet at 65535
+/// bytes, so an unusually large chunk is split across several segments.
+const MAX_SEGMENT_PAYLOAD: usize = 65_535 - 20 - 20;
+
+/// The `--pcap` output file, shared by every connection.
+///
+/// Packets are written synchronously under a mutex and flushed one by one, the same
+/// way the console lines are written, so the file is always a valid capture up to
+/// the last relayed chunk — even when the proxy is stopped with Ctrl-C. After the
+/// first write error the capture is abandoned (logged once) while the proxy itself
+/// keeps relaying.
+#[derive(Clone)]
+pub(crate) struct PcapWriter {
+    file: Arc<Mutex<Option<BufWriter<File>>>>,
+}
+
+impl PcapWriter {
+    /// Create (or truncate) `path` and write the pcapng section and interface
+    /// headers.
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&section_header_block())?;
+        file.write_all(&interface_description_block())?;
+        file.flush()?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(Some(file))),
+        })
+    }
+
+    /// Start capturing connection `conn_id` between `client` and `destination`,
+    /// writing its handshake.
+    pub(crate) fn connection(
+        &self,
+        conn_id: u64,
+        client: SocketAddr,
+        destination: SocketAddr,
+    ) -> PcapConnection {
+        let (client, destination) = same_family(client, destination);
+        let connection = PcapConnection {
+            writer: self.clone(),
+            comment: format!("connection #{conn_id}"),
+            client,
+            destination,
+            client_seq: AtomicU32::new(0),
+            destination_seq: AtomicU32::new(0),
+        };
+        connection.handshake();
+        connection
+    }
+
+    fn write_packet(&self, packet: &[u8], comment: &str) {
+        let block = enhanced_packet_block(packet, comment);
+        let mut file = self.file.lock().expect("pcap writer mutex poisoned");
+        let Some(writer) = file.as_mut() else {
+            return; // an earlier write failed; the capture was abandoned
+        };
+        if let Err(error) = writer.write_all(&block).and_then(|()| writer.flush()) {
+            log::error!(
+                event = "pcap_failed";
+                "Failed to write to the pcap file, capture stopped: {error}"
+            );
+            *file = None;
+        }
+    }
+}
+
+/// One connection's synthesized TCP conversation.
+///
+/// Each direction's next sequence number lives in an atomic so both relay
+/// directions can advance it through a shared reference; as with the idle-timeout
+/// clock, they are sub-futures of a single task, so `Relaxed` is all that is needed.
+pub(crate) struct PcapConnection {
+    writer: PcapWriter,
+    comment: String,
+    client: SocketAddr,
+    destination: SocketAddr,
+    client_seq: AtomicU32,
+    destination_seq: AtomicU32,
+}
+
+impl PcapConnection {
+    /// SYN, SYN-ACK, ACK. Each SYN consumes one sequence number.
+    fn handshake(&self) {
+        self.segment(Direction::ClientToDestination, TCP_SYN, &[]);
+        self.advance(Direction::ClientToDestination, 1);
+        self.segment(Direction::DestinationToClient, TCP_SYN | TCP_ACK, &[]);
+        self.advance(Direction::DestinationToClient, 1);
+        self.segment(Direction::ClientToDestination, TCP_ACK, &[]);
+    }
+
+    /// A chunk relayed in `direction`.
+    pub(crate) fn data(&self, direction: Direction, payload: &[u8]) {
+        for segment in payload.chunks(MAX_SEGMENT_PAYLOAD) {
+            self.segment(direction, TCP_PSH | TCP_ACK, segment);
+            self.advance(direction, segment.len() as u32);
+        }
+    }
+
+    /// `direction` ended cleanly: its sender closed (FIN, consuming one sequence
+    /// number).
+    pub(crate) fn fin(&self, direction: Direction) {
+        self.segment(direction, TCP_FIN | TCP_ACK, &[]);
+        self.advance(direction, 1);
+    }
+
+    /// `direction` ended with an I/O error, shown as a reset from its sender.
+    pub(crate) fn reset(&self, direction: Direction) {
+        self.segment(direction, TCP_RST | TCP_ACK, &[]);
+    }
+
+    fn sequence(&self, direction: Direction) -> &AtomicU32 {
+        match direction {
+            Direction::ClientToDestination => &self.client_seq,
+            Direction::DestinationToClient => &self.destination_seq,
+        }
+    }
+
+    fn advance(&self, direction: Direction, length: u32) {
+        let sequence = self.sequence(direction);
+        sequence.store(
+            sequence.load(Ordering::Relaxed).wrapping_add(length),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn segment(&self, direction: Direction, flags: u8, payload: &[u8]) {
+        let (source, target, source_mac, target_mac, acknowledged) = match direction {
+            Direction::ClientToDestination => (
+                self.client,
+                self.destination,
+                CLIENT_MAC,
+                DESTINATION_MAC,
+                Direction::DestinationToClient,
+            ),
+            Direction::DestinationToClient => (
+                self.destination,
+                self.client,
+                DESTINATION_MAC,
+                CLIENT_MAC,
+                Direction::ClientToDestination,
+            ),
+        };
+        // A bare SYN acknowledges nothing yet.
+        let ack = if flags & TCP_ACK != 0 {
+            self.sequence(acknowledged).load(Ordering::Relaxed)
+        } else {
+            0
+        };
+        let seq = self.sequence(direction).load(Ordering::Relaxed);
+        let tcp = tcp_segment(source, target, seq, ack, flags, payload);
+        let packet = ethernet_frame(source_mac, target_mac, source.ip(), target.ip(), &tcp);
+        self.writer.write_packet(&packet, &self.comment);
+    }
+}
+
+/// A TCP conversation needs both endpoints in one address family. When the client
+/// and the destination differ (an IPv4 client proxied to an IPv6 destination, or
+/// the reverse), the IPv4 side is written as its IPv4-mapped IPv6 address.
+fn same_family(client: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    fn to_v6(addr: SocketAddr) -> SocketAddr {
+        match addr.ip() {
+            IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+            IpAddr::V6(_) => addr,
+        }
+    }
+    if client.is_ipv4() == destination.is_ipv4() {
+        (client, destination)
+    } else {
+        (to_v6(client), to_v6(destination))
+    }
+}
+
+fn section_header_block() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes()); // major version
+    body.extend_from_slice(&0u16.to_le_bytes()); // minor version
+    body.extend_from_slice(&u64::MAX.to_le_bytes()); // section length: unspecified
+    block(BLOCK_SECTION_HEADER, &body)
+}
+
+fn interface_description_block() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes()); // reserved
+    body.extend_from_slice(&0u32.to_le_bytes()); // snap length: unlimited
+    // No `if_tsresol` option: the default resolution is microseconds, which is
+    // what `enhanced_packet_block` writes.
+    block(BLOCK_INTERFACE_DESCRIPTION, &body)
+}
+
+fn enhanced_packet_block(packet: &[u8], comment: &str) -> Vec<u8> {
+    let micros = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64);
+    let mut body = Vec::with_capacity(packet.len() + comment.len() + 40);
+    body.extend_from_slice(&0u32.to_le_bytes()); // interface id
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // captured length
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // original length
+    body.extend_from_slice(packet);
+    pad_to_32_bits(&mut body);
+    body.extend_from_slice(&OPTION_COMMENT.to_le_bytes());
+    body.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    body.extend_from_slice(comment.as_bytes());
+    pad_to_32_bits(&mut body);
+    body.extend_from_slice(&OPTION_END.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    block(BLOCK_ENHANCED_PACKET, &body)
+}
+
+/// Wrap `body` (already padded to 32 bits) in a block: type, total length, body,
+/// total length again.
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let total_length = (body.len() + 12) as u32;
+    let mut block = Vec::with_capacity(total_length as usize);
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&total_length.to_le_bytes());
+    block.extend_from_slice(body);
+    block.extend_from_slice(&total_length.to_le_bytes());
+    block
+}
+
+fn pad_to_32_bits(buffer: &mut Vec<u8>) {
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+fn ethernet_frame(
+    source_mac: [u8; 6],
+    target_mac: [u8; 6],
+    source: IpAddr,
+    target: IpAddr,
+    tcp: &[u8],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(14 + 40 + tcp.len());
+    frame.extend_from_slice(&target_mac);
+    frame.extend_from_slice(&source_mac);
+    match (source, target) {
+        (IpAddr::V4(source), IpAddr::V4(target)) => {
+            frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            let mut header = [0u8; 20];
+            header[0] = 0x45; // version 4, 5-word header
+            header[2..4].copy_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+            header[6] = 0x40; // don't fragment
+            header[8] = 64; // TTL
+            header[9] = IP_PROTOCOL_TCP;
+            header[12..16].copy_from_slice(&source.octets());
+            header[16..20].copy_from_slice(&target.octets());
+            let checksum = internet_checksum(&[&header]);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            frame.extend_from_slice(&header);
+        }
+        (source, target) => {
+            frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+            frame.extend_from_slice(&[0x60, 0, 0, 0]); // version 6, no class/flow
+            frame.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+            frame.push(IP_PROTOCOL_TCP);
+            frame.push(64); // hop limit
+            frame.extend_from_slice(&ipv6_octets(source));
+            frame.extend_from_slice(&ipv6_octets(target));
+        }
+    }
+    frame.extend_from_slice(tcp);
+    frame
+}
+
+fn ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn tcp_segment(
+    source: SocketAddr,
+    target: SocketAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&source.port().to_be_bytes());
+    segment.extend_from_slice(&target.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(5 << 4); // 5-word header, no options
+    segment.push(flags);
+    segment.extend_from_slice(&TCP_WINDOW.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]); // checksum, filled in below
+    segment.extend_from_slice(&[0, 0]); // urgent pointer
+    segment.extend_from_slice(payload);
+
+    // The checksum covers a pseudo-header of the IP addresses, protocol and length.
+    let length = segment.len() as u32;
+    let pseudo_header = match (source.ip(), target.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(target)) => {
+            let mut header = Vec::with_capacity(12);
+            header.extend_from_slice(&source.octets());
+            header.extend_from_slice(&target.octets());
+            header.extend_from_slice(&[0, IP_PROTOCOL_TCP]);
+            header.extend_from_slice(&(length as u16).to_be_bytes());
+            header
+        }
+        (source, target) => {
+            let mut header = Vec::with_capacity(40);
+            header.extend_from_slice(&ipv6_octets(source));
+            header.extend_from_slice(&ipv6_octets(target));
+            header.extend_from_slice(&length.to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0, IP_PROTOCOL_TCP]);
+            header
+        }
+    };
+    let checksum = internet_checksum(&[&pseudo_header, &segment]);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// The RFC 1071 one's-complement checksum over the concatenation of `parts`.
+pub(crate) fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut pending: Option<u8> = None;
+    for byte in parts.iter().flat_map(|part| part.iter().copied()) {
+        match pending.take() {
+            Some(high) => sum += u32::from(u16::from_be_bytes([high, byte])),
+            None => pending = Some(byte),
+        }
+    }
+    if let Some(high) = pending {
+        sum += u32::from(u16::from_be_bytes([high, 0]));
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// One relayed chunk read back from a `--pcap` capture (see [`read_capture`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RecordedChunk {
+    pub(crate) direction: Direction,
+    /// When the chunk was relayed, since the Unix epoch.
+    pub(crate) time: Duration,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// Read back the relayed chunks of a capture this module wrote, per connection id
+/// (taken from each packet's `connection #N` comment), in capture order. Only the
+/// data segments are returned; the handshake, FIN and RST segments carry no bytes
+/// of the conversation. A chunk larger than one segment comes back as several
+/// chunks, which is the same byte stream.
+///
+/// This reads the subset of pcapng the writer produces — little-endian, one
+/// Ethernet interface, enhanced packet blocks — and rejects anything else rather
+/// than guessing at a capture from another tool.
+pub(crate) fn read_capture(file: &[u8]) -> Result<BTreeMap<u64, Vec<RecordedChunk>>, String> {
+    let mut connections: BTreeMap<u64, Vec<RecordedChunk>> = BTreeMap::new();
+    let mut at = 0;
+    while at < file.len() {
+        let header = file
+            .get(at..at + 8)
+            .ok_or_else(|| format!("truncated block at byte {at}"))?;
+        let block_type = u32::from_le_bytes(header[0..4].try_into().expect("4 bytes"));
+        let total_length = u32::from_le_bytes(header[4..8].try_into().expect("4 bytes")) as usize;
+        let body = file
+            .get(at + 8..(at + total_length).saturating_sub(4))
+            .filter(|_| total_length >= 12 && total_length % 4 == 0)
+            .ok_or_else(|| format!("truncated or malformed block at byte {at}"))?;
+        match block_type {
+            _ if at == 0 && block_type != BLOCK_SECTION_HEADER => {
+                return Err("not a pcapng capture".into());
+            }
+            BLOCK_SECTION_HEADER if body.get(0..4) != Some(&BYTE_ORDER_MAGIC.to_le_bytes()[..]) => {
+                return Err("not a capture written by this proxy (unexpected byte order)".into());
+            }
+            BLOCK_INTERFACE_DESCRIPTION
+                if body.get(0..2) != Some(&LINKTYPE_ETHERNET.to_le_bytes()[..]) =>
+            {
+                return Err("not a capture written by this proxy (unexpected link type)".into());
+            }
+            BLOCK_ENHANCED_PACKET => {
+                if let Some((conn_id, chunk)) =
+                    recorded_chunk(body).ok_or_else(|| format!("malformed packet at byte {at}"))?
+                {
+                    connections.entry(conn_id).or_default().push(chunk);
+                }
+            }
+            _ => {}
+        }
+        at += total_length;
+    }
+    if at == 0 {
+        return Err("the capture is empty".into());
+    }
+    Ok(connections)
+}
+
+/// The connection id and relayed chunk of one enhanced packet block's `body`;
+/// `Some(None)` for a packet that carries no payload, `None` if it is malformed.
+fn recorded_chunk(body: &[u8]) -> Option<Option<(u64, RecordedChunk)>> {
+    let le_u32 = |at: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(body.get(at..at + 4)?.try_into().ok()?))
+    };
+    let micros = (u64::from(le_u32(4)?) << 32) | u64::from(le_u32(8)?);
+    let captured_length = le_u32(12)? as usize;
+    let frame = body.get(20..20 + captured_length)?;
+    let options = body.get((20 + captured_length).next_multiple_of(4)..)?;
+
+    let direction = match frame.get(6..12)? {
+        source if source == CLIENT_MAC => Direction::ClientToDestination,
+        source if source == DESTINATION_MAC => Direction::DestinationToClient,
+        _ => return None,
+    };
+    let tcp = match u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?) {
+        ETHERTYPE_IPV4 => {
+            let ip = frame.get(14..)?;
+            let header_length = usize::from(ip.first()? & 0x0F) * 4;
+            let total_length = usize::from(u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?));
+            ip.get(header_length..total_length)?
+        }
+        ETHERTYPE_IPV6 => {
+            let ip = frame.get(14..)?;
+            let payload_length = usize::from(u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?));
+            ip.get(40..40 + payload_length)?
+        }
+        _ => return None,
+    };
+    let payload = tcp.get(usize::from(tcp.get(12)? >> 4) * 4..)?;
+    if payload.is_empty() {
+        return Some(None);
+    }
+
+    // The options: `(code, length, value padded to 32 bits)` until `OPTION_END`.
+    let mut conn_id = None;
+    let mut at = 0;
+    while let Some(code) = options.get(at..at + 2) {
+        let code = u16::from_le_bytes(code.try_into().ok()?);
+        let length = usize::from(u16::from_le_bytes(
+            options.get(at + 2..at + 4)?.try_into().ok()?,
+        ));
+        let value = options.get(at + 4..at + 4 + length)?;
+        match code {
+            OPTION_END => break,
+            OPTION_COMMENT => {
+                conn_id = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|comment| comment.strip_prefix("connection #"))
+                    .and_then(|id| id.parse().ok());
+            }
+            _ => {}
+        }
+        at += 4 + length.next_multiple_of(4);
+    }
+    Some(Some((
+        conn_id?,
+        RecordedChunk {
+            direction,
+            time: Duration::from_micros(micros),
+            payload: payload.to_vec(),
+        },
+    )))
+}
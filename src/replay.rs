@@ -0,0 +1,392 @@
+//! `replay`: reproduce a connection recorded with `--pcap` without one of its
+//! peers — for instance a device's conversation, replayed in CI without the device.
+//!
+//! - `replay client` plays the recorded client: it sends the recorded client chunks
+//!   to a server and compares what comes back with the recorded responses.
+//! - `replay server` plays the recorded destination: it answers every client that
+//!   connects with the recorded responses, once that client's recorded requests
+//!   have arrived.
+//!
+//! Both sides keep the recorded timing: before each chunk it sends, a side waits
+//! the recorded gap since the previous chunk (in either direction), divided by
+//! `--speed`. Only the byte streams are compared — TCP is free to split and merge
+//! segments, so a run of chunks in one direction is treated as one block of bytes.
+
+use crate::args::ReplayMode;
+use crate::args::ReplayOptions;
+use crate::args::get_formatter_by_kind;
+use crate::conn::Direction;
+use crate::conn::connect_to_target;
+use crate::formatters::PayloadFormatter;
+use crate::pcap::RecordedChunk;
+use crate::pcap::read_capture;
+use logged_stream::BufferFormatter;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::io::{self};
+use tokio::net as tokio_net;
+use tokio::time::Instant;
+use tokio::time::sleep;
+use tokio::time::timeout_at;
+
+/// Run `replay` in `mode`. Returns whether the replay matched the recording; a
+/// replay that could not run at all (an unreadable capture, an unreachable server,
+/// ...) is an error, logged here.
+pub async fn run(mode: ReplayMode) -> io::Result<bool> {
+    match mode {
+        ReplayMode::Client {
+            remote_addr,
+            options,
+        } => {
+            let replay = Replay::load(&options)?;
+            let stream = match connect_to_target(&remote_addr, None).await {
+                Ok(stream) => stream,
+                Err(error) => {
+                    log::error!("Failed to connect to {remote_addr}: {error}");
+                    return Err(error.into());
+                }
+            };
+            log::info!(
+                "Replaying connection #{} against {remote_addr} as its client",
+                replay.conn_id
+            );
+            let outcome = replay.play_client(stream).await?;
+            log::info!("{outcome}");
+            Ok(outcome.mismatched == 0)
+        }
+        ReplayMode::Server {
+            bind_listener_addr,
+            options,
+        } => {
+            let replay = Replay::load(&options)?;
+            let listener = match tokio_net::TcpListener::bind(bind_listener_addr).await {
+                Ok(listener) => listener,
+                Err(error) => {
+                    log::error!("Failed to bind listener on {bind_listener_addr}: {error}");
+                    return Err(error);
+                }
+            };
+            log::info!(
+                "Replaying connection #{} as its server on {}, waiting for clients...",
+                replay.conn_id,
+                listener.local_addr()?
+            );
+            tokio::select! {
+                _ = replay.serve(listener) => {}
+                result = tokio::signal::ctrl_c() => match result {
+                    Ok(()) => log::info!("Received shutdown signal, stopping replay."),
+                    Err(error) => log::error!("Failed to listen for shutdown signal: {error}"),
+                },
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// One recorded connection, ready to be replayed.
+#[derive(Clone)]
+pub(crate) struct Replay {
+    conn_id: u64,
+    chunks: Arc<[RecordedChunk]>,
+    speed: f64,
+    wait: Duration,
+    formatter: Arc<PayloadFormatter>,
+}
+
+impl Replay {
+    /// Read `options.capture` and pick the connection to replay.
+    fn load(options: &ReplayOptions) -> io::Result<Self> {
+        let path = options.capture.display();
+        let file = std::fs::read(&options.capture).inspect_err(|error| {
+            log::error!("Failed to read capture {path}: {error}");
+        })?;
+        let connections = read_capture(&file).map_err(|error| {
+            log::error!("Failed to read capture {path}: {error}");
+            io::Error::new(io::ErrorKind::InvalidData, error)
+        })?;
+        let ids = || {
+            connections
+                .keys()
+                .map(|id| format!("#{id}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let selected = match options.connection {
+            Some(id) => connections.get_key_value(&id),
+            None if connections.len() == 1 => connections.iter().next(),
+            None => {
+                log::error!(
+                    "Capture {path} holds several connections ({}); pick one with --connection",
+                    ids()
+                );
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+        };
+        let Some((&conn_id, chunks)) = selected else {
+            log::error!(
+                "Capture {path} holds no data of connection #{} (recorded: {})",
+                options.connection.unwrap_or_default(),
+                ids()
+            );
+            return Err(io::ErrorKind::NotFound.into());
+        };
+        Ok(Self::new(
+            conn_id,
+            chunks.clone(),
+            options.speed,
+            Duration::from_secs(options.timeout),
+            get_formatter_by_kind(
+                options.formatting,
+                options.separator.as_str(),
+                options.hexdump_width as usize,
+            ),
+        ))
+    }
+
+    pub(crate) fn new(
+        conn_id: u64,
+        chunks: Vec<RecordedChunk>,
+        speed: f64,
+        wait: Duration,
+        formatter: PayloadFormatter,
+    ) -> Self {
+        Self {
+            conn_id,
+            chunks: chunks.into(),
+            speed,
+            wait,
+            formatter: Arc::new(formatter),
+        }
+    }
+
+    /// Play the recorded client over `stream`: send each client chunk, and read
+    /// and compare each run of server chunks.
+    pub(crate) async fn play_client<S>(&self, stream: S) -> io::Result<Outcome>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut outcome = Outcome::default();
+        let (mut reader, mut writer) = io::split(stream);
+        let mut previous = self.chunks.first().map(|chunk| chunk.time);
+        for run in self.runs() {
+            match run.direction {
+                Direction::ClientToDestination => {
+                    for chunk in run.chunks {
+                        self.pace(&mut previous, chunk.time).await;
+                        writer.write_all(&chunk.payload).await?;
+                        log::debug!("< {}", self.formatter.format_buffer(&chunk.payload));
+                    }
+                }
+                Direction::DestinationToClient => {
+                    outcome.expected += 1;
+                    let expected = run.bytes();
+                    if !self
+                        .receive(&mut reader, "response", outcome.expected, &expected)
+                        .await
+                    {
+                        outcome.mismatched += 1;
+                    }
+                    previous = run.chunks.last().map(|chunk| chunk.time);
+                }
+            }
+        }
+        let _ = writer.shutdown().await;
+        Ok(outcome)
+    }
+
+    /// Play the recorded server to every client accepted on `listener`, each on a
+    /// task of its own.
+    pub(crate) async fn serve(&self, listener: tokio_net::TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    log::info!("Replaying to client {addr}");
+                    let replay = self.clone();
+                    tokio::spawn(async move {
+                        match replay.play_server(stream).await {
+                            Ok(outcome) => log::info!("Client {addr}: {outcome}"),
+                            Err(error) => log::error!("Client {addr}: replay failed: {error}"),
+                        }
+                    });
+                }
+                Err(error) => {
+                    log::error!("Failed to accept incoming connection due to {error}");
+                    sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    }
+
+    /// Play the recorded server over `stream`: wait for each run of client chunks
+    /// (comparing them with the recording), then send the recorded responses.
+    pub(crate) async fn play_server(
+        &self,
+        mut stream: tokio_net::TcpStream,
+    ) -> io::Result<Outcome> {
+        let mut outcome = Outcome::default();
+        let (mut reader, mut writer) = stream.split();
+        let mut previous = self.chunks.first().map(|chunk| chunk.time);
+        for run in self.runs() {
+            match run.direction {
+                Direction::ClientToDestination => {
+                    outcome.expected += 1;
+                    let expected = run.bytes();
+                    // A request that differs is still answered: the point of the
+                    // fake server is to keep the client going.
+                    if !self
+                        .receive(&mut reader, "request", outcome.expected, &expected)
+                        .await
+                    {
+                        outcome.mismatched += 1;
+                    }
+                    previous = run.chunks.last().map(|chunk| chunk.time);
+                }
+                Direction::DestinationToClient => {
+                    for chunk in run.chunks {
+                        self.pace(&mut previous, chunk.time).await;
+                        writer.write_all(&chunk.payload).await?;
+                        log::debug!("> {}", self.formatter.format_buffer(&chunk.payload));
+                    }
+                }
+            }
+        }
+        let _ = writer.shutdown().await;
+        Ok(outcome)
+    }
+
+    /// The recording as runs of consecutive chunks in one direction.
+    fn runs(&self) -> impl Iterator<Item = Run<'_>> {
+        self.chunks
+            .chunk_by(|left, right| left.direction == right.direction)
+            .map(|chunks| Run {
+                direction: chunks[0].direction,
+                chunks,
+            })
+    }
+
+    /// Wait the recorded gap between the `previous` chunk and one recorded at
+    /// `time`, scaled by `--speed`, and make `time` the previous one.
+    async fn pace(&self, previous: &mut Option<Duration>, time: Duration) {
+        if let Some(previous) = previous.replace(time) {
+            if self.speed > 0.0 {
+                sleep(time.saturating_sub(previous).div_f64(self.speed)).await;
+            }
+        }
+    }
+
+    /// Read the other side's next `expected` bytes and compare them, logging the
+    /// result as the `index`-th `what` (response or request). Returns whether they
+    /// matched.
+    async fn receive<R>(&self, reader: &mut R, what: &str, index: u64, expected: &[u8]) -> bool
+    where
+        R: AsyncRead + Unpin,
+    {
+        let deadline = Instant::now() + self.wait;
+        let mut received = vec![0; expected.len()];
+        let mut filled = 0;
+        let mut ended = None;
+        while filled < expected.len() {
+            match timeout_at(deadline, reader.read(&mut received[filled..])).await {
+                Ok(Ok(0)) => {
+                    ended = Some("the connection was closed");
+                    break;
+                }
+                Ok(Ok(read_length)) => filled += read_length,
+                Ok(Err(_)) => {
+                    ended = Some("reading failed");
+                    break;
+                }
+                Err(_) => {
+                    ended = Some("timed out");
+                    break;
+                }
+            }
+        }
+        received.truncate(filled);
+        if filled > 0 {
+            log::debug!(
+                "{} {}",
+                if what == "response" { '>' } else { '<' },
+                self.formatter.format_buffer(&received)
+            );
+        }
+        if received == expected {
+            log::info!(
+                "{} #{index} matches the recording ({} bytes)",
+                capitalized(what),
+                expected.len()
+            );
+            return true;
+        }
+        match ended {
+            Some(reason) => log::error!(
+                "{} #{index} differs from the recording: {reason} after {filled} of {} bytes",
+                capitalized(what),
+                expected.len()
+            ),
+            None => {
+                let offset = received
+                    .iter()
+                    .zip(expected)
+                    .position(|(received, expected)| received != expected)
+                    .unwrap_or(filled);
+                log::error!(
+                    "{} #{index} differs from the recording at byte {offset}",
+                    capitalized(what)
+                );
+            }
+        }
+        log::error!("  expected: {}", self.formatter.format_buffer(expected));
+        log::error!("  received: {}", self.formatter.format_buffer(&received));
+        false
+    }
+}
+
+/// Consecutive recorded chunks in one direction.
+struct Run<'a> {
+    direction: Direction,
+    chunks: &'a [RecordedChunk],
+}
+
+impl Run<'_> {
+    /// The run's bytes, as one block.
+    fn bytes(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.payload.iter().copied())
+            .collect()
+    }
+}
+
+/// How a replayed connection compared with the recording: of the `expected`
+/// blocks of the other side (responses for a client, requests for a server),
+/// how many `mismatched`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Outcome {
+    pub(crate) expected: u64,
+    pub(crate) mismatched: u64,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replay finished: {} of {} matched the recording",
+            self.expected - self.mismatched,
+            self.expected
+        )
+    }
+}
+
+fn capitalized(what: &str) -> String {
+    let mut characters = what.chars();
+    characters
+        .next()
+        .map(|first| first.to_uppercase().chain(characters).collect())
+        .unwrap_or_default()
+}
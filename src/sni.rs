@@ -0,0 +1,185 @@
+//! `--sni-route`: one listener fanned out to several destinations by the server
+//! name each client's TLS hello asks for, without terminating TLS. The hello is
+//! peeked at, not read, so it stays in the socket: the destination picked gets the
+//! client's bytes untouched, the hello included.
+
+use crate::args::TargetAddr;
+use crate::inspect;
+use crate::inspect::Opening;
+use rustls::pki_types::DnsName;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// How long a client has to send its hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a client may send nothing before it is taken for one of a protocol in
+/// which the server speaks first (SMTP, MySQL, SSH, ...), when there is a default
+/// destination to send it to.
+pub(crate) const SILENCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait before peeking again at a hello that has only partly arrived.
+const PEEK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The most of a client's stream peeked at for its hello: a full record.
+const MAX_HELLO: usize = 5 + 16384;
+
+/// The fatal `unrecognized_name` alert a client without a route is sent.
+pub(crate) const UNRECOGNIZED_NAME_ALERT: [u8; 7] = [21, 3, 3, 0, 2, 2, 112];
+
+/// One `--sni-route`: the destination of the clients asking for a name.
+#[derive(Debug, Clone)]
+pub struct SniRoute {
+    pattern: Pattern,
+    pub remote_addr: TargetAddr,
+}
+
+/// The names an [`SniRoute`] is for, lowercased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Exact(String),
+    /// `*.example.com`, kept as `.example.com`: any name ending with it.
+    Suffix(String),
+}
+
+impl FromStr for SniRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, remote)) = s.split_once('=') else {
+            return Err(format!("invalid SNI route `{s}`: expected `NAME=REMOTE`"));
+        };
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if name == "*" {
+            return Err(format!(
+                "invalid SNI route `{s}`: `*` is not a name; the default destination is the listener's remote address"
+            ));
+        }
+        let (pattern, domain) = match name.strip_prefix("*.") {
+            Some(domain) => (Pattern::Suffix(format!(".{domain}")), domain),
+            None => (Pattern::Exact(name.clone()), name.as_str()),
+        };
+        if DnsName::try_from(domain).is_err() {
+            return Err(format!(
+                "invalid SNI route `{s}`: `{name}` is not a host name or a `*.domain` wildcard"
+            ));
+        }
+        let remote_addr = remote
+            .parse()
+            .map_err(|error| format!("invalid SNI route `{s}`: {error}"))?;
+        Ok(Self {
+            pattern,
+            remote_addr,
+        })
+    }
+}
+
+impl fmt::Display for SniRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.pattern {
+            Pattern::Exact(name) => write!(f, "{name}={}", self.remote_addr),
+            Pattern::Suffix(suffix) => write!(f, "*{suffix}={}", self.remote_addr),
+        }
+    }
+}
+
+/// The routes as in a reload's summary: `[api.lab=10.0.0.5:443, ...]`, or `none`.
+pub(crate) fn describe(routes: &[SniRoute]) -> String {
+    if routes.is_empty() {
+        return "none".to_string();
+    }
+    let routes: Vec<String> = routes.iter().map(SniRoute::to_string).collect();
+    format!("[{}]", routes.join(", "))
+}
+
+/// The destination of the clients asking for `name`: that of its exact route, or
+/// else of the wildcard route for the longest suffix of it.
+pub(crate) fn pick<'a>(routes: &'a [SniRoute], name: &str) -> Option<&'a TargetAddr> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let exact = routes
+        .iter()
+        .find(|route| route.pattern == Pattern::Exact(name.clone()));
+    exact
+        .or_else(|| {
+            routes
+                .iter()
+                .filter(|route| match &route.pattern {
+                    Pattern::Suffix(suffix) => name.ends_with(suffix.as_str()),
+                    Pattern::Exact(_) => false,
+                })
+                .max_by_key(|route| match &route.pattern {
+                    Pattern::Suffix(suffix) => suffix.len(),
+                    Pattern::Exact(_) => 0,
+                })
+        })
+        .map(|route| &route.remote_addr)
+}
+
+/// What a client's first bytes said of the name it asks for.
+pub(crate) enum Peeked {
+    /// A hello asking for this name.
+    Name(String),
+    /// A hello asking for no name.
+    NoName,
+    /// Anything but a TLS hello.
+    NotHello,
+    /// The client closed before sending a hello.
+    Closed,
+    /// Nothing for [`SILENCE_TIMEOUT`]: the client may be waiting for the server
+    /// to speak first.
+    Silent,
+}
+
+/// Peek at the hello `stream`'s client sends, leaving it in the socket; an error
+/// if it fails, or takes more than [`HELLO_TIMEOUT`] to arrive. With a `fallback`
+/// destination for the clients that send no hello, one that sends nothing at all
+/// is only waited for [`SILENCE_TIMEOUT`].
+pub(crate) async fn peek_server_name(stream: &TcpStream, fallback: bool) -> io::Result<Peeked> {
+    let peeking = async {
+        if fallback {
+            match timeout(SILENCE_TIMEOUT, stream.readable()).await {
+                Ok(ready) => ready?,
+                Err(_) => return Ok(Peeked::Silent),
+            }
+        }
+        let mut buffer = vec![0; MAX_HELLO];
+        loop {
+            let peeked = stream.peek(&mut buffer).await?;
+            if peeked == 0 {
+                return Ok(Peeked::Closed);
+            }
+            match inspect::opening(&buffer[..peeked]) {
+                Opening::Hello(hello) => {
+                    return Ok(hello.server_name.map_or(Peeked::NoName, Peeked::Name));
+                }
+                Opening::NotHello => return Ok(Peeked::NotHello),
+                Opening::Incomplete if peeked == buffer.len() => return Ok(Peeked::NotHello),
+                // The rest is on its way: peeking again right away would return
+                // the same bytes.
+                Opening::Incomplete => sleep(PEEK_INTERVAL).await,
+            }
+        }
+    };
+    timeout(HELLO_TIMEOUT, peeking).await.unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("no hello within {}s", HELLO_TIMEOUT.as_secs()),
+        ))
+    })
+}
+
+/// Send a client whose hello has no route the [`UNRECOGNIZED_NAME_ALERT`], and
+/// close. The peeked hello is read off first: closing with it still unread would
+/// reset the connection, and the alert with it.
+pub(crate) async fn turn_away(mut stream: TcpStream) {
+    let mut buffer = vec![0; MAX_HELLO];
+    while matches!(stream.try_read(&mut buffer), Ok(read) if read > 0) {}
+    let _ = stream.write_all(&UNRECOGNIZED_NAME_ALERT).await;
+    let _ = stream.shutdown().await;
+}
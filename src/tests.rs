@@ -13,14 +13,36 @@
 //! [`main.rs`](main.rs), so the submodules need no `cfg` attribute of their own.
 
 mod accept_loop;
+mod admin;
+mod capture;
 mod cli_args;
+mod config;
 mod conn_ids;
+mod decode;
 mod errors;
+mod faults;
 mod formatting;
 mod helpers;
 mod hostname;
 mod idle_timeout;
+mod inspect;
+mod jsonl;
+mod latency;
 mod log_capture;
+mod logfile;
+mod metrics;
+mod mitm;
+mod pcap;
+mod rate_limit;
 mod real_protocols;
+mod rechunk;
 mod relay;
+mod reload;
+mod replay;
+mod routes;
+mod shutdown;
+mod sni;
+mod summary;
 mod teardown;
+mod tls;
+mod tui;
@@ -0,0 +1,269 @@
+//! `--admin-addr`: the HTTP API that lists the live connections, closes one by its
+//! id, and pauses and resumes accepting.
+//!
+//! Each test starts a route and the API over a registry of its own, so the
+//! connection ids start at 1; the log lines are looked up by the test's own client
+//! and destination addresses, as the whole suite shares one capture buffer.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::Shared;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::http_request;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_route;
+use super::helpers::test_arguments;
+use super::log_capture::captured_events;
+use super::log_capture::captured_lines;
+use super::log_capture::install_capturing_logger;
+use crate::admin;
+use crate::admin::Registry;
+use crate::conn::Services;
+use crate::conn::Sinks;
+use serde_json::Value;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// Start a route named `test` relaying to a fresh echo server, and the admin API
+/// over its registry. Returns the route's address, the echo server's and the API's.
+async fn spawn_administered_proxy() -> (SocketAddr, SocketAddr, SocketAddr) {
+    let echo_addr = spawn_echo_server().await;
+    let listener = TcpListener::bind(LOOPBACK).await.expect("bind the route");
+    let listen_addr = listener.local_addr().expect("route local_addr");
+    let arguments = test_arguments(listen_addr, echo_addr, None, 8);
+    let sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    let mut route = arguments.routes().expect("valid routes").remove(0);
+    route.name = Some("test".to_string());
+    let registry = Registry::default();
+    spawn_route(
+        listener,
+        arguments,
+        route,
+        sinks,
+        Shared {
+            registry: registry.clone(),
+            ..Shared::default()
+        },
+    );
+    let admin_listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("bind the admin API");
+    let admin_addr = admin_listener.local_addr().expect("admin API local_addr");
+    tokio::spawn(admin::serve(admin_listener, registry));
+    (listen_addr, echo_addr, admin_addr)
+}
+
+/// Send `method path` to the API and parse the JSON answer.
+async fn call(admin_addr: SocketAddr, method: &str, path: &str) -> (String, Value) {
+    let (status, body) = http_request(admin_addr, method, path).await;
+    let body = serde_json::from_str(&body).expect("the API answers JSON");
+    (status, body)
+}
+
+/// `GET /connections` until `done` accepts the listing.
+async fn list_until(admin_addr: SocketAddr, done: impl Fn(&Value) -> bool) -> Value {
+    let deadline = Instant::now() + IO_TIMEOUT;
+    loop {
+        let (status, listing) = call(admin_addr, "GET", "/connections").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        if done(&listing) {
+            return listing;
+        }
+        assert!(Instant::now() < deadline, "unexpected listing: {listing:#}");
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// Each live connection is listed with its route, addresses and traffic, and with
+/// the age and idle time its own clocks report; a closed one disappears.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn connections_are_listed_while_they_are_open() {
+    let (proxy_addr, echo_addr, admin_addr) = spawn_administered_proxy().await;
+    let mut first = connect(proxy_addr).await;
+    assert_round_trip(&mut first, b"first").await;
+    let mut second = connect(proxy_addr).await;
+    assert_round_trip(&mut second, b"second!").await;
+    sleep(Duration::from_millis(300)).await;
+
+    let listing = list_until(admin_addr, |listing| {
+        listing["connections"].as_array().map(Vec::len) == Some(2)
+    })
+    .await;
+    assert_eq!(listing["paused"], false);
+    for (entry, (id, client, bytes)) in listing["connections"]
+        .as_array()
+        .expect("a list")
+        .iter()
+        .zip([(1, &first, 5), (2, &second, 7)])
+    {
+        let client_addr = client.local_addr().expect("client local_addr");
+        assert_eq!(entry["id"], id);
+        assert_eq!(entry["route"], "test");
+        assert_eq!(entry["client"], client_addr.to_string());
+        assert_eq!(entry["destination"], echo_addr.to_string());
+        assert_eq!(entry["destination_addr"], echo_addr.to_string());
+        assert_eq!(entry["client_bytes"], bytes);
+        assert_eq!(entry["destination_bytes"], bytes);
+        let age = entry["age_ms"].as_u64().expect("an age");
+        let idle = entry["idle_ms"].as_u64().expect("an idle time");
+        assert!(
+            (300..=age).contains(&idle),
+            "idle for {idle}ms of {age}ms: {entry}"
+        );
+    }
+
+    // Activity resets the idle time.
+    assert_round_trip(&mut first, b"again").await;
+    let listing = list_until(admin_addr, |_| true).await;
+    assert!(
+        listing["connections"][0]["idle_ms"].as_u64() < Some(300),
+        "{listing:#}"
+    );
+
+    drop(first);
+    let listing = list_until(admin_addr, |listing| {
+        listing["connections"].as_array().map(Vec::len) == Some(1)
+    })
+    .await;
+    assert_eq!(listing["connections"][0]["id"], 2);
+}
+
+/// `DELETE /connections/<id>` closes that connection only, logging it with what it
+/// relayed; its summary names the API as what closed it.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn delete_closes_the_connection() {
+    install_capturing_logger();
+    let (proxy_addr, echo_addr, admin_addr) = spawn_administered_proxy().await;
+    let mut doomed = connect(proxy_addr).await;
+    assert_round_trip(&mut doomed, b"doomed").await;
+    let mut spared = connect(proxy_addr).await;
+    assert_round_trip(&mut spared, b"spared").await;
+    let client_addr = doomed.local_addr().expect("client local_addr");
+
+    let (status, answer) = call(admin_addr, "DELETE", "/connections/1").await;
+    assert_eq!(
+        (status.as_str(), answer),
+        ("HTTP/1.1 200 OK", json!({ "closed": 1 }))
+    );
+    let mut buffer = [0; 16];
+    let read = timeout(IO_TIMEOUT, doomed.read(&mut buffer))
+        .await
+        .expect("the proxy should close the connection")
+        .unwrap_or(0);
+    assert_eq!(read, 0, "expected end-of-stream");
+    assert_round_trip(&mut spared, b"still here").await;
+
+    let expected = format!(
+        "[test] [#1] Forcibly closing connection from {client_addr} on request of the admin API after 6 bytes from the client and 6 bytes from the destination"
+    );
+    let deadline = Instant::now() + IO_TIMEOUT;
+    let summary = loop {
+        let summary = captured_events().into_iter().find(|event| {
+            event["event"] == "closed"
+                && event["client"] == client_addr.to_string()
+                && event["destination"] == echo_addr.to_string()
+        });
+        if let Some(summary) = summary {
+            break summary;
+        }
+        assert!(Instant::now() < deadline, "no summary for {client_addr}");
+        sleep(Duration::from_millis(20)).await;
+    };
+    assert_eq!(summary["admin_close"], true, "{summary}");
+    assert_eq!(summary["shutdown"], false, "{summary}");
+    let lines = captured_lines();
+    assert!(
+        lines.contains(&expected),
+        "missing {expected:?}; captured: {lines:?}"
+    );
+
+    let (status, answer) = call(admin_addr, "DELETE", "/connections/1").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    assert_eq!(answer, json!({ "error": "no live connection #1" }));
+    let (status, _) = call(admin_addr, "DELETE", "/connections/first").await;
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+}
+
+/// While paused, the route accepts nothing: a client's data waits in the backlog
+/// until the API resumes it.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn pause_holds_new_connections_until_resume() {
+    let (proxy_addr, _, admin_addr) = spawn_administered_proxy().await;
+    let (status, answer) = call(admin_addr, "POST", "/pause").await;
+    assert_eq!(
+        (status.as_str(), answer),
+        ("HTTP/1.1 200 OK", json!({ "paused": true }))
+    );
+
+    let mut client = connect(proxy_addr).await;
+    client
+        .write_all(b"waiting")
+        .await
+        .expect("the write is buffered");
+    let mut buffer = [0; 7];
+    assert!(
+        timeout(Duration::from_millis(300), client.read_exact(&mut buffer))
+            .await
+            .is_err(),
+        "nothing is relayed while paused"
+    );
+    let listing = list_until(admin_addr, |_| true).await;
+    assert_eq!(listing, json!({ "paused": true, "connections": [] }));
+
+    let (_, answer) = call(admin_addr, "POST", "/resume").await;
+    assert_eq!(answer, json!({ "paused": false }));
+    timeout(IO_TIMEOUT, client.read_exact(&mut buffer))
+        .await
+        .expect("the client is served once resumed")
+        .expect("read failed");
+    assert_eq!(&buffer, b"waiting");
+}
+
+/// Known paths answer other methods with a 405, and unknown paths with a 404.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn other_requests_are_refused() {
+    let (_, _, admin_addr) = spawn_administered_proxy().await;
+    let (status, _) = call(admin_addr, "GET", "/pause").await;
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    let (status, _) = call(admin_addr, "POST", "/connections").await;
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    let (status, _) = call(admin_addr, "GET", "/metrics").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+/// An endpoint task that panics, or returns, ends the proxy's `select!` with an
+/// error naming it, not as a clean stop, while the tasks still running are left
+/// alone.
+#[tokio::test]
+async fn failed_endpoint_is_an_error() {
+    let mut services = Services::default();
+    services.spawn("the metrics endpoint".to_string(), std::future::pending());
+    services.spawn("the admin API".to_string(), async {
+        panic!("listener gone");
+    });
+    let error = timeout(IO_TIMEOUT, services.failed())
+        .await
+        .expect("the panic should surface");
+    assert_eq!(error.to_string(), "the admin API panicked: listener gone");
+
+    services.spawn("the admin API".to_string(), async {});
+    let error = timeout(IO_TIMEOUT, services.failed())
+        .await
+        .expect("the return should surface");
+    assert_eq!(error.to_string(), "the admin API stopped");
+    assert!(
+        timeout(Duration::from_millis(100), services.failed())
+            .await
+            .is_err(),
+        "the metrics endpoint is still serving"
+    );
+    services.shutdown().await;
+}
@@ -0,0 +1,165 @@
+//! The `--capture-dir` files: one per connection, named after it, holding both
+//! directions with their markers and a trailer with the totals and the close
+//! reason — however the connection ended.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::helpers::temp_path;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// Spawn a proxy to `remote_addr` writing its captures to a fresh directory,
+/// which is returned along with the proxy's address.
+async fn spawn_capturing_proxy(
+    remote_addr: SocketAddr,
+    idle_timeout: Option<u64>,
+) -> (SocketAddr, PathBuf) {
+    let directory = temp_path("capture-dir");
+    let capture_dir = directory.clone();
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        idle_timeout,
+        TEST_MAX_CONNECTIONS,
+        |arguments| arguments.capture_dir = Some(capture_dir),
+    )
+    .await;
+    (proxy_addr, directory)
+}
+
+/// The capture file of the connection from `client`, once its trailer has been
+/// written (the trailer is the last thing written, after both relays ended).
+pub(super) async fn finished_capture(directory: &Path, client: SocketAddr) -> (String, String) {
+    let marker = format!("_{}-{}_", client.ip(), client.port());
+    let deadline = Instant::now() + IO_TIMEOUT;
+    loop {
+        let found = fs::read_dir(directory)
+            .expect("the capture directory exists")
+            .map(|entry| entry.expect("directory entry").path())
+            .find(|path| path.to_string_lossy().contains(&marker));
+        if let Some(path) = found {
+            let content = fs::read_to_string(&path).expect("the capture file is readable");
+            if content.contains("# close reason:") {
+                let name = path
+                    .file_name()
+                    .expect("a file name")
+                    .to_string_lossy()
+                    .into_owned();
+                return (name, content);
+            }
+        }
+        assert!(
+            Instant::now() < deadline,
+            "no finished capture file for {client}"
+        );
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// A proxied exchange is written to a file named after the connection, with the
+/// header, the request and the reply under their direction markers, and the
+/// totals and close reason in the trailer.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn connection_is_captured_to_its_own_file() {
+    let echo_addr = spawn_echo_server().await;
+    let (proxy_addr, directory) =
+        spawn_capturing_proxy(echo_addr, Some(IO_TIMEOUT.as_secs())).await;
+
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"abc").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    drop(client);
+
+    let (name, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+
+    assert!(name.starts_with("conn-1_"), "unexpected file name {name}");
+    assert!(name.ends_with("Z.log"), "unexpected file name {name}");
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(
+        lines[..3],
+        [
+            "# connection #1",
+            format!("# client: {client_addr}").as_str(),
+            format!("# destination: {echo_addr}").as_str(),
+        ]
+    );
+    let events: Vec<&str> = lines
+        .iter()
+        .filter(|line| !line.starts_with('#'))
+        // Drop the wall-clock time and the offset.
+        .map(|line| line.splitn(3, ' ').nth(2).expect("a stamped event line"))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            format!("* connected to {echo_addr}").as_str(),
+            "< 61:62:63",
+            "> 61:62:63",
+        ]
+    );
+    assert!(content.contains("# client to destination: 3 bytes in 1 chunks\n"));
+    assert!(content.contains("# destination to client: 3 bytes in 1 chunks\n"));
+    assert!(content.ends_with("# close reason: the client closed the connection\n"));
+}
+
+/// A connection whose destination is unreachable still gets its file, closed with
+/// the connect error.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn connect_failure_is_the_close_reason() {
+    let dead = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind to reserve a dead port");
+    let dead_remote_addr = dead.local_addr().expect("dead local_addr");
+    drop(dead);
+    let (proxy_addr, directory) =
+        spawn_capturing_proxy(dead_remote_addr, Some(IO_TIMEOUT.as_secs())).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    let mut rest = Vec::new();
+    timeout(IO_TIMEOUT, client.read_to_end(&mut rest))
+        .await
+        .expect("the proxy did not close the client")
+        .expect("client read");
+
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+
+    assert!(content.contains("# client to destination: 0 bytes in 0 chunks\n"));
+    assert!(
+        content.contains("# close reason: failed to connect to the destination: "),
+        "unexpected capture:\n{content}"
+    );
+}
+
+/// A connection closed by `--timeout` names the idle timeout as its close reason.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn idle_timeout_is_the_close_reason() {
+    let echo_addr = spawn_echo_server().await;
+    let (proxy_addr, directory) = spawn_capturing_proxy(echo_addr, Some(1)).await;
+
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"then silence").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+
+    assert!(
+        content.ends_with("# close reason: idle for 1s\n"),
+        "unexpected capture:\n{content}"
+    );
+}
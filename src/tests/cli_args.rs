@@ -2,10 +2,16 @@
 //! and the `--remote-addr` grammar plus its error messages. No sockets involved.
 
 use crate::args::Arguments;
+use crate::args::Cli;
+use crate::args::Command;
 use crate::args::LoggingLevel;
+use crate::args::OutputFormat;
 use crate::args::PayloadFormattingKind;
+use crate::args::ReplayMode;
 use crate::args::TargetAddr;
 use crate::args::TimestampPrecision;
+use crate::args::parse_byte_size;
+use std::path::PathBuf;
 
 /// `--timeout` is range-validated by clap: `0` and values large enough to overflow
 /// the monotonic clock are rejected, a normal value parses, and omitting it yields
@@ -155,12 +161,22 @@ fn value_enum_names_match_documented_cli_values() {
     );
     check!(
         PayloadFormattingKind,
-        &["decimal", "lowerhex", "upperhex", "binary", "octal"]
+        &[
+            "decimal",
+            "lowerhex",
+            "upperhex",
+            "binary",
+            "octal",
+            "hexdump",
+            "text",
+            "utf8-lossy",
+        ]
     );
     check!(
         TimestampPrecision,
         &["seconds", "milliseconds", "microseconds", "nanoseconds"]
     );
+    check!(OutputFormat, &["text", "jsonl"]);
 }
 
 /// `--threads` has a default and is range-validated: `0` (which Tokio forbids) and
@@ -204,6 +220,632 @@ fn threads_has_a_default_and_is_range_validated() {
     );
 }
 
+/// `--hexdump-width` defaults to the `hexdump -C` row of 16 bytes and is
+/// range-validated: a zero-byte row is rejected, as is one too wide to read.
+#[test]
+fn hexdump_width_has_a_default_and_is_range_validated() {
+    use clap::Parser;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    assert_eq!(
+        parse(&[])
+            .expect("default hexdump width should parse")
+            .hexdump_width,
+        16,
+    );
+    let arguments = parse(&["--formatting", "hexdump", "--hexdump-width", "8"])
+        .expect("an explicit hexdump width should parse");
+    assert_eq!(arguments.formatting, PayloadFormattingKind::Hexdump);
+    assert_eq!(arguments.hexdump_width, 8);
+    assert!(
+        parse(&["--hexdump-width", "1"]).is_ok(),
+        "the minimum (1) is accepted"
+    );
+    assert!(
+        parse(&["--hexdump-width", "256"]).is_ok(),
+        "the maximum (256) is accepted"
+    );
+    assert!(parse(&["--hexdump-width", "0"]).is_err(), "0 is rejected");
+    assert!(
+        parse(&["--hexdump-width", "257"]).is_err(),
+        "above the maximum is rejected"
+    );
+}
+
+/// The `--rate-limit-*` options are off by default, take a byte rate with an
+/// optional binary-multiple suffix, and reject a rate of zero.
+#[test]
+fn rate_limits_are_off_by_default_and_take_a_suffix() {
+    use clap::Parser;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    let defaults = parse(&[]).expect("no rate limits should parse");
+    assert_eq!(defaults.rate_limit_up, None);
+    assert_eq!(defaults.rate_limit_down, None);
+    assert_eq!(defaults.rate_limit_global, None);
+    let arguments = parse(&[
+        "--rate-limit-up",
+        "64K",
+        "--rate-limit-down",
+        "1500",
+        "--rate-limit-global",
+        "1M",
+    ])
+    .expect("explicit rate limits should parse");
+    assert_eq!(arguments.rate_limit_up, Some(64 * 1024));
+    assert_eq!(arguments.rate_limit_down, Some(1500));
+    assert_eq!(arguments.rate_limit_global, Some(1024 * 1024));
+    assert!(parse(&["--rate-limit-up", "0"]).is_err(), "0 is rejected");
+    assert!(
+        parse(&["--rate-limit-down", "fast"]).is_err(),
+        "a non-number is rejected"
+    );
+}
+
+/// `--fragment` takes a fixed size or a `MIN-MAX` range, `--fragment-delay` needs a
+/// `--fragment` to space out, and `--coalesce` takes a window of at least 1ms.
+#[test]
+fn rechunking_options_parse() {
+    use crate::netem::FragmentSize;
+    use clap::Parser;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    let defaults = parse(&[]).expect("no re-chunking should parse");
+    assert_eq!(defaults.fragment, None);
+    assert_eq!(defaults.fragment_delay, 0);
+    assert_eq!(defaults.coalesce, None);
+    let fixed =
+        parse(&["--fragment", "1", "--fragment-delay", "5"]).expect("a fixed size should parse");
+    assert_eq!(fixed.fragment, Some(FragmentSize { min: 1, max: 1 }));
+    assert_eq!(fixed.fragment_delay, 5);
+    let range = parse(&["--fragment", "8-64", "--coalesce", "20"])
+        .expect("a range and a window should parse");
+    assert_eq!(range.fragment, Some(FragmentSize { min: 8, max: 64 }));
+    assert_eq!(range.coalesce, Some(20));
+    assert_eq!(
+        range.fragment.map(|size| size.to_string()).as_deref(),
+        Some("8-64")
+    );
+
+    for size in ["0", "64-8", "70000", "a-b", "8-"] {
+        let error = parse(&["--fragment", size])
+            .expect_err("a malformed size is rejected")
+            .to_string();
+        assert!(
+            error.contains(&format!("invalid fragment size `{size}`")),
+            "{size}: {error}"
+        );
+    }
+    assert!(
+        parse(&["--fragment-delay", "10"]).is_err(),
+        "a delay without a fragment is rejected"
+    );
+    assert!(parse(&["--coalesce", "0"]).is_err(), "0 is rejected");
+}
+
+/// `--tls-cert` and `--tls-key` are off by default and only accepted together.
+#[test]
+fn tls_options_go_together() {
+    use clap::Parser;
+    use std::path::Path;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    let defaults = parse(&[]).expect("no TLS should parse");
+    assert_eq!(defaults.tls_cert, None);
+    assert_eq!(defaults.tls_key, None);
+    let arguments = parse(&["--tls-cert", "cert.pem", "--tls-key", "key.pem"])
+        .expect("a certificate and its key should parse");
+    assert_eq!(arguments.tls_cert.as_deref(), Some(Path::new("cert.pem")));
+    assert_eq!(arguments.tls_key.as_deref(), Some(Path::new("key.pem")));
+    assert!(
+        parse(&["--tls-cert", "cert.pem"]).is_err(),
+        "a certificate without its key is rejected"
+    );
+    assert!(
+        parse(&["--tls-key", "key.pem"]).is_err(),
+        "a key without its certificate is rejected"
+    );
+    let arguments = parse(&["--tls-mitm-ca", "ca.pem", "--tls-mitm-key", "ca-key.pem"])
+        .expect("a CA and its key should parse");
+    assert_eq!(arguments.tls_mitm_ca.as_deref(), Some(Path::new("ca.pem")));
+    assert_eq!(
+        arguments.tls_mitm_key.as_deref(),
+        Some(Path::new("ca-key.pem"))
+    );
+    assert!(
+        parse(&["--tls-mitm-ca", "ca.pem"]).is_err(),
+        "a CA without its key is rejected"
+    );
+    let arguments = parse(&[
+        "--tls-mitm-ca",
+        "ca.pem",
+        "--tls-mitm-key",
+        "ca-key.pem",
+        "--remote-ca",
+        "remote-ca.pem",
+    ])
+    .expect("interception takes the remote TLS options without --remote-tls");
+    assert!(!arguments.remote_tls);
+    assert!(arguments.originates_tls());
+    assert!(
+        parse(&[
+            "--tls-mitm-ca",
+            "ca.pem",
+            "--tls-mitm-key",
+            "ca-key.pem",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+        ])
+        .is_err(),
+        "minting contradicts a fixed certificate"
+    );
+}
+
+#[test]
+fn remote_tls_options_need_remote_tls() {
+    use clap::Parser;
+    use std::path::Path;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    let defaults = parse(&[]).expect("no remote TLS should parse");
+    assert!(!defaults.remote_tls);
+    assert!(!defaults.remote_insecure);
+    let arguments = parse(&[
+        "--remote-tls",
+        "--remote-ca",
+        "ca.pem",
+        "--remote-sni",
+        "device.lab",
+        "--remote-client-cert",
+        "client.pem",
+        "--remote-client-key",
+        "client-key.pem",
+    ])
+    .expect("every remote TLS option should parse");
+    assert!(arguments.remote_tls);
+    assert_eq!(arguments.remote_ca.as_deref(), Some(Path::new("ca.pem")));
+    assert_eq!(arguments.remote_sni.as_deref(), Some("device.lab"));
+    assert_eq!(
+        arguments.remote_client_cert.as_deref(),
+        Some(Path::new("client.pem"))
+    );
+    assert_eq!(
+        arguments.remote_client_key.as_deref(),
+        Some(Path::new("client-key.pem"))
+    );
+    assert!(
+        parse(&["--remote-tls", "--remote-insecure"])
+            .expect("insecure should parse")
+            .remote_insecure
+    );
+    for without_tls in [
+        &["--remote-ca", "ca.pem"][..],
+        &["--remote-sni", "device.lab"],
+        &["--remote-insecure"],
+    ] {
+        assert!(
+            parse(without_tls).is_err(),
+            "{without_tls:?} without --remote-tls is rejected"
+        );
+    }
+    assert!(
+        parse(&["--remote-tls", "--remote-client-cert", "client.pem"]).is_err(),
+        "a client certificate without its key is rejected"
+    );
+    assert!(
+        parse(&["--remote-tls", "--remote-insecure", "--remote-ca", "ca.pem"]).is_err(),
+        "skipping verification contradicts a CA bundle"
+    );
+}
+
+/// `--decode` takes a protocol it knows, and `--decode-body` needs it.
+#[test]
+fn decode_takes_a_known_protocol() {
+    use crate::args::DecodeProtocol;
+    use clap::Parser;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    let arguments = parse(&[]).expect("the defaults should parse");
+    assert_eq!(arguments.decode, None);
+    assert!(!arguments.decode_body);
+    let arguments =
+        parse(&["--decode", "http", "--decode-body"]).expect("`--decode http` should parse");
+    assert_eq!(arguments.decode, Some(DecodeProtocol::Http));
+    assert!(arguments.decode_body);
+
+    assert!(parse(&["--decode", "grpc"]).is_err(), "an unknown protocol");
+    assert!(
+        parse(&["--decode-body"]).is_err(),
+        "`--decode-body` without `--decode` is rejected"
+    );
+}
+
+/// `--fault` is repeatable and reads `[DIRECTION:]ACTION[=VALUE][@TRIGGER]`, the
+/// direction defaulting to both and the trigger to none; a malformed fault is
+/// rejected with what is wrong with it.
+#[test]
+fn faults_parse_in_their_documented_form() {
+    use crate::fault::Action;
+    use crate::fault::Fault;
+    use crate::fault::Side;
+    use crate::fault::Trigger;
+    use clap::Parser;
+    use std::time::Duration;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    let defaults = parse(&[]).expect("no faults should parse");
+    assert!(defaults.faults.is_empty());
+    assert_eq!(defaults.seed, None);
+    let arguments = parse(&[
+        "--fault",
+        "up:reset@4K",
+        "--fault",
+        "truncate@500ms",
+        "--fault",
+        "down:stall=2s@100",
+        "--fault",
+        "both:flip=0.25",
+        "--seed",
+        "42",
+    ])
+    .expect("valid faults should parse");
+    assert_eq!(
+        arguments.faults,
+        [
+            Fault {
+                side: Side::Up,
+                action: Action::Reset,
+                trigger: Some(Trigger::Bytes(4096)),
+            },
+            Fault {
+                side: Side::Both,
+                action: Action::Truncate,
+                trigger: Some(Trigger::After(Duration::from_millis(500))),
+            },
+            Fault {
+                side: Side::Down,
+                action: Action::Stall(Duration::from_secs(2)),
+                trigger: Some(Trigger::Bytes(100)),
+            },
+            Fault {
+                side: Side::Both,
+                action: Action::Flip("0.25".parse().expect("a valid probability")),
+                trigger: None,
+            },
+        ]
+    );
+    assert_eq!(arguments.seed, Some(42));
+    let written: Vec<String> = arguments.faults.iter().map(Fault::to_string).collect();
+    assert_eq!(
+        written,
+        [
+            "up:reset@4096",
+            "both:truncate@500ms",
+            "down:stall=2s@100",
+            "both:flip=0.25"
+        ]
+    );
+
+    for (fault, reason) in [
+        ("sideways:reset", "unknown direction `sideways`"),
+        ("up:explode", "unknown action `explode`"),
+        ("reset=1", "`reset` takes no value"),
+        ("stall", "`stall` needs a duration"),
+        ("stall=2m", "invalid duration `2m`"),
+        ("flip", "`flip` needs a probability"),
+        ("replace=1.5", "invalid probability `1.5`"),
+        ("reset@0", "invalid size `0`"),
+    ] {
+        let error = parse(&["--fault", fault])
+            .expect_err("a malformed fault is rejected")
+            .to_string();
+        assert!(
+            error.contains(&format!("invalid fault `{fault}`: {reason}")),
+            "{fault}: {error}"
+        );
+    }
+}
+
+/// The `--log-file` options: sizes take an optional binary-multiple suffix, the
+/// rotation options are rejected without a file to rotate, and `--log-keep` has a
+/// default and must keep at least one segment.
+#[test]
+fn log_file_options_parse_and_require_a_file() {
+    use clap::Parser;
+
+    fn parse(extra: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut argv = vec!["logged_tcp_proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:0"];
+        argv.extend_from_slice(extra);
+        Arguments::try_parse_from(argv)
+    }
+
+    let defaults = parse(&[]).expect("no log file should parse");
+    assert_eq!(defaults.log_file, None);
+    assert_eq!(defaults.log_keep, 5);
+    assert!(!defaults.log_compress);
+
+    let arguments = parse(&[
+        "--log-file",
+        "proxy.log",
+        "--log-rotate-size",
+        "10M",
+        "--log-rotate-interval",
+        "3600",
+        "--log-keep",
+        "3",
+        "--log-compress",
+    ])
+    .expect("a full log-file configuration should parse");
+    assert_eq!(arguments.log_rotate_size, Some(10 * 1024 * 1024));
+    assert_eq!(arguments.log_rotate_interval, Some(3600));
+    assert_eq!(arguments.log_keep, 3);
+    assert!(arguments.log_compress);
+
+    for orphan in [
+        &["--log-rotate-size", "1K"][..],
+        &["--log-rotate-interval", "60"],
+        &["--log-compress"],
+    ] {
+        assert!(parse(orphan).is_err(), "{orphan:?} without --log-file");
+    }
+    assert!(parse(&["--log-file", "a", "--log-keep", "0"]).is_err());
+    assert!(parse(&["--log-file", "a", "--log-rotate-interval", "0"]).is_err());
+
+    assert_eq!(parse_byte_size("512"), Ok(512));
+    assert_eq!(parse_byte_size("4K"), Ok(4096));
+    assert_eq!(parse_byte_size("4kib"), Ok(4096));
+    assert_eq!(parse_byte_size("2 M"), Ok(2 * 1024 * 1024));
+    assert_eq!(parse_byte_size("1G"), Ok(1024 * 1024 * 1024));
+    for invalid in ["", "0", "0K", "K", "1.5M", "1T", "-1", "99999999999G"] {
+        assert!(parse_byte_size(invalid).is_err(), "{invalid:?} is rejected");
+    }
+}
+
+/// `--route` repeats, takes an optional name (the listen address names it
+/// otherwise), and stands in for the `-b`/`-r` pair, which must come together.
+/// Duplicate names are rejected when the routes are collected.
+#[test]
+fn routes_parse_and_replace_the_listener_pair() {
+    use clap::Parser;
+
+    fn parse(argv: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut full = vec!["logged_tcp_proxy"];
+        full.extend_from_slice(argv);
+        Arguments::try_parse_from(full)
+    }
+    fn names(arguments: &Arguments) -> Vec<Option<String>> {
+        arguments
+            .routes()
+            .expect("valid routes")
+            .into_iter()
+            .map(|route| route.name)
+            .collect()
+    }
+
+    let arguments = parse(&[
+        "--route",
+        "db=127.0.0.1:15432=db.internal:5432",
+        "--route",
+        "127.0.0.1:16379=127.0.0.1:6379",
+    ])
+    .expect("routes alone should parse");
+    assert_eq!(arguments.bind_listener_addr, None);
+    let routes = arguments.routes().expect("valid routes");
+    assert_eq!(routes[0].listen_addr.to_string(), "127.0.0.1:15432");
+    assert_eq!(routes[0].destination(), "db.internal:5432");
+    assert_eq!(
+        names(&arguments),
+        [Some("db".to_string()), Some("127.0.0.1:16379".to_string())]
+    );
+
+    // The `-b`/`-r` pair is an unnamed route of its own, first.
+    let arguments = parse(&[
+        "-b",
+        "127.0.0.1:0",
+        "-r",
+        "127.0.0.1:9",
+        "--route",
+        "extra=127.0.0.1:0=127.0.0.1:10",
+    ])
+    .expect("the pair and a route should parse");
+    assert_eq!(names(&arguments), [None, Some("extra".to_string())]);
+
+    for invalid in [
+        &["-b", "127.0.0.1:0"][..],
+        &["-r", "127.0.0.1:9", "--route", "127.0.0.1:0=127.0.0.1:10"],
+        &["--route", "127.0.0.1:0"],
+        &["--route", "localhost:80=127.0.0.1:10"],
+        &["--route", "127.0.0.1:0=http://example.com"],
+        &["--route", "a=b=127.0.0.1:0=127.0.0.1:10"],
+        &["--route", "two words=127.0.0.1:0=127.0.0.1:10"],
+        &["--route", "=127.0.0.1:0=127.0.0.1:10"],
+    ] {
+        assert!(parse(invalid).is_err(), "{invalid:?} is rejected");
+    }
+
+    let duplicated = parse(&[
+        "--route",
+        "same=127.0.0.1:0=127.0.0.1:9",
+        "--route",
+        "same=127.0.0.1:0=127.0.0.1:10",
+    ])
+    .expect("duplicates are only caught when the routes are collected");
+    assert!(duplicated.routes().is_err());
+}
+
+/// `--sni-route` repeats, and lets `-b` go without `-r`: the routes alone then
+/// pick each connection's destination.
+#[test]
+fn sni_routes_make_the_remote_address_optional() {
+    use clap::Parser;
+
+    fn parse(argv: &[&str]) -> Result<Arguments, clap::Error> {
+        let mut full = vec!["logged_tcp_proxy"];
+        full.extend_from_slice(argv);
+        Arguments::try_parse_from(full)
+    }
+
+    let arguments = parse(&[
+        "-b",
+        "127.0.0.1:0",
+        "--sni-route",
+        "api.lab=127.0.0.1:9",
+        "--sni-route",
+        "*.Web.Lab.=web.internal:443",
+    ])
+    .expect("SNI routes alone should parse");
+    assert!(arguments.remote_addr.is_none());
+    let routes: Vec<String> = arguments
+        .sni_routes
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        routes,
+        ["api.lab=127.0.0.1:9", "*.web.lab=web.internal:443"]
+    );
+    let listeners = arguments.routes().expect("valid routes");
+    assert!(listeners[0].remote_addr.is_none());
+    assert_eq!(listeners[0].destination(), "by SNI");
+
+    for invalid in [
+        &["-b", "127.0.0.1:0"][..],
+        &["-b", "127.0.0.1:0", "--sni-route", "*=127.0.0.1:9"],
+        &["-b", "127.0.0.1:0", "--sni-route", "api.lab"],
+        &["-b", "127.0.0.1:0", "--sni-route", "api.lab=http://api.lab"],
+    ] {
+        assert!(parse(invalid).is_err(), "{invalid:?} is rejected");
+    }
+}
+
+/// The command line runs the proxy with or without the `proxy` subcommand (so
+/// command lines from before the subcommands keep working), and `replay` takes a
+/// mode, a capture and its own options, with `--speed` validated.
+#[test]
+fn subcommands_keep_the_plain_proxy_command_line() {
+    use clap::Parser;
+
+    fn parse(argv: &[&str]) -> Result<Command, clap::Error> {
+        let mut full = vec!["logged_tcp_proxy"];
+        full.extend_from_slice(argv);
+        Cli::try_parse_from(full).map(Cli::into_command)
+    }
+
+    for argv in [
+        &["-b", "127.0.0.1:0", "-r", "127.0.0.1:9"][..],
+        &["proxy", "-b", "127.0.0.1:0", "-r", "127.0.0.1:9"],
+    ] {
+        let Ok(Command::Proxy(arguments)) = parse(argv) else {
+            panic!("{argv:?} should run the proxy");
+        };
+        assert_eq!(
+            arguments.remote_addr.map(|remote| remote.to_string()),
+            Some("127.0.0.1:9".to_string())
+        );
+    }
+    assert!(parse(&[]).is_err(), "the proxy's addresses are required");
+
+    let Ok(Command::Replay(replay)) = parse(&[
+        "replay",
+        "client",
+        "-r",
+        "device:502",
+        "session.pcapng",
+        "-c",
+        "3",
+        "--speed",
+        "2.5",
+    ]) else {
+        panic!("a client replay should parse");
+    };
+    let ReplayMode::Client {
+        remote_addr,
+        options,
+    } = replay.mode
+    else {
+        panic!("expected the client mode");
+    };
+    assert_eq!(remote_addr.to_string(), "device:502");
+    assert_eq!(options.capture, PathBuf::from("session.pcapng"));
+    assert_eq!(options.connection, Some(3));
+    assert_eq!(options.speed, 2.5);
+    assert_eq!(options.timeout, 5);
+
+    let Ok(Command::Replay(replay)) =
+        parse(&["replay", "server", "-b", "127.0.0.1:0", "session.pcapng"])
+    else {
+        panic!("a server replay should parse");
+    };
+    let ReplayMode::Server { options, .. } = replay.mode else {
+        panic!("expected the server mode");
+    };
+    assert_eq!(options.connection, None);
+    assert_eq!(options.speed, 1.0);
+
+    for invalid in [
+        &["replay", "server", "-b", "127.0.0.1:0"][..],
+        &["replay", "-b", "127.0.0.1:0", "session.pcapng"],
+        &[
+            "replay",
+            "server",
+            "-b",
+            "127.0.0.1:0",
+            "c",
+            "--speed",
+            "-1",
+        ],
+        &[
+            "replay",
+            "server",
+            "-b",
+            "127.0.0.1:0",
+            "c",
+            "--speed",
+            "inf",
+        ],
+        &["replay", "server", "-b", "127.0.0.1:0", "c", "-t", "0"],
+    ] {
+        assert!(parse(invalid).is_err(), "{invalid:?} is rejected");
+    }
+}
+
 /// `--remote-addr` accepts either a literal `IP:port` (parsed straight to a socket
 /// address, never resolved) or a `hostname:port` (kept as a name and resolved
 /// lazily at connect time). Malformed values are rejected at parse time without any
@@ -221,13 +863,13 @@ fn remote_addr_accepts_ip_and_hostname() {
         parse("127.0.0.1:8080")
             .expect("an IPv4 remote should parse")
             .remote_addr,
-        TargetAddr::Socket(_)
+        Some(TargetAddr::Socket(_))
     ));
     assert!(matches!(
         parse("[::1]:8080")
             .expect("a bracketed IPv6 remote should parse")
             .remote_addr,
-        TargetAddr::Socket(_)
+        Some(TargetAddr::Socket(_))
     ));
 
     // A hostname parses (offline) into `Named`, proving resolution is deferred.
@@ -235,7 +877,7 @@ fn remote_addr_accepts_ip_and_hostname() {
         .expect("a hostname remote should parse")
         .remote_addr
     {
-        TargetAddr::Named { host, port } => {
+        Some(TargetAddr::Named { host, port }) => {
             assert_eq!(host, "example.com");
             assert_eq!(port, 443);
         }
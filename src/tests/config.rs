@@ -0,0 +1,336 @@
+//! `--config`: a TOML file filling in the options the command line leaves out,
+//! `[[route]]` tables with settings of their own, and errors that point at the
+//! file's line.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::Shared;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_route;
+use super::helpers::temp_path;
+use super::helpers::test_arguments;
+use super::log_capture::captured_lines;
+use super::log_capture::install_capturing_logger;
+use crate::args::Arguments;
+use crate::args::Cli;
+use crate::args::Command;
+use crate::args::LoggingLevel;
+use crate::args::PayloadFormattingKind;
+use crate::args::Route;
+use crate::args::RouteSettings;
+use crate::config;
+use crate::conn::Sinks;
+use crate::sni;
+use std::fs;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+/// Write `contents` to a fresh `.toml` file and run the command line `argv` with
+/// `--config` pointing at it, returning the merged arguments or the error.
+fn load(contents: &str, argv: &[&str]) -> Result<Arguments, String> {
+    let path = temp_path("config").with_extension("toml");
+    fs::write(&path, contents).expect("write the config file");
+    let path_arg = path.to_str().expect("a UTF-8 temp path").to_string();
+    let mut full = vec!["logged_tcp_proxy", "--config", &path_arg];
+    full.extend_from_slice(argv);
+    let parsed = Cli::try_parse_explicit_from(full);
+    let Ok((Command::Proxy(arguments), explicit)) = parsed else {
+        panic!("{argv:?} should parse as the proxy: {parsed:?}");
+    };
+    let merged = config::apply(&arguments, &explicit);
+    let _ = fs::remove_file(&path);
+    // Errors name the file; keep only the part after it, which the tests compare.
+    merged.map_err(|error| {
+        error
+            .strip_prefix(path_arg.as_str())
+            .expect("the error names the file")
+            .to_string()
+    })
+}
+
+const CONFIG: &str = r#"
+level = "info"
+bind-listener-addr = "127.0.0.1:0"
+remote-addr = "db.internal:5432"
+timeout = 30
+shutdown-grace = 20
+max-connections = 64
+threads = 2
+formatting = "decimal"
+connection-ids = false
+log-file = "proxy.log"
+log-rotate-size = "10M"
+
+[[route]]
+name = "cache"
+listen = "127.0.0.1:0"
+remote = "127.0.0.1:6379"
+timeout = 5
+formatting = "text"
+"#;
+
+/// The file sets what the command line leaves out, and its routes come before
+/// the command line's, each keeping the settings it gives itself.
+#[test]
+fn file_values_fill_in_what_the_command_line_leaves_out() {
+    let arguments = load(CONFIG, &["--route", "extra=127.0.0.1:0=127.0.0.1:9"])
+        .expect("a valid file should load");
+    assert_eq!(arguments.level, LoggingLevel::Info);
+    assert_eq!(
+        arguments.remote_addr.as_ref().map(ToString::to_string),
+        Some("db.internal:5432".to_string())
+    );
+    assert_eq!(arguments.timeout, Some(30));
+    assert_eq!(arguments.shutdown_grace, 20);
+    assert_eq!(arguments.max_connections, 64);
+    assert_eq!(arguments.threads, 2);
+    assert_eq!(arguments.formatting, PayloadFormattingKind::Decimal);
+    assert!(!arguments.connection_ids);
+    assert_eq!(arguments.log_rotate_size, Some(10 * 1024 * 1024));
+    // Untouched by the file: still the command line's default.
+    assert_eq!(arguments.separator, ":");
+
+    let routes = arguments.routes().expect("valid routes");
+    let names: Vec<_> = routes.iter().map(|route| route.name.as_deref()).collect();
+    assert_eq!(names, [None, Some("cache"), Some("extra")]);
+    assert_eq!(
+        routes[1].settings,
+        RouteSettings {
+            timeout: Some(5),
+            formatting: Some(PayloadFormattingKind::Text),
+            ..RouteSettings::default()
+        }
+    );
+}
+
+/// An option given on the command line wins over the file, including over a
+/// route's own setting; a default the command line merely left alone does not.
+#[test]
+fn command_line_flags_override_file_values() {
+    let arguments = load(
+        CONFIG,
+        &[
+            "-t",
+            "7",
+            "-f",
+            "binary",
+            "-b",
+            "127.0.0.1:0",
+            "-r",
+            "127.0.0.1:9",
+            "-l",
+            "debug",
+        ],
+    )
+    .expect("a valid file should load");
+    assert_eq!(arguments.timeout, Some(7));
+    assert_eq!(arguments.formatting, PayloadFormattingKind::Binary);
+    assert_eq!(arguments.level, LoggingLevel::Debug);
+    assert_eq!(
+        arguments.remote_addr.as_ref().map(ToString::to_string),
+        Some("127.0.0.1:9".to_string())
+    );
+    assert_eq!(arguments.max_connections, 64, "not given: the file's value");
+
+    let routes = arguments.routes().expect("valid routes");
+    assert_eq!(routes[1].settings, RouteSettings::default());
+    assert_eq!(routes[1].settings.apply(arguments.clone()).timeout, Some(7));
+}
+
+/// `fault` lists faults at the top level and per route, a route's replacing the
+/// proxy-wide ones for its connections; `--fault` on the command line replaces
+/// both.
+#[test]
+fn faults_are_set_per_route() {
+    let contents = r#"
+bind-listener-addr = "127.0.0.1:0"
+remote-addr = "127.0.0.1:9"
+fault = ["up:reset@1K"]
+seed = 7
+
+[[route]]
+name = "flaky"
+listen = "127.0.0.1:0"
+remote = "127.0.0.1:9"
+fault = ["down:truncate@10", "flip=0.001"]
+"#;
+    let describe = |arguments: &Arguments| -> Vec<String> {
+        arguments.faults.iter().map(ToString::to_string).collect()
+    };
+    let arguments = load(contents, &[]).expect("a valid file should load");
+    assert_eq!(describe(&arguments), ["up:reset@1024"]);
+    assert_eq!(arguments.seed, Some(7));
+    let routes = arguments.routes().expect("valid routes");
+    assert_eq!(
+        describe(&routes[1].settings.apply(arguments.clone())),
+        ["down:truncate@10", "both:flip=0.001"]
+    );
+
+    let arguments = load(contents, &["--fault", "down:stall=1s"]).expect("a valid file");
+    let routes = arguments.routes().expect("valid routes");
+    for route in &routes {
+        assert_eq!(
+            describe(&route.settings.apply(arguments.clone())),
+            ["down:stall=1s"]
+        );
+    }
+
+    assert_eq!(
+        load(
+            "bind-listener-addr = \"127.0.0.1:0\"\nremote-addr = \"127.0.0.1:9\"\nfault = [\n  \"reset\",\n  \"stall\",\n]",
+            &[]
+        )
+        .err()
+        .as_deref(),
+        Some(":5: invalid fault `stall`: `stall` needs a duration, such as `stall=2s`")
+    );
+}
+
+/// `sni-route` lists routes as the command line writes them, and lets
+/// `bind-listener-addr` go without a `remote-addr`; a bad one is reported with its
+/// line.
+#[test]
+fn sni_routes_stand_in_for_the_remote_address() {
+    let arguments = load(
+        "bind-listener-addr = \"127.0.0.1:0\"\nsni-route = [\"api.lab=127.0.0.1:9\", \"*.web.lab=web:443\"]",
+        &[],
+    )
+    .expect("a valid file should load");
+    assert!(arguments.remote_addr.is_none());
+    assert_eq!(
+        sni::describe(&arguments.sni_routes),
+        "[api.lab=127.0.0.1:9, *.web.lab=web:443]"
+    );
+    assert_eq!(
+        arguments.routes().expect("valid routes")[0].destination(),
+        "by SNI"
+    );
+
+    assert_eq!(
+        load("bind-listener-addr = \"127.0.0.1:0\"", &[])
+            .err()
+            .as_deref(),
+        Some(
+            ": `bind-listener-addr` needs a `remote-addr`, or `sni-route`s to pick the destination by"
+        )
+    );
+    assert_eq!(
+        load(
+            "bind-listener-addr = \"127.0.0.1:0\"\nsni-route = [\n  \"*=127.0.0.1:9\",\n]",
+            &[]
+        )
+        .err()
+        .as_deref(),
+        Some(
+            ":3: invalid SNI route `*=127.0.0.1:9`: `*` is not a name; the default destination is the listener's remote address"
+        )
+    );
+}
+
+/// Values are checked by the command line's rules, and a bad one is reported with
+/// the line it is on.
+#[test]
+fn invalid_values_are_reported_with_their_line() {
+    let cases = [
+        (
+            "threads = 1025",
+            ":1: invalid threads `1025`: expected 1..=1024",
+        ),
+        (
+            "\ntimeout = 3153600001",
+            ":2: invalid timeout `3153600001`: expected 1..=3153600000",
+        ),
+        (
+            "max-connections = 0",
+            ":1: invalid max-connections `0`: expected 1..=4294967295",
+        ),
+        (
+            "formatting = \"hex\"",
+            ":1: invalid formatting `hex`: expected one of decimal, lowerhex, upperhex, binary, octal, hexdump, text, utf8-lossy",
+        ),
+        (
+            "bind-listener-addr = \"127.0.0.1:0\"\n\n\nremote-addr = \"http://example.com\"",
+            ":4: invalid remote address `http://example.com`: expected `IP:port` or `host:port`, not a URL",
+        ),
+        (
+            "[[route]]\nname = \"two words\"\nlisten = \"127.0.0.1:0\"\nremote = \"127.0.0.1:9\"",
+            ":2: the name `two words` must be non-empty, without spaces, `[`, `]` or `#`",
+        ),
+        (
+            "[[route]]\nlisten = \"localhost:80\"\nremote = \"127.0.0.1:9\"",
+            ":2: `localhost:80` is not a literal `IP:port` listen address",
+        ),
+        (
+            "[[route]]\nname = \"a\"\nlisten = \"127.0.0.1:0\"\nremote = \"127.0.0.1:9\"\n\
+             [[route]]\nname = \"a\"\nlisten = \"127.0.0.1:0\"\nremote = \"127.0.0.1:9\"",
+            ":6: route name `a` is used more than once",
+        ),
+        (
+            "decode = \"grpc\"",
+            ":1: invalid decode `grpc`: expected one of http",
+        ),
+        (
+            "log-file = \"a.log\"\nlog-rotate-size = \"1T\"",
+            ":2: invalid size `1T`: expected a byte count with an optional K, M or G suffix",
+        ),
+    ];
+    for (contents, expected) in cases {
+        assert_eq!(load(contents, &[]).err().as_deref(), Some(expected));
+    }
+    assert_eq!(
+        load(
+            "bind-listener-addr = \"127.0.0.1:0\"\nremote-addr = \"127.0.0.1:9\"\ndecode-body = true",
+            &[]
+        )
+        .err()
+        .as_deref(),
+        Some(": `decode-body` needs a `decode`")
+    );
+
+    let unknown = load("tmieout = 3", &[]).expect_err("an unknown key is rejected");
+    assert!(
+        unknown.starts_with(":1: unknown field `tmieout`"),
+        "{unknown}"
+    );
+    assert_eq!(
+        load("level = \"info\"", &[]).err().as_deref(),
+        Some(
+            ": nothing to listen on: set `bind-listener-addr` and `remote-addr`, or add a `[[route]]`"
+        )
+    );
+}
+
+/// A route's own settings apply to its connections: here its lines drop the
+/// `[#N]` tag while the proxy-wide default keeps it.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn route_settings_apply_to_its_connections() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let placeholder: SocketAddr = LOOPBACK.parse().expect("LOOPBACK parses");
+    let arguments = test_arguments(placeholder, echo_addr, Some(IO_TIMEOUT.as_secs()), 8);
+    let sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    let listener = TcpListener::bind(LOOPBACK).await.expect("bind the route");
+    let listen_addr = listener.local_addr().expect("route local_addr");
+    let route = Route {
+        name: Some("untagged".to_string()),
+        listen_addr,
+        remote_addr: Some(echo_addr.into()),
+        settings: RouteSettings {
+            connection_ids: Some(false),
+            ..RouteSettings::default()
+        },
+    };
+    spawn_route(listener, arguments, route, sinks, Shared::default());
+
+    let mut client = connect(listen_addr).await;
+    assert_round_trip(&mut client, b"route settings").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    let expected = format!("[untagged] Incoming connection from {client_addr}");
+    let lines = captured_lines();
+    assert!(
+        lines.contains(&expected),
+        "missing {expected:?}; captured: {lines:?}"
+    );
+}
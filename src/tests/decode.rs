@@ -0,0 +1,313 @@
+//! `--decode http`: the traffic relayed is parsed as HTTP/1.x, and each request
+//! and response is logged as one line — start line, headers and body size — while
+//! the bytes still reach the other side untouched.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_proxy_configured;
+use super::log_capture::event;
+use super::log_capture::events;
+use super::log_capture::install_capturing_logger;
+use crate::args::DecodeProtocol;
+use crate::args::PayloadFormattingKind;
+use crate::conn::Direction;
+use crate::decode::Decoded;
+use crate::decode::HttpDecoder;
+use crate::decode::Message;
+use crate::decode::StartLine;
+use std::net::SocketAddr;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::time::timeout;
+
+/// Feed `bytes` to `decoder` in `direction` one byte at a time, as the worst
+/// split a relay could read them in.
+fn feed_bytewise(decoder: &mut HttpDecoder, direction: Direction, bytes: &[u8]) -> Vec<Decoded> {
+    bytes
+        .iter()
+        .flat_map(|byte| decoder.feed(direction, std::slice::from_ref(byte)))
+        .collect()
+}
+
+/// The messages of `decoded`, which must hold nothing else.
+fn messages(decoded: Vec<Decoded>) -> Vec<Message> {
+    decoded
+        .into_iter()
+        .map(|decoded| match decoded {
+            Decoded::Message(message) => message,
+            Decoded::Stopped(reason) => panic!("decoding stopped: {reason}"),
+        })
+        .collect()
+}
+
+/// The `(method, target)` of a request, or the status of a response, as text.
+fn start(message: &Message) -> String {
+    match &message.start {
+        StartLine::Request { method, target, .. } => format!("{method} {target}"),
+        StartLine::Response { status, .. } => status.to_string(),
+    }
+}
+
+/// Pipelined requests are paired with their responses, however the bytes are
+/// split: a `HEAD` response's `Content-Length` frames no body, an interim `100`
+/// answers no request, and chunked and fixed-length bodies are measured without
+/// their framing.
+#[test]
+fn pipelined_messages_are_framed_one_by_one() {
+    let mut decoder = HttpDecoder::new(true);
+    let requests = feed_bytewise(
+        &mut decoder,
+        Direction::ClientToDestination,
+        b"HEAD /a HTTP/1.1\r\nHost: lab\r\n\r\n\
+          POST /b HTTP/1.1\r\nHost: lab\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\nhello\
+          PUT /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;ext=1\r\nabc\r\n2\r\nde\r\n0\r\nX-Trailer: 1\r\n\r\n",
+    );
+    let requests = messages(requests);
+    assert_eq!(
+        requests.iter().map(start).collect::<Vec<_>>(),
+        ["HEAD /a", "POST /b", "PUT /c"]
+    );
+    assert_eq!(requests[0].headers, [("Host".into(), "lab".into())]);
+    assert_eq!(requests[1].body.as_deref(), Some(&b"hello"[..]));
+    assert_eq!(requests[2].body_size, 5);
+    assert!(requests[2].chunked);
+    assert_eq!(requests[2].body.as_deref(), Some(&b"abcde"[..]));
+
+    let responses = feed_bytewise(
+        &mut decoder,
+        Direction::DestinationToClient,
+        b"HTTP/1.1 200 OK\r\nContent-Length: 1234\r\n\r\n\
+          HTTP/1.1 100 Continue\r\n\r\n\
+          HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok\
+          HTTP/1.1 204 No Content\r\n\r\n",
+    );
+    let responses = messages(responses);
+    assert_eq!(
+        responses.iter().map(start).collect::<Vec<_>>(),
+        ["200", "100", "201", "204"]
+    );
+    assert_eq!(responses[0].body_size, 0, "a HEAD response has no body");
+    assert_eq!(responses[2].body_size, 2);
+}
+
+/// A response with neither length nor chunking ends with the connection; one cut
+/// short of its length is reported as such.
+#[test]
+fn close_ends_a_body_without_a_length() {
+    let mut decoder = HttpDecoder::new(false);
+    decoder.feed(Direction::ClientToDestination, b"GET / HTTP/1.0\r\n\r\n");
+    let open = decoder.feed(
+        Direction::DestinationToClient,
+        b"HTTP/1.0 200 OK\r\nServer: old\r\n\r\nsome body",
+    );
+    assert!(open.is_empty(), "the body is still open: {open:?}");
+    let closed = messages(decoder.finish(Direction::DestinationToClient));
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].body_size, 9);
+    assert_eq!(closed[0].body, None, "bodies are only kept when asked for");
+
+    let mut decoder = HttpDecoder::new(false);
+    decoder.feed(
+        Direction::ClientToDestination,
+        b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort",
+    );
+    assert_eq!(
+        decoder.finish(Direction::ClientToDestination),
+        [Decoded::Stopped(
+            "the stream ended 5 bytes into a body".to_string()
+        )]
+    );
+}
+
+/// Once the server agrees to an upgrade, neither direction is decoded any more.
+#[test]
+fn upgrade_stops_both_directions() {
+    let mut decoder = HttpDecoder::new(false);
+    let request = decoder.feed(
+        Direction::ClientToDestination,
+        b"GET /chat HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n",
+    );
+    assert_eq!(messages(request).len(), 1);
+    let response = decoder.feed(
+        Direction::DestinationToClient,
+        b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n\x81\x05hello",
+    );
+    let [Decoded::Message(switching), Decoded::Stopped(reason)] = response.as_slice() else {
+        panic!("the 101 and the end of decoding expected: {response:?}");
+    };
+    assert_eq!(start(switching), "101");
+    assert_eq!(reason, "the peers switched to websocket");
+    assert!(
+        decoder
+            .feed(Direction::ClientToDestination, b"\x81\x85frame")
+            .is_empty()
+    );
+    assert!(decoder.finish(Direction::DestinationToClient).is_empty());
+}
+
+/// Bytes that are not HTTP stop the decoding of their direction at once, with the
+/// reason; the other direction goes on.
+#[test]
+fn other_protocols_stop_the_decoding() {
+    let mut decoder = HttpDecoder::new(false);
+    assert_eq!(
+        decoder.feed(Direction::ClientToDestination, b"\x16\x03\x01\x02\x00"),
+        [Decoded::Stopped(
+            "`\\u{16}\\u{3}\\u{1}\\u{2}\\0` is not the start of an HTTP/1.x request".to_string()
+        )]
+    );
+    assert!(
+        decoder
+            .feed(Direction::ClientToDestination, b"GET / HTTP/1.1\r\n\r\n")
+            .is_empty()
+    );
+    assert_eq!(
+        decoder.feed(Direction::DestinationToClient, b"SSH-2.0-OpenSSH\r\n"),
+        [Decoded::Stopped(
+            "`SSH-2.0-OpenSSH` is not the start of an HTTP/1.x response".to_string()
+        )]
+    );
+
+    let mut decoder = HttpDecoder::new(false);
+    assert_eq!(
+        decoder.feed(
+            Direction::ClientToDestination,
+            b"GET / HTTP/1.1\r\nContent-Length: ten\r\n\r\n"
+        ),
+        [Decoded::Stopped("invalid Content-Length `ten`".to_string())]
+    );
+}
+
+/// Spawn a proxy to `remote_addr` decoding HTTP, with the bodies shown as `text`
+/// if `bodies`.
+async fn spawn_decoding_proxy(remote_addr: SocketAddr, bodies: bool) -> SocketAddr {
+    spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.decode = Some(DecodeProtocol::Http);
+            arguments.decode_body = bodies;
+            arguments.formatting = PayloadFormattingKind::Text;
+        },
+    )
+    .await
+}
+
+/// Two pipelined requests to a real `tiny_http` server are logged with their
+/// responses, one line each, and the conversation is relayed intact.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn real_http_exchange_is_logged_per_message() {
+    install_capturing_logger();
+    let server = tiny_http::Server::http("127.0.0.1:0").expect("failed to start http server");
+    let http_addr = server
+        .server_addr()
+        .to_ip()
+        .expect("http server ip address");
+    std::thread::spawn(move || {
+        for mut request in server.incoming_requests().take(2) {
+            let mut body = String::new();
+            let _ = request.as_reader().read_to_string(&mut body);
+            let reply = format!("{} {} {body}", request.method(), request.url());
+            let _ = request.respond(tiny_http::Response::from_string(reply));
+        }
+    });
+    let proxy_addr = spawn_decoding_proxy(http_addr, true).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client
+        .write_all(
+            b"GET /status?verbose=1 HTTP/1.1\r\nHost: lab\r\n\r\n\
+              POST /items HTTP/1.1\r\nHost: lab\r\nContent-Length: 6\r\nConnection: close\r\n\r\nwidget",
+        )
+        .await
+        .expect("client write");
+    let mut response = String::new();
+    timeout(IO_TIMEOUT, client.read_to_string(&mut response))
+        .await
+        .expect("the server never closed")
+        .expect("client read");
+    assert!(response.ends_with("POST /items widget"), "{response}");
+
+    let requests = events(client_addr, "http_request", 2).await;
+    assert_eq!(requests[0]["method"], "GET");
+    assert_eq!(requests[0]["target"], "/status?verbose=1");
+    assert_eq!(requests[0]["version"], "HTTP/1.1");
+    assert_eq!(requests[0]["headers"], "Host: lab");
+    assert_eq!(requests[0]["body_bytes"], 0);
+    assert!(
+        requests[0]["message"]
+            .as_str()
+            .expect("the request line")
+            .ends_with(
+                "] < HTTP request: GET /status?verbose=1 HTTP/1.1 [Host: lab], body 0 bytes"
+            ),
+        "{}",
+        requests[0]
+    );
+    assert_eq!(requests[1]["direction"], "client_to_destination");
+    assert_eq!(requests[1]["body_bytes"], 6);
+    assert!(
+        requests[1]["message"]
+            .as_str()
+            .expect("the request line")
+            .ends_with(", body 6 bytes: widget"),
+        "{}",
+        requests[1]
+    );
+
+    let responses = events(client_addr, "http_response", 2).await;
+    assert_eq!(responses[0]["direction"], "destination_to_client");
+    assert_eq!(responses[0]["status"], 200);
+    assert_eq!(responses[0]["reason"], "OK");
+    assert_eq!(responses[0]["body_bytes"], 22);
+    assert!(
+        responses[1]["message"]
+            .as_str()
+            .expect("the response line")
+            .ends_with(", body 18 bytes: POST /items widget"),
+        "{}",
+        responses[1]
+    );
+}
+
+/// Without `--decode-body`, a message's line stops at the size of its body.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn bodies_are_only_shown_when_asked_for() {
+    install_capturing_logger();
+    let server = tiny_http::Server::http("127.0.0.1:0").expect("failed to start http server");
+    let http_addr = server
+        .server_addr()
+        .to_ip()
+        .expect("http server ip address");
+    std::thread::spawn(move || {
+        if let Ok(request) = server.recv() {
+            let _ = request.respond(tiny_http::Response::from_string("secret"));
+        }
+    });
+    let proxy_addr = spawn_decoding_proxy(http_addr, false).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .expect("client write");
+    let mut response = String::new();
+    timeout(IO_TIMEOUT, client.read_to_string(&mut response))
+        .await
+        .expect("the server never closed")
+        .expect("client read");
+
+    let response = event(client_addr, "http_response").await;
+    assert!(
+        response["message"]
+            .as_str()
+            .expect("the response line")
+            .ends_with(", body 6 bytes"),
+        "{response}"
+    );
+    assert!(response.get("body").is_none(), "{response}");
+}
@@ -1,12 +1,15 @@
-//! Failure paths that must not panic: an unavailable listen address and an
-//! unreachable remote.
+//! Failure paths that must not panic: an unavailable listen address (or route), an
+//! output file that cannot be created, and an unreachable remote.
 
 use super::helpers::IO_TIMEOUT;
 use super::helpers::LOOPBACK;
 use super::helpers::TEST_MAX_CONNECTIONS;
 use super::helpers::connect;
 use super::helpers::spawn_proxy;
+use super::helpers::temp_path;
 use super::helpers::test_arguments;
+use crate::args::Route;
+use crate::args::RouteSettings;
 use crate::conn::initialize_tcp_listener;
 use tokio::io::AsyncReadExt;
 use tokio::net::TcpListener;
@@ -23,12 +26,11 @@ async fn bind_failure_returns_error() {
     let in_use_addr = occupier.local_addr().expect("occupier local_addr");
 
     // `remote_addr` is irrelevant: the bind fails before any connection is served.
-    let result = initialize_tcp_listener(test_arguments(
-        in_use_addr,
-        in_use_addr,
+    let result = initialize_tcp_listener(
+        test_arguments(in_use_addr, in_use_addr, None, TEST_MAX_CONNECTIONS),
         None,
-        TEST_MAX_CONNECTIONS,
-    ))
+        None,
+    )
     .await;
 
     assert!(
@@ -37,6 +39,85 @@ async fn bind_failure_returns_error() {
     );
 }
 
+/// Every route is bound before any is served: one unavailable route address fails
+/// the whole startup, as does a route name given twice.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn any_bad_route_fails_startup() {
+    let occupier = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind occupier");
+    let in_use_addr = occupier.local_addr().expect("occupier local_addr");
+    let free_addr = LOOPBACK.parse().expect("LOOPBACK parses");
+    let route = |name: &str, listen_addr| Route {
+        name: Some(name.to_string()),
+        listen_addr,
+        remote_addr: Some(in_use_addr.into()),
+        settings: RouteSettings::default(),
+    };
+
+    let mut arguments = test_arguments(free_addr, in_use_addr, None, TEST_MAX_CONNECTIONS);
+    arguments.routes = vec![route("taken", in_use_addr)];
+    assert!(
+        initialize_tcp_listener(arguments, None, None)
+            .await
+            .is_err(),
+        "a route on an in-use address should fail the startup"
+    );
+
+    let mut arguments = test_arguments(free_addr, in_use_addr, None, TEST_MAX_CONNECTIONS);
+    arguments.routes = vec![route("twice", free_addr), route("twice", free_addr)];
+    assert!(
+        initialize_tcp_listener(arguments, None, None)
+            .await
+            .is_err(),
+        "a duplicated route name should fail the startup"
+    );
+}
+
+/// A `--pcap` file that cannot be created (here: its directory does not exist) is a
+/// startup error, like a failed bind, rather than a capture that silently never
+/// happens.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn uncreatable_pcap_file_returns_error() {
+    let mut arguments = test_arguments(
+        LOOPBACK.parse().expect("LOOPBACK parses"),
+        LOOPBACK.parse().expect("LOOPBACK parses"),
+        None,
+        TEST_MAX_CONNECTIONS,
+    );
+    arguments.pcap = Some(temp_path("missing-dir").join("capture.pcapng"));
+
+    let result = initialize_tcp_listener(arguments, None, None).await;
+
+    assert!(
+        result.is_err(),
+        "an uncreatable pcap file should return an error, not start the proxy"
+    );
+}
+
+/// Likewise a `--capture-dir` that cannot be created (here: a regular file is in
+/// the way) stops the proxy at startup.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn uncreatable_capture_dir_returns_error() {
+    let blocker = temp_path("capture-blocker");
+    std::fs::write(&blocker, b"").expect("failed to create the blocking file");
+    let mut arguments = test_arguments(
+        LOOPBACK.parse().expect("LOOPBACK parses"),
+        LOOPBACK.parse().expect("LOOPBACK parses"),
+        None,
+        TEST_MAX_CONNECTIONS,
+    );
+    arguments.capture_dir = Some(blocker.join("captures"));
+
+    let result = initialize_tcp_listener(arguments, None, None).await;
+    let _ = std::fs::remove_file(&blocker);
+
+    assert!(
+        result.is_err(),
+        "an uncreatable capture directory should return an error, not start the proxy"
+    );
+}
+
 /// When the remote is unreachable, the proxy must not panic: it logs the failure
 /// and closes the already-accepted client connection cleanly (the client's read
 /// returns end-of-stream rather than hanging).
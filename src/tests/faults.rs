@@ -0,0 +1,227 @@
+//! `--fault`: a reset aborts both peers at its byte count, a truncate half-closes
+//! its direction early, a stall holds it back, corruption with a `--seed` tampers
+//! with the same bytes on every run however they are chunked, and each injected
+//! fault is logged on its connection.
+//!
+//! The logged events are looked up by the test's own client address, in the
+//! `--output-format jsonl` rendering of the records.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::helpers::spawn_reply_server;
+use super::log_capture::event;
+use super::log_capture::install_capturing_logger;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// Spawn a proxy to `remote_addr` injecting `faults`, with its random choices
+/// drawn from `seed`.
+async fn spawn_faulty_proxy(remote_addr: SocketAddr, faults: &[&str], seed: u64) -> SocketAddr {
+    let faults = faults
+        .iter()
+        .map(|fault| fault.parse().expect("a valid fault"))
+        .collect();
+    spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.faults = faults;
+            arguments.seed = Some(seed);
+        },
+    )
+    .await
+}
+
+/// Read from `stream` until it ends, returning what was read and how it ended.
+async fn read_until_end(stream: &mut TcpStream) -> (Vec<u8>, io::Result<()>) {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 1024];
+    loop {
+        match stream.read(&mut buffer).await {
+            Ok(0) => return (received, Ok(())),
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(error) => return (received, Err(error)),
+        }
+    }
+}
+
+/// `up:reset@100` lets the first 100 bytes of the request through, then resets
+/// both sockets: the destination reads exactly those bytes and then a reset, the
+/// client a reset instead of an answer; the fault and the reset are logged.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn reset_aborts_both_peers_at_its_byte_count() {
+    install_capturing_logger();
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let remote_addr = listener.local_addr().expect("remote local_addr");
+    let (ended, end) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("remote accept");
+        let _ = ended.send(read_until_end(&mut stream).await);
+    });
+    let proxy_addr = spawn_faulty_proxy(remote_addr, &["up:reset@100"], 1).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client.write_all(&[7; 300]).await.expect("client write");
+    let (answer, client_end) = timeout(IO_TIMEOUT, read_until_end(&mut client))
+        .await
+        .expect("the client was never reset");
+    let (request, remote_end) = timeout(IO_TIMEOUT, end)
+        .await
+        .expect("the remote was never reset")
+        .expect("the remote reported");
+
+    assert!(answer.is_empty(), "no answer, got {answer:?}");
+    assert_eq!(
+        client_end.expect_err("the client is reset").kind(),
+        io::ErrorKind::ConnectionReset
+    );
+    assert_eq!(request, [7; 100], "exactly the bytes before the fault");
+    assert_eq!(
+        remote_end.expect_err("the remote is reset").kind(),
+        io::ErrorKind::ConnectionReset
+    );
+    let fault = event(client_addr, "fault").await;
+    assert_eq!(fault["fault"], "up:reset@100");
+    assert_eq!(fault["direction"], "client_to_destination");
+    let summary = event(client_addr, "closed").await;
+    assert_eq!(summary["fault_reset"], true);
+    assert!(
+        summary["message"]
+            .as_str()
+            .expect("the summary's line")
+            .contains("(a fault reset it)"),
+        "{summary}"
+    );
+}
+
+/// `down:truncate@5` half-closes the answer after its first five bytes, while the
+/// other direction keeps relaying.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn truncate_half_closes_its_direction_early() {
+    install_capturing_logger();
+    let remote_addr = spawn_reply_server(b"hello world").await;
+    let proxy_addr = spawn_faulty_proxy(remote_addr, &["down:truncate@5"], 1).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client.write_all(b"ping").await.expect("client write");
+    let (answer, end) = timeout(IO_TIMEOUT, read_until_end(&mut client))
+        .await
+        .expect("the answer was never cut short");
+    end.expect("a clean end-of-stream");
+    assert_eq!(answer, b"hello");
+    // The upload still goes through: the reply server answers it, unseen.
+    client
+        .write_all(b"more")
+        .await
+        .expect("the client can still send");
+
+    let fault = event(client_addr, "fault").await;
+    assert_eq!(fault["fault"], "down:truncate@5");
+    assert_eq!(fault["direction"], "destination_to_client");
+    assert!(
+        fault["message"]
+            .as_str()
+            .expect("the fault's line")
+            .ends_with("Injected fault down:truncate@5: truncating the stream after 5 bytes"),
+        "{fault}"
+    );
+}
+
+/// `down:stall=500ms@4` relays the first four bytes of the echo at once and the
+/// rest half a second later.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn stall_holds_its_direction_back() {
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_faulty_proxy(echo_addr, &["down:stall=500ms@4"], 1).await;
+
+    let mut client = connect(proxy_addr).await;
+    let sent = Instant::now();
+    client.write_all(b"abcdefgh").await.expect("client write");
+    let mut head = [0u8; 4];
+    timeout(IO_TIMEOUT, client.read_exact(&mut head))
+        .await
+        .expect("the head never arrived")
+        .expect("client read");
+    let head_arrived = sent.elapsed();
+    let mut tail = [0u8; 4];
+    timeout(IO_TIMEOUT, client.read_exact(&mut tail))
+        .await
+        .expect("the tail never arrived")
+        .expect("client read");
+    let tail_arrived = sent.elapsed();
+
+    assert_eq!((&head, &tail), (b"abcd", b"efgh"));
+    assert!(
+        head_arrived < Duration::from_millis(300),
+        "the head is not stalled, took {head_arrived:?}"
+    );
+    assert!(
+        tail_arrived >= Duration::from_millis(500) && tail_arrived < Duration::from_millis(1000),
+        "the tail is stalled for 500ms, took {tail_arrived:?}"
+    );
+}
+
+/// With the same `--seed`, `up:flip` and `down:replace` tamper with the same bytes
+/// whether the request is sent in one write or many, and each change is logged
+/// with its offset and values.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn corruption_is_repeatable_with_a_seed() {
+    const LEN: usize = 4000;
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let request: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+
+    let mut echoes = Vec::new();
+    for writes in [1, 20] {
+        let proxy_addr =
+            spawn_faulty_proxy(echo_addr, &["up:flip=0.01", "down:replace=0.01"], 42).await;
+        let mut client = connect(proxy_addr).await;
+        let client_addr = client.local_addr().expect("client local_addr");
+        for part in request.chunks(LEN / writes) {
+            client.write_all(part).await.expect("client write");
+            sleep(Duration::from_millis(5)).await;
+        }
+        let mut echo = vec![0u8; LEN];
+        timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+            .await
+            .expect("the echo never completed")
+            .expect("client read");
+        echoes.push(echo);
+
+        let fault = event(client_addr, "fault").await;
+        let message = fault["message"].as_str().expect("the fault's line");
+        assert!(
+            message.contains(": changed ") && message.contains(" byte(s) at offsets "),
+            "{message}"
+        );
+    }
+
+    assert_eq!(echoes[0], echoes[1], "the same seed tampers the same way");
+    let changed = echoes[0]
+        .iter()
+        .zip(&request)
+        .filter(|(echoed, sent)| echoed != sent)
+        .count();
+    assert!(
+        (20..=200).contains(&changed),
+        "about 2% of the bytes should differ, {changed} did"
+    );
+}
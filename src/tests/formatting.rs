@@ -7,6 +7,13 @@
 //! a rendering change in `logged-stream`, which Dependabot bumps automatically —
 //! could ship with every check green while `--formatting octal` quietly printed
 //! decimal. These are pure unit tests: no sockets, no runtime, microseconds to run.
+//!
+//! The text modes (`text`, `utf8-lossy`) must keep one chunk on one line, so their
+//! escaping is pinned here too.
+//!
+//! `hexdump` renders a multi-line block rather than one separator-joined line; its
+//! rows are split into tagged console lines by the per-connection logger, which the
+//! black-box `scripts/integration_test.py` covers.
 
 use crate::args::PayloadFormattingKind;
 use crate::args::get_formatter_by_kind;
@@ -17,6 +24,9 @@ use logged_stream::BufferFormatter;
 /// `0x6F`/`0xFF` separate the bases from one another and pin hexadecimal letter case.
 const SAMPLE: &[u8] = &[0x00, 0x01, 0x6F, 0xFF];
 
+/// The `--hexdump-width` default, passed wherever a test does not exercise it.
+const HEXDUMP_WIDTH: usize = 16;
+
 /// Every `--formatting` value renders the payload in its own notation, with the
 /// padding and letter case the console output has always used.
 #[test]
@@ -30,10 +40,16 @@ fn each_formatting_kind_renders_its_own_notation() {
             PayloadFormattingKind::Binary,
             "00000000:00000001:01101111:11111111",
         ),
+        (
+            PayloadFormattingKind::Hexdump,
+            "00000000  00 01 6f ff                                       |..o.|",
+        ),
+        (PayloadFormattingKind::Text, "\\x00\\x01o\\xff"),
+        (PayloadFormattingKind::Utf8Lossy, "\\x00\\x01o\u{fffd}"),
     ];
 
     for (kind, expected) in cases {
-        let rendered = get_formatter_by_kind(kind, ":").format_buffer(SAMPLE);
+        let rendered = get_formatter_by_kind(kind, ":", HEXDUMP_WIDTH).format_buffer(SAMPLE);
         assert_eq!(
             rendered, expected,
             "`--formatting {kind}` rendered the payload as `{rendered}`, expected `{expected}`"
@@ -53,13 +69,16 @@ fn no_two_formatting_kinds_render_alike() {
         PayloadFormattingKind::UpperHex,
         PayloadFormattingKind::Octal,
         PayloadFormattingKind::Binary,
+        PayloadFormattingKind::Hexdump,
+        PayloadFormattingKind::Text,
+        PayloadFormattingKind::Utf8Lossy,
     ];
 
     for (index, kind) in kinds.iter().enumerate() {
         for other in &kinds[index + 1..] {
             assert_ne!(
-                get_formatter_by_kind(*kind, ":").format_buffer(SAMPLE),
-                get_formatter_by_kind(*other, ":").format_buffer(SAMPLE),
+                get_formatter_by_kind(*kind, ":", HEXDUMP_WIDTH).format_buffer(SAMPLE),
+                get_formatter_by_kind(*other, ":", HEXDUMP_WIDTH).format_buffer(SAMPLE),
                 "`--formatting {kind}` and `--formatting {other}` render identically"
             );
         }
@@ -79,7 +98,7 @@ fn the_separator_is_placed_between_bytes() {
         (", ", "de, ad, be"),
     ] {
         assert_eq!(
-            get_formatter_by_kind(PayloadFormattingKind::LowerHex, separator)
+            get_formatter_by_kind(PayloadFormattingKind::LowerHex, separator, HEXDUMP_WIDTH)
                 .format_buffer(payload),
             expected,
             "separator {separator:?} should yield `{expected}`"
@@ -88,7 +107,109 @@ fn the_separator_is_placed_between_bytes() {
 
     // A single byte has nothing to separate, so the separator never appears.
     assert_eq!(
-        get_formatter_by_kind(PayloadFormattingKind::LowerHex, "--").format_buffer(&[0xDE]),
+        get_formatter_by_kind(PayloadFormattingKind::LowerHex, "--", HEXDUMP_WIDTH)
+            .format_buffer(&[0xDE]),
         "de",
     );
 }
+
+/// `hexdump` matches `hexdump -C` byte for byte: an eight-digit offset, sixteen hex
+/// bytes split into two groups of eight, and the ASCII gutter, with one row per
+/// sixteen bytes. The short last row is padded so its gutter lines up.
+#[test]
+fn hexdump_renders_hexdump_c_rows() {
+    let payload = b"Hello, MODBUS!\r\n\x00\x7f through the proxy";
+
+    let rendered = get_formatter_by_kind(PayloadFormattingKind::Hexdump, ":", HEXDUMP_WIDTH)
+        .format_buffer(payload);
+
+    assert_eq!(
+        rendered,
+        "00000000  48 65 6c 6c 6f 2c 20 4d  4f 44 42 55 53 21 0d 0a  |Hello, MODBUS!..|\n\
+         00000010  00 7f 20 74 68 72 6f 75  67 68 20 74 68 65 20 70  |.. through the p|\n\
+         00000020  72 6f 78 79                                       |roxy|",
+    );
+}
+
+/// `--hexdump-width` sets the bytes per row; the offsets advance by the width and the
+/// extra group space still falls after every eighth byte. `--separator` does not
+/// apply: the layout fixes its own spacing.
+#[test]
+fn hexdump_honors_the_row_width_and_ignores_the_separator() {
+    let payload: Vec<u8> = (b'a'..=b'j').collect();
+
+    let rendered =
+        get_formatter_by_kind(PayloadFormattingKind::Hexdump, "--", 4).format_buffer(&payload);
+    assert_eq!(
+        rendered,
+        "00000000  61 62 63 64  |abcd|\n\
+         00000004  65 66 67 68  |efgh|\n\
+         00000008  69 6a        |ij|",
+    );
+
+    let rendered =
+        get_formatter_by_kind(PayloadFormattingKind::Hexdump, ":", 10).format_buffer(&payload);
+    assert_eq!(
+        rendered,
+        "00000000  61 62 63 64 65 66 67 68  69 6a  |abcdefghij|"
+    );
+}
+
+/// Exactly one full row renders as a single line: no trailing empty row.
+#[test]
+fn hexdump_of_exactly_one_row_is_a_single_line() {
+    let rendered = get_formatter_by_kind(PayloadFormattingKind::Hexdump, ":", 4)
+        .format_buffer(b"\x01\x02\x03\x04");
+
+    assert_eq!(rendered, "00000000  01 02 03 04  |....|");
+}
+
+/// `text` keeps a chunk on one line: line breaks and tabs are escaped by name, the
+/// backslash itself is escaped (so `\\x41` in the payload cannot pass for an escaped
+/// byte), and every other non-printable or non-ASCII byte becomes `\xNN`.
+#[test]
+fn text_escapes_non_printable_bytes_onto_one_line() {
+    let payload = b"GET / HTTP/1.1\r\nHost: a\tb\r\n\r\n\\x41\x00\x1b[0m\x7f\xc3\xa9";
+
+    let rendered = get_formatter_by_kind(PayloadFormattingKind::Text, ":", HEXDUMP_WIDTH)
+        .format_buffer(payload);
+
+    assert_eq!(
+        rendered,
+        "GET / HTTP/1.1\\r\\nHost: a\\tb\\r\\n\\r\\n\\\\x41\\x00\\x1b[0m\\x7f\\xc3\\xa9",
+    );
+    assert!(
+        !rendered.contains('\n'),
+        "a text chunk must stay on one line"
+    );
+}
+
+/// `utf8-lossy` shows valid UTF-8 as characters, replaces an invalid byte with
+/// U+FFFD, and escapes control characters — ASCII ones as `\xNN`, others as `\u{..}`.
+#[test]
+fn utf8_lossy_decodes_text_and_replaces_invalid_sequences() {
+    let payload = "caf\u{e9} \u{2713}\r\n\u{85}".as_bytes();
+    let mut with_invalid = payload.to_vec();
+    with_invalid.extend_from_slice(&[0xFF, b'!', 0x00]);
+
+    let rendered = get_formatter_by_kind(PayloadFormattingKind::Utf8Lossy, ":", HEXDUMP_WIDTH)
+        .format_buffer(&with_invalid);
+
+    assert_eq!(rendered, "caf\u{e9} \u{2713}\\r\\n\\u{85}\u{fffd}!\\x00");
+}
+
+/// `utf8-lossy` keeps a chunk on one line for viewers that break lines at Unicode
+/// line endings too: NEL and the line and paragraph separators are escaped.
+#[test]
+fn utf8_lossy_escapes_unicode_line_breaks() {
+    let payload = "a\u{85}b\u{2028}c\u{2029}d".as_bytes();
+
+    let rendered = get_formatter_by_kind(PayloadFormattingKind::Utf8Lossy, ":", HEXDUMP_WIDTH)
+        .format_buffer(payload);
+
+    assert_eq!(rendered, "a\\u{85}b\\u{2028}c\\u{2029}d");
+    assert!(
+        !rendered.contains(['\u{85}', '\u{2028}', '\u{2029}']),
+        "a utf8-lossy chunk must stay on one line"
+    );
+}
@@ -2,18 +2,31 @@
 //! the echo-server and proxy spawners, and the client-side round-trip helpers used
 //! across the test submodules.
 
+use crate::admin::Registry;
 use crate::args::Arguments;
 use crate::args::LoggingLevel;
+use crate::args::OutputFormat;
 use crate::args::PayloadFormattingKind;
+use crate::args::Route;
 use crate::args::TargetAddr;
 use crate::args::TimestampPrecision;
-use crate::conn::run_accept_loop;
+use crate::conn::ConnIds;
+use crate::conn::RouteConfig;
+use crate::conn::Shutdown;
+use crate::conn::Sinks;
+use crate::conn::serve_route;
+use crate::metrics::RouteMetrics;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::sync::watch;
 use tokio::time::timeout;
 
 /// Upper bound for any single network operation in the tests. Generous enough to
@@ -38,22 +51,79 @@ pub(super) fn test_arguments(
 ) -> Arguments {
     Arguments {
         level: LoggingLevel::Off,
-        bind_listener_addr,
-        remote_addr: remote_addr.into(),
+        bind_listener_addr: Some(bind_listener_addr),
+        remote_addr: Some(remote_addr.into()),
+        routes: Vec::new(),
+        config: None,
         timeout,
+        // Only read when `initialize_tcp_listener` drains; the tests call `drain`
+        // with a grace of their own.
+        shutdown_grace: 0,
         max_connections,
         // Irrelevant to the relay path under test: the worker-thread count only
         // shapes the runtime built in `main`, which these tests do not exercise.
         threads: 4,
         formatting: PayloadFormattingKind::LowerHex,
         separator: ":".to_string(),
+        hexdump_width: 16,
         precision: TimestampPrecision::Seconds,
         // The default: console lines are tagged with per-connection `[#N]` ids.
         // The `conn_ids` submodule flips this locally to cover the opt-out.
         connection_ids: true,
+        latency_to_destination: 0,
+        jitter_to_destination: 0,
+        latency_to_client: 0,
+        jitter_to_client: 0,
+        rate_limit_up: None,
+        rate_limit_down: None,
+        rate_limit_global: None,
+        fragment: None,
+        fragment_delay: 0,
+        coalesce: None,
+        faults: Vec::new(),
+        seed: None,
+        tls_cert: None,
+        tls_key: None,
+        tls_mitm_ca: None,
+        tls_mitm_key: None,
+        remote_tls: false,
+        remote_ca: None,
+        remote_sni: None,
+        remote_client_cert: None,
+        remote_client_key: None,
+        remote_insecure: false,
+        inspect_tls: false,
+        sni_routes: Vec::new(),
+        decode: None,
+        decode_body: false,
+        pcap: None,
+        capture_dir: None,
+        metrics_addr: None,
+        admin_addr: None,
+        tui: false,
+        output_format: OutputFormat::Text,
+        log_file: None,
+        log_rotate_size: None,
+        log_rotate_interval: None,
+        log_keep: 5,
+        log_compress: false,
     }
 }
 
+/// A fresh path under the system temp directory for a file a test makes the proxy
+/// write (e.g. `--pcap`). The process id and a counter keep it unique across
+/// parallel tests and concurrent runs; the `label` names the test in the file name.
+/// The file is not created — and, like the ports, not cleaned up beyond what the
+/// test itself removes.
+pub(super) fn temp_path(label: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir().join(format!(
+        "logged_tcp_proxy-{label}-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
 /// Spawn a minimal echo server on an ephemeral loopback port. Returns the bound
 /// address; the server runs until the test's runtime is dropped.
 pub(super) async fn spawn_echo_server() -> SocketAddr {
@@ -119,6 +189,28 @@ pub(super) async fn spawn_localhost_echo_server() -> u16 {
     port
 }
 
+/// A server that answers whatever it is sent with `reply`, so a test can tell
+/// which remote a route reached.
+pub(super) async fn spawn_reply_server(reply: &'static [u8]) -> SocketAddr {
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind the reply server");
+    let addr = listener.local_addr().expect("reply server local_addr");
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buffer = [0; 1024];
+                while let Ok(read_length) = stream.read(&mut buffer).await {
+                    if read_length == 0 || stream.write_all(reply).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
 /// Bind a proxy listener on an ephemeral loopback port and start its accept loop
 /// in the background. Returns the proxy's bound address. Uses a bounded idle
 /// timeout so a stuck test fails fast rather than hanging.
@@ -177,10 +269,49 @@ pub(super) async fn spawn_proxy_configured(
     let addr = listener.local_addr().expect("proxy local_addr");
     let mut arguments = test_arguments(addr, remote_addr, timeout, max_connections);
     edit(&mut arguments);
-    tokio::spawn(run_accept_loop(listener, arguments));
+    let sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    let route = arguments.routes().expect("valid routes").remove(0);
+    spawn_route(listener, arguments, route, sinks, Shared::default());
     addr
 }
 
+/// What a route started by [`spawn_route`] shares with the rest of a proxy, as
+/// `initialize_tcp_listener` hands it to each of its routes. The defaults are
+/// fresh ones that nothing else sees.
+#[derive(Clone, Default)]
+pub(super) struct Shared {
+    pub(super) conn_ids: ConnIds,
+    pub(super) shutdown: Shutdown,
+    pub(super) metrics: Arc<RouteMetrics>,
+    pub(super) registry: Registry,
+}
+
+/// Start [`serve_route`] in the background on an already-bound `listener`, for a
+/// route that is never reloaded.
+pub(super) fn spawn_route(
+    listener: TcpListener,
+    arguments: Arguments,
+    route: Route,
+    sinks: Sinks,
+    shared: Shared,
+) {
+    let (reloads, config) = watch::channel(RouteConfig::new(arguments, route));
+    tokio::spawn(async move {
+        // Nothing reloads this route: the sender only has to outlive the loop.
+        let _reloads = reloads;
+        serve_route(
+            listener,
+            config,
+            sinks,
+            shared.conn_ids,
+            shared.shutdown,
+            shared.metrics,
+            shared.registry,
+        )
+        .await;
+    });
+}
+
 /// Like [`spawn_proxy`] but with an explicit remote target, so a test can point
 /// the proxy at a `hostname:port` (resolved at connect time) instead of a literal
 /// address. Reuses the same ephemeral-port + auto-cleanup setup as the others.
@@ -190,7 +321,7 @@ pub(super) async fn spawn_proxy_with_target(remote: TargetAddr) -> SocketAddr {
         placeholder,
         Some(IO_TIMEOUT.as_secs()),
         TEST_MAX_CONNECTIONS,
-        |arguments| arguments.remote_addr = remote,
+        |arguments| arguments.remote_addr = Some(remote),
     )
     .await
 }
@@ -222,3 +353,39 @@ pub(super) async fn assert_round_trip(client: &mut TcpStream, payload: &[u8]) {
         "payload must round-trip through the proxy"
     );
 }
+
+/// Send an HTTP/1.1 `method` request for `path` to one of the proxy's HTTP
+/// endpoints and return the status line and the body, read until the endpoint
+/// closes the connection.
+pub(super) async fn http_request(addr: SocketAddr, method: &str, path: &str) -> (String, String) {
+    let mut stream = connect(addr).await;
+    let request = format!("{method} {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    timeout(IO_TIMEOUT, stream.write_all(request.as_bytes()))
+        .await
+        .expect("write timed out")
+        .expect("failed to send the request");
+    let mut response = String::new();
+    timeout(IO_TIMEOUT, stream.read_to_string(&mut response))
+        .await
+        .expect("the endpoint should answer and close")
+        .expect("failed to read the response");
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .expect("a response head and body");
+    let status = head.lines().next().unwrap_or_default().to_string();
+    (status, body.to_string())
+}
+
+/// Send a request through `client` and read back `expected.len()` bytes.
+pub(super) async fn exchange(client: &mut TcpStream, expected: &[u8]) -> Vec<u8> {
+    timeout(IO_TIMEOUT, client.write_all(b"which remote?"))
+        .await
+        .expect("write timed out")
+        .expect("write failed");
+    let mut reply = vec![0; expected.len()];
+    timeout(IO_TIMEOUT, client.read_exact(&mut reply))
+        .await
+        .expect("read timed out")
+        .expect("read failed");
+    reply
+}
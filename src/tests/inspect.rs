@@ -0,0 +1,203 @@
+//! `--inspect-tls`: a TLS handshake relayed without being terminated is parsed on
+//! the way, and the hellos and alerts it shows in the clear are logged on the
+//! connection, while the bytes still reach the other side untouched.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::log_capture::captured_events;
+use super::log_capture::event;
+use super::log_capture::install_capturing_logger;
+use super::tls::TestCert;
+use super::tls::spawn_tls_echo_server;
+use super::tls::tls_connect_to;
+use crate::inspect::Inspector;
+use crate::inspect::Message;
+use rustls::ClientConfig;
+use rustls::ClientConnection;
+use rustls::RootCertStore;
+use rustls::SupportedProtocolVersion;
+use rustls::pki_types::ServerName;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+
+/// Spawn a proxy to `remote_addr` with `--inspect-tls`.
+async fn spawn_inspecting_proxy(remote_addr: SocketAddr) -> SocketAddr {
+    spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| arguments.inspect_tls = true,
+    )
+    .await
+}
+
+/// A client configuration trusting `root` alone and offering `versions` and
+/// `alpn`.
+fn client_config(
+    root: &TestCert,
+    versions: &[&'static SupportedProtocolVersion],
+    alpn: &[&str],
+) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(root.der.clone()).expect("a valid root");
+    let mut config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(versions)
+            .expect("supported versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    config.alpn_protocols = alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    Arc::new(config)
+}
+
+/// Both hellos of a TLS session relayed end to end are logged: what the client
+/// offered and what the server picked.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn hellos_of_a_relayed_handshake_are_logged() {
+    install_capturing_logger();
+    let cert = TestCert::new("api.lab");
+    let (remote_addr, _names) = spawn_tls_echo_server(&cert, None, &["http/1.1"]).await;
+    let proxy_addr = spawn_inspecting_proxy(remote_addr).await;
+
+    let (client_addr, mut client) =
+        tls_connect_to(proxy_addr, &cert, "api.lab", &["h2", "http/1.1"]).await;
+    client.write_all(b"opaque").await.expect("client write");
+    let mut echo = [0u8; 6];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(&echo, b"opaque");
+    drop(client);
+
+    let client_hello = event(client_addr, "tls_client_hello").await;
+    assert_eq!(client_hello["direction"], "client_to_destination");
+    assert_eq!(client_hello["server_name"], "api.lab");
+    assert_eq!(client_hello["alpn"], "h2, http/1.1");
+    assert_eq!(client_hello["tls_versions"], "TLS 1.3, TLS 1.2");
+    let cipher_suites = client_hello["cipher_suites"]
+        .as_str()
+        .expect("the offered cipher suites");
+    assert!(
+        cipher_suites.starts_with("TLS13_AES_256_GCM_SHA384, "),
+        "{client_hello}"
+    );
+    assert!(
+        client_hello["message"]
+            .as_str()
+            .expect("the ClientHello line")
+            .contains("] < TLS ClientHello: SNI api.lab, ALPN h2, http/1.1, versions TLS 1.3, TLS 1.2, cipher suites TLS13_"),
+        "{client_hello}"
+    );
+
+    let server_hello = event(client_addr, "tls_server_hello").await;
+    assert_eq!(server_hello["direction"], "destination_to_client");
+    assert_eq!(server_hello["tls_version"], "TLS 1.3");
+    assert_eq!(server_hello["hello_retry"], false);
+    // TLS 1.3 sends the ALPN pick encrypted, after the ServerHello.
+    assert_eq!(server_hello["alpn"], "");
+}
+
+/// A TLS 1.2 client that does not trust the server's certificate, which it gets
+/// in the clear, says so in a plaintext alert, which is logged.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn alert_rejecting_the_certificate_is_logged() {
+    install_capturing_logger();
+    let cert = TestCert::new("api.lab");
+    let (remote_addr, _names) = spawn_tls_echo_server(&cert, None, &[]).await;
+    let proxy_addr = spawn_inspecting_proxy(remote_addr).await;
+
+    let stream = connect(proxy_addr).await;
+    let client_addr = stream.local_addr().expect("client local_addr");
+    let untrusted = TestCert::ca("Other CA");
+    let config = client_config(&untrusted, &[&rustls::version::TLS12], &[]);
+    let handshake = TlsConnector::from(config).connect(
+        ServerName::try_from("api.lab").expect("a valid name"),
+        stream,
+    );
+    let result = timeout(IO_TIMEOUT, handshake)
+        .await
+        .expect("the handshake timed out");
+    assert!(result.is_err(), "the certificate is not trusted");
+
+    let server_hello = event(client_addr, "tls_server_hello").await;
+    assert_eq!(server_hello["tls_version"], "TLS 1.2");
+    let alert = event(client_addr, "tls_alert").await;
+    assert_eq!(alert["direction"], "client_to_destination");
+    assert_eq!(alert["alert"], "fatal UnknownCA");
+    assert!(
+        alert["message"]
+            .as_str()
+            .expect("the alert line")
+            .ends_with("] < TLS alert: fatal UnknownCA"),
+        "{alert}"
+    );
+}
+
+/// Traffic that is not TLS is relayed without a word from the inspection.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn plaintext_is_not_taken_for_tls() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_inspecting_proxy(echo_addr).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    // Starts like a handshake record, but is not one.
+    let request = b"\x16\x01GET / HTTP/1.0\r\n\r\n";
+    client.write_all(request).await.expect("client write");
+    let mut echo = vec![0u8; request.len()];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(&echo, request);
+    drop(client);
+
+    event(client_addr, "closed").await;
+    let inspected = captured_events().into_iter().any(|event| {
+        event["client"] == client_addr.to_string()
+            && event["event"]
+                .as_str()
+                .is_some_and(|name| name.starts_with("tls_"))
+    });
+    assert!(!inspected, "plaintext was inspected as TLS");
+}
+
+/// A hello split across reads, down to single bytes, is parsed all the same.
+#[test]
+fn hello_split_into_single_bytes_is_parsed() {
+    let cert = TestCert::new("api.lab");
+    let mut connection = ClientConnection::new(
+        client_config(&cert, rustls::DEFAULT_VERSIONS, &["h2"]),
+        ServerName::try_from("api.lab").expect("a valid name"),
+    )
+    .expect("a client connection");
+    let mut hello = Vec::new();
+    connection.write_tls(&mut hello).expect("the ClientHello");
+
+    let mut inspector = Inspector::new();
+    let mut messages = Vec::new();
+    for byte in &hello {
+        messages.extend(inspector.feed(std::slice::from_ref(byte)));
+    }
+    let [Message::ClientHello(parsed)] = messages.as_slice() else {
+        panic!("one ClientHello expected: {messages:?}");
+    };
+    assert_eq!(parsed.server_name.as_deref(), Some("api.lab"));
+    assert_eq!(parsed.alpn, ["h2"]);
+    assert_eq!(parsed.versions, [0x0304, 0x0303]);
+    // Once the records are encrypted, nothing is looked at any more.
+    assert!(inspector.feed(b"\x17\x03\x03\x00\x01?").is_empty());
+    assert!(inspector.feed(&hello).is_empty());
+}
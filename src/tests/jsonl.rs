@@ -0,0 +1,118 @@
+//! `--output-format jsonl`: the rendering of one record as a JSON object, and the
+//! connection fields every per-connection event carries.
+//!
+//! As with the text lines, the payload events log at `debug` and so are left to
+//! the black-box `scripts/integration_test.py`; these tests cover the rendering
+//! itself and the `info` lifecycle events.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::log_capture::captured_events;
+use super::log_capture::install_capturing_logger;
+use crate::args::OutputFormat;
+use crate::jsonl::render;
+use serde_json::Value;
+use serde_json::json;
+
+/// Render a record carrying `key_values` and `message` at `info`, and parse it back.
+fn render_parsed(key_values: &[(&str, log::kv::Value<'_>)], message: &str) -> (String, Value) {
+    let line = render(
+        &log::Record::builder()
+            .level(log::Level::Info)
+            .key_values(&key_values)
+            .args(format_args!("{message}"))
+            .build(),
+        "2024-01-02T03:04:05Z",
+        42,
+    );
+    let parsed = serde_json::from_str(&line).expect("a rendered line is valid JSON");
+    (line, parsed)
+}
+
+/// The fixed fields come first, the key-values follow in the order they were
+/// attached, and the message is last.
+#[test]
+fn fields_keep_their_order() {
+    let (line, _) = render_parsed(
+        &[("event", "accepted".into()), ("conn_id", 7u64.into())],
+        "Incoming connection",
+    );
+    assert_eq!(
+        line,
+        r#"{"timestamp":"2024-01-02T03:04:05Z","monotonic_us":42,"level":"INFO","event":"accepted","conn_id":7,"message":"Incoming connection"}"#
+    );
+}
+
+/// Counts and flags stay JSON numbers and booleans; anything else is a string.
+#[test]
+fn numbers_and_flags_stay_typed() {
+    let (_, parsed) = render_parsed(
+        &[
+            ("bytes", 1500u64.into()),
+            ("forced", true.into()),
+            ("client", log::kv::Value::from_display(&"127.0.0.1:1")),
+        ],
+        "",
+    );
+    assert_eq!(parsed["bytes"], json!(1500));
+    assert_eq!(parsed["forced"], json!(true));
+    assert_eq!(parsed["client"], json!("127.0.0.1:1"));
+}
+
+/// Quotes, backslashes and control characters in a value or the message are
+/// escaped, so every record stays one valid line.
+#[test]
+fn strings_are_escaped() {
+    let awkward = "a \"quoted\" \\ line\nwith\ta tab and \u{1}";
+    let (line, parsed) = render_parsed(&[("payload", awkward.into())], awkward);
+    assert!(!line.contains('\n'), "line break leaked into: {line}");
+    assert_eq!(parsed["payload"], json!(awkward));
+    assert_eq!(parsed["message"], json!(awkward));
+}
+
+/// A connection's events carry its id, the client address, the configured
+/// destination and, once connected, the destination address actually reached.
+/// The text tag is left out of the message: the id is a field of its own.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn connection_events_carry_the_connection_fields() {
+    install_capturing_logger();
+
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_proxy_configured(
+        echo_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| arguments.output_format = OutputFormat::Jsonl,
+    )
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"structured events").await;
+    let client_addr = client.local_addr().expect("client local_addr").to_string();
+
+    let events: Vec<Value> = captured_events()
+        .into_iter()
+        .filter(|event| event["client"] == json!(client_addr))
+        .collect();
+    let accepted = events
+        .iter()
+        .find(|event| event["event"] == json!("accepted"))
+        .expect("no accepted event");
+    assert_eq!(accepted["conn_id"], json!(1));
+    assert_eq!(accepted["destination"], json!(echo_addr.to_string()));
+    assert_eq!(
+        accepted["message"],
+        json!(format!("Incoming connection from {client_addr}"))
+    );
+
+    let connected = events
+        .iter()
+        .find(|event| event["event"] == json!("connected"))
+        .expect("no connected event for a literal destination");
+    assert_eq!(connected["conn_id"], json!(1));
+    assert_eq!(connected["destination_addr"], json!(echo_addr.to_string()));
+}
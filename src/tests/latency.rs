@@ -0,0 +1,229 @@
+//! `--latency-to-*` and `--jitter-to-*`: each direction is delayed by its own
+//! setting, the jitter never reorders the stream, a half-close follows the delayed
+//! data, and the idle timeout counts a chunk as activity when it is read.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// A destination that reads one chunk, reports when it arrived, answers `pong` at
+/// once and then holds the connection open until the proxy closes it.
+async fn spawn_timed_server() -> (SocketAddr, oneshot::Receiver<Instant>) {
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let addr = listener.local_addr().expect("remote local_addr");
+    let (arrived, arrival) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("remote accept");
+        let mut buffer = [0u8; 64];
+        let n = stream.read(&mut buffer).await.expect("remote read");
+        assert!(n > 0, "the remote expected a request");
+        let _ = arrived.send(Instant::now());
+        let _ = stream.write_all(b"pong").await;
+        while let Ok(n) = stream.read(&mut buffer).await {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+    (addr, arrival)
+}
+
+/// The latency to the destination delays the request and the latency to the client
+/// delays the reply, each by its own amount.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn latency_delays_each_direction_separately() {
+    let (remote_addr, arrival) = spawn_timed_server().await;
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.latency_to_destination = 400;
+            arguments.latency_to_client = 150;
+        },
+    )
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    let sent = Instant::now();
+    client.write_all(b"ping").await.expect("client write");
+    let mut reply = [0u8; 4];
+    timeout(IO_TIMEOUT, client.read_exact(&mut reply))
+        .await
+        .expect("the reply never arrived")
+        .expect("client read");
+    let replied = Instant::now();
+    let arrived = arrival.await.expect("the remote saw the request");
+    assert_eq!(&reply, b"pong");
+
+    let to_destination = arrived - sent;
+    let to_client = replied - arrived;
+    assert!(
+        to_destination >= Duration::from_millis(400) && to_destination < Duration::from_millis(700),
+        "the request should take about 400ms, took {to_destination:?}"
+    );
+    assert!(
+        to_client >= Duration::from_millis(150) && to_client < Duration::from_millis(400),
+        "the reply should take about 150ms, took {to_client:?}"
+    );
+}
+
+/// Jitter picks a different delay for each chunk, but a chunk is never written
+/// before the one read ahead of it: many small writes come back intact and in
+/// order, and their delays overlap rather than add up.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn jitter_keeps_the_stream_in_order() {
+    const CHUNKS: u8 = 40;
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_proxy_configured(
+        echo_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.jitter_to_destination = 100;
+            arguments.jitter_to_client = 100;
+        },
+    )
+    .await;
+
+    let client = connect(proxy_addr).await;
+    let (mut read_half, mut write_half) = client.into_split();
+    let started = Instant::now();
+    let writer = tokio::spawn(async move {
+        for i in 0..CHUNKS {
+            write_half.write_all(&[i; 8]).await.expect("client write");
+            sleep(Duration::from_millis(5)).await;
+        }
+        write_half
+    });
+    let mut received = vec![0u8; CHUNKS as usize * 8];
+    timeout(IO_TIMEOUT, read_half.read_exact(&mut received))
+        .await
+        .expect("the echo never completed")
+        .expect("client read");
+    let _write_half = writer.await.expect("the writer panicked");
+
+    let expected: Vec<u8> = (0..CHUNKS).flat_map(|i| [i; 8]).collect();
+    assert_eq!(received, expected, "the jitter must not reorder the bytes");
+    assert!(
+        started.elapsed() < Duration::from_secs(3),
+        "the delays should overlap, took {:?}",
+        started.elapsed()
+    );
+}
+
+/// A client that half-closes right after sending has its close forwarded only
+/// after the delayed data: the destination reads every byte before end-of-stream,
+/// and its answer, sent after that, still reaches the client.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn half_close_follows_the_delayed_data() {
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let remote_addr = listener.local_addr().expect("remote local_addr");
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("remote accept");
+        let mut request = Vec::new();
+        stream
+            .read_to_end(&mut request)
+            .await
+            .expect("remote read to end");
+        let answer = format!(
+            "got {} bytes: {}",
+            request.len(),
+            String::from_utf8_lossy(&request)
+        );
+        let _ = stream.write_all(answer.as_bytes()).await;
+    });
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.latency_to_destination = 200;
+            arguments.jitter_to_destination = 50;
+            arguments.latency_to_client = 100;
+        },
+    )
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    for part in [&b"first "[..], b"second ", b"third"] {
+        client.write_all(part).await.expect("client write");
+        sleep(Duration::from_millis(10)).await;
+    }
+    client.shutdown().await.expect("client half-close");
+    let mut answer = String::new();
+    timeout(IO_TIMEOUT, client.read_to_string(&mut answer))
+        .await
+        .expect("the answer never arrived")
+        .expect("client read");
+    assert_eq!(answer, "got 18 bytes: first second third");
+}
+
+/// The idle timeout counts a chunk as activity when the proxy reads it, not when
+/// its delayed write happens: with a 1s timeout and 600ms of latency, a request
+/// sent 300ms in closes the connection about 1s after it was sent (not 1.6s), and
+/// the destination still receives it in between.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn idle_timeout_counts_activity_when_read() {
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let remote_addr = listener.local_addr().expect("remote local_addr");
+    let (arrived, arrival) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("remote accept");
+        let mut buffer = [0u8; 64];
+        let n = stream.read(&mut buffer).await.expect("remote read");
+        let _ = arrived.send((n, Instant::now()));
+        // Never answer: the connection stays idle until the timeout closes it.
+        let _ = stream.read(&mut buffer).await;
+    });
+    let proxy_addr =
+        spawn_proxy_configured(remote_addr, Some(1), TEST_MAX_CONNECTIONS, |arguments| {
+            arguments.latency_to_destination = 600;
+        })
+        .await;
+
+    let mut client = connect(proxy_addr).await;
+    sleep(Duration::from_millis(300)).await;
+    let sent = Instant::now();
+    client.write_all(b"ping").await.expect("client write");
+    let mut buffer = [0u8; 16];
+    let result = timeout(IO_TIMEOUT, client.read(&mut buffer))
+        .await
+        .expect("the idle timeout did not fire");
+    let closed = Instant::now();
+    match result {
+        Ok(0) | Err(_) => {}
+        Ok(n) => panic!("expected the idle connection to close, but read {n} bytes"),
+    }
+    let (n, arrived) = arrival.await.expect("the remote saw the request");
+
+    assert_eq!(n, 4, "the delayed request is delivered before the timeout");
+    assert!(
+        arrived - sent >= Duration::from_millis(600),
+        "the request should be delayed by 600ms, took {:?}",
+        arrived - sent
+    );
+    assert!(
+        closed - sent >= Duration::from_millis(900) && closed - sent < Duration::from_millis(1400),
+        "the timeout should count from the read, about 1s after the send, but closed after {:?}",
+        closed - sent
+    );
+}
@@ -1,8 +1,14 @@
 //! Capture of the proxy's own `log` output, so a test can assert on which
 //! lifecycle lines were — and were not — emitted.
 
+use super::helpers::IO_TIMEOUT;
+use serde_json::Value;
+use std::net::SocketAddr;
 use std::sync::Mutex;
 use std::sync::Once;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::time::sleep;
 
 /// Every message the proxy logs, captured so a test can assert on what was — and
 /// was not — logged. Tests share one process and run in parallel, so assertions
@@ -10,6 +16,10 @@ use std::sync::Once;
 /// length of this buffer as a whole.
 static CAPTURED_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
+/// The same records rendered as `--output-format jsonl` lines (with a fixed
+/// timestamp), so a test can assert on their structured fields.
+static CAPTURED_EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
 struct CapturingLogger;
 
 impl log::Log for CapturingLogger {
@@ -22,6 +32,10 @@ impl log::Log for CapturingLogger {
             .lock()
             .expect("captured logs mutex poisoned")
             .push(record.args().to_string());
+        CAPTURED_EVENTS
+            .lock()
+            .expect("captured events mutex poisoned")
+            .push(crate::jsonl::render(record, "-", 0));
     }
 
     fn flush(&self) {}
@@ -79,3 +93,51 @@ pub(super) fn logged_destinations() -> Vec<String> {
         .map(str::to_string)
         .collect()
 }
+
+/// Every captured record as its parsed `--output-format jsonl` object. Assertions
+/// on it must, again, key off the test's own unique ephemeral addresses.
+pub(super) fn captured_events() -> Vec<Value> {
+    CAPTURED_EVENTS
+        .lock()
+        .expect("captured events mutex poisoned")
+        .iter()
+        .map(|line| serde_json::from_str(line).expect("a rendered line is valid JSON"))
+        .collect()
+}
+
+/// Wait for the connection from `client_addr` to log an event named `name`, and
+/// return it.
+pub(super) async fn event(client_addr: SocketAddr, name: &str) -> Value {
+    let deadline = Instant::now() + IO_TIMEOUT;
+    loop {
+        let event = captured_events()
+            .into_iter()
+            .find(|event| event["event"] == name && event["client"] == client_addr.to_string());
+        if let Some(event) = event {
+            return event;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "no {name} event for {client_addr}; captured: {:?}",
+            captured_lines()
+        );
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// Wait for the connection from `client_addr` to log `count` events named `name`,
+/// and return them in order.
+pub(super) async fn events(client_addr: SocketAddr, name: &str, count: usize) -> Vec<Value> {
+    let deadline = Instant::now() + IO_TIMEOUT;
+    loop {
+        let events: Vec<Value> = captured_events()
+            .into_iter()
+            .filter(|event| event["event"] == name && event["client"] == client_addr.to_string())
+            .collect();
+        if events.len() >= count || Instant::now() >= deadline {
+            assert_eq!(events.len(), count, "{name} events of {client_addr}");
+            return events;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+}
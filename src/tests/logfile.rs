@@ -0,0 +1,210 @@
+//! The `--log-file` sink: size- and age-based rotation that never splits a record,
+//! `--log-keep` pruning, gzip compression of rotated segments, and numbering that
+//! resumes across restarts. These drive [`LogFile`] directly with whole records, as
+//! `env_logger` does (one `write` per rendered record).
+
+use super::helpers::temp_path;
+use crate::logfile::LogFile;
+use crate::logfile::Rotation;
+use crate::logfile::segments;
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A fresh directory holding nothing but the test's log file, whose path is
+/// returned. Segments are found by scanning the file's directory, so each test
+/// gets a directory of its own.
+fn log_path(label: &str) -> PathBuf {
+    let directory = temp_path(label);
+    fs::create_dir_all(&directory).expect("failed to create the test directory");
+    directory.join("proxy.log")
+}
+
+/// Remove the test's directory once its assertions have passed.
+fn remove_log_directory(path: &Path) {
+    if let Some(directory) = path.parent() {
+        let _ = fs::remove_dir_all(directory);
+    }
+}
+
+fn rotation(size: Option<u64>, keep: usize, compress: bool) -> Rotation {
+    Rotation {
+        size,
+        interval: None,
+        keep,
+        compress,
+    }
+}
+
+/// The `index`-th test record: one 20-byte line.
+fn record(index: usize) -> String {
+    format!("record number {index:05}\n")
+}
+
+/// The content of a segment, decompressed if it is a `.gz`.
+fn read_segment(path: &Path) -> String {
+    let bytes = fs::read(path).expect("failed to read a segment");
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        let mut content = String::new();
+        GzDecoder::new(&bytes[..])
+            .read_to_string(&mut content)
+            .expect("a compressed segment is valid gzip");
+        content
+    } else {
+        String::from_utf8(bytes).expect("a segment is UTF-8")
+    }
+}
+
+/// Every record written, in order: the kept segments oldest first, then the
+/// active file.
+fn read_all(path: &Path) -> (Vec<String>, String) {
+    let segments = segments(path)
+        .expect("failed to list the segments")
+        .iter()
+        .map(|segment| read_segment(&segment.path))
+        .collect();
+    let active = fs::read_to_string(path).expect("failed to read the active file");
+    (segments, active)
+}
+
+/// With a size limit, the file is rotated *before* a record would grow it past
+/// the limit, so every segment holds whole records and stays within the limit.
+#[test]
+fn size_rotation_never_splits_a_record() {
+    let path = log_path("log-size");
+    let mut file = LogFile::open(&path, rotation(Some(50), 100, false)).expect("open");
+    for index in 0..10 {
+        file.write_all(record(index).as_bytes()).expect("write");
+    }
+    drop(file);
+
+    let (segments, active) = read_all(&path);
+    // 20-byte records under a 50-byte limit: two per file.
+    assert_eq!(segments.len(), 4);
+    for segment in &segments {
+        assert_eq!(
+            segment.len(),
+            40,
+            "segment is not two whole records: {segment:?}"
+        );
+    }
+    let all: String = segments.concat() + &active;
+    let expected: String = (0..10).map(record).collect();
+    assert_eq!(all, expected, "records were lost, reordered or split");
+    remove_log_directory(&path);
+}
+
+/// A record larger than the limit is written whole, into a file of its own.
+#[test]
+fn an_oversized_record_gets_a_file_of_its_own() {
+    let path = log_path("log-oversized");
+    let big = format!("{}\n", "x".repeat(99));
+    let mut file = LogFile::open(&path, rotation(Some(30), 100, false)).expect("open");
+    file.write_all(record(0).as_bytes()).expect("write");
+    file.write_all(big.as_bytes()).expect("write");
+    file.write_all(record(1).as_bytes()).expect("write");
+    drop(file);
+
+    let (segments, active) = read_all(&path);
+    assert_eq!(segments, vec![record(0), big]);
+    assert_eq!(active, record(1));
+    remove_log_directory(&path);
+}
+
+/// Only the newest `--log-keep` segments survive; the oldest are deleted.
+#[test]
+fn keep_prunes_the_oldest_segments() {
+    let path = log_path("log-keep");
+    let mut file = LogFile::open(&path, rotation(Some(20), 2, false)).expect("open");
+    for index in 0..6 {
+        file.write_all(record(index).as_bytes()).expect("write");
+    }
+    drop(file);
+
+    let numbers: Vec<u64> = segments(&path)
+        .expect("segments")
+        .iter()
+        .map(|segment| segment.number)
+        .collect();
+    assert_eq!(numbers, vec![4, 5], "expected only the two newest segments");
+    let (segments, active) = read_all(&path);
+    assert_eq!(segments, vec![record(3), record(4)]);
+    assert_eq!(active, record(5));
+    remove_log_directory(&path);
+}
+
+/// With `--log-compress`, rotated segments are replaced by a `.gz` holding exactly
+/// their content; the active file stays plain text.
+#[test]
+fn rotated_segments_are_gzip_compressed() {
+    let path = log_path("log-compress");
+    let mut file = LogFile::open(&path, rotation(Some(20), 100, true)).expect("open");
+    for index in 0..3 {
+        file.write_all(record(index).as_bytes()).expect("write");
+    }
+    drop(file); // waits for the background compression
+
+    let segments = segments(&path).expect("segments");
+    assert_eq!(segments.len(), 2);
+    for (index, segment) in segments.iter().enumerate() {
+        assert!(
+            segment.path.to_string_lossy().ends_with(".gz"),
+            "segment {} was not compressed",
+            segment.path.display()
+        );
+        assert_eq!(read_segment(&segment.path), record(index));
+    }
+    assert_eq!(fs::read_to_string(&path).expect("active"), record(2));
+    remove_log_directory(&path);
+}
+
+/// With an interval, a record written once the file is that old starts a new one.
+#[test]
+fn interval_rotation_starts_a_new_file() {
+    let path = log_path("log-interval");
+    let age_limit = Rotation {
+        interval: Some(Duration::from_millis(100)),
+        ..rotation(None, 100, false)
+    };
+    let mut file = LogFile::open(&path, age_limit).expect("open");
+    file.write_all(record(0).as_bytes()).expect("write");
+    file.write_all(record(1).as_bytes()).expect("write");
+    std::thread::sleep(Duration::from_millis(200));
+    file.write_all(record(2).as_bytes()).expect("write");
+    drop(file);
+
+    let (segments, active) = read_all(&path);
+    assert_eq!(segments, vec![record(0) + &record(1)]);
+    assert_eq!(active, record(2));
+    remove_log_directory(&path);
+}
+
+/// Reopening appends to the existing file (its size counts towards the limit) and
+/// continues the segment numbering instead of overwriting older segments.
+#[test]
+fn reopening_resumes_size_and_numbering() {
+    let path = log_path("log-reopen");
+    for run in 0..2 {
+        let mut file = LogFile::open(&path, rotation(Some(40), 100, false)).expect("open");
+        for index in 0..3 {
+            file.write_all(record(run * 3 + index).as_bytes())
+                .expect("write");
+        }
+    }
+
+    let numbers: Vec<u64> = segments(&path)
+        .expect("segments")
+        .iter()
+        .map(|segment| segment.number)
+        .collect();
+    assert_eq!(numbers, vec![1, 2]);
+    let (segments, active) = read_all(&path);
+    let all: String = segments.concat() + &active;
+    let expected: String = (0..6).map(record).collect();
+    assert_eq!(all, expected);
+    remove_log_directory(&path);
+}
@@ -0,0 +1,231 @@
+//! `--metrics-addr`: the Prometheus endpoint, scraped over loopback while a route
+//! relays, refuses and holds back connections.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::Shared;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::http_request;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_route;
+use super::helpers::test_arguments;
+use crate::args::TargetAddr;
+use crate::conn::Sinks;
+use crate::metrics;
+use crate::metrics::Metrics;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio::time::sleep;
+
+/// Start a route named `test` relaying to `remote`, and the metrics endpoint for
+/// it. Returns the route's address and the endpoint's.
+async fn spawn_measured_proxy(
+    remote: TargetAddr,
+    timeout: Option<u64>,
+    max_connections: u32,
+) -> (SocketAddr, SocketAddr) {
+    let listener = TcpListener::bind(LOOPBACK).await.expect("bind the route");
+    let listen_addr = listener.local_addr().expect("route local_addr");
+    let mut arguments = test_arguments(listen_addr, listen_addr, timeout, max_connections);
+    arguments.remote_addr = Some(remote);
+    let sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    let route = arguments.routes().expect("valid routes").remove(0);
+    let metrics = Arc::new(Metrics::default());
+    spawn_route(
+        listener,
+        arguments,
+        route,
+        sinks,
+        Shared {
+            metrics: metrics.route("test".to_string()),
+            ..Shared::default()
+        },
+    );
+    let metrics_listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("bind the metrics endpoint");
+    let metrics_addr = metrics_listener
+        .local_addr()
+        .expect("metrics endpoint local_addr");
+    tokio::spawn(metrics::serve(metrics_listener, metrics));
+    (listen_addr, metrics_addr)
+}
+
+/// The value of `series` (name and labels, as written) in a scrape.
+fn sample(scrape: &str, series: &str) -> Option<f64> {
+    scrape
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(|value| value.parse().expect("a numeric sample"))
+}
+
+/// Scrape until `series` reaches `expected`, returning the last scrape.
+async fn scrape_until(metrics_addr: SocketAddr, series: &str, expected: f64) -> String {
+    let deadline = Instant::now() + IO_TIMEOUT;
+    loop {
+        let (_, scrape) = http_request(metrics_addr, "GET", "/metrics").await;
+        if sample(&scrape, series) == Some(expected) {
+            return scrape;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "{series} never reached {expected}; last scrape:\n{scrape}"
+        );
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// A relayed connection shows up as accepted, then closed, with its bytes counted
+/// per direction.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn scrape_counts_connections_and_bytes() {
+    let echo_addr = spawn_echo_server().await;
+    let (proxy_addr, metrics_addr) = spawn_measured_proxy(echo_addr.into(), None, 8).await;
+
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"metrics!").await;
+    let scrape = scrape_until(
+        metrics_addr,
+        "logged_tcp_proxy_connections_active{route=\"test\"}",
+        1.0,
+    )
+    .await;
+    assert_eq!(
+        sample(
+            &scrape,
+            "logged_tcp_proxy_connections_accepted_total{route=\"test\"}"
+        ),
+        Some(1.0)
+    );
+
+    drop(client);
+    let scrape = scrape_until(
+        metrics_addr,
+        "logged_tcp_proxy_connections_closed_total{route=\"test\"}",
+        1.0,
+    )
+    .await;
+    for (series, expected) in [
+        ("logged_tcp_proxy_connections_active{route=\"test\"}", 0.0),
+        (
+            "logged_tcp_proxy_bytes_relayed_total{route=\"test\",direction=\"client_to_destination\"}",
+            8.0,
+        ),
+        (
+            "logged_tcp_proxy_bytes_relayed_total{route=\"test\",direction=\"destination_to_client\"}",
+            8.0,
+        ),
+        ("logged_tcp_proxy_accept_errors_total{route=\"test\"}", 0.0),
+        (
+            "logged_tcp_proxy_accept_backoff_seconds{route=\"test\"}",
+            0.0,
+        ),
+    ] {
+        assert_eq!(sample(&scrape, series), Some(expected), "{series}");
+    }
+    assert!(
+        scrape.contains("# TYPE logged_tcp_proxy_connections_accepted_total counter\n"),
+        "{scrape}"
+    );
+}
+
+/// A refused connect is counted by its error kind; a hostname that does not
+/// resolve is counted apart.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn scrape_counts_connect_and_dns_failures() {
+    let dead = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("reserve a dead port");
+    let dead_addr = dead.local_addr().expect("dead local_addr");
+    drop(dead);
+    let (refused_proxy, refused_metrics) = spawn_measured_proxy(dead_addr.into(), None, 8).await;
+    drop(connect(refused_proxy).await);
+    scrape_until(
+        refused_metrics,
+        "logged_tcp_proxy_connect_failures_total{route=\"test\",kind=\"connection_refused\"}",
+        1.0,
+    )
+    .await;
+
+    let unresolvable = TargetAddr::Named {
+        host: "logged-tcp-proxy-test.invalid".to_string(),
+        port: 80,
+    };
+    let (dns_proxy, dns_metrics) = spawn_measured_proxy(unresolvable, None, 8).await;
+    drop(connect(dns_proxy).await);
+    let scrape = scrape_until(
+        dns_metrics,
+        "logged_tcp_proxy_dns_failures_total{route=\"test\"}",
+        1.0,
+    )
+    .await;
+    assert!(
+        !scrape.contains("logged_tcp_proxy_connect_failures_total{"),
+        "a DNS failure is not a connect failure:\n{scrape}"
+    );
+}
+
+/// An idle connection closed by `--timeout` is counted.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn scrape_counts_idle_timeouts() {
+    let echo_addr = spawn_echo_server().await;
+    let (proxy_addr, metrics_addr) = spawn_measured_proxy(echo_addr.into(), Some(1), 8).await;
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"idle").await;
+    scrape_until(
+        metrics_addr,
+        "logged_tcp_proxy_idle_timeouts_total{route=\"test\"}",
+        1.0,
+    )
+    .await;
+}
+
+/// A client held back by `--max-connections` adds its wait to the slot-wait
+/// summary once a slot frees.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn scrape_reports_the_wait_for_a_connection_slot() {
+    let echo_addr = spawn_echo_server().await;
+    let (proxy_addr, metrics_addr) = spawn_measured_proxy(echo_addr.into(), None, 1).await;
+    let mut holder = connect(proxy_addr).await;
+    assert_round_trip(&mut holder, b"holding").await;
+    let (_, scrape) = http_request(metrics_addr, "GET", "/metrics").await;
+    assert_eq!(
+        sample(
+            &scrape,
+            "logged_tcp_proxy_connection_slot_wait_seconds_count{route=\"test\"}"
+        ),
+        Some(0.0)
+    );
+
+    let mut waiting = connect(proxy_addr).await;
+    sleep(Duration::from_millis(200)).await;
+    drop(holder);
+    assert_round_trip(&mut waiting, b"served").await;
+    let scrape = scrape_until(
+        metrics_addr,
+        "logged_tcp_proxy_connection_slot_wait_seconds_count{route=\"test\"}",
+        1.0,
+    )
+    .await;
+    let waited = sample(
+        &scrape,
+        "logged_tcp_proxy_connection_slot_wait_seconds_sum{route=\"test\"}",
+    )
+    .expect("the wait is summed");
+    assert!(waited >= 0.2, "waited {waited}s");
+}
+
+/// Only `GET /metrics` is served.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn other_paths_are_not_found() {
+    let echo_addr = spawn_echo_server().await;
+    let (_, metrics_addr) = spawn_measured_proxy(echo_addr.into(), None, 8).await;
+    let (status, _) = http_request(metrics_addr, "GET", "/metrics?format=text").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let (status, _) = http_request(metrics_addr, "GET", "/").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
@@ -0,0 +1,171 @@
+//! `--tls-mitm-ca`/`--tls-mitm-key`: the proxy completes each client's handshake
+//! with a certificate minted for the name it asked for, signed by a CA minted for
+//! the test, and dials the destination over TLS of its own, with or without
+//! `--remote-tls`, offering it the same name and ALPN protocols, the destination's
+//! pick being the client's too. Both sessions are logged, and the payload relayed
+//! in between is captured in plaintext.
+
+use super::capture::finished_capture;
+use super::helpers::IO_TIMEOUT;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::spawn_proxy_configured;
+use super::helpers::temp_path;
+use super::log_capture::captured_events;
+use super::log_capture::captured_lines;
+use super::log_capture::install_capturing_logger;
+use super::tls::TestCert;
+use super::tls::spawn_tls_echo_server;
+use super::tls::tls_connect_to;
+use crate::args::Arguments;
+use crate::tls::Mint;
+use serde_json::Value;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// Spawn a proxy to `remote_addr` minting certificates with `ca`, configured
+/// further by `configure` and capturing to a fresh directory, which is returned
+/// along with the proxy's address.
+async fn spawn_mitm_proxy(
+    remote_addr: SocketAddr,
+    ca: &TestCert,
+    configure: impl FnOnce(&mut Arguments),
+) -> (SocketAddr, PathBuf) {
+    let directory = temp_path("mitm-capture");
+    let capture_dir = directory.clone();
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.tls_mitm_ca = Some(ca.cert_path.clone());
+            arguments.tls_mitm_key = Some(ca.key_path.clone());
+            arguments.capture_dir = Some(capture_dir);
+            configure(arguments);
+        },
+    )
+    .await;
+    (proxy_addr, directory)
+}
+
+/// Wait for the connection from `client_addr` to log its session with `stream`,
+/// `client` or `destination`.
+async fn session(client_addr: SocketAddr, stream: &str) -> Value {
+    let deadline = Instant::now() + IO_TIMEOUT;
+    loop {
+        let session = captured_events().into_iter().find(|event| {
+            event["event"] == "tls_established"
+                && event["client"] == client_addr.to_string()
+                && event["stream"] == stream
+        });
+        if let Some(session) = session {
+            return session;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "no {stream} session for {client_addr}; captured: {:?}",
+            captured_lines()
+        );
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// A client asking for `api.lab` trusts the certificate minted for it; the TLS
+/// destination is asked for `api.lab` too, and picks `http/1.1` out of the
+/// client's ALPN offer, which the client then gets.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn minted_certificate_bridges_the_client_to_a_tls_destination() {
+    install_capturing_logger();
+    let ca = TestCert::ca("Lab CA");
+    let server_cert = TestCert::new("api.lab");
+    let (remote_addr, mut names) = spawn_tls_echo_server(&server_cert, None, &["http/1.1"]).await;
+    let (proxy_addr, directory) = spawn_mitm_proxy(remote_addr, &ca, |arguments| {
+        arguments.remote_tls = true;
+        arguments.remote_ca = Some(server_cert.cert_path.clone());
+    })
+    .await;
+
+    let (client_addr, mut client) =
+        tls_connect_to(proxy_addr, &ca, "api.lab", &["h2", "http/1.1"]).await;
+    assert_eq!(client.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+    client.write_all(b"secret").await.expect("client write");
+    let mut echo = [0u8; 6];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(&echo, b"secret");
+    let _ = client.shutdown().await;
+    drop(client);
+    assert_eq!(names.recv().await, Some(Some("api.lab".to_string())));
+
+    let client_session = session(client_addr, "client").await;
+    assert_eq!(client_session["server_name"], "api.lab");
+    assert_eq!(client_session["alpn"], "http/1.1");
+    assert!(
+        client_session["message"]
+            .as_str()
+            .expect("the client's session line")
+            .ends_with(", SNI api.lab, ALPN http/1.1"),
+        "{client_session}"
+    );
+    let destination_session = session(client_addr, "destination").await;
+    assert_eq!(destination_session["alpn"], "http/1.1");
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+    assert!(content.contains(" < 73:65:63:72:65:74\n"), "{content}");
+    assert!(content.contains(" > 73:65:63:72:65:74\n"), "{content}");
+}
+
+/// Interception implies TLS to the destination: without `--remote-tls`, a client
+/// that sends no server name gets a certificate for the address it dialed, and
+/// the TLS-only destination is dialed over TLS by its address in turn.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn interception_reaches_a_tls_destination_without_remote_tls() {
+    install_capturing_logger();
+    let ca = TestCert::ca("Lab CA");
+    let server_cert = TestCert::new("127.0.0.1");
+    let (remote_addr, mut names) = spawn_tls_echo_server(&server_cert, None, &[]).await;
+    let (proxy_addr, directory) = spawn_mitm_proxy(remote_addr, &ca, |arguments| {
+        arguments.remote_ca = Some(server_cert.cert_path.clone());
+    })
+    .await;
+
+    let (client_addr, mut client) =
+        tls_connect_to(proxy_addr, &ca, &proxy_addr.ip().to_string(), &[]).await;
+    client.write_all(b"hello").await.expect("client write");
+    let mut echo = [0u8; 5];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(&echo, b"hello");
+    drop(client);
+    assert_eq!(names.recv().await, Some(None));
+
+    let client_session = session(client_addr, "client").await;
+    assert_eq!(client_session["server_name"], "");
+    session(client_addr, "destination").await;
+    let _ = fs::remove_dir_all(&directory);
+}
+
+/// A CA key that cannot be loaded names its file.
+#[test]
+fn unreadable_ca_key_names_its_file() {
+    let ca = TestCert::ca("Lab CA");
+    // A certificate is not a key.
+    let error = match Mint::load(&ca.cert_path, &ca.cert_path) {
+        Ok(_) => panic!("a certificate for a key is rejected"),
+        Err(error) => error.to_string(),
+    };
+    assert!(
+        error.starts_with(&format!("{}: ", ca.cert_path.display())),
+        "{error}"
+    );
+}
@@ -0,0 +1,160 @@
+//! `--rate-limit-up`, `--rate-limit-down` and `--rate-limit-global`: each direction
+//! of a connection is paced by its own limit, the global limit is shared by all
+//! connections, and a transfer the limits stretch out is not taken for idleness.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_proxy_configured;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio::time::timeout;
+
+/// A destination that reads `expected` bytes from every connection it accepts and
+/// reports when each request was complete; then it answers with as many bytes of
+/// its own and holds the connection open.
+async fn spawn_counting_server(expected: usize) -> (SocketAddr, mpsc::Receiver<Instant>) {
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let addr = listener.local_addr().expect("remote local_addr");
+    let (complete, completions) = mpsc::channel(16);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.expect("remote accept");
+            let complete = complete.clone();
+            tokio::spawn(async move {
+                let mut request = vec![0u8; expected];
+                stream.read_exact(&mut request).await.expect("remote read");
+                let _ = complete.send(Instant::now()).await;
+                let _ = stream.write_all(&vec![0x5A; expected]).await;
+                let mut buffer = [0u8; 64];
+                while let Ok(n) = stream.read(&mut buffer).await {
+                    if n == 0 {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    (addr, completions)
+}
+
+/// The upload limit paces the request and the download limit the answer, each by
+/// its own rate: 2000 bytes at 8000 B/s take about a quarter of a second, the same
+/// back at 2000 B/s about a second.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn each_direction_has_its_own_limit() {
+    const LEN: usize = 2000;
+    let (remote_addr, mut completions) = spawn_counting_server(LEN).await;
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.rate_limit_up = Some(8000);
+            arguments.rate_limit_down = Some(2000);
+        },
+    )
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    let sent = Instant::now();
+    client.write_all(&[0xA5; LEN]).await.expect("client write");
+    let mut answer = vec![0u8; LEN];
+    timeout(IO_TIMEOUT, client.read_exact(&mut answer))
+        .await
+        .expect("the answer never arrived")
+        .expect("client read");
+    let answered = Instant::now();
+    let arrived = completions
+        .recv()
+        .await
+        .expect("the remote got the request");
+
+    let up = arrived - sent;
+    let down = answered - arrived;
+    assert!(
+        up >= Duration::from_millis(150) && up < Duration::from_millis(700),
+        "the request should take about 0.25s at 8000 B/s, took {up:?}"
+    );
+    assert!(
+        down >= Duration::from_millis(850) && down < Duration::from_millis(2000),
+        "the answer should take about 1s at 2000 B/s, took {down:?}"
+    );
+}
+
+/// The global limit is one budget for every connection: two clients uploading 2000
+/// bytes each at once share 4000 B/s, so the pair takes about a second where
+/// either alone would take half that.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn global_limit_is_shared_by_all_connections() {
+    const LEN: usize = 2000;
+    let (remote_addr, mut completions) = spawn_counting_server(LEN).await;
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| arguments.rate_limit_global = Some(4000),
+    )
+    .await;
+
+    let mut first = connect(proxy_addr).await;
+    let mut second = connect(proxy_addr).await;
+    let sent = Instant::now();
+    first
+        .write_all(&[1; LEN])
+        .await
+        .expect("first client write");
+    second
+        .write_all(&[2; LEN])
+        .await
+        .expect("second client write");
+    let mut last = sent;
+    for _ in 0..2 {
+        let arrived = timeout(IO_TIMEOUT, completions.recv())
+            .await
+            .expect("a request never arrived")
+            .expect("the remote got the request");
+        last = last.max(arrived);
+    }
+
+    let took = last - sent;
+    assert!(
+        took >= Duration::from_millis(850) && took < Duration::from_millis(2500),
+        "4000 bytes at a shared 4000 B/s should take about 1s, took {took:?}"
+    );
+}
+
+/// A rate-limited transfer counts as activity for the idle timeout as it trickles
+/// out: 3000 bytes at 1000 B/s take three times the 1s timeout, and all of them
+/// arrive.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn throttled_transfer_is_not_idle() {
+    const LEN: usize = 3000;
+    let (remote_addr, mut completions) = spawn_counting_server(LEN).await;
+    let proxy_addr =
+        spawn_proxy_configured(remote_addr, Some(1), TEST_MAX_CONNECTIONS, |arguments| {
+            arguments.rate_limit_up = Some(1000);
+        })
+        .await;
+
+    let mut client = connect(proxy_addr).await;
+    let sent = Instant::now();
+    client.write_all(&[0x42; LEN]).await.expect("client write");
+    let arrived = timeout(IO_TIMEOUT, completions.recv())
+        .await
+        .expect("the throttled request was cut off by the idle timeout")
+        .expect("the remote got the request");
+    assert!(
+        arrived - sent >= Duration::from_millis(2500),
+        "3000 bytes at 1000 B/s should take about 3s, took {:?}",
+        arrived - sent
+    );
+}
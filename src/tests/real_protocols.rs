@@ -1,6 +1,6 @@
 //! Realism tests: genuine MODBUS TCP and HTTP/1.1 conversations are relayed
-//! unchanged. The proxy never parses either protocol; these pin its actual
-//! use case rather than adding code coverage.
+//! unchanged. Without `--decode` the proxy parses neither protocol; these pin its
+//! actual use case rather than adding code coverage.
 
 use super::helpers::IO_TIMEOUT;
 use super::helpers::LOOPBACK;
@@ -0,0 +1,190 @@
+//! `--fragment` and `--coalesce`: a chunk is written on in pieces of a fixed or
+//! random size, or consecutive small reads are gathered into one write, and the
+//! payload lines logged (here, in the `--capture-dir` file) are the writes actually
+//! made rather than the reads.
+
+use super::capture::finished_capture;
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::helpers::temp_path;
+use crate::args::Arguments;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// A destination that reads `expected` bytes from the first connection it accepts
+/// and reports the size of every read it took to get them.
+async fn spawn_recording_server(expected: usize) -> (SocketAddr, oneshot::Receiver<Vec<usize>>) {
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let addr = listener.local_addr().expect("remote local_addr");
+    let (done, reads) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("remote accept");
+        let mut sizes = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while sizes.iter().sum::<usize>() < expected {
+            match stream.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => sizes.push(n),
+            }
+        }
+        let _ = done.send(sizes);
+    });
+    (addr, reads)
+}
+
+/// Spawn a proxy to `remote_addr` configured by `configure`, capturing to a fresh
+/// directory, which is returned along with the proxy's address.
+async fn spawn_rechunking_proxy(
+    remote_addr: SocketAddr,
+    configure: impl FnOnce(&mut Arguments),
+) -> (SocketAddr, PathBuf) {
+    let directory = temp_path("rechunk");
+    let capture_dir = directory.clone();
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.capture_dir = Some(capture_dir);
+            configure(arguments);
+        },
+    )
+    .await;
+    (proxy_addr, directory)
+}
+
+/// The payload lines of a capture file with `marker`, without their stamps.
+fn payload_lines(content: &str, marker: char) -> Vec<String> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.splitn(3, ' ').nth(2))
+        .filter_map(|event| event.strip_prefix(marker))
+        .map(|payload| payload.trim_start().to_string())
+        .collect()
+}
+
+/// `--fragment 3 --fragment-delay 20` writes a 10-byte request on as three 3-byte
+/// pieces and the 1-byte rest, far enough apart for the destination to read each
+/// on its own, and logs each piece.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn fixed_fragments_reach_the_destination_apart() {
+    let (remote_addr, reads) = spawn_recording_server(10).await;
+    let (proxy_addr, directory) = spawn_rechunking_proxy(remote_addr, |arguments| {
+        arguments.fragment = Some("3".parse().expect("a valid fragment size"));
+        arguments.fragment_delay = 20;
+    })
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client.write_all(b"abcdefghij").await.expect("client write");
+    let sizes = timeout(IO_TIMEOUT, reads)
+        .await
+        .expect("the request never arrived")
+        .expect("the remote reported");
+    drop(client);
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+
+    assert_eq!(sizes, [3, 3, 3, 1]);
+    assert_eq!(
+        payload_lines(&content, '<'),
+        ["61:62:63", "64:65:66", "67:68:69", "6a"]
+    );
+}
+
+/// `--fragment 2-5` cuts every write into pieces of two to five bytes, in both
+/// directions, without losing or reordering any byte.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn random_fragments_stay_within_their_range() {
+    const LEN: usize = 300;
+    let echo_addr = spawn_echo_server().await;
+    let (proxy_addr, directory) = spawn_rechunking_proxy(echo_addr, |arguments| {
+        arguments.fragment = Some("2-5".parse().expect("a valid fragment size"));
+        arguments.seed = Some(7);
+    })
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    let request: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+    client.write_all(&request).await.expect("client write");
+    let mut echo = vec![0u8; LEN];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never completed")
+        .expect("client read");
+    drop(client);
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+
+    assert_eq!(echo, request);
+    for marker in ['<', '>'] {
+        let pieces = payload_lines(&content, marker);
+        let sizes: Vec<usize> = pieces
+            .iter()
+            .map(|piece| piece.split(':').count())
+            .collect();
+        // Only the last piece of a chunk may be cut shorter than the range.
+        assert!(
+            sizes.iter().all(|size| (1..=5).contains(size)),
+            "{marker} pieces out of range: {sizes:?}"
+        );
+        assert!(
+            sizes.iter().any(|size| *size != sizes[0]),
+            "{marker} piece sizes are picked at random: {sizes:?}"
+        );
+        assert_eq!(
+            sizes.iter().sum::<usize>(),
+            LEN,
+            "{marker} pieces: {sizes:?}"
+        );
+    }
+}
+
+/// `--coalesce 200` gathers five 2-byte writes sent 10ms apart into one write,
+/// which the destination reads, and the capture logs, as a single 10-byte chunk.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn coalesce_gathers_small_reads_into_one_write() {
+    let (remote_addr, reads) = spawn_recording_server(10).await;
+    let (proxy_addr, directory) = spawn_rechunking_proxy(remote_addr, |arguments| {
+        arguments.coalesce = Some(200);
+    })
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    client.set_nodelay(true).expect("client set_nodelay");
+    let client_addr = client.local_addr().expect("client local_addr");
+    for pair in b"abcdefghij".chunks(2) {
+        client.write_all(pair).await.expect("client write");
+        sleep(Duration::from_millis(10)).await;
+    }
+    let sizes = timeout(IO_TIMEOUT, reads)
+        .await
+        .expect("the request never arrived")
+        .expect("the remote reported");
+    drop(client);
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+
+    assert_eq!(sizes, [10]);
+    assert_eq!(
+        payload_lines(&content, '<'),
+        ["61:62:63:64:65:66:67:68:69:6a"]
+    );
+}
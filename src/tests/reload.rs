@@ -0,0 +1,303 @@
+//! Reloading the configuration (SIGHUP): what a reload reports and refuses, and
+//! how a route's accept loop hands the new settings to new connections only,
+//! resizing its connection limit on the way.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::connect;
+use super::helpers::exchange;
+use super::helpers::spawn_reply_server;
+use super::helpers::temp_path;
+use super::helpers::test_arguments;
+use crate::admin::Registry;
+use crate::args::Cli;
+use crate::args::Command;
+use crate::args::LoggingLevel;
+use crate::config;
+use crate::config::CommandLine;
+use crate::conn::ConnIds;
+use crate::conn::ConnectionLimit;
+use crate::conn::RouteConfig;
+use crate::conn::Shutdown;
+use crate::conn::Sinks;
+use crate::conn::serve_route;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+/// How long a client that should be held back waits before the test decides it
+/// was.
+const HELD_BACK: Duration = Duration::from_millis(300);
+
+/// The command line `--config <path>`, as the proxy keeps it for reloads.
+fn command_line(path: &Path) -> CommandLine {
+    let path = path.to_str().expect("a UTF-8 temp path");
+    let parsed = Cli::try_parse_explicit_from(["logged_tcp_proxy", "--config", path]);
+    let Ok((Command::Proxy(arguments), explicit)) = parsed else {
+        panic!("--config alone should parse as the proxy: {parsed:?}");
+    };
+    CommandLine {
+        arguments,
+        explicit,
+    }
+}
+
+const RUNNING: &str = r#"
+level = "info"
+threads = 4
+bind-listener-addr = "127.0.0.1:15000"
+remote-addr = "127.0.0.1:9"
+
+[[route]]
+name = "db"
+listen = "127.0.0.1:15001"
+remote = "127.0.0.1:5432"
+"#;
+
+/// A reload reports each changed connection setting per route, and keeps the
+/// options that only a restart can change at their running values.
+#[test]
+fn reload_reports_changes_and_keeps_restart_only_options() {
+    let path = temp_path("reload").with_extension("toml");
+    fs::write(&path, RUNNING).expect("write the config file");
+    let command_line = command_line(&path);
+    let running = command_line
+        .load()
+        .expect("the running configuration loads");
+
+    fs::write(
+        &path,
+        r#"
+level = "debug"
+threads = 8
+bind-listener-addr = "127.0.0.1:15000"
+remote-addr = "127.0.0.1:9"
+timeout = 30
+shutdown-grace = 5
+latency-to-client = 200
+rate-limit-down = "64K"
+rate-limit-global = 1000
+fragment = "8-64"
+fragment-delay = 5
+coalesce = 20
+sni-route = ["api.lab=127.0.0.1:8443"]
+inspect-tls = true
+decode = "http"
+seed = 3
+
+[[route]]
+name = "db"
+listen = "127.0.0.1:15001"
+remote = "127.0.0.1:5433"
+max-connections = 8
+fault = ["up:reset@1K"]
+"#,
+    )
+    .expect("rewrite the config file");
+    let reload = config::reload(&command_line, &running);
+    let _ = fs::remove_file(&path);
+    let reload = reload.expect("a valid reload");
+
+    assert_eq!(
+        reload.changes,
+        [
+            "shutdown-grace 10s -> 5s",
+            "latency-to-client 0ms -> 200ms",
+            "fragment-delay 0ms -> 5ms",
+            "rate-limit-down none -> 65536B/s",
+            "fragment none -> 8-64",
+            "coalesce none -> 20ms",
+            "sni-route none -> [api.lab=127.0.0.1:8443]",
+            "inspect-tls false -> true",
+            "decode none -> http",
+            "timeout none -> 30s",
+            "[db] remote-addr 127.0.0.1:5432 -> 127.0.0.1:5433",
+            "[db] timeout none -> 30s",
+            "[db] max-connections 512 -> 8",
+            "[db] fault none -> [up:reset@1024]",
+        ]
+    );
+    assert_eq!(
+        reload.ignored,
+        ["level", "threads", "rate-limit-global", "seed"]
+    );
+    assert_eq!(reload.arguments.level, LoggingLevel::Info);
+    assert_eq!(reload.arguments.threads, 4);
+    assert_eq!(reload.arguments.rate_limit_global, None);
+    assert_eq!(reload.arguments.seed, None);
+    assert_eq!(reload.routes[1].settings.max_connections, Some(8));
+}
+
+/// An invalid file, or one that changes the listeners, is rejected: the caller
+/// keeps the running configuration.
+#[test]
+fn invalid_or_relisting_reload_is_rejected() {
+    let path = temp_path("reload").with_extension("toml");
+    fs::write(&path, RUNNING).expect("write the config file");
+    let command_line = command_line(&path);
+    let running = command_line
+        .load()
+        .expect("the running configuration loads");
+
+    fs::write(&path, RUNNING.replace("threads = 4", "threads = 0")).expect("rewrite");
+    let invalid = config::reload(&command_line, &running).err();
+    fs::write(&path, RUNNING.replace("15001", "15002")).expect("rewrite");
+    let moved = config::reload(&command_line, &running).err();
+    fs::write(&path, RUNNING.replace("name = \"db\"", "name = \"pg\"")).expect("rewrite");
+    let renamed = config::reload(&command_line, &running).err();
+    let _ = fs::remove_file(&path);
+
+    let invalid = invalid.expect("an invalid value is rejected");
+    assert!(
+        invalid.ends_with(":3: invalid threads `0`: expected 1..=1024"),
+        "{invalid}"
+    );
+    for rejected in [moved, renamed] {
+        assert!(
+            rejected.is_some_and(|error| error.contains("needs a restart")),
+            "a changed listener is rejected"
+        );
+    }
+}
+
+/// Growing the limit frees slots at once; shrinking it withdraws the free slots
+/// and then, as connections give theirs back, the rest.
+#[tokio::test]
+async fn connection_limit_resizes_while_slots_are_held() {
+    async fn slot(limit: &mut ConnectionLimit) -> Option<OwnedSemaphorePermit> {
+        timeout(HELD_BACK, async {
+            loop {
+                let permit = limit.acquire().await.expect("the semaphore stays open");
+                if let Some(permit) = limit.admit(permit) {
+                    return permit;
+                }
+            }
+        })
+        .await
+        .ok()
+    }
+
+    let mut limit = ConnectionLimit::new(1);
+    let first = slot(&mut limit).await.expect("one slot");
+    assert!(slot(&mut limit).await.is_none(), "the only slot is held");
+    limit.resize(2);
+    let second = slot(&mut limit).await.expect("the added slot");
+
+    // Both slots are held: the shrink can only take one as it comes back.
+    limit.resize(1);
+    drop(first);
+    assert!(
+        slot(&mut limit).await.is_none(),
+        "the returned slot is withdrawn"
+    );
+    drop(second);
+    let third = slot(&mut limit).await.expect("one slot remains");
+    assert!(slot(&mut limit).await.is_none(), "and only one");
+    drop(third);
+}
+
+/// Start `serve_route` on an ephemeral port, relaying to `remote_addr` with at
+/// most `max_connections` at a time. Returns its address and the sender a reload
+/// goes through.
+async fn spawn_reloadable_route(
+    remote_addr: SocketAddr,
+    max_connections: u32,
+) -> (SocketAddr, watch::Sender<RouteConfig>) {
+    let listener = TcpListener::bind(LOOPBACK).await.expect("bind the route");
+    let listen_addr = listener.local_addr().expect("route local_addr");
+    let arguments = test_arguments(
+        listen_addr,
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        max_connections,
+    );
+    let sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    let route = arguments
+        .routes()
+        .expect("the listener pair is a route")
+        .remove(0);
+    let (reloads, config) = watch::channel(RouteConfig::new(arguments, route));
+    tokio::spawn(serve_route(
+        listener,
+        config,
+        sinks,
+        ConnIds::default(),
+        Shutdown::default(),
+        Arc::default(),
+        Registry::default(),
+    ));
+    (listen_addr, reloads)
+}
+
+/// What a reload hands the route started by [`spawn_reloadable_route`].
+fn reloaded(remote_addr: SocketAddr, max_connections: u32) -> RouteConfig {
+    let placeholder: SocketAddr = LOOPBACK.parse().expect("LOOPBACK parses");
+    let arguments = test_arguments(
+        placeholder,
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        max_connections,
+    );
+    let route = arguments
+        .routes()
+        .expect("the listener pair is a route")
+        .remove(0);
+    RouteConfig::new(arguments, route)
+}
+
+/// After a reload, new connections go to the new remote while one accepted
+/// before it keeps relaying to the old one.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn reload_applies_to_new_connections_only() {
+    let old_remote = spawn_reply_server(b"old remote").await;
+    let new_remote = spawn_reply_server(b"new remote").await;
+    let (proxy_addr, reloads) = spawn_reloadable_route(old_remote, 8).await;
+
+    let mut before = connect(proxy_addr).await;
+    assert_eq!(exchange(&mut before, b"old remote").await, b"old remote");
+
+    reloads.send_replace(reloaded(new_remote, 8));
+    let mut after = connect(proxy_addr).await;
+    assert_eq!(exchange(&mut after, b"new remote").await, b"new remote");
+    assert_eq!(exchange(&mut before, b"old remote").await, b"old remote");
+}
+
+/// A client held back by `--max-connections` is served as soon as a reload raises
+/// the limit, while the connection holding the only slot stays open.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn raising_max_connections_serves_a_waiting_client() {
+    let remote = spawn_reply_server(b"served").await;
+    let (proxy_addr, reloads) = spawn_reloadable_route(remote, 1).await;
+
+    let mut holder = connect(proxy_addr).await;
+    assert_eq!(exchange(&mut holder, b"served").await, b"served");
+
+    let mut waiting = connect(proxy_addr).await;
+    waiting
+        .write_all(b"anyone?")
+        .await
+        .expect("the backlog takes the write");
+    let mut reply = [0; 6];
+    assert!(
+        timeout(HELD_BACK, waiting.read_exact(&mut reply))
+            .await
+            .is_err(),
+        "the second client waits for a slot"
+    );
+
+    reloads.send_replace(reloaded(remote, 2));
+    timeout(IO_TIMEOUT, waiting.read_exact(&mut reply))
+        .await
+        .expect("the waiting client is served after the reload")
+        .expect("read failed");
+    assert_eq!(&reply, b"served");
+    drop(holder);
+}
@@ -0,0 +1,257 @@
+//! `replay`: reading a `--pcap` capture back into per-connection chunks, and
+//! replaying a connection as its client (compared against a live server) or as its
+//! server (answering a live client), with the recorded gaps scaled by `--speed`.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::helpers::temp_path;
+use crate::args::PayloadFormattingKind;
+use crate::args::get_formatter_by_kind;
+use crate::conn::Direction;
+use crate::pcap::PcapWriter;
+use crate::pcap::RecordedChunk;
+use crate::pcap::read_capture;
+use crate::replay::Outcome;
+use crate::replay::Replay;
+use std::fs;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio::time::timeout;
+
+fn chunk(direction: Direction, millis: u64, payload: &[u8]) -> RecordedChunk {
+    RecordedChunk {
+        direction,
+        time: Duration::from_millis(millis),
+        payload: payload.to_vec(),
+    }
+}
+
+/// A replay of `chunks` as connection #1, waiting at most `wait` for the other side.
+fn replay(chunks: Vec<RecordedChunk>, speed: f64, wait: Duration) -> Replay {
+    Replay::new(
+        1,
+        chunks,
+        speed,
+        wait,
+        get_formatter_by_kind(PayloadFormattingKind::LowerHex, ":", 16),
+    )
+}
+
+/// Replay `replay` as the client of `server_addr`.
+async fn play_client(replay: &Replay, server_addr: SocketAddr) -> Outcome {
+    let stream = connect(server_addr).await;
+    timeout(IO_TIMEOUT, replay.play_client(stream))
+        .await
+        .expect("the client replay hung")
+        .expect("the client replay failed")
+}
+
+/// The chunks of every connection come back under its id, in order and with their
+/// direction; the handshake and the closes carry no chunk.
+#[test]
+fn capture_reads_back_into_chunks_per_connection() {
+    let path = temp_path("replay-read");
+    let writer = PcapWriter::create(&path).expect("create the capture");
+    let client: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+    let destination: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    let first = writer.connection(1, client, destination);
+    let second = writer.connection(2, "[::1]:50001".parse().unwrap(), destination);
+    first.data(Direction::ClientToDestination, b"request");
+    second.data(Direction::ClientToDestination, b"other");
+    first.data(Direction::DestinationToClient, b"response");
+    first.fin(Direction::ClientToDestination);
+    second.reset(Direction::DestinationToClient);
+    drop((first, second, writer));
+
+    let file = fs::read(&path).expect("read the capture");
+    let _ = fs::remove_file(&path);
+    let connections = read_capture(&file).expect("a capture the proxy wrote is readable");
+
+    let shape: Vec<_> = connections
+        .iter()
+        .map(|(id, chunks)| {
+            let chunks: Vec<_> = chunks
+                .iter()
+                .map(|chunk| (chunk.direction, chunk.payload.as_slice()))
+                .collect();
+            (*id, chunks)
+        })
+        .collect();
+    assert_eq!(
+        shape,
+        vec![
+            (
+                1,
+                vec![
+                    (Direction::ClientToDestination, &b"request"[..]),
+                    (Direction::DestinationToClient, &b"response"[..]),
+                ]
+            ),
+            (2, vec![(Direction::ClientToDestination, &b"other"[..])]),
+        ]
+    );
+    let times: Vec<Duration> = connections[&1].iter().map(|chunk| chunk.time).collect();
+    assert!(times.is_sorted(), "chunk times go forward: {times:?}");
+}
+
+/// Anything that is not a pcapng capture is rejected with a reason.
+#[test]
+fn capture_from_elsewhere_is_rejected() {
+    assert!(read_capture(b"not a capture at all").is_err());
+    assert!(read_capture(b"").is_err());
+}
+
+/// Replaying as the client against a server that answers as recorded matches;
+/// requests and responses are compared as byte streams, however they were split.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn client_replay_matches_a_server_that_answers_the_same() {
+    let echo_addr = spawn_echo_server().await;
+    let replay = replay(
+        vec![
+            chunk(Direction::ClientToDestination, 0, b"first"),
+            chunk(Direction::DestinationToClient, 1, b"fir"),
+            chunk(Direction::DestinationToClient, 2, b"st"),
+            chunk(Direction::ClientToDestination, 3, b"second"),
+            chunk(Direction::ClientToDestination, 4, b" part"),
+            chunk(Direction::DestinationToClient, 5, b"second part"),
+        ],
+        1.0,
+        IO_TIMEOUT,
+    );
+
+    let outcome = play_client(&replay, echo_addr).await;
+    assert_eq!(
+        outcome,
+        Outcome {
+            expected: 2,
+            mismatched: 0
+        }
+    );
+}
+
+/// A response that differs from the recording, or never comes, is a mismatch.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn client_replay_reports_responses_that_differ() {
+    let echo_addr = spawn_echo_server().await;
+    let replay = replay(
+        vec![
+            chunk(Direction::ClientToDestination, 0, b"ping"),
+            chunk(Direction::DestinationToClient, 0, b"pong"),
+            chunk(Direction::ClientToDestination, 0, b"ping"),
+            chunk(Direction::DestinationToClient, 0, b"ping"),
+            // The echo server never sends more than it got.
+            chunk(Direction::DestinationToClient, 0, b"and more"),
+        ],
+        0.0,
+        Duration::from_millis(300),
+    );
+
+    let outcome = play_client(&replay, echo_addr).await;
+    assert_eq!(
+        outcome,
+        Outcome {
+            expected: 2,
+            mismatched: 2
+        }
+    );
+}
+
+/// Replaying as the server answers a client's recorded request with the recorded
+/// response, then closes, and does so for every client that connects.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn server_replay_answers_each_client() {
+    let listener = TcpListener::bind(LOOPBACK).await.expect("bind");
+    let server_addr = listener.local_addr().expect("local_addr");
+    let replay = replay(
+        vec![
+            chunk(Direction::ClientToDestination, 0, b"hello"),
+            chunk(Direction::DestinationToClient, 1, b"world"),
+            chunk(Direction::DestinationToClient, 2, b"!"),
+        ],
+        0.0,
+        IO_TIMEOUT,
+    );
+    tokio::spawn(async move { replay.serve(listener).await });
+
+    for _ in 0..2 {
+        let mut client = connect(server_addr).await;
+        client.write_all(b"hello").await.expect("client write");
+        let mut answer = Vec::new();
+        timeout(IO_TIMEOUT, client.read_to_end(&mut answer))
+            .await
+            .expect("the replayed server did not finish")
+            .expect("client read");
+        assert_eq!(answer, b"world!");
+    }
+}
+
+/// The recorded gaps between chunks are waited, divided by `--speed`; a speed of
+/// `0` sends without waiting.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn speed_scales_the_recorded_gaps() {
+    let echo_addr = spawn_echo_server().await;
+    let recording = vec![
+        chunk(Direction::ClientToDestination, 0, b"a"),
+        chunk(Direction::ClientToDestination, 600, b"b"),
+        chunk(Direction::DestinationToClient, 601, b"ab"),
+    ];
+
+    let started = Instant::now();
+    play_client(&replay(recording.clone(), 2.0, IO_TIMEOUT), echo_addr).await;
+    let halved = started.elapsed();
+    assert!(
+        halved >= Duration::from_millis(300) && halved < Duration::from_millis(600),
+        "a 600ms gap at speed 2 took {halved:?}"
+    );
+
+    let started = Instant::now();
+    play_client(&replay(recording, 0.0, IO_TIMEOUT), echo_addr).await;
+    let immediate = started.elapsed();
+    assert!(
+        immediate < Duration::from_millis(300),
+        "speed 0 still waited {immediate:?}"
+    );
+}
+
+/// A connection recorded through the proxy with `--pcap` replays against the same
+/// server without a difference.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn recorded_connection_replays_against_the_same_server() {
+    let path = temp_path("replay-record");
+    let echo_addr = spawn_echo_server().await;
+    let pcap_path = path.clone();
+    let proxy_addr = spawn_proxy_configured(
+        echo_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| arguments.pcap = Some(pcap_path),
+    )
+    .await;
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"recorded request").await;
+    assert_round_trip(&mut client, b"and another").await;
+    drop(client);
+
+    let file = fs::read(&path).expect("the capture file exists");
+    let _ = fs::remove_file(&path);
+    let mut connections = read_capture(&file).expect("readable capture");
+    let chunks = connections.remove(&1).expect("connection #1 was recorded");
+
+    let outcome = play_client(&replay(chunks, 1.0, IO_TIMEOUT), echo_addr).await;
+    assert_eq!(
+        outcome,
+        Outcome {
+            expected: 2,
+            mismatched: 0
+        }
+    );
+}
@@ -0,0 +1,135 @@
+//! `--route`: several listeners in one process, each relaying to its own remote
+//! with a connection limit of its own, their lines prefixed with the route's name,
+//! and connection ids unique across all of them.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::Shared;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::exchange;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_reply_server;
+use super::helpers::spawn_route;
+use super::helpers::test_arguments;
+use super::log_capture::captured_events;
+use super::log_capture::captured_lines;
+use super::log_capture::install_capturing_logger;
+use crate::args::Route;
+use crate::args::RouteSettings;
+use crate::conn::ConnIds;
+use crate::conn::Sinks;
+use serde_json::json;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+/// Start one accept loop per `(name, remote)` route, on ephemeral ports, sharing
+/// one id counter as `initialize_tcp_listener` does. Returns the listen addresses,
+/// in order.
+async fn spawn_routes(routes: &[(&str, SocketAddr)], max_connections: u32) -> Vec<SocketAddr> {
+    let placeholder: SocketAddr = LOOPBACK.parse().expect("LOOPBACK parses");
+    let arguments = test_arguments(
+        placeholder,
+        placeholder,
+        Some(IO_TIMEOUT.as_secs()),
+        max_connections,
+    );
+    let sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    let conn_ids = ConnIds::default();
+    let mut listen_addrs = Vec::new();
+    for (name, remote_addr) in routes {
+        let listener = TcpListener::bind(LOOPBACK)
+            .await
+            .expect("failed to bind a route");
+        let listen_addr = listener.local_addr().expect("route local_addr");
+        let route = Route {
+            name: Some(name.to_string()),
+            listen_addr,
+            remote_addr: Some((*remote_addr).into()),
+            settings: RouteSettings::default(),
+        };
+        spawn_route(
+            listener,
+            arguments.clone(),
+            route,
+            sinks.clone(),
+            Shared {
+                conn_ids: conn_ids.clone(),
+                ..Shared::default()
+            },
+        );
+        listen_addrs.push(listen_addr);
+    }
+    listen_addrs
+}
+
+/// Each route relays to its own remote.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn each_route_relays_to_its_own_remote() {
+    let first_remote = spawn_reply_server(b"first remote").await;
+    let second_remote = spawn_reply_server(b"second remote").await;
+    let routes = spawn_routes(&[("first", first_remote), ("second", second_remote)], 8).await;
+
+    let mut first = connect(routes[0]).await;
+    let mut second = connect(routes[1]).await;
+    assert_eq!(
+        exchange(&mut second, b"second remote").await,
+        b"second remote"
+    );
+    assert_eq!(exchange(&mut first, b"first remote").await, b"first remote");
+}
+
+/// Ids are minted across routes, in accept order, and every line of a connection
+/// starts with its route's name ahead of the `[#N]` tag; the name is also the
+/// `route` field of the connection's structured events.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn ids_are_unique_across_routes_and_lines_name_the_route() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let routes = spawn_routes(&[("alpha", echo_addr), ("beta", echo_addr)], 8).await;
+
+    let mut clients = Vec::new();
+    for route in [0, 1, 0] {
+        let mut client = connect(routes[route]).await;
+        assert_round_trip(&mut client, b"routed").await;
+        let client_addr = client.local_addr().expect("client local_addr");
+        clients.push((route, client_addr, client));
+    }
+
+    let lines = captured_lines();
+    for (index, (route, client_addr, _)) in clients.iter().enumerate() {
+        let name = ["alpha", "beta"][*route];
+        let expected = format!(
+            "[{name}] [#{}] Incoming connection from {client_addr}",
+            index + 1
+        );
+        assert!(
+            lines.contains(&expected),
+            "missing {expected:?}; captured: {lines:?}"
+        );
+        let accepted = captured_events()
+            .into_iter()
+            .find(|event| {
+                event["client"] == json!(client_addr.to_string())
+                    && event["event"] == json!("accepted")
+            })
+            .expect("no accepted event");
+        assert_eq!(accepted["route"], json!(name));
+        assert_eq!(accepted["conn_id"], json!(index + 1));
+    }
+}
+
+/// Every route has its own `--max-connections` slots: a route at its limit does
+/// not hold back another route's connections.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_full_route_does_not_block_another() {
+    let echo_addr = spawn_echo_server().await;
+    let routes = spawn_routes(&[("busy", echo_addr), ("idle", echo_addr)], 1).await;
+
+    let mut busy = connect(routes[0]).await;
+    assert_round_trip(&mut busy, b"holding the only slot").await;
+
+    let mut other = connect(routes[1]).await;
+    assert_round_trip(&mut other, b"served regardless").await;
+    drop(busy);
+}
@@ -0,0 +1,175 @@
+//! The drain phase at shutdown: the connections still open get the grace period
+//! to finish on their own, and those left at the deadline, or at a second signal,
+//! are closed with a line that names them and their byte counts.
+//!
+//! The tests call `drain` directly with a route of their own, so the whole suite
+//! shares one capture buffer but each assertion keys off the test's own client
+//! address (or its own grace period, for the proxy-wide lines).
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::Shared;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_route;
+use super::helpers::test_arguments;
+use super::log_capture::captured_lines;
+use super::log_capture::install_capturing_logger;
+use crate::conn::Shutdown;
+use crate::conn::Sinks;
+use crate::conn::drain;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+/// How long a drain that should still be waiting is given before the test decides
+/// it is.
+const STILL_WAITING: Duration = Duration::from_millis(300);
+
+/// Start `serve_route` on an ephemeral port, relaying to a fresh echo server and
+/// counting its connections in the returned `Shutdown`.
+async fn spawn_drainable_proxy() -> (SocketAddr, Shutdown) {
+    let echo_addr = spawn_echo_server().await;
+    let listener = TcpListener::bind(LOOPBACK).await.expect("bind the route");
+    let listen_addr = listener.local_addr().expect("route local_addr");
+    let arguments = test_arguments(listen_addr, echo_addr, None, 8);
+    let sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    let route = arguments
+        .routes()
+        .expect("the listener pair is a route")
+        .remove(0);
+    let shutdown = Shutdown::default();
+    spawn_route(
+        listener,
+        arguments,
+        route,
+        sinks,
+        Shared {
+            shutdown: shutdown.clone(),
+            ..Shared::default()
+        },
+    );
+    (listen_addr, shutdown)
+}
+
+/// The client's read ends without data: the proxy closed the connection.
+async fn assert_closed(client: &mut tokio::net::TcpStream) {
+    let mut buffer = [0; 16];
+    let read = timeout(IO_TIMEOUT, client.read(&mut buffer))
+        .await
+        .expect("the proxy should close the connection")
+        .unwrap_or(0);
+    assert_eq!(read, 0, "expected end-of-stream");
+}
+
+/// A connection that finishes within the grace period ends the drain early, and
+/// nothing is forced.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn drain_waits_for_active_connections_to_finish() {
+    install_capturing_logger();
+    let (proxy_addr, shutdown) = spawn_drainable_proxy().await;
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"still talking").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    assert_eq!(shutdown.active(), 1);
+
+    let draining = shutdown.clone();
+    let mut drain = tokio::spawn(async move {
+        drain(&draining, Duration::from_secs(97), std::future::pending()).await;
+    });
+    assert!(
+        timeout(STILL_WAITING, &mut drain).await.is_err(),
+        "the drain waits while the connection is open"
+    );
+    assert_round_trip(&mut client, b"served while draining").await;
+
+    drop(client);
+    timeout(IO_TIMEOUT, drain)
+        .await
+        .expect("the drain should end once the connection does")
+        .expect("the drain task panicked");
+    assert_eq!(shutdown.active(), 0);
+    let lines = captured_lines();
+    assert!(
+        lines.contains(&"Waiting up to 97s for 1 active connection(s) to finish...".to_string()),
+        "missing the draining line; captured: {lines:?}"
+    );
+    assert!(
+        !lines.iter().any(
+            |line| line.contains("Forcibly closing") && line.contains(&client_addr.to_string())
+        ),
+        "a connection that finished in time is not forced; captured: {lines:?}"
+    );
+}
+
+/// At the end of the grace period the connections still open are closed, each
+/// with its `[#N]` tag and what it relayed.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn grace_deadline_forces_the_rest_closed() {
+    install_capturing_logger();
+    let (proxy_addr, shutdown) = spawn_drainable_proxy().await;
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"hello").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+
+    timeout(
+        IO_TIMEOUT,
+        drain(
+            &shutdown,
+            Duration::from_millis(200),
+            std::future::pending(),
+        ),
+    )
+    .await
+    .expect("the drain should end at its deadline");
+    assert_closed(&mut client).await;
+    assert_eq!(shutdown.active(), 0);
+
+    let expected = format!(
+        "[#1] Forcibly closing connection from {client_addr} at shutdown after 5 bytes from the client and 5 bytes from the destination"
+    );
+    let lines = captured_lines();
+    assert!(
+        lines.contains(&expected),
+        "missing {expected:?}; captured: {lines:?}"
+    );
+}
+
+/// A second signal cuts a long grace period short.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn second_signal_forces_the_rest_closed() {
+    install_capturing_logger();
+    let (proxy_addr, shutdown) = spawn_drainable_proxy().await;
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"abc").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+
+    let (signal, second_signal) = oneshot::channel::<()>();
+    let draining = shutdown.clone();
+    let drain = tokio::spawn(async move {
+        drain(&draining, Duration::from_secs(3600), async {
+            let _ = second_signal.await;
+            Ok(())
+        })
+        .await;
+    });
+    signal.send(()).expect("the drain is waiting");
+    timeout(IO_TIMEOUT, drain)
+        .await
+        .expect("the drain should end at the second signal")
+        .expect("the drain task panicked");
+    assert_closed(&mut client).await;
+
+    let expected = format!(
+        "[#1] Forcibly closing connection from {client_addr} at shutdown after 3 bytes from the client and 3 bytes from the destination"
+    );
+    let lines = captured_lines();
+    assert!(
+        lines.contains(&expected),
+        "missing {expected:?}; captured: {lines:?}"
+    );
+}
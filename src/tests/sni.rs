@@ -0,0 +1,321 @@
+//! `--sni-route`: each connection goes to the destination its client's TLS hello
+//! names, the hello and all after it relayed untouched; a client whose name has no
+//! route goes to the listener's remote address, or is turned away without one.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::log_capture::captured_events;
+use super::log_capture::event;
+use super::log_capture::install_capturing_logger;
+use super::tls::TestCert;
+use super::tls::spawn_tls_echo_server;
+use super::tls::tls_connect_to;
+use crate::sni;
+use crate::sni::SniRoute;
+use rustls::ClientConfig;
+use rustls::ClientConnection;
+use rustls::RootCertStore;
+use rustls::pki_types::ServerName;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+/// Spawn a proxy whose listener's remote address is `default`, if any, routing by
+/// `routes`.
+async fn spawn_sni_proxy(default: Option<SocketAddr>, routes: &[String]) -> SocketAddr {
+    let routes: Vec<SniRoute> = routes
+        .iter()
+        .map(|route| route.parse().expect("a valid SNI route"))
+        .collect();
+    let placeholder = "127.0.0.1:9".parse().expect("a valid address");
+    spawn_proxy_configured(
+        default.unwrap_or(placeholder),
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.remote_addr = default.map(Into::into);
+            arguments.sni_routes = routes;
+        },
+    )
+    .await
+}
+
+/// Open a TLS session through `proxy_addr` asking for `name`, trusting `cert`,
+/// and check that it echoes.
+async fn echo_as(proxy_addr: SocketAddr, cert: &TestCert, name: &str) -> SocketAddr {
+    let (client_addr, mut client) = tls_connect_to(proxy_addr, cert, name, &[]).await;
+    client.write_all(b"routed").await.expect("client write");
+    let mut echo = [0u8; 6];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(&echo, b"routed");
+    client_addr
+}
+
+/// The first flight of a TLS client asking for `name`.
+fn client_hello(name: &str) -> Vec<u8> {
+    let cert = TestCert::new(name);
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.der.clone()).expect("a valid root");
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("the default versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    let mut connection = ClientConnection::new(
+        Arc::new(config),
+        ServerName::try_from(name.to_string()).expect("a valid name"),
+    )
+    .expect("a client connection");
+    let mut hello = Vec::new();
+    connection.write_tls(&mut hello).expect("the ClientHello");
+    hello
+}
+
+/// An exact name, a wildcard and an unrouted name each reach their own server,
+/// which completes the handshake with the client itself.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn names_pick_their_destination() {
+    install_capturing_logger();
+    let api = TestCert::new("api.lab");
+    let web = TestCert::new("www.web.lab");
+    let other = TestCert::new("other.lab");
+    let (api_addr, mut api_names) = spawn_tls_echo_server(&api, None, &[]).await;
+    let (web_addr, mut web_names) = spawn_tls_echo_server(&web, None, &[]).await;
+    let (other_addr, mut other_names) = spawn_tls_echo_server(&other, None, &[]).await;
+    let proxy_addr = spawn_sni_proxy(
+        Some(other_addr),
+        &[
+            format!("API.lab.={api_addr}"),
+            format!("*.web.lab={web_addr}"),
+        ],
+    )
+    .await;
+
+    let client_addr = echo_as(proxy_addr, &api, "api.lab").await;
+    assert_eq!(api_names.recv().await, Some(Some("api.lab".to_string())));
+    let routed = event(client_addr, "sni_routed").await;
+    assert_eq!(routed["server_name"], "api.lab");
+    assert!(
+        routed["message"]
+            .as_str()
+            .expect("the routing line")
+            .ends_with(&format!("Server name api.lab routed to {api_addr}")),
+        "{routed}"
+    );
+
+    echo_as(proxy_addr, &web, "www.web.lab").await;
+    assert_eq!(
+        web_names.recv().await,
+        Some(Some("www.web.lab".to_string()))
+    );
+
+    let client_addr = echo_as(proxy_addr, &other, "other.lab").await;
+    assert_eq!(
+        other_names.recv().await,
+        Some(Some("other.lab".to_string()))
+    );
+    let routed = event(client_addr, "sni_routed").await;
+    assert!(
+        routed["message"]
+            .as_str()
+            .expect("the routing line")
+            .ends_with(&format!(
+                "Routing to the default destination {other_addr}: server name other.lab matches no SNI route"
+            )),
+        "{routed}"
+    );
+}
+
+/// Without a default destination, a name no route is for gets the fatal
+/// `unrecognized_name` alert, and nothing is connected to.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn unrouted_name_without_a_default_is_rejected() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_sni_proxy(None, &[format!("api.lab={echo_addr}")]).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client
+        .write_all(&client_hello("nowhere.lab"))
+        .await
+        .expect("client write");
+    let mut reply = Vec::new();
+    timeout(IO_TIMEOUT, client.read_to_end(&mut reply))
+        .await
+        .expect("the connection was never closed")
+        .expect("client read");
+    assert_eq!(reply, sni::UNRECOGNIZED_NAME_ALERT);
+
+    let rejected = event(client_addr, "sni_rejected").await;
+    assert_eq!(rejected["server_name"], "nowhere.lab");
+    assert!(
+        rejected["message"]
+            .as_str()
+            .expect("the rejection")
+            .ends_with(
+                "server name nowhere.lab matches no SNI route, and there is no default destination"
+            ),
+        "{rejected}"
+    );
+}
+
+/// A client that is not speaking TLS still reaches the default destination, its
+/// bytes untouched.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn plaintext_goes_to_the_default_destination() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_sni_proxy(Some(echo_addr), &["api.lab=127.0.0.1:9".to_string()]).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client.write_all(b"PING\r\n").await.expect("client write");
+    let mut echo = [0u8; 6];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(&echo, b"PING\r\n");
+
+    let routed = event(client_addr, "sni_routed").await;
+    assert_eq!(routed["server_name"], "");
+    assert!(
+        routed["message"]
+            .as_str()
+            .expect("the routing line")
+            .ends_with("the client sent no TLS hello"),
+        "{routed}"
+    );
+}
+
+/// A client that hangs up without a word, as a load balancer's health check does,
+/// is logged as an ordinary close, not as a rejection.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn client_closing_before_its_hello_is_no_rejection() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_sni_proxy(None, &[format!("api.lab={echo_addr}")]).await;
+
+    let client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    drop(client);
+
+    let closed = event(client_addr, "closed_before_hello").await;
+    assert_eq!(closed["level"], "INFO");
+    assert!(
+        closed["message"]
+            .as_str()
+            .expect("the closing line")
+            .ends_with(&format!(
+                "Connection from {client_addr} closed by the client before its TLS hello"
+            )),
+        "{closed}"
+    );
+    assert!(
+        !captured_events()
+            .iter()
+            .any(|event| event["event"] == "sni_rejected"
+                && event["client"] == client_addr.to_string()),
+        "the close is no rejection"
+    );
+}
+
+/// A client of a protocol in which the server speaks first sends no hello: after
+/// a moment of silence it goes to the default destination, whose banner reaches it.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn silent_client_goes_to_the_default_destination() {
+    install_capturing_logger();
+    let banner_server = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("bind the banner server");
+    let banner_addr = banner_server
+        .local_addr()
+        .expect("banner server local_addr");
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = banner_server.accept().await {
+            tokio::spawn(async move {
+                let _ = stream.write_all(b"220 smtp.lab ESMTP\r\n").await;
+                let mut buffer = [0; 64];
+                let _ = stream.read(&mut buffer).await;
+            });
+        }
+    });
+    let proxy_addr = spawn_sni_proxy(Some(banner_addr), &["api.lab=127.0.0.1:9".to_string()]).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    let mut banner = [0; 20];
+    timeout(IO_TIMEOUT, client.read_exact(&mut banner))
+        .await
+        .expect("the banner never arrived")
+        .expect("client read");
+    assert_eq!(&banner, b"220 smtp.lab ESMTP\r\n");
+
+    let routed = event(client_addr, "sni_routed").await;
+    assert_eq!(routed["server_name"], "");
+    assert!(
+        routed["message"]
+            .as_str()
+            .expect("the routing line")
+            .ends_with(&format!(
+                "Routing to the default destination {banner_addr}: the client sent nothing within 1s"
+            )),
+        "{routed}"
+    );
+}
+
+/// An exact route beats a wildcard, and a longer wildcard a shorter one; a
+/// wildcard is for subdomains, not the domain itself.
+#[test]
+fn exact_and_longest_wildcard_win() {
+    let routes: Vec<SniRoute> = [
+        "*.lab=127.0.0.1:1",
+        "*.api.lab=127.0.0.1:2",
+        "v1.api.lab=127.0.0.1:3",
+    ]
+    .iter()
+    .map(|route| route.parse().expect("a valid SNI route"))
+    .collect();
+    let pick = |name| sni::pick(&routes, name).map(ToString::to_string);
+
+    assert_eq!(pick("v1.api.lab").as_deref(), Some("127.0.0.1:3"));
+    assert_eq!(pick("V2.Api.Lab.").as_deref(), Some("127.0.0.1:2"));
+    assert_eq!(pick("api.lab").as_deref(), Some("127.0.0.1:1"));
+    assert_eq!(pick("lab"), None);
+    assert_eq!(pick("example.com"), None);
+    assert_eq!(
+        sni::describe(&routes),
+        "[*.lab=127.0.0.1:1, *.api.lab=127.0.0.1:2, v1.api.lab=127.0.0.1:3]"
+    );
+    assert_eq!(sni::describe(&[]), "none");
+}
+
+/// Routes that name no host, or no destination, are rejected with the reason.
+#[test]
+fn invalid_routes_are_rejected() {
+    for (invalid, reason) in [
+        ("api.lab", "expected `NAME=REMOTE`"),
+        ("*=127.0.0.1:9", "`*` is not a name"),
+        ("*.*.lab=127.0.0.1:9", "is not a host name"),
+        ("two words=127.0.0.1:9", "is not a host name"),
+        ("=127.0.0.1:9", "is not a host name"),
+        ("api.lab=", "invalid SNI route `api.lab=`: "),
+    ] {
+        let error = invalid
+            .parse::<SniRoute>()
+            .expect_err("an invalid SNI route");
+        assert!(error.contains(reason), "{invalid:?}: {error}");
+    }
+}
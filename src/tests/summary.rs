@@ -0,0 +1,181 @@
+//! The closing summary every relayed connection logs last: its duration, the bytes
+//! and chunks each way, which side closed first, whether the idle timeout fired,
+//! and how long the destination took to answer.
+//!
+//! The summary is looked up by the test's own client and destination addresses —
+//! the pair, since a client port freed by one test may be handed to another's —
+//! in the `--output-format jsonl` rendering of the record, whose `message` is the
+//! console line.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_with_timeout;
+use super::log_capture::captured_events;
+use super::log_capture::captured_lines;
+use super::log_capture::install_capturing_logger;
+use serde_json::Value;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// Wait for the `closed` event of the connection from `client_addr` to
+/// `destination`, and return it with its console line.
+async fn summary(client_addr: SocketAddr, destination: SocketAddr) -> (Value, String) {
+    let deadline = Instant::now() + IO_TIMEOUT;
+    loop {
+        let event = captured_events().into_iter().find(|event| {
+            event["event"] == "closed"
+                && event["client"] == client_addr.to_string()
+                && event["destination"] == destination.to_string()
+        });
+        if let Some(event) = event {
+            let line = event["message"]
+                .as_str()
+                .expect("the summary's console line")
+                .to_string();
+            assert!(
+                line.starts_with(&format!("[#1] Connection from {client_addr} closed after ")),
+                "{line}"
+            );
+            return (event, line);
+        }
+        assert!(
+            Instant::now() < deadline,
+            "no summary for {client_addr}; captured: {:?}",
+            captured_lines()
+        );
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// The fields of a summary that do not depend on timing.
+fn counts(event: &Value) -> Value {
+    json!({
+        "client_bytes": event["client_bytes"],
+        "client_chunks": event["client_chunks"],
+        "destination_bytes": event["destination_bytes"],
+        "destination_chunks": event["destination_chunks"],
+        "closed_first": event["closed_first"],
+        "idle_timeout": event["idle_timeout"],
+        "shutdown": event["shutdown"],
+    })
+}
+
+/// A client that closes after two round trips: the summary counts each chunk,
+/// names the client as the side that closed, and is computed without a
+/// `--timeout`.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn summary_counts_traffic_and_the_closing_side() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_proxy_with_timeout(echo_addr, None).await;
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"hello").await;
+    assert_round_trip(&mut client, b"world!").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    drop(client);
+
+    let (event, line) = summary(client_addr, echo_addr).await;
+    assert_eq!(
+        counts(&event),
+        json!({
+            "client_bytes": 11,
+            "client_chunks": 2,
+            "destination_bytes": 11,
+            "destination_chunks": 2,
+            "closed_first": "client",
+            "idle_timeout": false,
+            "shutdown": false,
+        })
+    );
+    assert!(event["duration_us"].as_u64().is_some(), "{event}");
+    assert!(event["connect_us"].as_u64().is_some(), "{event}");
+    assert!(
+        line.contains(
+            "s (the client closed first): 11 bytes in 2 chunk(s) from the client, 11 bytes in 2 chunk(s) from the destination, connected in "
+        ),
+        "{line}"
+    );
+}
+
+/// A destination that answers and hangs up is named as the side that closed,
+/// however long the client keeps its end open afterwards.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn summary_names_a_destination_that_closes_first() {
+    install_capturing_logger();
+    let server = TcpListener::bind(LOOPBACK).await.expect("bind the server");
+    let server_addr = server.local_addr().expect("server local_addr");
+    tokio::spawn(async move {
+        let (mut stream, _) = server.accept().await.expect("accept the proxy");
+        let _ = stream.write_all(b"bye").await;
+    });
+    let proxy_addr = spawn_proxy_with_timeout(server_addr, None).await;
+    let mut client = connect(proxy_addr).await;
+    let mut received = Vec::new();
+    timeout(IO_TIMEOUT, client.read_to_end(&mut received))
+        .await
+        .expect("the destination's close should reach the client")
+        .expect("read failed");
+    assert_eq!(received, b"bye");
+    let client_addr = client.local_addr().expect("client local_addr");
+    sleep(Duration::from_millis(100)).await;
+    drop(client);
+
+    let (event, line) = summary(client_addr, server_addr).await;
+    assert_eq!(
+        counts(&event),
+        json!({
+            "client_bytes": 0,
+            "client_chunks": 0,
+            "destination_bytes": 3,
+            "destination_chunks": 1,
+            "closed_first": "destination",
+            "idle_timeout": false,
+            "shutdown": false,
+        })
+    );
+    assert!(
+        line.contains("s (the destination closed first): 0 bytes in 0 chunk(s) from the client"),
+        "{line}"
+    );
+}
+
+/// When the idle timeout closes the connection, the summary says so and names no
+/// closing side.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn summary_reports_the_idle_timeout() {
+    install_capturing_logger();
+    let echo_addr = spawn_echo_server().await;
+    let proxy_addr = spawn_proxy_with_timeout(echo_addr, Some(1)).await;
+    let mut client = connect(proxy_addr).await;
+    assert_round_trip(&mut client, b"ping").await;
+    let client_addr = client.local_addr().expect("client local_addr");
+
+    let (event, line) = summary(client_addr, echo_addr).await;
+    assert_eq!(
+        counts(&event),
+        json!({
+            "client_bytes": 4,
+            "client_chunks": 1,
+            "destination_bytes": 4,
+            "destination_chunks": 1,
+            "closed_first": null,
+            "idle_timeout": true,
+            "shutdown": false,
+        })
+    );
+    assert!(event["duration_us"].as_u64() >= Some(1_000_000), "{event}");
+    assert!(
+        line.contains("s (the idle timeout fired): 4 bytes"),
+        "{line}"
+    );
+}
@@ -0,0 +1,461 @@
+//! `--tls-cert`/`--tls-key`: the proxy completes the client's handshake with a
+//! self-signed certificate minted for the test, relays and logs the payload
+//! decrypted, and turns away a client that fails the handshake, logging it, before
+//! the destination is dialed.
+//!
+//! `--remote-tls`: the proxy dials a TLS echo server on behalf of a plaintext
+//! client, checking the server's certificate against `--remote-ca` (or not at all
+//! with `--remote-insecure`), sending `--remote-sni` and presenting a
+//! `--remote-client-cert`; a server it cannot trust fails the connect.
+
+use super::capture::finished_capture;
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::TEST_MAX_CONNECTIONS;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_proxy_configured;
+use super::helpers::temp_path;
+use super::log_capture::event;
+use super::log_capture::install_capturing_logger;
+use crate::args::Arguments;
+use crate::tls;
+use rustls::ClientConfig;
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::ServerName;
+use rustls::pki_types::pem::PemObject;
+use rustls::server::WebPkiClientVerifier;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::TlsConnector;
+
+/// A self-signed certificate for one name, written as PEM files.
+pub(super) struct TestCert {
+    pub(super) cert_path: PathBuf,
+    pub(super) key_path: PathBuf,
+    pub(super) der: CertificateDer<'static>,
+}
+
+impl TestCert {
+    pub(super) fn new(name: &str) -> Self {
+        let minted = rcgen::generate_simple_self_signed(vec![name.to_string()])
+            .expect("a self-signed certificate");
+        Self::write(&minted.cert, &minted.signing_key)
+    }
+
+    /// A self-signed CA certificate named `name`.
+    pub(super) fn ca(name: &str) -> Self {
+        let mut params = rcgen::CertificateParams::default();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            rcgen::KeyUsagePurpose::KeyCertSign,
+            rcgen::KeyUsagePurpose::DigitalSignature,
+        ];
+        let key = rcgen::KeyPair::generate().expect("a CA key");
+        let cert = params.self_signed(&key).expect("a CA certificate");
+        Self::write(&cert, &key)
+    }
+
+    fn write(cert: &rcgen::Certificate, key: &rcgen::KeyPair) -> Self {
+        let cert_path = temp_path("tls-cert");
+        let key_path = temp_path("tls-key");
+        fs::write(&cert_path, cert.pem()).expect("write the certificate");
+        fs::write(&key_path, key.serialize_pem()).expect("write the key");
+        Self {
+            cert_path,
+            key_path,
+            der: cert.der().clone(),
+        }
+    }
+}
+
+impl Drop for TestCert {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.cert_path);
+        let _ = fs::remove_file(&self.key_path);
+    }
+}
+
+/// Spawn a proxy to `remote_addr` terminating TLS with `cert`, capturing to a
+/// fresh directory, which is returned along with the proxy's address.
+async fn spawn_tls_proxy(remote_addr: SocketAddr, cert: &TestCert) -> (SocketAddr, PathBuf) {
+    let directory = temp_path("tls-capture");
+    let capture_dir = directory.clone();
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.tls_cert = Some(cert.cert_path.clone());
+            arguments.tls_key = Some(cert.key_path.clone());
+            arguments.capture_dir = Some(capture_dir);
+        },
+    )
+    .await;
+    (proxy_addr, directory)
+}
+
+/// A TLS client of `addr` that trusts `cert` alone, asking for `localhost`.
+async fn tls_connect(
+    addr: SocketAddr,
+    cert: &TestCert,
+) -> (
+    SocketAddr,
+    tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
+) {
+    tls_connect_to(addr, cert, "localhost", &[]).await
+}
+
+/// A TLS client of `addr` that trusts `root` alone, asking for `name` (a DNS name,
+/// or an IP address for no SNI at all) and offering the `alpn` protocols.
+pub(super) async fn tls_connect_to(
+    addr: SocketAddr,
+    root: &TestCert,
+    name: &str,
+    alpn: &[&str],
+) -> (
+    SocketAddr,
+    tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
+) {
+    let mut roots = RootCertStore::empty();
+    roots.add(root.der.clone()).expect("a valid root");
+    let mut config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("the default versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    config.alpn_protocols = alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    let stream = connect(addr).await;
+    let client_addr = stream.local_addr().expect("client local_addr");
+    let server_name = ServerName::try_from(name.to_string()).expect("a valid name");
+    let stream = timeout(
+        IO_TIMEOUT,
+        TlsConnector::from(Arc::new(config)).connect(server_name, stream),
+    )
+    .await
+    .expect("the handshake timed out")
+    .expect("the handshake failed");
+    (client_addr, stream)
+}
+
+/// A TLS client's request reaches a plain destination decrypted and its echo comes
+/// back encrypted; the session is logged, and the capture file holds the plaintext.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn tls_client_is_relayed_in_plaintext() {
+    install_capturing_logger();
+    let cert = TestCert::new("localhost");
+    let echo_addr = spawn_echo_server().await;
+    let (proxy_addr, directory) = spawn_tls_proxy(echo_addr, &cert).await;
+
+    let (client_addr, mut client) = tls_connect(proxy_addr, &cert).await;
+    client.write_all(b"hello").await.expect("client write");
+    let mut echo = [0u8; 5];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(&echo, b"hello");
+    let _ = client.shutdown().await;
+    drop(client);
+
+    let established = event(client_addr, "tls_established").await;
+    assert_eq!(established["tls_version"], "TLS 1.3");
+    assert_eq!(established["server_name"], "localhost");
+    assert!(
+        established["message"]
+            .as_str()
+            .expect("the established line")
+            .contains(&format!(
+                "TLS established with client {client_addr}: TLS 1.3, "
+            )),
+        "{established}"
+    );
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+    assert!(content.contains(" < 68:65:6c:6c:6f\n"), "{content}");
+    assert!(content.contains(" > 68:65:6c:6c:6f\n"), "{content}");
+}
+
+/// A client speaking plaintext to a TLS listener fails the handshake: it is logged
+/// and the connection closed, and the destination is never dialed.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn failed_handshake_never_reaches_the_destination() {
+    install_capturing_logger();
+    let cert = TestCert::new("localhost");
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let remote_addr = listener.local_addr().expect("remote local_addr");
+    let (proxy_addr, directory) = spawn_tls_proxy(remote_addr, &cert).await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client
+        .write_all(b"GET / HTTP/1.0\r\n\r\n")
+        .await
+        .expect("client write");
+    let mut answer = Vec::new();
+    // The proxy answers the garbage with an alert, then closes.
+    let _ = timeout(IO_TIMEOUT, client.read_to_end(&mut answer))
+        .await
+        .expect("the connection was never closed");
+
+    let failed = event(client_addr, "tls_handshake_failed").await;
+    assert_eq!(failed["level"], "ERROR");
+    assert!(
+        failed["message"]
+            .as_str()
+            .expect("the failure's line")
+            .contains(&format!("TLS handshake with client {client_addr} failed: ")),
+        "{failed}"
+    );
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+    assert!(
+        content.contains("# close reason: TLS handshake failed: "),
+        "{content}"
+    );
+    assert!(
+        timeout(Duration::from_millis(200), listener.accept())
+            .await
+            .is_err(),
+        "the destination was dialed"
+    );
+}
+
+/// A certificate or key that cannot be loaded names its file.
+#[test]
+fn unreadable_certificate_names_its_file() {
+    let cert = TestCert::new("localhost");
+    let missing = Path::new("/nonexistent/cert.pem");
+    let error = match tls::acceptor(missing, &cert.key_path) {
+        Ok(_) => panic!("a missing certificate is rejected"),
+        Err(error) => error.to_string(),
+    };
+    assert!(error.starts_with("/nonexistent/cert.pem: "), "{error}");
+    // A key is not a certificate.
+    let error = match tls::acceptor(&cert.key_path, &cert.key_path) {
+        Ok(_) => panic!("a file without a certificate is rejected"),
+        Err(error) => error.to_string(),
+    };
+    assert!(error.ends_with(": no certificate in the file"), "{error}");
+}
+
+/// A TLS echo server with `cert` that, given a `client_ca`, demands a client
+/// certificate it signed, and picks the first of the `alpn` protocols a client
+/// offers. It reports the server name each client asked for.
+pub(super) async fn spawn_tls_echo_server(
+    cert: &TestCert,
+    client_ca: Option<&TestCert>,
+    alpn: &[&str],
+) -> (SocketAddr, mpsc::UnboundedReceiver<Option<String>>) {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("the default versions");
+    let builder = match client_ca {
+        None => builder.with_no_client_auth(),
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            roots.add(client_ca.der.clone()).expect("a valid root");
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .expect("a client verifier");
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    let key = PrivateKeyDer::from_pem_file(&cert.key_path).expect("the server key");
+    let mut config = builder
+        .with_single_cert(vec![cert.der.clone()], key)
+        .expect("the server certificate");
+    config.alpn_protocols = alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind(LOOPBACK)
+        .await
+        .expect("failed to bind remote");
+    let addr = listener.local_addr().expect("remote local_addr");
+    let (names, received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            let names = names.clone();
+            tokio::spawn(async move {
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let _ = names.send(stream.get_ref().1.server_name().map(str::to_string));
+                let (mut reader, mut writer) = tokio::io::split(stream);
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                let _ = writer.shutdown().await;
+            });
+        }
+    });
+    (addr, received)
+}
+
+/// Spawn a proxy to `remote_addr` with `--remote-tls`, configured further by
+/// `configure` and capturing to a fresh directory, which is returned along with
+/// the proxy's address.
+async fn spawn_remote_tls_proxy(
+    remote_addr: SocketAddr,
+    configure: impl FnOnce(&mut Arguments),
+) -> (SocketAddr, PathBuf) {
+    let directory = temp_path("remote-tls-capture");
+    let capture_dir = directory.clone();
+    let proxy_addr = spawn_proxy_configured(
+        remote_addr,
+        Some(IO_TIMEOUT.as_secs()),
+        TEST_MAX_CONNECTIONS,
+        |arguments| {
+            arguments.remote_tls = true;
+            arguments.capture_dir = Some(capture_dir);
+            configure(arguments);
+        },
+    )
+    .await;
+    (proxy_addr, directory)
+}
+
+/// Send `request` through the proxy at `proxy_addr` in plaintext and check that it
+/// is echoed back; return the client's address.
+async fn echo_through(proxy_addr: SocketAddr, request: &[u8]) -> SocketAddr {
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    client.write_all(request).await.expect("client write");
+    let mut echo = vec![0u8; request.len()];
+    timeout(IO_TIMEOUT, client.read_exact(&mut echo))
+        .await
+        .expect("the echo never arrived")
+        .expect("client read");
+    assert_eq!(echo, request);
+    client_addr
+}
+
+/// A plaintext client reaches a TLS destination trusted through `--remote-ca`,
+/// by its IP address: the session is logged, and the capture file holds the
+/// plaintext both ways.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn plaintext_client_reaches_a_tls_destination() {
+    install_capturing_logger();
+    let cert = TestCert::new("127.0.0.1");
+    let (remote_addr, mut names) = spawn_tls_echo_server(&cert, None, &[]).await;
+    let (proxy_addr, directory) = spawn_remote_tls_proxy(remote_addr, |arguments| {
+        arguments.remote_ca = Some(cert.cert_path.clone());
+    })
+    .await;
+
+    let client_addr = echo_through(proxy_addr, b"secret").await;
+    // No server name is sent for an IP address.
+    assert_eq!(names.recv().await, Some(None));
+
+    let established = event(client_addr, "tls_established").await;
+    assert_eq!(established["stream"], "destination");
+    assert_eq!(established["tls_version"], "TLS 1.3");
+    assert!(
+        established["message"]
+            .as_str()
+            .expect("the established line")
+            .contains(&format!(
+                "TLS established with destination {remote_addr}: TLS 1.3, "
+            )),
+        "{established}"
+    );
+    let (_, content) = finished_capture(&directory, client_addr).await;
+    let _ = fs::remove_dir_all(&directory);
+    assert!(content.contains(" < 73:65:63:72:65:74\n"), "{content}");
+    assert!(content.contains(" > 73:65:63:72:65:74\n"), "{content}");
+}
+
+/// A destination whose certificate `--remote-ca` does not vouch for fails the
+/// connect: it is logged, and the client connection closed.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn untrusted_destination_fails_the_connect() {
+    install_capturing_logger();
+    let cert = TestCert::new("127.0.0.1");
+    let other = TestCert::new("127.0.0.1");
+    let (remote_addr, _names) = spawn_tls_echo_server(&cert, None, &[]).await;
+    let (proxy_addr, directory) = spawn_remote_tls_proxy(remote_addr, |arguments| {
+        arguments.remote_ca = Some(other.cert_path.clone());
+    })
+    .await;
+
+    let mut client = connect(proxy_addr).await;
+    let client_addr = client.local_addr().expect("client local_addr");
+    let mut answer = Vec::new();
+    timeout(IO_TIMEOUT, client.read_to_end(&mut answer))
+        .await
+        .expect("the connection was never closed")
+        .expect("client read");
+    assert!(answer.is_empty());
+
+    let failed = event(client_addr, "connect_failed").await;
+    assert_eq!(failed["level"], "ERROR");
+    assert!(
+        failed["message"]
+            .as_str()
+            .expect("the failure's line")
+            .contains(&format!(
+                "Failed to connect to destination {remote_addr}: TLS handshake failed: "
+            )),
+        "{failed}"
+    );
+    let _ = fs::remove_dir_all(&directory);
+}
+
+/// `--remote-insecure` takes any certificate, here one for another name that
+/// nothing vouches for.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn insecure_destination_is_relayed() {
+    let cert = TestCert::new("localhost");
+    let (remote_addr, _names) = spawn_tls_echo_server(&cert, None, &[]).await;
+    let (proxy_addr, directory) = spawn_remote_tls_proxy(remote_addr, |arguments| {
+        arguments.remote_insecure = true;
+    })
+    .await;
+
+    echo_through(proxy_addr, b"hello").await;
+    let _ = fs::remove_dir_all(&directory);
+}
+
+/// `--remote-sni` is the name sent and checked against the certificate, and a
+/// destination demanding a client certificate gets `--remote-client-cert`.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn sni_and_client_certificate_reach_the_destination() {
+    let cert = TestCert::new("device.lab");
+    let client_cert = TestCert::new("proxy.lab");
+    let (remote_addr, mut names) = spawn_tls_echo_server(&cert, Some(&client_cert), &[]).await;
+    let (proxy_addr, directory) = spawn_remote_tls_proxy(remote_addr, |arguments| {
+        arguments.remote_ca = Some(cert.cert_path.clone());
+        arguments.remote_sni = Some("device.lab".to_string());
+        arguments.remote_client_cert = Some(client_cert.cert_path.clone());
+        arguments.remote_client_key = Some(client_cert.key_path.clone());
+    })
+    .await;
+
+    echo_through(proxy_addr, b"hello").await;
+    assert_eq!(names.recv().await, Some(Some("device.lab".to_string())));
+    let _ = fs::remove_dir_all(&directory);
+}
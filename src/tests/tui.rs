@@ -0,0 +1,390 @@
+//! `--tui`: the view's state, built from the records its logger is handed and the
+//! chunks the relays feed it, drawn on an in-memory backend instead of a terminal.
+//!
+//! The records are built like `ConnLog` builds its lines — an `event`, the
+//! `conn_id`, `client` and `destination`, then the line's own key-values — and go
+//! through [`TuiLogger`] itself, so its filtering is covered along the way.
+
+use super::helpers::IO_TIMEOUT;
+use super::helpers::LOOPBACK;
+use super::helpers::Shared;
+use super::helpers::assert_round_trip;
+use super::helpers::connect;
+use super::helpers::spawn_echo_server;
+use super::helpers::spawn_route;
+use super::helpers::test_arguments;
+use crate::args::PayloadFormattingKind;
+use crate::conn::Direction;
+use crate::conn::Sinks;
+use crate::tui::App;
+use crate::tui::Feed;
+use crate::tui::RECENTLY_CLOSED;
+use crate::tui::TuiLogger;
+use crate::tui::Update;
+use crate::tui::View;
+use log::Log;
+use ratatui::Terminal;
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::KeyCode;
+use ratatui::crossterm::event::KeyEvent;
+use ratatui::crossterm::event::KeyModifiers;
+use std::sync::mpsc;
+use std::time::Instant;
+use tokio::net::TcpListener;
+
+const CLIENT: &str = "127.0.0.1:50000";
+const DESTINATION: &str = "127.0.0.1:9100";
+
+/// A view showing the payload in lower hex, and a logger feeding it at `level`.
+fn view(level: log::LevelFilter) -> (App, TuiLogger, Feed, mpsc::Receiver<Update>) {
+    let (feed, updates) = Feed::channel();
+    let filter = env_logger::Builder::new().filter_level(level).build();
+    let logger = TuiLogger::new(feed.clone(), filter, false);
+    let app = App::new(View {
+        formatting: PayloadFormattingKind::LowerHex,
+        separator: ":".to_string(),
+        hexdump_width: 16,
+    });
+    (app, logger, feed, updates)
+}
+
+/// Log one of connection `conn_id`'s lines through `logger`, with `fields` after
+/// the ones every connection line carries.
+fn log_event(
+    logger: &TuiLogger,
+    level: log::Level,
+    conn_id: u64,
+    event: &str,
+    fields: &[(&str, &str)],
+    message: &str,
+) {
+    let conn_id = conn_id.to_string();
+    let mut key_values = vec![
+        ("event", event),
+        ("conn_id", conn_id.as_str()),
+        ("client", CLIENT),
+        ("destination", DESTINATION),
+    ];
+    key_values.extend_from_slice(fields);
+    logger.log(
+        &log::Record::builder()
+            .level(level)
+            .key_values(&key_values)
+            .args(format_args!("[#{conn_id}] {message}"))
+            .build(),
+    );
+}
+
+/// Apply every update sent so far.
+fn apply(app: &mut App, updates: &mpsc::Receiver<Update>) {
+    for update in updates.try_iter() {
+        app.receive(update);
+    }
+}
+
+/// The view as drawn on a 120x30 screen, one string per row.
+fn draw(app: &App) -> Vec<String> {
+    let mut terminal = Terminal::new(TestBackend::new(120, 30)).expect("a test terminal");
+    terminal
+        .draw(|frame| app.draw(frame))
+        .expect("drawing succeeds");
+    let buffer = terminal.backend().buffer();
+    buffer
+        .content()
+        .chunks(buffer.area.width as usize)
+        .map(|row| row.iter().map(|cell| cell.symbol()).collect())
+        .collect()
+}
+
+fn contains(screen: &[String], text: &str) -> bool {
+    screen.iter().any(|row| row.contains(text))
+}
+
+fn press(app: &mut App, code: KeyCode) -> bool {
+    app.key(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+/// A connection's events move its row from connecting to closed, its chunks add
+/// up in the byte columns, and the proxy's own lines go to the log pane.
+#[test]
+fn events_drive_the_connection_table() {
+    let (mut app, logger, feed, updates) = view(log::LevelFilter::Info);
+    logger.log(
+        &log::Record::builder()
+            .level(log::Level::Info)
+            .key_values(&[("event", "listening")])
+            .args(format_args!("Listener bound to 127.0.0.1:9000"))
+            .build(),
+    );
+    log_event(&logger, log::Level::Info, 1, "accepted", &[], "Incoming");
+    apply(&mut app, &updates);
+    let screen = draw(&app);
+    assert!(
+        contains(&screen, "Listener bound to 127.0.0.1:9000"),
+        "{screen:#?}"
+    );
+    assert!(contains(&screen, CLIENT), "{screen:#?}");
+    assert!(contains(&screen, "connecting"), "{screen:#?}");
+
+    feed.payload(1, Direction::ClientToDestination, b"hello");
+    feed.payload(1, Direction::DestinationToClient, b"hello, you");
+    apply(&mut app, &updates);
+    let screen = draw(&app);
+    assert!(
+        contains(&screen, "1 open, 0 recently closed"),
+        "{screen:#?}"
+    );
+    let row = screen
+        .iter()
+        .find(|row| row.contains(CLIENT))
+        .expect("the connection's row");
+    let columns: Vec<&str> = row.split_whitespace().collect();
+    assert!(
+        columns
+            .windows(3)
+            .any(|window| window == ["5", "10", "open"]),
+        "{row}"
+    );
+
+    log_event(
+        &logger,
+        log::Level::Info,
+        1,
+        "closed",
+        &[
+            ("idle_timeout", "false"),
+            ("shutdown", "false"),
+            ("admin_close", "false"),
+            ("closed_first", "client"),
+        ],
+        "Connection closed",
+    );
+    apply(&mut app, &updates);
+    let screen = draw(&app);
+    assert!(contains(&screen, "closed (client closed)"), "{screen:#?}");
+    assert!(
+        contains(&screen, "0 open, 1 recently closed"),
+        "{screen:#?}"
+    );
+    // A connection's lifecycle lines stay out of the log pane.
+    assert!(!contains(&screen, "[#1] Connection closed"), "{screen:#?}");
+}
+
+/// `--level` decides what reaches the log pane, but the table gets the connection
+/// events it is built from whatever the level, and a connection's warnings and
+/// errors are shown.
+#[test]
+fn level_filters_the_log_pane_only() {
+    let (mut app, logger, _feed, updates) = view(log::LevelFilter::Error);
+    log_event(&logger, log::Level::Info, 7, "accepted", &[], "Incoming");
+    log_event(
+        &logger,
+        log::Level::Error,
+        7,
+        "connect_failed",
+        &[],
+        "Failed to connect to destination",
+    );
+    log_event(&logger, log::Level::Debug, 7, "half_close", &[], "Shutdown");
+    apply(&mut app, &updates);
+    let screen = draw(&app);
+    assert!(contains(&screen, "closed (connect failed)"), "{screen:#?}");
+    assert!(
+        contains(&screen, "ERROR [#7] Failed to connect to destination"),
+        "{screen:#?}"
+    );
+    assert!(!contains(&screen, "[#7] Incoming"), "{screen:#?}");
+}
+
+/// The payload pane shows the selected connection's chunks in the chosen
+/// formatting, switched with `f`, and in the chosen directions, with `d`.
+#[test]
+fn payload_pane_switches_formatting_and_direction() {
+    let (mut app, logger, feed, updates) = view(log::LevelFilter::Info);
+    log_event(&logger, log::Level::Info, 1, "accepted", &[], "Incoming");
+    feed.payload(1, Direction::ClientToDestination, &[0xab, 0xcd]);
+    feed.payload(1, Direction::DestinationToClient, &[0xef]);
+    apply(&mut app, &updates);
+    let screen = draw(&app);
+    assert!(
+        contains(&screen, "#1 payload: lowerhex, both directions"),
+        "{screen:#?}"
+    );
+    assert!(
+        contains(&screen, "< 2 bytes from the client"),
+        "{screen:#?}"
+    );
+    assert!(contains(&screen, "ab:cd"), "{screen:#?}");
+    assert!(
+        contains(&screen, "> 1 bytes from the destination"),
+        "{screen:#?}"
+    );
+
+    // The next formatting after `lowerhex` is `upperhex`, applied to what was
+    // already relayed.
+    assert!(!press(&mut app, KeyCode::Char('f')));
+    let screen = draw(&app);
+    assert!(contains(&screen, "#1 payload: upperhex"), "{screen:#?}");
+    assert!(contains(&screen, "AB:CD"), "{screen:#?}");
+    assert!(!contains(&screen, "ab:cd"), "{screen:#?}");
+
+    press(&mut app, KeyCode::Char('d'));
+    let screen = draw(&app);
+    assert!(contains(&screen, "client to destination"), "{screen:#?}");
+    assert!(contains(&screen, "AB:CD"), "{screen:#?}");
+    assert!(!contains(&screen, "> 1 bytes"), "{screen:#?}");
+    press(&mut app, KeyCode::Char('d'));
+    let screen = draw(&app);
+    assert!(!contains(&screen, "AB:CD"), "{screen:#?}");
+    assert!(
+        contains(&screen, "> 1 bytes from the destination"),
+        "{screen:#?}"
+    );
+}
+
+/// While paused, the view stays as it was; resuming applies what it held.
+#[test]
+fn pause_holds_updates_until_resumed() {
+    let (mut app, logger, feed, updates) = view(log::LevelFilter::Info);
+    log_event(&logger, log::Level::Info, 1, "accepted", &[], "Incoming");
+    apply(&mut app, &updates);
+    press(&mut app, KeyCode::Char('p'));
+    feed.payload(1, Direction::ClientToDestination, b"late");
+    log_event(&logger, log::Level::Info, 2, "accepted", &[], "Incoming");
+    apply(&mut app, &updates);
+    let screen = draw(&app);
+    assert!(contains(&screen, "PAUSED, 2 update(s) held"), "{screen:#?}");
+    assert!(contains(&screen, "Nothing relayed yet"), "{screen:#?}");
+    assert!(contains(&screen, "1 open"), "{screen:#?}");
+
+    press(&mut app, KeyCode::Char(' '));
+    let screen = draw(&app);
+    assert!(!contains(&screen, "PAUSED"), "{screen:#?}");
+    assert!(contains(&screen, "6c:61:74:65"), "{screen:#?}");
+    assert!(contains(&screen, "2 open"), "{screen:#?}");
+}
+
+/// Closed connections stay listed until [`RECENTLY_CLOSED`] newer ones closed;
+/// the selection then moves on to a connection still listed.
+#[test]
+fn closed_connections_are_kept_up_to_the_limit() {
+    let (mut app, logger, _feed, updates) = view(log::LevelFilter::Info);
+    let total = RECENTLY_CLOSED as u64 + 3;
+    for conn_id in 1..=total {
+        log_event(
+            &logger,
+            log::Level::Info,
+            conn_id,
+            "accepted",
+            &[],
+            "Incoming",
+        );
+        log_event(
+            &logger,
+            log::Level::Info,
+            conn_id,
+            "connected",
+            &[],
+            "Connected",
+        );
+        log_event(
+            &logger,
+            log::Level::Info,
+            conn_id,
+            "closed",
+            &[("closed_first", "destination")],
+            "Connection closed",
+        );
+    }
+    log_event(
+        &logger,
+        log::Level::Info,
+        total + 1,
+        "accepted",
+        &[],
+        "Incoming",
+    );
+    apply(&mut app, &updates);
+    let screen = draw(&app);
+    assert!(
+        contains(
+            &screen,
+            &format!("1 open, {RECENTLY_CLOSED} recently closed")
+        ),
+        "{screen:#?}"
+    );
+    // Connection #1 was selected first; it is gone, so #4 is.
+    assert!(contains(&screen, " #4 payload"), "{screen:#?}");
+
+    press(&mut app, KeyCode::Down);
+    let screen = draw(&app);
+    assert!(contains(&screen, " #5 payload"), "{screen:#?}");
+    press(&mut app, KeyCode::Up);
+    press(&mut app, KeyCode::Up);
+    let screen = draw(&app);
+    assert!(contains(&screen, " #4 payload"), "{screen:#?}");
+}
+
+/// `q`, `Esc` and `Ctrl-C` ask for a shutdown, and the status line says a second
+/// one forces it.
+#[test]
+fn quit_keys_ask_for_a_shutdown() {
+    let (mut app, ..) = view(log::LevelFilter::Info);
+    assert!(press(&mut app, KeyCode::Char('q')));
+    assert!(press(&mut app, KeyCode::Esc));
+    assert!(app.key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+    assert!(!press(&mut app, KeyCode::Char('c')));
+    let screen = draw(&app);
+    assert!(
+        contains(
+            &screen,
+            "Shutting down: q again closes the remaining connections"
+        ),
+        "{screen:#?}"
+    );
+}
+
+/// The relays of a real route hand every chunk they write on to the feed, tagged
+/// with the connection and its direction.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn relays_feed_the_payload_pane() {
+    let echo_addr = spawn_echo_server().await;
+    let listener = TcpListener::bind(LOOPBACK).await.expect("bind the route");
+    let listen_addr = listener.local_addr().expect("route local_addr");
+    let arguments = test_arguments(listen_addr, echo_addr, None, 8);
+    let (feed, updates) = Feed::channel();
+    let mut sinks = Sinks::open(&arguments).expect("failed to open the proxy's sinks");
+    sinks.feed_tui(feed);
+    let route = arguments.routes().expect("valid routes").remove(0);
+    spawn_route(listener, arguments, route, sinks, Shared::default());
+
+    let mut client = connect(listen_addr).await;
+    assert_round_trip(&mut client, b"ping").await;
+    // The feed is a plain channel: wait on it off the runtime's workers.
+    let chunks = tokio::task::spawn_blocking(move || {
+        let deadline = Instant::now() + IO_TIMEOUT;
+        let mut chunks = Vec::new();
+        while chunks.len() < 2 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match updates.recv_timeout(remaining) {
+                Ok(Update::Payload {
+                    conn_id,
+                    direction,
+                    bytes,
+                }) => chunks.push((conn_id, direction, bytes)),
+                Ok(Update::Record(_)) => {}
+                Err(error) => panic!("expected two chunks, got {chunks:?}: {error}"),
+            }
+        }
+        chunks
+    })
+    .await
+    .expect("the feed reader panicked");
+    assert_eq!(
+        chunks,
+        [
+            (1, Direction::ClientToDestination, b"ping".to_vec()),
+            (1, Direction::DestinationToClient, b"ping".to_vec()),
+        ]
+    );
+}
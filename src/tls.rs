@@ -0,0 +1,534 @@
+//! `--tls-cert`/`--tls-key`: TLS terminated on the listeners. A client's handshake
+//! is completed by the proxy with the given certificate, and what it sends and
+//! receives is relayed — and logged — decrypted.
+//!
+//! `--remote-tls`: TLS originated towards the destination. The proxy dials the
+//! destination as a TLS client, so a plaintext client, or one whose TLS the
+//! proxy terminates, reaches a TLS-only server and its payload is logged in the
+//! clear.
+//!
+//! `--tls-mitm-ca`/`--tls-mitm-key`: TLS terminated with a certificate [`Mint`]ed
+//! for the name each client asks for, signed by a CA the clients trust. The
+//! client's [`Hello`] is read first, so that the destination can be offered the
+//! same name and protocols before the client's handshake is completed.
+
+use crate::args::Arguments;
+use crate::args::TargetAddr;
+use rustls::ClientConfig;
+use rustls::CommonState;
+use rustls::DigitallySignedStruct;
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::SignatureScheme;
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::client::danger::ServerCertVerified;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::ServerName;
+use rustls::pki_types::UnixTime;
+use rustls::pki_types::pem::PemObject;
+use rustls::server::Acceptor;
+use rustls::sign::CertifiedKey;
+use rustls::sign::SingleCertAndKey;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::time::timeout;
+use tokio_rustls::LazyConfigAcceptor;
+use tokio_rustls::StartHandshake;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::TlsStream;
+
+/// How long a peer has to complete its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn invalid(path: &Path, error: &dyn std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {error}", path.display()),
+    )
+}
+
+/// Every certificate of a PEM file, in order; at least one.
+fn certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| invalid(path, &error))?;
+    if certificates.is_empty() {
+        return Err(invalid(path, &"no certificate in the file"));
+    }
+    Ok(certificates)
+}
+
+/// The private key of a PEM file: PKCS#8, PKCS#1 or SEC1.
+fn private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|error| invalid(path, &error))
+}
+
+/// The acceptor for the `--tls-cert` chain and its `--tls-key`, both PEM files.
+/// The chain starts with the proxy's own certificate; the key may be PKCS#8,
+/// PKCS#1 or SEC1.
+pub(crate) fn acceptor(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+    let chain = certificates(cert)?;
+    let private_key = private_key(key)?;
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| {
+            builder
+                .with_no_client_auth()
+                .with_single_cert(chain, private_key)
+        })
+        .map_err(|error| invalid(key, &error))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Complete a client's handshake on `stream`, within [`HANDSHAKE_TIMEOUT`].
+pub(crate) async fn accept<S>(acceptor: &TlsAcceptor, stream: S) -> io::Result<MaybeTls<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(accepted) => Ok(MaybeTls::Tls(Box::new(TlsStream::Server(accepted?)))),
+        Err(_) => Err(handshake_timed_out()),
+    }
+}
+
+fn handshake_timed_out() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("no handshake within {}s", HANDSHAKE_TIMEOUT.as_secs()),
+    )
+}
+
+/// `--remote-tls` and its options: how the proxy speaks TLS to the destination.
+#[derive(Clone)]
+pub(crate) struct RemoteTls {
+    config: Arc<ClientConfig>,
+    /// `--remote-sni`; by default the name, or the address, of the target.
+    server_name: Option<ServerName<'static>>,
+}
+
+impl RemoteTls {
+    /// The destinations' TLS settings, or `None` without `--remote-tls` or
+    /// `--tls-mitm-ca`. The server's certificate is checked against the
+    /// `--remote-ca` bundle, or the system's trusted roots without one, unless
+    /// `--remote-insecure`.
+    pub(crate) fn new(arguments: &Arguments) -> io::Result<Option<Self>> {
+        if !arguments.originates_tls() {
+            return Ok(None);
+        }
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|error| io::Error::other(error.to_string()))?;
+        let builder = if arguments.remote_insecure {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider())))
+        } else {
+            let mut roots = RootCertStore::empty();
+            match &arguments.remote_ca {
+                Some(path) => {
+                    for certificate in certificates(path)? {
+                        roots
+                            .add(certificate)
+                            .map_err(|error| invalid(path, &error))?;
+                    }
+                }
+                None => {
+                    let native = rustls_native_certs::load_native_certs();
+                    roots.add_parsable_certificates(native.certs);
+                    if roots.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            "no trusted root certificate found on the system; give one with --remote-ca",
+                        ));
+                    }
+                }
+            }
+            builder.with_root_certificates(roots)
+        };
+        let config = match (&arguments.remote_client_cert, &arguments.remote_client_key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(certificates(cert)?, private_key(key)?)
+                .map_err(|error| invalid(key, &error))?,
+            _ => builder.with_no_client_auth(),
+        };
+        let server_name = arguments
+            .remote_sni
+            .as_deref()
+            .map(|name| {
+                ServerName::try_from(name.to_string()).map_err(|error| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("--remote-sni {name}: {error}"),
+                    )
+                })
+            })
+            .transpose()?;
+        Ok(Some(Self {
+            config: Arc::new(config),
+            server_name,
+        }))
+    }
+
+    /// These settings for the destination of a client that sent `hello`: its
+    /// server name, unless `--remote-sni` overrides it, and its ALPN protocols.
+    pub(crate) fn for_hello<S>(&self, hello: &Hello<S>) -> Self {
+        let mut config = ClientConfig::clone(&self.config);
+        config.alpn_protocols = hello.alpn.clone();
+        let server_name = self.server_name.clone().or_else(|| {
+            hello
+                .server_name
+                .clone()
+                .and_then(|name| ServerName::try_from(name).ok())
+        });
+        Self {
+            config: Arc::new(config),
+            server_name,
+        }
+    }
+
+    /// Complete the handshake with the destination `target` on `stream`, within
+    /// [`HANDSHAKE_TIMEOUT`].
+    pub(crate) async fn connect<S>(&self, target: &TargetAddr, stream: S) -> io::Result<MaybeTls<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let server_name = match (&self.server_name, target) {
+            (Some(name), _) => name.clone(),
+            (None, TargetAddr::Socket(addr)) => ServerName::IpAddress(addr.ip().into()),
+            (None, TargetAddr::Named { host, .. }) => {
+                ServerName::try_from(host.clone()).map_err(|error| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{host}: {error}; give a name with --remote-sni"),
+                    )
+                })?
+            }
+        };
+        match timeout(
+            HANDSHAKE_TIMEOUT,
+            TlsConnector::from(self.config.clone()).connect(server_name, stream),
+        )
+        .await
+        {
+            Ok(connected) => Ok(MaybeTls::Tls(Box::new(TlsStream::Client(connected?)))),
+            Err(_) => Err(handshake_timed_out()),
+        }
+    }
+}
+
+/// How long a minted certificate is valid for, from the day before it is minted.
+const MINTED_VALIDITY_DAYS: i64 = 365;
+
+/// How many minted certificates are kept for reuse; past it, the cache starts over.
+const MAX_MINTED: usize = 1024;
+
+/// `--tls-mitm-ca` and its `--tls-mitm-key`: certificates minted on the fly for
+/// the names the clients ask for, signed by the CA. All of them share one key,
+/// generated at startup.
+pub(crate) struct Mint {
+    issuer: rcgen::Issuer<'static, rcgen::KeyPair>,
+    ca: CertificateDer<'static>,
+    key: rcgen::KeyPair,
+    minted: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl Mint {
+    /// Load the CA certificate and its key, PEM files; the key in PKCS#8 form.
+    pub(crate) fn load(ca_cert: &Path, ca_key: &Path) -> io::Result<Self> {
+        let ca = certificates(ca_cert)?.swap_remove(0);
+        let key_pem = std::fs::read_to_string(ca_key).map_err(|error| invalid(ca_key, &error))?;
+        let ca_key_pair =
+            rcgen::KeyPair::from_pem(&key_pem).map_err(|error| invalid(ca_key, &error))?;
+        let issuer = rcgen::Issuer::from_ca_cert_der(&ca, ca_key_pair)
+            .map_err(|error| invalid(ca_cert, &error))?;
+        let key = rcgen::KeyPair::generate().map_err(io::Error::other)?;
+        Ok(Self {
+            issuer,
+            ca,
+            key,
+            minted: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The certificate for `name`, a DNS name or an IP address, with the CA's
+    /// after it; minted the first time it is asked for.
+    fn certificate(&self, name: &str) -> io::Result<Arc<CertifiedKey>> {
+        let mut minted = self
+            .minted
+            .lock()
+            .expect("minted certificates mutex poisoned");
+        if let Some(certified) = minted.get(name) {
+            return Ok(certified.clone());
+        }
+        let mut params =
+            rcgen::CertificateParams::new(vec![name.to_string()]).map_err(|error| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("{name}: {error}"))
+            })?;
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        let today = jiff::Zoned::now().date();
+        let day = |date: jiff::civil::Date| {
+            rcgen::date_time_ymd(i32::from(date.year()), date.month() as u8, date.day() as u8)
+        };
+        params.not_before = day(today.yesterday().unwrap_or(today));
+        params.not_after = day(today
+            .checked_add(jiff::Span::new().days(MINTED_VALIDITY_DAYS))
+            .unwrap_or(today));
+        params.key_usages = vec![rcgen::KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        let certificate = params
+            .signed_by(&self.key, &self.issuer)
+            .map_err(io::Error::other)?;
+        let key = provider()
+            .key_provider
+            .load_private_key(PrivateKeyDer::Pkcs8(self.key.serialize_der().into()))
+            .map_err(io::Error::other)?;
+        let certified = Arc::new(CertifiedKey::new(
+            vec![certificate.der().clone(), self.ca.clone()],
+            key,
+        ));
+        if minted.len() >= MAX_MINTED {
+            minted.clear();
+        }
+        minted.insert(name.to_string(), certified.clone());
+        Ok(certified)
+    }
+}
+
+/// A client's ClientHello, read before its handshake goes on: the server name it
+/// asked for and the ALPN protocols it offered.
+pub(crate) struct Hello<S> {
+    start: StartHandshake<S>,
+    pub(crate) server_name: Option<String>,
+    pub(crate) alpn: Vec<Vec<u8>>,
+}
+
+impl<S> Hello<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Read the client's hello on `stream`, within [`HANDSHAKE_TIMEOUT`].
+    pub(crate) async fn read(stream: S) -> io::Result<Self> {
+        let acceptor = LazyConfigAcceptor::new(Acceptor::default(), stream);
+        let start = match timeout(HANDSHAKE_TIMEOUT, acceptor).await {
+            Ok(start) => start?,
+            Err(_) => return Err(handshake_timed_out()),
+        };
+        let hello = start.client_hello();
+        let server_name = hello.server_name().map(str::to_string);
+        let alpn = hello
+            .alpn()
+            .map(|protocols| protocols.map(<[u8]>::to_vec).collect())
+            .unwrap_or_default();
+        Ok(Self {
+            start,
+            server_name,
+            alpn,
+        })
+    }
+
+    /// Complete the handshake with a certificate from `mint` for the name the
+    /// client asked for, or for `fallback` (the address it dialed) if it named
+    /// none, agreeing on the `alpn` protocol if one is given.
+    pub(crate) async fn complete(
+        self,
+        mint: &Mint,
+        fallback: IpAddr,
+        alpn: Option<&str>,
+    ) -> io::Result<MaybeTls<S>> {
+        let name = self
+            .server_name
+            .clone()
+            .unwrap_or_else(|| fallback.to_string());
+        let mut config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(SingleCertAndKey::from(mint.certificate(&name)?)));
+        config.alpn_protocols = alpn
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .into_iter()
+            .collect();
+        match timeout(HANDSHAKE_TIMEOUT, self.start.into_stream(Arc::new(config))).await {
+            Ok(accepted) => Ok(MaybeTls::Tls(Box::new(TlsStream::Server(accepted?)))),
+            Err(_) => Err(handshake_timed_out()),
+        }
+    }
+}
+
+/// `--remote-insecure`: any certificate is taken for the destination's, though the
+/// handshake's signatures are still checked against it.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// What a peer negotiated: the protocol version, the cipher suite, the ALPN
+/// protocol if any and, for a client, the server name it asked for, if any.
+pub(crate) struct Session {
+    pub(crate) version: &'static str,
+    pub(crate) cipher_suite: String,
+    pub(crate) alpn: Option<String>,
+    pub(crate) server_name: Option<String>,
+}
+
+impl Session {
+    fn new(connection: &CommonState, server_name: Option<&str>) -> Self {
+        let version = match connection.protocol_version() {
+            Some(rustls::ProtocolVersion::TLSv1_3) => "TLS 1.3",
+            Some(rustls::ProtocolVersion::TLSv1_2) => "TLS 1.2",
+            _ => "TLS",
+        };
+        let cipher_suite = connection
+            .negotiated_cipher_suite()
+            .map_or_else(String::new, |suite| format!("{:?}", suite.suite()));
+        Self {
+            version,
+            cipher_suite,
+            alpn: connection
+                .alpn_protocol()
+                .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+            server_name: server_name.map(str::to_string),
+        }
+    }
+}
+
+/// A connection's stream to a peer, as accepted or with TLS on top.
+pub(crate) enum MaybeTls<S> {
+    Plain(S),
+    Tls(Box<TlsStream<S>>),
+}
+
+impl<S> MaybeTls<S> {
+    /// What the peer negotiated; `None` for a plain stream.
+    pub(crate) fn session(&self) -> Option<Session> {
+        match self {
+            MaybeTls::Tls(stream) => Some(match stream.as_ref() {
+                TlsStream::Server(stream) => {
+                    let connection = stream.get_ref().1;
+                    Session::new(connection, connection.server_name())
+                }
+                TlsStream::Client(stream) => Session::new(stream.get_ref().1, None),
+            }),
+            MaybeTls::Plain(_) => None,
+        }
+    }
+
+    /// The stream underneath the TLS, if any.
+    pub(crate) fn get_ref(&self) -> &S {
+        match self {
+            MaybeTls::Plain(stream) => stream,
+            MaybeTls::Tls(stream) => stream.get_ref().0,
+        }
+    }
+}
+
+impl<S> AsyncRead for MaybeTls<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTls::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S> AsyncWrite for MaybeTls<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTls::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTls::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTls::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTls::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,863 @@
+//! `--tui`: an interactive terminal view of the running proxy in place of the
+//! console log — a table of the live and recently closed connections, the payload
+//! of the selected one, and the proxy's own lines underneath.
+//!
+//! The view is built from what the proxy already reports. [`TuiLogger`] stands in
+//! for the console logger and turns the `event`s every connection logs
+//! (`accepted`, `connected`, `closed`, ...) into table updates, reading their
+//! key-values rather than their text; the relays hand their chunks to a [`Feed`],
+//! like they do to `--pcap` and `--capture-dir`, so the payload pane keeps the raw
+//! bytes and can show them again in another formatting. The terminal is drawn from
+//! a thread of its own, which the updates reach over a channel.
+//!
+//! | Key                  | Action                                                 |
+//! | -------------------- | ------------------------------------------------------ |
+//! | `↑` / `↓`, `k` / `j` | Select the previous / next connection                  |
+//! | `PgUp` / `PgDn`      | Scroll the payload pane; `End` follows its tail again  |
+//! | `f`                  | Show the payload in the next `--formatting`            |
+//! | `d`                  | Show both directions, or only one of them              |
+//! | `p`, `space`         | Pause the view; resume to catch up with what it held   |
+//! | `q`, `Esc`, `Ctrl-C` | Shut the proxy down, like Ctrl-C; again to force it    |
+
+use crate::args::Arguments;
+use crate::args::PayloadFormattingKind;
+use crate::args::get_formatter_by_kind;
+use crate::conn::Direction;
+use crate::formatters::PayloadFormatter;
+use clap::ValueEnum;
+use logged_stream::BufferFormatter;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event;
+use ratatui::crossterm::event::KeyCode;
+use ratatui::crossterm::event::KeyEvent;
+use ratatui::crossterm::event::KeyEventKind;
+use ratatui::crossterm::event::KeyModifiers;
+use ratatui::layout::Constraint;
+use ratatui::layout::Layout;
+use ratatui::style::Color;
+use ratatui::style::Style;
+use ratatui::style::Stylize;
+use ratatui::text::Line;
+use ratatui::widgets::Block;
+use ratatui::widgets::Paragraph;
+use ratatui::widgets::Row;
+use ratatui::widgets::Table;
+use ratatui::widgets::TableState;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::io::IsTerminal;
+use std::io::{self};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How often the view is redrawn, and how long a key press may wait.
+const FRAME: Duration = Duration::from_millis(100);
+
+/// How many closed connections the table keeps, the oldest leaving first.
+pub(crate) const RECENTLY_CLOSED: usize = 50;
+
+/// How much payload the pane keeps per connection; older chunks are dropped.
+const PAYLOAD_LIMIT: usize = 1024 * 1024;
+
+/// How many of the proxy's own lines the log pane keeps.
+const LOG_LINES: usize = 200;
+
+/// The most updates applied between two frames, so a busy proxy cannot keep the
+/// view from being drawn (or a key from being read).
+const UPDATES_PER_FRAME: usize = 10_000;
+
+/// The connection events that change the table; a connection's other lines (its
+/// payload records, its streams' half-closes, ...) are left to the log file.
+const TRACKED_EVENTS: &[&str] = &[
+    "accepted",
+    "connected",
+    "connect_failed",
+    "idle_close",
+    "forced_close",
+    "admin_close",
+    "closed",
+];
+
+/// What the view is told.
+pub(crate) enum Update {
+    /// A line the proxy logged.
+    Record(Record),
+    /// A chunk connection `conn_id` relayed in `direction`.
+    Payload {
+        conn_id: u64,
+        direction: Direction,
+        bytes: Vec<u8>,
+    },
+}
+
+/// The parts of a logged line the view reads.
+pub(crate) struct Record {
+    pub(crate) level: log::Level,
+    /// The line's key-values, as text.
+    pub(crate) fields: Vec<(String, String)>,
+    pub(crate) message: String,
+    /// Whether the line goes to the log pane: `--level` lets it through and it is
+    /// the proxy's own, or a connection's warning or error.
+    pub(crate) shown: bool,
+}
+
+impl Record {
+    fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.field(key) == Some("true")
+    }
+}
+
+/// Where the relays send their chunks for the payload pane. Clones share the
+/// channel.
+#[derive(Clone)]
+pub(crate) struct Feed(mpsc::Sender<Update>);
+
+impl Feed {
+    /// A feed, and the end of the channel the view reads.
+    pub(crate) fn channel() -> (Self, mpsc::Receiver<Update>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self(sender), receiver)
+    }
+
+    /// Connection `conn_id` relayed `payload` in `direction`.
+    pub(crate) fn payload(&self, conn_id: u64, direction: Direction, payload: &[u8]) {
+        let _ = self.0.send(Update::Payload {
+            conn_id,
+            direction,
+            bytes: payload.to_vec(),
+        });
+    }
+}
+
+/// What the proxy needs of a running view: where to send the chunks, and the
+/// shutdowns asked for from the keyboard (counted, so a second one can force it).
+#[derive(Clone)]
+pub(crate) struct Link {
+    pub(crate) feed: Feed,
+    pub(crate) quits: watch::Receiver<u64>,
+}
+
+impl Link {
+    /// Resolves at the next shutdown asked for from the keyboard. Never resolves
+    /// without a view, or once it has stopped.
+    pub(crate) async fn quit(link: &mut Option<Link>) {
+        if let Some(link) = link {
+            if link.quits.changed().await.is_ok() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+/// The running view: the terminal it took over and the thread drawing it.
+pub(crate) struct Tui {
+    feed: Feed,
+    quits: watch::Receiver<u64>,
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<Vec<String>>,
+}
+
+impl Tui {
+    /// Take the terminal over (raw mode, alternate screen) and start drawing. Fails
+    /// when standard output is not a terminal.
+    pub(crate) fn start(arguments: &Arguments) -> io::Result<Self> {
+        if !io::stdout().is_terminal() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "standard output is not a terminal",
+            ));
+        }
+        let terminal = ratatui::try_init().inspect_err(|_| ratatui::restore())?;
+        let (feed, updates) = Feed::channel();
+        let (quitter, quits) = watch::channel(0);
+        let stop = Arc::new(AtomicBool::new(false));
+        let view = View {
+            formatting: arguments.formatting,
+            separator: arguments.separator.clone(),
+            hexdump_width: arguments.hexdump_width as usize,
+        };
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut app = App::new(view);
+            let result = run(terminal, &mut app, &updates, &stopped, &quitter);
+            ratatui::restore();
+            if let Err(error) = result {
+                eprintln!("The terminal UI failed, shutting the proxy down: {error}");
+                quitter.send_modify(|quits| *quits += 1);
+            }
+            app.warnings()
+        });
+        Ok(Self {
+            feed,
+            quits,
+            stop,
+            thread,
+        })
+    }
+
+    /// The logger that feeds the view. `filter` is the console logger the proxy
+    /// would have used: its `--level` decides what reaches the log pane, and with
+    /// `to_file` (`--log-file`) it still writes every line it lets through.
+    pub(crate) fn logger(&self, filter: env_logger::Logger, to_file: bool) -> TuiLogger {
+        TuiLogger::new(self.feed.clone(), filter, to_file)
+    }
+
+    pub(crate) fn link(&self) -> Link {
+        Link {
+            feed: self.feed.clone(),
+            quits: self.quits.clone(),
+        }
+    }
+
+    /// Give the terminal back, then print the proxy's warnings and errors the log
+    /// pane showed, so a failure is not lost with the view.
+    pub(crate) fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Ok(warnings) = self.thread.join() {
+            for warning in warnings {
+                eprintln!("{warning}");
+            }
+        }
+    }
+}
+
+/// The `log` backend with `--tui`: every line is turned into an [`Update`] for
+/// the view, and still written to the `--log-file` if there is one.
+pub(crate) struct TuiLogger {
+    feed: Feed,
+    filter: env_logger::Logger,
+    to_file: bool,
+}
+
+impl TuiLogger {
+    pub(crate) fn new(feed: Feed, filter: env_logger::Logger, to_file: bool) -> Self {
+        Self {
+            feed,
+            filter,
+            to_file,
+        }
+    }
+
+    /// Install it as the process's logger. The connection events are let through
+    /// whatever the `--level`, as the table is built from them.
+    pub(crate) fn install(self) -> Result<(), log::SetLoggerError> {
+        let max_level = self.filter.filter().max(log::LevelFilter::Info);
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl log::Log for TuiLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let matches = self.filter.matches(record);
+        if matches && self.to_file {
+            self.filter.log(record);
+        }
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+        let fields = fields.0;
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        let connection = field("conn_id").is_some();
+        let tracked =
+            connection && field("event").is_some_and(|event| TRACKED_EVENTS.contains(&event));
+        let shown = matches && (!connection || record.level() <= log::Level::Warn);
+        if !tracked && !shown {
+            return;
+        }
+        let _ = self.feed.0.send(Update::Record(Record {
+            level: record.level(),
+            fields,
+            message: record.args().to_string(),
+            shown,
+        }));
+    }
+
+    fn flush(&self) {
+        if self.to_file {
+            self.filter.flush();
+        }
+    }
+}
+
+/// Collects a record's key-values as text.
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for Fields {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// Draw `app` until `stop` is set, applying the updates as they come and telling
+/// `quitter` when a key asks for a shutdown.
+fn run(
+    mut terminal: DefaultTerminal,
+    app: &mut App,
+    updates: &mpsc::Receiver<Update>,
+    stop: &AtomicBool,
+    quitter: &watch::Sender<u64>,
+) -> io::Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        for update in updates.try_iter().take(UPDATES_PER_FRAME) {
+            app.receive(update);
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+        if event::poll(FRAME)? {
+            if let event::Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && app.key(key) {
+                    quitter.send_modify(|quits| *quits += 1);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How the payload pane shows the bytes, as `--formatting`, `--separator` and
+/// `--hexdump-width` set it.
+pub(crate) struct View {
+    pub(crate) formatting: PayloadFormattingKind,
+    pub(crate) separator: String,
+    pub(crate) hexdump_width: usize,
+}
+
+/// Which directions the payload pane shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DirectionFilter {
+    Both,
+    Only(Direction),
+}
+
+impl DirectionFilter {
+    fn next(self) -> Self {
+        match self {
+            Self::Both => Self::Only(Direction::ClientToDestination),
+            Self::Only(Direction::ClientToDestination) => {
+                Self::Only(Direction::DestinationToClient)
+            }
+            Self::Only(Direction::DestinationToClient) => Self::Both,
+        }
+    }
+
+    fn shows(self, direction: Direction) -> bool {
+        match self {
+            Self::Both => true,
+            Self::Only(shown) => shown == direction,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Both => "both directions",
+            Self::Only(Direction::ClientToDestination) => "client to destination",
+            Self::Only(Direction::DestinationToClient) => "destination to client",
+        }
+    }
+}
+
+/// Where a connection of the table is at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    /// Accepted, waiting for the destination to answer.
+    Connecting,
+    Open,
+    /// Being closed, for the reason given, before its summary is logged.
+    Closing(&'static str),
+    Closed(String),
+}
+
+impl State {
+    fn describe(&self) -> String {
+        match self {
+            State::Connecting => "connecting".to_string(),
+            State::Open => "open".to_string(),
+            State::Closing(reason) => format!("closing ({reason})"),
+            State::Closed(reason) => format!("closed ({reason})"),
+        }
+    }
+}
+
+/// One row of the table, and the payload it relayed.
+struct Connection {
+    client: String,
+    destination: String,
+    /// The route's name, if it has one.
+    route: Option<String>,
+    state: State,
+    client_bytes: u64,
+    destination_bytes: u64,
+    chunks: VecDeque<(Direction, Vec<u8>)>,
+    /// The bytes held in `chunks`, at most [`PAYLOAD_LIMIT`].
+    buffered: usize,
+    /// The bytes dropped from the front of `chunks` to stay under the limit.
+    dropped: u64,
+}
+
+/// The view's state: what the proxy reported, and what the keys chose to show of
+/// it. Kept apart from the terminal, so it can be drawn on any backend.
+pub(crate) struct App {
+    view: View,
+    formatter: PayloadFormatter,
+    connections: BTreeMap<u64, Connection>,
+    /// The closed connections still in the table, the oldest first.
+    closed: VecDeque<u64>,
+    selected: Option<u64>,
+    directions: DirectionFilter,
+    /// While paused, the updates are held rather than applied.
+    paused: bool,
+    held: Vec<Update>,
+    /// How many lines the payload pane is scrolled back from its tail.
+    scroll: usize,
+    /// The furthest back the pane could scroll when it was last drawn.
+    scroll_limit: Cell<usize>,
+    log: VecDeque<(log::Level, String)>,
+    /// How many shutdowns the keys asked for.
+    quits: u32,
+}
+
+impl App {
+    pub(crate) fn new(view: View) -> Self {
+        let formatter = get_formatter_by_kind(view.formatting, &view.separator, view.hexdump_width);
+        Self {
+            view,
+            formatter,
+            connections: BTreeMap::new(),
+            closed: VecDeque::new(),
+            selected: None,
+            directions: DirectionFilter::Both,
+            paused: false,
+            held: Vec::new(),
+            scroll: 0,
+            scroll_limit: Cell::new(0),
+            log: VecDeque::new(),
+            quits: 0,
+        }
+    }
+
+    /// Apply `update`, or hold it while the view is paused.
+    pub(crate) fn receive(&mut self, update: Update) {
+        if self.paused {
+            self.held.push(update);
+            return;
+        }
+        match update {
+            Update::Record(record) => self.record(record),
+            Update::Payload {
+                conn_id,
+                direction,
+                bytes,
+            } => self.payload(conn_id, direction, bytes),
+        }
+    }
+
+    fn record(&mut self, record: Record) {
+        if record.shown {
+            if self.log.len() == LOG_LINES {
+                self.log.pop_front();
+            }
+            self.log.push_back((record.level, record.message.clone()));
+        }
+        let Some(conn_id) = record.field("conn_id").and_then(|id| id.parse().ok()) else {
+            return;
+        };
+        let event = record.field("event").unwrap_or_default();
+        if event == "accepted" {
+            self.connections.insert(
+                conn_id,
+                Connection {
+                    client: record.field("client").unwrap_or_default().to_string(),
+                    destination: record.field("destination").unwrap_or_default().to_string(),
+                    route: record.field("route").map(ToString::to_string),
+                    state: State::Connecting,
+                    client_bytes: 0,
+                    destination_bytes: 0,
+                    chunks: VecDeque::new(),
+                    buffered: 0,
+                    dropped: 0,
+                },
+            );
+            self.selected.get_or_insert(conn_id);
+            return;
+        }
+        let Some(connection) = self.connections.get_mut(&conn_id) else {
+            return;
+        };
+        if let Some(destination_addr) = record.field("destination_addr") {
+            if destination_addr != connection.destination {
+                connection.destination = format!("{} ({destination_addr})", connection.destination);
+            }
+        }
+        let closing = match event {
+            "connected" => {
+                connection.state = State::Open;
+                return;
+            }
+            "connect_failed" => {
+                connection.state = State::Closed("connect failed".to_string());
+                self.retire(conn_id);
+                return;
+            }
+            "idle_close" => "idle timeout",
+            "forced_close" => "shutdown",
+            "admin_close" => "admin API",
+            "closed" => {
+                let reason = if record.flag("idle_timeout") {
+                    "idle timeout".to_string()
+                } else if record.flag("shutdown") {
+                    "shutdown".to_string()
+                } else if record.flag("admin_close") {
+                    "admin API".to_string()
+                } else if let Some(peer) = record.field("closed_first") {
+                    format!("{peer} closed")
+                } else {
+                    "done".to_string()
+                };
+                connection.state = State::Closed(reason);
+                self.retire(conn_id);
+                return;
+            }
+            _ => return,
+        };
+        // Closed before the destination answered, a connection logs no summary.
+        if connection.state == State::Connecting {
+            connection.state = State::Closed(closing.to_string());
+            self.retire(conn_id);
+        } else {
+            connection.state = State::Closing(closing);
+        }
+    }
+
+    fn payload(&mut self, conn_id: u64, direction: Direction, bytes: Vec<u8>) {
+        let Some(connection) = self.connections.get_mut(&conn_id) else {
+            return;
+        };
+        // A literal destination logs no `connected` event as text: the first
+        // chunk tells that it answered.
+        if connection.state == State::Connecting {
+            connection.state = State::Open;
+        }
+        match direction {
+            Direction::ClientToDestination => connection.client_bytes += bytes.len() as u64,
+            Direction::DestinationToClient => connection.destination_bytes += bytes.len() as u64,
+        }
+        connection.buffered += bytes.len();
+        connection.chunks.push_back((direction, bytes));
+        while connection.buffered > PAYLOAD_LIMIT {
+            let Some((_, dropped)) = connection.chunks.pop_front() else {
+                break;
+            };
+            connection.buffered -= dropped.len();
+            connection.dropped += dropped.len() as u64;
+        }
+    }
+
+    /// Keep closed connection `conn_id` among the recently closed ones, letting
+    /// the oldest go past [`RECENTLY_CLOSED`].
+    fn retire(&mut self, conn_id: u64) {
+        if self.closed.contains(&conn_id) {
+            return;
+        }
+        self.closed.push_back(conn_id);
+        while self.closed.len() > RECENTLY_CLOSED {
+            let Some(oldest) = self.closed.pop_front() else {
+                break;
+            };
+            self.connections.remove(&oldest);
+            if self.selected == Some(oldest) {
+                self.selected = self
+                    .connections
+                    .range(oldest..)
+                    .next()
+                    .or_else(|| self.connections.iter().next_back())
+                    .map(|(conn_id, _)| *conn_id);
+                self.scroll = 0;
+            }
+        }
+    }
+
+    /// React to `key`; `true` if it asks for a shutdown.
+    pub(crate) fn key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.quits += 1;
+                return true;
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.quits += 1;
+                return true;
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.select(false),
+            KeyCode::Down | KeyCode::Char('j') => self.select(true),
+            KeyCode::PageUp => {
+                self.scroll = (self.scroll + self.page()).min(self.scroll_limit.get());
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(self.page()),
+            KeyCode::Home => self.scroll = self.scroll_limit.get(),
+            KeyCode::End => self.scroll = 0,
+            KeyCode::Char('f') => {
+                let kinds = PayloadFormattingKind::value_variants();
+                let current = kinds
+                    .iter()
+                    .position(|kind| *kind == self.view.formatting)
+                    .unwrap_or_default();
+                self.view.formatting = kinds[(current + 1) % kinds.len()];
+                self.formatter = get_formatter_by_kind(
+                    self.view.formatting,
+                    &self.view.separator,
+                    self.view.hexdump_width,
+                );
+                self.scroll = 0;
+            }
+            KeyCode::Char('d') => {
+                self.directions = self.directions.next();
+                self.scroll = 0;
+            }
+            KeyCode::Char('p' | ' ') => {
+                self.paused = !self.paused;
+                if !self.paused {
+                    for update in std::mem::take(&mut self.held) {
+                        self.receive(update);
+                    }
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Select the connection after the selected one, or the one before.
+    fn select(&mut self, next: bool) {
+        let ids: Vec<u64> = self.connections.keys().copied().collect();
+        let position = self
+            .selected
+            .and_then(|selected| ids.iter().position(|conn_id| *conn_id == selected));
+        let position = match (position, next) {
+            (None, _) => 0,
+            (Some(position), true) => (position + 1).min(ids.len().saturating_sub(1)),
+            (Some(position), false) => position.saturating_sub(1),
+        };
+        if let Some(conn_id) = ids.get(position) {
+            if self.selected != Some(*conn_id) {
+                self.selected = Some(*conn_id);
+                self.scroll = 0;
+            }
+        }
+    }
+
+    /// How far `PgUp` and `PgDn` scroll.
+    fn page(&self) -> usize {
+        10
+    }
+
+    /// The proxy's warnings and errors the log pane kept, to print once the
+    /// terminal is given back.
+    fn warnings(&self) -> Vec<String> {
+        self.log
+            .iter()
+            .filter(|(level, _)| *level <= log::Level::Warn)
+            .map(|(level, message)| format!("{level:<5} {message}"))
+            .collect()
+    }
+
+    /// Draw the view on `frame`.
+    pub(crate) fn draw(&self, frame: &mut Frame) {
+        let [table_area, payload_area, log_area, status_area] = Layout::vertical([
+            Constraint::Percentage(35),
+            Constraint::Min(5),
+            Constraint::Length(6),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let open = self.connections.len() - self.closed.len();
+        let rows = self.connections.iter().map(|(conn_id, connection)| {
+            let client = match &connection.route {
+                Some(route) => format!("[{route}] {}", connection.client),
+                None => connection.client.clone(),
+            };
+            let row = Row::new([
+                conn_id.to_string(),
+                client,
+                connection.destination.clone(),
+                connection.client_bytes.to_string(),
+                connection.destination_bytes.to_string(),
+                connection.state.describe(),
+            ]);
+            if matches!(connection.state, State::Closed(_)) {
+                row.fg(Color::DarkGray)
+            } else {
+                row
+            }
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(6),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+                Constraint::Length(12),
+                Constraint::Length(12),
+                Constraint::Length(27),
+            ],
+        )
+        .header(
+            Row::new([
+                "Id",
+                "Client",
+                "Destination",
+                "Client bytes",
+                "Dest. bytes",
+                "State",
+            ])
+            .bold(),
+        )
+        .row_highlight_style(Style::new().reversed())
+        .block(Block::bordered().title(format!(
+            " Connections: {open} open, {} recently closed ",
+            self.closed.len()
+        )));
+        let mut table_state =
+            TableState::default().with_selected(self.selected.and_then(|selected| {
+                self.connections
+                    .keys()
+                    .position(|conn_id| *conn_id == selected)
+            }));
+        frame.render_stateful_widget(table, table_area, &mut table_state);
+
+        let title = match self.selected {
+            Some(conn_id) => format!(
+                " #{conn_id} payload: {}, {} ",
+                self.view.formatting,
+                self.directions.describe()
+            ),
+            None => " Payload ".to_string(),
+        };
+        let block = Block::bordered().title(title);
+        let inner = block.inner(payload_area);
+        let lines = self.payload_lines(inner.width as usize, inner.height as usize);
+        frame.render_widget(Paragraph::new(lines).block(block), payload_area);
+
+        let block = Block::bordered().title(" Log ");
+        let visible = block.inner(log_area).height as usize;
+        let lines: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(visible))
+            .map(|(level, message)| {
+                let line = Line::from(format!("{level:<5} {message}"));
+                match level {
+                    log::Level::Error => line.fg(Color::Red),
+                    log::Level::Warn => line.fg(Color::Yellow),
+                    _ => line,
+                }
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(block), log_area);
+
+        let mut status = String::new();
+        if self.quits > 0 {
+            status.push_str("Shutting down: q again closes the remaining connections | ");
+        }
+        if self.paused {
+            status.push_str(&format!("PAUSED, {} update(s) held | ", self.held.len()));
+        }
+        status.push_str("↑↓ select  PgUp/PgDn scroll  f formatting  d direction  p pause  q quit");
+        frame.render_widget(Line::from(status).reversed(), status_area);
+    }
+
+    /// The tail of the selected connection's payload as it fits `height` lines of
+    /// `width` columns, scrolled back by `scroll` lines. Only the chunks that show
+    /// are formatted, newest first.
+    fn payload_lines(&self, width: usize, height: usize) -> Vec<Line<'static>> {
+        let Some(connection) = self
+            .selected
+            .and_then(|conn_id| self.connections.get(&conn_id))
+        else {
+            return vec![Line::from("No connection selected").italic()];
+        };
+        let width = width.max(1);
+        let wanted = height + self.scroll;
+        let mut lines = Vec::new();
+        let mut complete = true;
+        for (direction, bytes) in connection.chunks.iter().rev() {
+            if !self.directions.shows(*direction) {
+                continue;
+            }
+            if lines.len() >= wanted {
+                complete = false;
+                break;
+            }
+            let text = self.formatter.format_buffer(bytes);
+            let mut chunk = Vec::new();
+            for line in text.split('\n') {
+                let characters: Vec<char> = line.chars().collect();
+                if characters.is_empty() {
+                    chunk.push(Line::from(""));
+                }
+                for piece in characters.chunks(width) {
+                    chunk.push(Line::from(piece.iter().collect::<String>()));
+                }
+            }
+            lines.extend(chunk.into_iter().rev());
+            let header = match direction {
+                Direction::ClientToDestination => {
+                    Line::from(format!("< {} bytes from the client", bytes.len())).fg(Color::Green)
+                }
+                Direction::DestinationToClient => {
+                    Line::from(format!("> {} bytes from the destination", bytes.len()))
+                        .fg(Color::Cyan)
+                }
+            };
+            lines.push(header.bold());
+        }
+        if complete && connection.dropped > 0 {
+            lines.push(
+                Line::from(format!(
+                    "({} earlier bytes dropped, past the pane's limit)",
+                    connection.dropped
+                ))
+                .italic(),
+            );
+        }
+        if lines.is_empty() {
+            return vec![Line::from("Nothing relayed yet").italic()];
+        }
+        lines.reverse();
+        // Past the oldest line the pane stops scrolling.
+        let limit = lines.len().saturating_sub(height);
+        self.scroll_limit
+            .set(if complete { limit } else { usize::MAX });
+        let end = lines.len() - self.scroll.min(limit);
+        lines.drain(end.saturating_sub(height)..end).collect()
+    }
+}